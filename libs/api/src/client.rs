@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use reqwest::{Client, header};
 use serde_json::{Value, json};
 
@@ -7,10 +9,64 @@ use crate::{
     util::{extract_file_by_name, normalize_seed},
 };
 
+/// 访问 NovelAI 的 HTTP 连接池设置
+///
+/// 生成任务按队列逐个执行，同一时刻发往 `image.novelai.net` 的请求很少超过一个，
+/// 保留少量空闲连接即可复用 TLS 握手；调大只在外部并发调用客户端时有意义
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// 每个主机保留的空闲连接上限
+    pub max_idle_per_host: usize,
+    /// 空闲连接的保活时间，`None` 表示不过期
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_idle_per_host: 4,
+            idle_timeout: Some(Duration::from_secs(90)),
+        }
+    }
+}
+
+fn build_client(proxy: Option<reqwest::Proxy>, pool: PoolConfig) -> NaiResult<Client> {
+    let mut headers = header::HeaderMap::new();
+
+    headers.insert(header::ACCEPT, header::HeaderValue::from_static("*/*"));
+    headers.insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("application/json"),
+    );
+    headers.insert(
+        header::ORIGIN,
+        header::HeaderValue::from_static("https://novelai.net"),
+    );
+    headers.insert(
+        header::REFERER,
+        header::HeaderValue::from_static("https://novelai.net/"),
+    );
+
+    let mut builder = Client::builder()
+        .default_headers(headers)
+        .pool_max_idle_per_host(pool.max_idle_per_host)
+        .pool_idle_timeout(pool.idle_timeout);
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(proxy);
+    }
+    Ok(builder.build()?)
+}
+
+/// 图片生成接口的默认地址
+const DEFAULT_IMAGE_BASE_URL: &str = "https://image.novelai.net";
+
 #[derive(Debug, Clone)]
 pub struct NaiClient {
     client: Client,
     token: String,
+    proxy: Option<reqwest::Proxy>,
+    pool: PoolConfig,
+    image_base_url: String,
 }
 
 impl NaiClient {
@@ -23,28 +79,43 @@ impl NaiClient {
             .unwrap_or(token.as_str())
             .to_string();
 
-        let mut headers = header::HeaderMap::new();
-
-        headers.insert(header::ACCEPT, header::HeaderValue::from_static("*/*"));
-        headers.insert(
-            header::CONTENT_TYPE,
-            header::HeaderValue::from_static("application/json"),
-        );
-        headers.insert(
-            header::ORIGIN,
-            header::HeaderValue::from_static("https://novelai.net"),
-        );
-        headers.insert(
-            header::REFERER,
-            header::HeaderValue::from_static("https://novelai.net/"),
-        );
-
+        let pool = PoolConfig::default();
         Ok(Self {
-            client: Client::builder().default_headers(headers).build()?,
+            client: build_client(None, pool)?,
             token,
+            proxy: None,
+            pool,
+            image_base_url: DEFAULT_IMAGE_BASE_URL.to_string(),
         })
     }
 
+    /// 替换图片生成接口的地址（不含路径，如 `http://127.0.0.1:8080`），
+    /// 用于兼容的转发服务或测试
+    pub fn with_image_base_url(mut self, base_url: &str) -> Self {
+        self.image_base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// 通过代理访问 NovelAI，支持 `http://`、`https://` 与 `socks5://` / `socks5h://`
+    pub fn with_proxy(mut self, proxy_url: &str) -> NaiResult<Self> {
+        let proxy = reqwest::Proxy::all(proxy_url)?;
+        self.client = build_client(Some(proxy.clone()), self.pool)?;
+        self.proxy = Some(proxy);
+        Ok(self)
+    }
+
+    /// 调整连接池设置，已设置的代理保持不变
+    pub fn with_pool_config(mut self, pool: PoolConfig) -> NaiResult<Self> {
+        self.client = build_client(self.proxy.clone(), pool)?;
+        self.pool = pool;
+        Ok(self)
+    }
+
+    /// 当前的连接池设置
+    pub fn pool_config(&self) -> PoolConfig {
+        self.pool
+    }
+
     async fn post_raw(&self, url: &str, payload: &Value) -> NaiResult<Vec<u8>> {
         let resp = self
             .client
@@ -60,21 +131,21 @@ impl NaiClient {
         if status.is_success() {
             Ok(body.to_vec())
         } else {
-            Err(NaiError::BadStatus {
-                status: status.as_u16(),
-                body: String::from_utf8_lossy(&body).to_string(),
-            })
+            Err(NaiError::from_status(
+                status.as_u16(),
+                String::from_utf8_lossy(&body).to_string(),
+            ))
         }
     }
 
     async fn post_generate_image(&self, payload: &Value) -> NaiResult<Vec<u8>> {
-        self.post_raw("https://image.novelai.net/ai/generate-image", payload)
-            .await
+        let url = format!("{}/ai/generate-image", self.image_base_url);
+        self.post_raw(&url, payload).await
     }
 
     async fn post_argument_image(&self, payload: &Value) -> NaiResult<Vec<u8>> {
-        self.post_raw("https://image.novelai.net/ai/argument-image", payload)
-            .await
+        let url = format!("{}/ai/argument-image", self.image_base_url);
+        self.post_raw(&url, payload).await
     }
 
     pub async fn inquire_quota(&self) -> NaiResult<u64> {
@@ -103,104 +174,339 @@ impl NaiClient {
     }
 
     pub async fn generate_image(&self, req: &ImageGenerationRequest) -> NaiResult<Vec<u8>> {
-        let seed = normalize_seed(req.seed.unwrap_or(-1));
-        let uc_preset_id = req.uc_preset_id();
-        let use_coords = req.need_use_coords();
-        let prompt = if req.add_quality_tags {
-            format!("{}{}", req.prompt_positive, req.model.quality_tags())
-        } else {
-            req.prompt_positive.clone()
-        };
+        let seed = normalize_seed(req.seed);
+        let payload = build_payload(req, seed);
+
+        let bytes = self.post_generate_image(&payload).await?;
+        let image = extract_file_by_name(&bytes, "image_0.png").ok_or(NaiError::BadResult {
+            file_name: "image_0.png".to_string(),
+        })?;
+
+        Ok(image)
+    }
+}
 
-        let mut payload = json!({
-            "input": prompt,
-            "model": req.model,
-            "action": Action::Generate,
-            "parameters": {
-                "params_version": 3,
-                "width": req.width,
-                "height": req.height,
-                "scale": req.scale,
-                "sampler": req.sampler,
-                "steps": req.steps,
-                "n_samples": 1,
-                "ucPreset": uc_preset_id,
-                "qualityToggle": req.add_quality_tags,
-                "autoSmea": false,
-                "dynamic_thresholding": false,
-                "legacy": false,
-                "legacy_v3_extend": false,
-                "add_original_image": true,
-                "seed": seed,
-                "negative_prompt": req.prompt_negative,
-                "cfg_rescale": req.cfg_rescale,
-                "noise_schedule": req.noise,
-                "autoSmea": false,
-                "legacy": false,
-                "dynamic_thresholding": false,
-                "stream": "msgpack"
-            },
-            "use_new_shared_trial": true,
-        });
-
-        let enabled_chars = req
-            .character_prompts
-            .clone()
-            .unwrap_or_default()
-            .into_iter()
-            .filter(|c| c.enabled)
-            .collect::<Vec<_>>();
-        let char_positive = enabled_chars
-            .iter()
-            .map(|c| {
-                json!({
-                    "char_caption": c.prompt,
-                    "centers": [{"x": c.center.x, "y": c.center.y}]
-                })
+/// 构建 generate-image 请求体（不含鉴权信息）
+///
+/// 质量标签只在这里追加到提示词末尾（与 dry-run 显示一致），`qualityToggle` 固定为 false，
+/// 避免 NovelAI 再追加一次
+pub fn build_payload(req: &ImageGenerationRequest, seed: u64) -> Value {
+    let uc_preset_id = req.uc_preset_id();
+    let use_coords = req.need_use_coords();
+    let prompt = req
+        .model
+        .append_quality_tags(&req.prompt_positive, req.add_quality_tags);
+
+    let mut payload = json!({
+        "input": prompt,
+        "model": req.model,
+        "action": Action::Generate,
+        "parameters": {
+            "params_version": 3,
+            "width": req.width,
+            "height": req.height,
+            "scale": req.scale,
+            "sampler": req.sampler,
+            "steps": req.steps,
+            "n_samples": 1,
+            "ucPreset": uc_preset_id,
+            "qualityToggle": false,
+            // V4 / V4.5 模型不支持 SMEA，始终关闭，也不让 NovelAI 按分辨率自动开启
+            "sm": false,
+            "sm_dyn": false,
+            "autoSmea": false,
+            "dynamic_thresholding": false,
+            "legacy": false,
+            "legacy_v3_extend": false,
+            "add_original_image": true,
+            "seed": seed,
+            "negative_prompt": req.prompt_negative,
+            "cfg_rescale": req.cfg_rescale,
+            "noise_schedule": req.noise,
+            "stream": "msgpack"
+        },
+        "use_new_shared_trial": true,
+    });
+
+    let enabled_chars = req
+        .character_prompts
+        .clone()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|c| c.enabled)
+        .collect::<Vec<_>>();
+    // 按角色开关处理后的 (提示词, 负面提示词)
+    let char_captions = enabled_chars
+        .iter()
+        .map(|c| (c.caption(req.model), c.uc_caption(&req.prompt_negative)))
+        .collect::<Vec<_>>();
+    let char_positive = enabled_chars
+        .iter()
+        .zip(&char_captions)
+        .map(|(c, (caption, _))| {
+            json!({
+                "char_caption": caption,
+                "centers": [{"x": c.center.x, "y": c.center.y}]
             })
-            .collect::<Vec<_>>();
-        let char_negative = enabled_chars
-            .iter()
-            .map(|c| {
-                json!({
-                    "char_caption": c.uc,
-                    "centers": [{"x": c.center.x, "y": c.center.y}]
-                })
+        })
+        .collect::<Vec<_>>();
+    let char_negative = enabled_chars
+        .iter()
+        .zip(&char_captions)
+        .map(|(c, (_, uc))| {
+            json!({
+                "char_caption": uc,
+                "centers": [{"x": c.center.x, "y": c.center.y}]
+            })
+        })
+        .collect::<Vec<_>>();
+    let char_prompts = enabled_chars
+        .iter()
+        .zip(&char_captions)
+        .map(|(c, (caption, uc))| {
+            json!({
+                "prompt": caption,
+                "uc": uc,
+                "center": c.center,
+                "enabled": c.enabled
             })
-            .collect::<Vec<_>>();
-
-        payload["parameters"]["use_coords"] = json!(req.need_use_coords());
-        payload["parameters"]["characterPrompts"] = json!(enabled_chars);
-        payload["parameters"]["v4_prompt"] = json!({
-            "caption": {
-                "base_caption": prompt,
-                "char_captions": char_positive
-            },
-            "use_coords": use_coords,
-            "use_order": true
-        });
-        payload["parameters"]["v4_negative_prompt"] = json!({
-            "caption": {
-                "base_caption": req.prompt_negative,
-                "char_captions": char_negative
-            },
-            "legacy_uc": false
-        });
-
-        if req.sampler == Sampler::EulerAncestral {
-            payload["parameters"]["deliberate_euler_ancestral_bug"] = json!(false);
-            payload["parameters"]["prefer_brownian"] = json!(true);
+        })
+        .collect::<Vec<_>>();
+
+    payload["parameters"]["use_coords"] = json!(req.need_use_coords());
+    payload["parameters"]["characterPrompts"] = json!(char_prompts);
+    payload["parameters"]["v4_prompt"] = json!({
+        "caption": {
+            "base_caption": prompt,
+            "char_captions": char_positive
+        },
+        "use_coords": use_coords,
+        "use_order": true
+    });
+    payload["parameters"]["v4_negative_prompt"] = json!({
+        "caption": {
+            "base_caption": req.prompt_negative,
+            "char_captions": char_negative
+        },
+        "legacy_uc": false
+    });
+
+    if let (Some(params), Value::Object(extra)) = (
+        payload["parameters"].as_object_mut(),
+        sampler_params(req.sampler),
+    ) {
+        params.extend(extra);
+    }
+
+    if req.variety_plus {
+        payload["parameters"]["skip_cfg_above_sigma"] = json!(req.model.skip_cfg_above_sigma());
+    }
+
+    payload
+}
+
+/// 各采样器需要额外发送的参数，合并到 `parameters` 对象中
+fn sampler_params(sampler: Sampler) -> Value {
+    match sampler {
+        // 祖先采样器：关闭旧版 euler ancestral 的 bug 兼容，使用布朗噪声
+        Sampler::EulerAncestral | Sampler::Dpm2sAncestral => json!({
+            "deliberate_euler_ancestral_bug": false,
+            "prefer_brownian": true,
+        }),
+        // SDE 采样器依赖布朗树噪声以保证同种子可复现
+        Sampler::DpmSde | Sampler::Dpm2mSde => json!({
+            "prefer_brownian": true,
+        }),
+        // 确定性采样器无需额外参数
+        Sampler::Euler | Sampler::Dpm2m | Sampler::DdimV3 => json!({}),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(sampler: Sampler) -> ImageGenerationRequest {
+        let mut req: ImageGenerationRequest =
+            serde_json::from_value(json!({ "width": 832, "height": 1216 })).unwrap();
+        req.sampler = sampler;
+        req
+    }
+
+    #[test]
+    fn test_with_proxy() {
+        let client = NaiClient::new("token".to_string()).unwrap();
+        assert!(client.clone().with_proxy("http://127.0.0.1:8080").is_ok());
+        assert!(client.clone().with_proxy("socks5://127.0.0.1:1080").is_ok());
+        assert!(client.with_proxy("not a url").is_err());
+    }
+
+    #[test]
+    fn test_pool_config_survives_proxy() {
+        let pool = PoolConfig {
+            max_idle_per_host: 16,
+            idle_timeout: None,
+        };
+        let client = NaiClient::new("token".to_string())
+            .unwrap()
+            .with_pool_config(pool)
+            .unwrap()
+            .with_proxy("http://127.0.0.1:8080")
+            .unwrap();
+        assert_eq!(client.pool_config(), pool);
+        assert!(client.proxy.is_some());
+    }
+
+    #[test]
+    fn test_content_filter_classification() {
+        let body = r#"{"statusCode":400,"message":"Prompt was rejected by the content filter"}"#;
+        assert!(matches!(
+            NaiError::from_status(400, body.to_string()),
+            NaiError::ContentFiltered { status: 400, .. }
+        ));
+        assert!(matches!(
+            NaiError::from_status(400, r#"{"message":"invalid width"}"#.to_string()),
+            NaiError::BadStatus { .. }
+        ));
+        assert!(matches!(
+            NaiError::from_status(
+                400,
+                r#"{"message":"Inappropriate content detected"}"#.to_string()
+            ),
+            NaiError::ContentFiltered { .. }
+        ));
+        // 只有 400 才视为内容过滤
+        for status in [401, 404, 429, 500] {
+            assert!(matches!(
+                NaiError::from_status(status, body.to_string()),
+                NaiError::BadStatus { .. }
+            ));
+        }
+        // 不是 JSON，或关键字只出现在 message 以外（如回显的提示词）
+        for body in [
+            "rejected by the content filter",
+            r#"{"message":"invalid sampler","prompt":"content filter"}"#,
+            r#"{"message":"field 'filtered' is not allowed"}"#,
+        ] {
+            assert!(matches!(
+                NaiError::from_status(400, body.to_string()),
+                NaiError::BadStatus { .. }
+            ));
         }
+    }
+
+    #[test]
+    fn test_payload_quality_tags_applied_once() {
+        let mut req = request(Sampler::default());
+        req.prompt_positive = "1girl".to_string();
+        req.add_quality_tags = true;
+        let payload = build_payload(&req, 1);
+        let quality = req.model.quality_tags();
+        let input = payload["input"].as_str().unwrap();
+        assert_eq!(input.matches(quality).count(), 1);
+        assert_eq!(payload["parameters"]["qualityToggle"], json!(false));
+        let base = payload["parameters"]["v4_prompt"]["caption"]["base_caption"]
+            .as_str()
+            .unwrap();
+        assert_eq!(base, input);
 
-        if req.variety_plus {
-            payload["parameters"]["skip_cfg_above_sigma"] = json!(req.model.skip_cfg_above_sigma());
+        req.add_quality_tags = false;
+        let payload = build_payload(&req, 1);
+        assert_eq!(payload["input"], json!("1girl"));
+        assert_eq!(payload["parameters"]["qualityToggle"], json!(false));
+    }
+
+    #[test]
+    fn test_payload_smea_disabled() {
+        for sampler in Sampler::ALL {
+            let payload = build_payload(&request(sampler), 1);
+            let params = &payload["parameters"];
+            assert_eq!(params["sm"], json!(false));
+            assert_eq!(params["sm_dyn"], json!(false));
+            assert_eq!(params["autoSmea"], json!(false));
         }
+    }
 
-        let bytes = self.post_generate_image(&payload).await?;
-        let image = extract_file_by_name(&bytes, "image_0.png").ok_or(NaiError::BadResult {
-            file_name: "image_0.png".to_string(),
-        })?;
+    #[test]
+    fn test_payload_ancestral_samplers() {
+        for sampler in [Sampler::EulerAncestral, Sampler::Dpm2sAncestral] {
+            let payload = build_payload(&request(sampler), 1);
+            let params = &payload["parameters"];
+            assert_eq!(params["sampler"], json!(sampler));
+            assert_eq!(params["deliberate_euler_ancestral_bug"], json!(false));
+            assert_eq!(params["prefer_brownian"], json!(true));
+        }
+    }
 
-        Ok(image)
+    #[test]
+    fn test_payload_sde_samplers() {
+        for sampler in [Sampler::DpmSde, Sampler::Dpm2mSde] {
+            let payload = build_payload(&request(sampler), 1);
+            let params = &payload["parameters"];
+            assert_eq!(params["prefer_brownian"], json!(true));
+            assert!(params.get("deliberate_euler_ancestral_bug").is_none());
+        }
+    }
+
+    #[test]
+    fn test_payload_deterministic_samplers() {
+        for sampler in [Sampler::Euler, Sampler::Dpm2m, Sampler::DdimV3] {
+            let payload = build_payload(&request(sampler), 1);
+            let params = &payload["parameters"];
+            assert!(params.get("prefer_brownian").is_none());
+            assert!(params.get("deliberate_euler_ancestral_bug").is_none());
+        }
+    }
+
+    fn with_characters(flags: bool) -> ImageGenerationRequest {
+        let mut req = request(Sampler::Euler);
+        req.prompt_negative = "lowres".into();
+        req.character_prompts = Some(vec![
+            serde_json::from_value(json!({
+                "prompt": "girl",
+                "uc": "hat",
+                "add_quality_tags": flags,
+                "inherit_uc": flags
+            }))
+            .unwrap(),
+            serde_json::from_value(json!({ "prompt": "boy", "uc": "" })).unwrap(),
+        ]);
+        req
+    }
+
+    #[test]
+    fn test_payload_character_defaults_unchanged() {
+        let payload = build_payload(&with_characters(false), 1);
+        let params = &payload["parameters"];
+        let chars = &params["v4_prompt"]["caption"]["char_captions"];
+        assert_eq!(chars[0]["char_caption"], json!("girl"));
+        let uc = &params["v4_negative_prompt"]["caption"]["char_captions"];
+        assert_eq!(uc[0]["char_caption"], json!("hat"));
+        assert_eq!(uc[1]["char_caption"], json!(""));
+        assert_eq!(
+            params["characterPrompts"][0],
+            json!({
+                "prompt": "girl",
+                "uc": "hat",
+                "center": { "x": 0.5, "y": 0.5 },
+                "enabled": true
+            })
+        );
+    }
+
+    #[test]
+    fn test_payload_character_quality_and_uc_flags() {
+        let req = with_characters(true);
+        let payload = build_payload(&req, 1);
+        let params = &payload["parameters"];
+        let quality = format!("girl{}", req.model.quality_tags());
+        let chars = &params["v4_prompt"]["caption"]["char_captions"];
+        assert_eq!(chars[0]["char_caption"], json!(quality));
+        assert_eq!(chars[1]["char_caption"], json!("boy"));
+        let uc = &params["v4_negative_prompt"]["caption"]["char_captions"];
+        assert_eq!(uc[0]["char_caption"], json!("lowres, hat"));
+        assert_eq!(uc[1]["char_caption"], json!(""));
+        assert_eq!(params["characterPrompts"][0]["prompt"], json!(quality));
+        assert_eq!(params["characterPrompts"][0]["uc"], json!("lowres, hat"));
     }
 }
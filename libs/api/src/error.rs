@@ -6,6 +6,9 @@ pub enum NaiError {
     Http(#[from] reqwest::Error),
     #[error("unexpected response status {status}: {body}")]
     BadStatus { status: u16, body: String },
+    /// 提示词触发 NovelAI 内容过滤被拒绝，换种子重试可能成功
+    #[error("rejected by content filter (status {status}): {body}")]
+    ContentFiltered { status: u16, body: String },
     #[error("missing zip entry: {file_name}")]
     BadResult { file_name: String },
     #[error("general error: {msg}")]
@@ -13,3 +16,29 @@ pub enum NaiError {
 }
 
 pub type NaiResult<T> = Result<T, NaiError>;
+
+/// 内容过滤拒绝时 JSON 响应体 `message` 中出现的短语（小写匹配）
+const CONTENT_FILTER_MARKERS: &[&str] = &["content filter", "inappropriate content"];
+
+impl NaiError {
+    /// 按非成功响应分类：400 且 JSON 响应体的 `message` 提到内容过滤的为 `ContentFiltered`，
+    /// 其余为 `BadStatus`
+    ///
+    /// 只看 `message` 字段，避免参数校验等错误因回显了提示词或字段名而被误判为可重试
+    pub fn from_status(status: u16, body: String) -> Self {
+        let filtered = status == 400
+            && serde_json::from_str::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|value| value.get("message")?.as_str().map(str::to_lowercase))
+                .is_some_and(|message| {
+                    CONTENT_FILTER_MARKERS
+                        .iter()
+                        .any(|marker| message.contains(marker))
+                });
+        if filtered {
+            Self::ContentFiltered { status, body }
+        } else {
+            Self::BadStatus { status, body }
+        }
+    }
+}
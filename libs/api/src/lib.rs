@@ -5,7 +5,10 @@ pub mod error;
 pub mod types;
 pub mod util;
 
-pub use client::NaiClient;
+pub use client::{NaiClient, PoolConfig, build_payload};
 pub use error::{NaiError, NaiResult};
-pub use types::{Action, Center, CharacterPrompt, ImageGenerationRequest, Model, Noise, Sampler};
-pub use util::{default_true, extract_file_by_name, normalize_seed};
+pub use types::{
+    Action, Center, CenterPreset, CharacterPrompt, GenerationLimits, ImageGenerationRequest,
+    LimitExceeded, Model, Noise, Sampler, WeightRange, is_compatible,
+};
+pub use util::{default_true, extract_file_by_name, fixed_seed, normalize_seed, random_seed};
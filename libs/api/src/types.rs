@@ -12,6 +12,44 @@ pub enum Model {
 }
 
 impl Model {
+    pub const ALL: [Model; 2] = [Self::V45Full, Self::V45Curated];
+
+    /// 推荐的采样步数
+    pub const fn recommended_steps(&self) -> u32 {
+        match self {
+            Self::V45Full => 28,
+            Self::V45Curated => 28,
+        }
+    }
+
+    /// 推荐的 CFG scale
+    pub const fn recommended_scale(&self) -> f32 {
+        match self {
+            Self::V45Full => 5.0,
+            Self::V45Curated => 6.0,
+        }
+    }
+
+    /// 允许的最大 UC 预设编号；最大值即 "None"（不使用预设）
+    /// - V4.5 Full: 0 Heavy, 1 Light, 2 Furry Focus, 3 Human Focus, 4 None
+    /// - V4.5 Curated: 0 Heavy, 1 Light, 2 Human Focus, 3 None
+    pub const fn max_uc_preset(&self) -> u8 {
+        match self {
+            Self::V45Full => 4,
+            Self::V45Curated => 3,
+        }
+    }
+
+    /// 允许的采样步数范围
+    pub const fn steps_range(&self) -> (u32, u32) {
+        (1, 50)
+    }
+
+    /// 允许的 CFG scale 范围
+    pub const fn scale_range(&self) -> (f32, f32) {
+        (0.0, 10.0)
+    }
+
     pub const fn quality_tags(&self) -> &'static str {
         match self {
             Self::V45Full => ", very aesthetic, masterpiece, no text",
@@ -21,10 +59,19 @@ impl Model {
         }
     }
 
+    /// 按 `add_quality_tags` 在提示词末尾追加质量标签，与实际发送给 NAI 的内容一致
+    pub fn append_quality_tags(&self, prompt: &str, add_quality_tags: bool) -> String {
+        if add_quality_tags {
+            format!("{}{}", prompt, self.quality_tags())
+        } else {
+            prompt.to_string()
+        }
+    }
+
     pub const fn skip_cfg_above_sigma(&self) -> f32 {
         match self {
             Self::V45Full => 58.0,
-            Self::V45Curated => 36.158_893_609_242_725,
+            Self::V45Curated => 36.158_894,
         }
     }
 }
@@ -48,6 +95,32 @@ pub enum Sampler {
     DdimV3,
 }
 
+impl Sampler {
+    pub const ALL: [Sampler; 7] = [
+        Self::Euler,
+        Self::EulerAncestral,
+        Self::Dpm2sAncestral,
+        Self::Dpm2m,
+        Self::DpmSde,
+        Self::Dpm2mSde,
+        Self::DdimV3,
+    ];
+
+    /// 该采样器支持的噪声调度，第一个为默认值
+    pub const fn supported_noises(&self) -> &'static [Noise] {
+        match self {
+            // DDIM 不使用噪声调度，karras 等设置会被忽略
+            Self::DdimV3 => &[Noise::Native],
+            _ => &Noise::ALL,
+        }
+    }
+}
+
+/// 判断采样器与噪声调度是否兼容
+pub fn is_compatible(sampler: Sampler, noise: Noise) -> bool {
+    sampler.supported_noises().contains(&noise)
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
 pub enum Noise {
     #[serde(rename = "native")]
@@ -61,6 +134,15 @@ pub enum Noise {
     PolyExponential,
 }
 
+impl Noise {
+    pub const ALL: [Noise; 4] = [
+        Self::Karras,
+        Self::Native,
+        Self::Exponential,
+        Self::PolyExponential,
+    ];
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ImageGenerationRequest {
     /// Model to use for image generation
@@ -116,22 +198,215 @@ pub struct ImageGenerationRequest {
     pub legacy_uc: bool,
 }
 
+/// 图片宽高必须是该值的倍数
+const DIMENSION_STEP: u32 = 64;
+
+/// 冒号权重（`1.5::tag::`）的允许范围
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WeightRange {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Default for WeightRange {
+    fn default() -> Self {
+        Self { min: 0.0, max: 2.0 }
+    }
+}
+
+impl WeightRange {
+    pub fn contains(&self, weight: f64) -> bool {
+        (self.min..=self.max).contains(&weight)
+    }
+
+    pub fn clamp(&self, weight: f64) -> f64 {
+        weight.clamp(self.min, self.max)
+    }
+}
+
+/// 单次生成允许的最大宽高与步数，超出的请求直接拒绝
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenerationLimits {
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_steps: u32,
+}
+
+impl Default for GenerationLimits {
+    fn default() -> Self {
+        Self {
+            max_width: 2048,
+            max_height: 2048,
+            max_steps: 50,
+        }
+    }
+}
+
+/// 生成参数超出 `GenerationLimits`
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, Serialize)]
+#[serde(tag = "field", rename_all = "snake_case")]
+pub enum LimitExceeded {
+    #[error("width {value} exceeds the maximum of {max}")]
+    Width { value: u32, max: u32 },
+    #[error("height {value} exceeds the maximum of {max}")]
+    Height { value: u32, max: u32 },
+    #[error("steps {value} exceeds the maximum of {max}")]
+    Steps { value: u32, max: u32 },
+}
+
+impl GenerationLimits {
+    pub fn check(&self, width: u32, height: u32, steps: u32) -> Result<(), LimitExceeded> {
+        if width > self.max_width {
+            return Err(LimitExceeded::Width {
+                value: width,
+                max: self.max_width,
+            });
+        }
+        if height > self.max_height {
+            return Err(LimitExceeded::Height {
+                value: height,
+                max: self.max_height,
+            });
+        }
+        if steps > self.max_steps {
+            return Err(LimitExceeded::Steps {
+                value: steps,
+                max: self.max_steps,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// 将提示词中超出范围的冒号权重修正到范围内，返回修正后的文本和每处修正的 (原值, 新值)
+///
+/// 识别规则与编辑器的解析器一致：`-?数字[.数字]` 紧跟 `::` 即为权重开始
+fn clamp_colon_weights(prompt: &str, range: WeightRange) -> (String, Vec<(f64, f64)>) {
+    let bytes = prompt.as_bytes();
+    let mut out = String::with_capacity(prompt.len());
+    let mut clamped = Vec::new();
+    let mut copied = 0;
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let Some(len) = weight_number_len(&bytes[pos..]) else {
+            pos += 1;
+            continue;
+        };
+        let number = &prompt[pos..pos + len];
+        if let Ok(weight) = number.parse::<f64>()
+            && !range.contains(weight)
+        {
+            let fixed = range.clamp(weight);
+            out.push_str(&prompt[copied..pos]);
+            out.push_str(&fixed.to_string());
+            copied = pos + len;
+            clamped.push((weight, fixed));
+        }
+        // 跳过数字和 `::`
+        pos += len + 2;
+    }
+    out.push_str(&prompt[copied..]);
+    (out, clamped)
+}
+
+/// 若以权重开始语法开头，返回数字部分的字节长度
+fn weight_number_len(bytes: &[u8]) -> Option<usize> {
+    let mut len = 0;
+    if bytes.first() == Some(&b'-') {
+        len += 1;
+    }
+    let mut has_digit = false;
+    let mut has_dot = false;
+    while let Some(&b) = bytes.get(len) {
+        if b.is_ascii_digit() {
+            has_digit = true;
+        } else if b == b'.' && !has_dot {
+            has_dot = true;
+        } else {
+            break;
+        }
+        len += 1;
+    }
+    (has_digit && bytes[len..].starts_with(b"::")).then_some(len)
+}
+
 impl ImageGenerationRequest {
-    pub fn uc_preset_id(&self) -> u8 {
-        match self.model {
-            // 0-4 are valid for V4.5 Full models
-            // 0: Heavy, 1: Light, 2: Furry Focus, 3: Human Focus, 4: None
-            Model::V45Full => self
-                .undesired_content_preset
-                .map(|id| id.min(4))
-                .unwrap_or(4),
-            // 0-3 are valid for V4.5 Curated models
-            // 0: Heavy, 1: Light, 2: Human Focus, 3: None
-            Model::V45Curated => self
-                .undesired_content_preset
-                .map(|id| id.min(3))
-                .unwrap_or(3),
+    /// 校验并修正请求参数，返回所做修正的说明
+    ///
+    /// - 与采样器不兼容的噪声调度会被替换为该采样器的默认噪声调度
+    /// - 宽高会被修正为 64 的倍数（至少 64）
+    /// - 超出 `weight_range` 的冒号权重会被修正到范围内
+    pub fn validate(&mut self, weight_range: WeightRange) -> Vec<String> {
+        let mut warnings = Vec::new();
+        for (name, value) in [("width", &mut self.width), ("height", &mut self.height)] {
+            let fixed = (*value / DIMENSION_STEP).max(1) * DIMENSION_STEP;
+            if fixed != *value {
+                warnings.push(format!(
+                    "{name} {value} is not a multiple of {DIMENSION_STEP}, using {fixed}"
+                ));
+                *value = fixed;
+            }
         }
+        if !is_compatible(self.sampler, self.noise) {
+            let fallback = self.sampler.supported_noises()[0];
+            warnings.push(format!(
+                "noise {:?} is not supported by sampler {:?}, using {:?}",
+                self.noise, self.sampler, fallback
+            ));
+            self.noise = fallback;
+        }
+
+        let mut prompts = vec![
+            ("prompt", &mut self.prompt_positive),
+            ("negative prompt", &mut self.prompt_negative),
+        ];
+        if let Some(chars) = self.character_prompts.as_mut() {
+            for char in chars {
+                prompts.push(("character prompt", &mut char.prompt));
+                prompts.push(("character uc", &mut char.uc));
+            }
+        }
+        for (field, prompt) in prompts {
+            let (fixed, clamped) = clamp_colon_weights(prompt, weight_range);
+            if clamped.is_empty() {
+                continue;
+            }
+            for (from, to) in clamped {
+                warnings.push(format!(
+                    "weight {from} in {field} is outside {}..={}, clamped to {to}",
+                    weight_range.min, weight_range.max
+                ));
+            }
+            *prompt = fixed;
+        }
+        warnings
+    }
+
+    /// 检查宽高与步数是否超出上限
+    pub fn check_limits(&self, limits: GenerationLimits) -> Result<(), LimitExceeded> {
+        limits.check(self.width, self.height, self.steps)
+    }
+
+    /// 按 NovelAI 网页端公式估算本次请求消耗的 Anlas（仅供参考）
+    ///
+    /// Opus 订阅在 1024x1024 像素以内、不超过 28 步时，每次请求免费一张
+    pub fn estimated_anlas(&self, opus: bool) -> u32 {
+        let pixels = (self.width as f64 * self.height as f64).max(65536.0);
+        let per_sample = (2.951823174884865e-6 * pixels
+            + 5.753298233447344e-7 * pixels * self.steps as f64)
+            .ceil()
+            .max(2.0) as u32;
+        let samples = self.quantity.unwrap_or(1).max(1);
+        let free = opus && self.steps <= 28 && pixels <= 1024.0 * 1024.0;
+        per_sample * (samples - free as u32)
+    }
+
+    /// 按模型范围截断 UC 预设编号，未指定时为 "None"（见 [`Model::max_uc_preset`]）
+    pub fn uc_preset_id(&self) -> u8 {
+        let max = self.model.max_uc_preset();
+        self.undesired_content_preset
+            .map(|id| id.min(max))
+            .unwrap_or(max)
     }
 
     pub fn need_use_coords(&self) -> bool {
@@ -157,20 +432,124 @@ pub struct CharacterPrompt {
     pub center: Center,
     #[serde(default = "default_true")]
     pub enabled: bool,
+    /// 在该角色的提示词末尾追加模型的质量标签
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub add_quality_tags: bool,
+    /// 将基础负面提示词合并到该角色的负面提示词之前
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub inherit_uc: bool,
 }
 
+impl CharacterPrompt {
+    /// 实际发送的角色提示词
+    pub fn caption(&self, model: Model) -> String {
+        model.append_quality_tags(&self.prompt, self.add_quality_tags)
+    }
+
+    /// 实际发送的角色负面提示词
+    pub fn uc_caption(&self, base_negative: &str) -> String {
+        let base = base_negative.trim();
+        if !self.inherit_uc || base.is_empty() {
+            self.uc.clone()
+        } else if self.uc.trim().is_empty() {
+            base.to_string()
+        } else {
+            format!("{}, {}", base, self.uc)
+        }
+    }
+}
+
+/// 角色位置；反序列化时也接受预设名，如 `"top_left"`，见 [`CenterPreset`]
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "CenterInput")]
 pub struct Center {
     pub x: f32,
     pub y: f32,
 }
 
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CenterInput {
+    Preset(CenterPreset),
+    Coords { x: f32, y: f32 },
+}
+
+impl From<CenterInput> for Center {
+    fn from(input: CenterInput) -> Self {
+        match input {
+            CenterInput::Preset(preset) => preset.to_center(),
+            CenterInput::Coords { x, y } => Center { x, y },
+        }
+    }
+}
+
+/// 常用的角色位置，对应 NovelAI 5x5 网格的中心与四边
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CenterPreset {
+    Center,
+    Left,
+    Right,
+    Top,
+    Bottom,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl CenterPreset {
+    pub const ALL: [CenterPreset; 9] = [
+        Self::Center,
+        Self::Left,
+        Self::Right,
+        Self::Top,
+        Self::Bottom,
+        Self::TopLeft,
+        Self::TopRight,
+        Self::BottomLeft,
+        Self::BottomRight,
+    ];
+
+    /// 对应的网格坐标；除 `Center` 外都会让请求启用坐标
+    pub fn to_center(self) -> Center {
+        let (x, y) = match self {
+            Self::Center => (0.5, 0.5),
+            Self::Left => (0.1, 0.5),
+            Self::Right => (0.9, 0.5),
+            Self::Top => (0.5, 0.1),
+            Self::Bottom => (0.5, 0.9),
+            Self::TopLeft => (0.1, 0.1),
+            Self::TopRight => (0.9, 0.1),
+            Self::BottomLeft => (0.1, 0.9),
+            Self::BottomRight => (0.9, 0.9),
+        };
+        Center { x, y }
+    }
+}
+
 impl Default for Center {
     fn default() -> Self {
         Self { x: 0.5, y: 0.5 }
     }
 }
 
+impl Center {
+    /// 将 `count` 个角色从左到右均匀排开，x 取 NovelAI 5x5 网格的列中心（0.1 ~ 0.9）
+    pub fn spread(count: usize) -> Vec<Center> {
+        const COLUMNS: [f32; 5] = [0.1, 0.3, 0.5, 0.7, 0.9];
+        (0..count)
+            .map(|i| {
+                let column = ((i * 2 + 1) * COLUMNS.len() / (count * 2)).min(COLUMNS.len() - 1);
+                Center {
+                    x: COLUMNS[column],
+                    y: 0.5,
+                }
+            })
+            .collect()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Action {
     #[serde(rename = "generate")]
@@ -184,3 +563,192 @@ fn default_steps() -> u32 {
 fn defualt_scale() -> f32 {
     5.0
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_center_presets() {
+        let coords: Vec<_> = CenterPreset::ALL
+            .iter()
+            .map(|p| {
+                let c = p.to_center();
+                (c.x, c.y)
+            })
+            .collect();
+        assert_eq!(
+            coords,
+            vec![
+                (0.5, 0.5),
+                (0.1, 0.5),
+                (0.9, 0.5),
+                (0.5, 0.1),
+                (0.5, 0.9),
+                (0.1, 0.1),
+                (0.9, 0.1),
+                (0.1, 0.9),
+                (0.9, 0.9),
+            ]
+        );
+
+        let chars: Vec<CharacterPrompt> = serde_json::from_value(serde_json::json!([
+            { "prompt": "a", "uc": "", "center": "top_left" },
+            { "prompt": "b", "uc": "", "center": { "x": 0.3, "y": 0.7 } },
+        ]))
+        .unwrap();
+        assert_eq!((chars[0].center.x, chars[0].center.y), (0.1, 0.1));
+        assert_eq!((chars[1].center.x, chars[1].center.y), (0.3, 0.7));
+        // 序列化仍输出坐标
+        assert_eq!(
+            serde_json::to_value(&chars[0].center).unwrap(),
+            serde_json::json!({ "x": 0.1f32, "y": 0.1f32 })
+        );
+
+        let mut req: ImageGenerationRequest =
+            serde_json::from_value(serde_json::json!({ "width": 832, "height": 1216 })).unwrap();
+        req.character_prompts = Some(vec![CharacterPrompt {
+            center: CenterPreset::Center.to_center(),
+            ..chars[0].clone()
+        }]);
+        assert!(!req.need_use_coords());
+        req.character_prompts = Some(chars);
+        assert!(req.need_use_coords());
+        assert!(serde_json::from_value::<Center>(serde_json::json!("middle")).is_err());
+    }
+
+    #[test]
+    fn test_center_spread() {
+        let xs = |count| {
+            Center::spread(count)
+                .iter()
+                .map(|c| c.x)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(xs(1), vec![0.5]);
+        assert_eq!(xs(2), vec![0.3, 0.7]);
+        assert_eq!(xs(3), vec![0.1, 0.5, 0.9]);
+    }
+
+    #[test]
+    fn test_model_recommendations() {
+        assert_eq!(Model::V45Full.recommended_steps(), 28);
+        assert_eq!(Model::V45Full.recommended_scale(), 5.0);
+        assert_eq!(Model::V45Curated.recommended_steps(), 28);
+        assert_eq!(Model::V45Curated.recommended_scale(), 6.0);
+        for model in Model::ALL {
+            let (min_steps, max_steps) = model.steps_range();
+            assert!((min_steps..=max_steps).contains(&model.recommended_steps()));
+            let (min_scale, max_scale) = model.scale_range();
+            assert!((min_scale..=max_scale).contains(&model.recommended_scale()));
+        }
+    }
+
+    #[test]
+    fn test_append_quality_tags() {
+        assert_eq!(
+            Model::V45Full.append_quality_tags("1girl", true),
+            "1girl, very aesthetic, masterpiece, no text"
+        );
+        assert_eq!(
+            Model::V45Curated.append_quality_tags("1girl", false),
+            "1girl"
+        );
+    }
+
+    #[test]
+    fn test_sampler_noise_compatibility() {
+        assert!(is_compatible(Sampler::EulerAncestral, Noise::Karras));
+        assert!(is_compatible(Sampler::DdimV3, Noise::Native));
+        assert!(!is_compatible(Sampler::DdimV3, Noise::Karras));
+    }
+
+    #[test]
+    fn test_validate_corrects_incompatible_noise() {
+        let mut req: ImageGenerationRequest =
+            serde_json::from_str(r#"{"width": 832, "height": 1216, "sampler": "ddim_v3"}"#)
+                .unwrap();
+        assert_eq!(req.noise, Noise::Karras);
+
+        let warnings = req.validate(WeightRange::default());
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(req.noise, Noise::Native);
+        assert!(req.validate(WeightRange::default()).is_empty());
+    }
+
+    #[test]
+    fn test_validate_rounds_dimensions() {
+        let mut req: ImageGenerationRequest =
+            serde_json::from_str(r#"{"width": 830, "height": 20}"#).unwrap();
+        assert_eq!(req.validate(WeightRange::default()).len(), 2);
+        assert_eq!((req.width, req.height), (768, 64));
+    }
+
+    #[test]
+    fn test_check_limits() {
+        let mut req: ImageGenerationRequest =
+            serde_json::from_str(r#"{"width": 832, "height": 1216, "steps": 28}"#).unwrap();
+        let limits = GenerationLimits::default();
+        assert!(req.check_limits(limits).is_ok());
+
+        req.width = 4096;
+        let err = req.check_limits(limits).unwrap_err();
+        assert_eq!(
+            err,
+            LimitExceeded::Width {
+                value: 4096,
+                max: 2048
+            }
+        );
+        assert!(err.to_string().contains("2048"));
+
+        req.width = 832;
+        req.steps = 51;
+        assert!(matches!(
+            req.check_limits(limits),
+            Err(LimitExceeded::Steps { value: 51, max: 50 })
+        ));
+    }
+
+    #[test]
+    fn test_estimated_anlas() {
+        let mut req: ImageGenerationRequest =
+            serde_json::from_str(r#"{"width": 832, "height": 1216, "steps": 28}"#).unwrap();
+        assert_eq!(req.estimated_anlas(false), 20);
+        assert_eq!(req.estimated_anlas(true), 0);
+
+        req.steps = 50;
+        assert_eq!(req.estimated_anlas(true), 33);
+        // 最低消耗 2
+        req.width = 64;
+        req.height = 64;
+        req.steps = 1;
+        assert_eq!(req.estimated_anlas(false), 2);
+    }
+
+    #[test]
+    fn test_validate_clamps_colon_weights() {
+        let mut req: ImageGenerationRequest = serde_json::from_str(
+            r#"{"width": 832, "height": 1216,
+                "prompt_positive": "1000::cat::, 1.5::dog::, 2 girls",
+                "prompt_negative": "-5::blurry::"}"#,
+        )
+        .unwrap();
+
+        let warnings = req.validate(WeightRange::default());
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(req.prompt_positive, "2::cat::, 1.5::dog::, 2 girls");
+        assert_eq!(req.prompt_negative, "0::blurry::");
+
+        let mut req: ImageGenerationRequest = serde_json::from_str(
+            r#"{"width": 832, "height": 1216, "prompt_positive": "-1::cat::"}"#,
+        )
+        .unwrap();
+        let range = WeightRange {
+            min: -2.0,
+            max: 2.0,
+        };
+        assert!(req.validate(range).is_empty());
+        assert_eq!(req.prompt_positive, "-1::cat::");
+    }
+}
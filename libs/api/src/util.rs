@@ -3,13 +3,20 @@ use std::io::{Cursor, Read};
 use rand::Rng;
 use zip::ZipArchive;
 
-pub fn normalize_seed(seed: i64) -> u64 {
-    if seed == -1 {
-        let mut rng = rand::rng();
-        rng.random_range(1_000_000_000u64..=9_999_999_999u64)
-    } else {
-        seed.max(0) as u64
-    }
+/// 固定种子；`None` 或负数表示随机，0 和正数按原值固定
+pub fn fixed_seed(seed: Option<i64>) -> Option<u64> {
+    seed.filter(|&s| s >= 0).map(|s| s as u64)
+}
+
+/// 生成 10 位随机种子
+pub fn random_seed() -> u64 {
+    let mut rng = rand::rng();
+    rng.random_range(1_000_000_000u64..=9_999_999_999u64)
+}
+
+/// 解析实际使用的种子：固定种子原样返回，否则生成随机种子
+pub fn normalize_seed(seed: Option<i64>) -> u64 {
+    fixed_seed(seed).unwrap_or_else(random_seed)
 }
 
 pub fn extract_file_by_name(bytes: &[u8], name: &str) -> Option<Vec<u8>> {
@@ -23,3 +30,23 @@ pub fn extract_file_by_name(bytes: &[u8], name: &str) -> Option<Vec<u8>> {
 pub const fn default_true() -> bool {
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seed_rules() {
+        assert_eq!(fixed_seed(None), None);
+        assert_eq!(fixed_seed(Some(-1)), None);
+        assert_eq!(fixed_seed(Some(-5)), None);
+        assert_eq!(fixed_seed(Some(0)), Some(0));
+        assert_eq!(fixed_seed(Some(42)), Some(42));
+
+        assert_eq!(normalize_seed(Some(0)), 0);
+        assert_eq!(normalize_seed(Some(42)), 42);
+        for seed in [None, Some(-1), Some(-5)] {
+            assert!((1_000_000_000..=9_999_999_999).contains(&normalize_seed(seed)));
+        }
+    }
+}
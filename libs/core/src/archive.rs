@@ -1,14 +1,18 @@
 use std::{
     collections::HashSet,
     fs,
+    io::{self, Write},
     path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
 };
 
-use anyhow::anyhow;
-use chrono::Local;
+use crate::{CoreError, CoreResult, CoreStorage, GalleryTimezone, imaging};
+use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
-use tracing::info;
-use crate::{CoreResult, CoreStorage};
+use tracing::{info, warn};
 
 /// 单个归档文件信息
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -18,11 +22,27 @@ pub struct ArchiveInfo {
     pub created_at: String,
 }
 
+/// 归档内的单个文件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    /// zip 内路径，如 `2024-03-01/xxx.png`
+    pub name: String,
+    /// 解压后大小
+    pub size: u64,
+    pub compressed_size: u64,
+}
+
 /// 归档创建结果
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ArchiveResult {
     pub archives: Vec<ArchiveInfo>,
     pub deleted_records: usize,
+    /// 是否在处理完所有日期之前被取消
+    #[serde(default)]
+    pub cancelled: bool,
+    /// 取消时尚未处理的日期，文件夹与记录保持不变
+    #[serde(default)]
+    pub remaining_dates: Vec<String>,
 }
 
 /// 可归档的日期信息
@@ -33,10 +53,142 @@ pub struct ArchivableDate {
     pub total_size: u64,
 }
 
+/// 读取图片时默认的缓冲区大小
+pub const DEFAULT_ARCHIVE_BUFFER_SIZE: usize = 128 * 1024;
+
+/// 归档文件的压缩方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum ArchiveCompression {
+    /// 只存储不压缩，速度最快
+    Stored,
+    Zstd {
+        level: i64,
+    },
+}
+
+impl Default for ArchiveCompression {
+    fn default() -> Self {
+        Self::Zstd { level: 19 }
+    }
+}
+
+impl ArchiveCompression {
+    /// 解析 `stored`、`zstd` 或 `zstd:<level>`（level 1-22）
+    pub fn parse(value: &str) -> CoreResult<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "stored" => Ok(Self::Stored),
+            "zstd" => Ok(Self::default()),
+            other => {
+                let level = other
+                    .strip_prefix("zstd:")
+                    .and_then(|level| level.parse::<i64>().ok())
+                    .filter(|level| (1..=22).contains(level))
+                    .ok_or_else(|| {
+                        CoreError::invalid(format!("invalid archive compression: {}", value))
+                    })?;
+                Ok(Self::Zstd { level })
+            }
+        }
+    }
+
+    fn file_options(self) -> zip::write::SimpleFileOptions {
+        let options = zip::write::SimpleFileOptions::default().large_file(true);
+        match self {
+            Self::Stored => options.compression_method(zip::CompressionMethod::Stored),
+            Self::Zstd { level } => options
+                .compression_method(zip::CompressionMethod::Zstd)
+                .compression_level(Some(level)),
+        }
+    }
+}
+
+/// 归档时的压缩方式、读取缓冲区大小与保留天数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArchiveOptions {
+    pub compression: ArchiveCompression,
+    /// 每次从图片文件读取的字节数，决定单个文件占用的内存上限
+    pub buffer_size: usize,
+    /// 今天之前最近的 N 个日期文件夹不列为可归档（0 表示今天之前的都可归档）
+    pub keep_recent_days: u32,
+}
+
+impl Default for ArchiveOptions {
+    fn default() -> Self {
+        Self {
+            compression: ArchiveCompression::default(),
+            buffer_size: DEFAULT_ARCHIVE_BUFFER_SIZE,
+            keep_recent_days: 0,
+        }
+    }
+}
+
+/// 以固定大小的缓冲区把文件流式写入 `out`，不整体读入内存
+fn copy_file(path: &Path, out: &mut impl Write, buffer_size: usize) -> io::Result<u64> {
+    let file = fs::File::open(path)?;
+    let mut reader = io::BufReader::with_capacity(buffer_size.max(1), file);
+    io::copy(&mut reader, out)
+}
+
+/// 是否为合法的日期目录名（`YYYY-MM-DD` 且是真实存在的日期）
+pub fn is_valid_date(date: &str) -> bool {
+    date.len() == 10 && NaiveDate::parse_from_str(date, "%Y-%m-%d").is_ok()
+}
+
+/// 目录下是否有子目录
+fn has_subdirectories(dir: &Path) -> io::Result<bool> {
+    for entry in fs::read_dir(dir)? {
+        if entry?.file_type()?.is_dir() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// 将 `gallery_dir/date` 中的文件打包为 zip 流式写入 `writer`，不修改目录与数据库
+///
+/// 写入端无需 Seek；图片本身已压缩，因此只存储不再压缩以便边打包边下载
+pub fn write_date_zip<W: Write>(
+    gallery_dir: &Path,
+    date: &str,
+    writer: W,
+    buffer_size: usize,
+) -> CoreResult<()> {
+    if !is_valid_date(date) {
+        return Err(CoreError::invalid(format!(
+            "invalid date (expected YYYY-MM-DD): {}",
+            date
+        )));
+    }
+    let dir = gallery_dir.join(date);
+    if !dir.is_dir() {
+        return Err(CoreError::not_found(format!("date folder {}", date)));
+    }
+
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    files.sort();
+
+    let mut zip = zip::ZipWriter::new_stream(writer);
+    let options = ArchiveCompression::Stored.file_options();
+    for file_path in files {
+        let file_name = file_path.file_name().unwrap().to_string_lossy();
+        zip.start_file(format!("{}/{}", date, file_name), options)?;
+        copy_file(&file_path, &mut zip, buffer_size)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
 /// 归档管理器
 pub struct ArchiveManager<'a> {
     gallery_dir: &'a Path,
     storage: &'a CoreStorage,
+    timezone: GalleryTimezone,
+    options: ArchiveOptions,
+    cancel: Option<Arc<AtomicBool>>,
 }
 
 impl<'a> ArchiveManager<'a> {
@@ -44,9 +196,32 @@ impl<'a> ArchiveManager<'a> {
         Self {
             gallery_dir,
             storage,
+            timezone: GalleryTimezone::default(),
+            options: ArchiveOptions::default(),
+            cancel: None,
         }
     }
 
+    /// 指定取消标志；每个日期开始前检查，置位后停止处理剩余日期
+    ///
+    /// 正在处理的日期会完整归档，已创建的归档及其文件夹、记录的删除不受影响。
+    pub fn with_cancel_flag(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// 指定压缩方式与读取缓冲区大小
+    pub fn with_options(mut self, options: ArchiveOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// 指定判断"今天"及记录日期所用的时区
+    pub fn with_timezone(mut self, timezone: GalleryTimezone) -> Self {
+        self.timezone = timezone;
+        self
+    }
+
     /// 列出所有归档文件
     pub async fn list_archives(&self) -> CoreResult<Vec<ArchiveInfo>> {
         let gallery_dir = self.gallery_dir.to_path_buf();
@@ -59,24 +234,22 @@ impl<'a> ArchiveManager<'a> {
             for entry in fs::read_dir(&gallery_dir)? {
                 let entry = entry?;
                 let path = entry.path();
-                if path.is_file() {
-                    if let Some(ext) = path.extension() {
-                        if ext == "zip" {
-                            if let Some(name) = path.file_name() {
-                                let metadata = fs::metadata(&path)?;
-                                let created = metadata
-                                    .created()
-                                    .or_else(|_| metadata.modified())
-                                    .unwrap_or(std::time::SystemTime::UNIX_EPOCH);
-                                let created_dt: chrono::DateTime<chrono::Local> = created.into();
-                                archives.push(ArchiveInfo {
-                                    name: name.to_string_lossy().to_string(),
-                                    size: metadata.len(),
-                                    created_at: created_dt.to_rfc3339(),
-                                });
-                            }
-                        }
-                    }
+                if path.is_file()
+                    && let Some(ext) = path.extension()
+                    && ext == "zip"
+                    && let Some(name) = path.file_name()
+                {
+                    let metadata = fs::metadata(&path)?;
+                    let created = metadata
+                        .created()
+                        .or_else(|_| metadata.modified())
+                        .unwrap_or(std::time::SystemTime::UNIX_EPOCH);
+                    let created_dt: chrono::DateTime<chrono::Local> = created.into();
+                    archives.push(ArchiveInfo {
+                        name: name.to_string_lossy().to_string(),
+                        size: metadata.len(),
+                        created_at: created_dt.to_rfc3339(),
+                    });
                 }
             }
 
@@ -84,15 +257,15 @@ impl<'a> ArchiveManager<'a> {
             archives.sort_by(|a, b| b.created_at.cmp(&a.created_at));
             Ok(archives)
         })
-        .await
-        .map_err(|e| anyhow!("join error: {e}"))?
+        .await?
     }
 
-    /// 列出所有可归档的日期（今天之前的日期文件夹）
+    /// 列出所有可归档的日期（今天之前的日期文件夹，不含 `keep_recent_days` 保留的最近几个）
     pub async fn list_archivable_dates(&self) -> CoreResult<Vec<ArchivableDate>> {
         let gallery_dir = self.gallery_dir.to_path_buf();
+        let today = self.timezone.today();
+        let keep_recent_days = self.options.keep_recent_days;
         tokio::task::spawn_blocking(move || {
-            let today = Local::now().format("%Y-%m-%d").to_string();
             let mut dates = Vec::new();
 
             if !gallery_dir.exists() {
@@ -102,52 +275,52 @@ impl<'a> ArchiveManager<'a> {
             for entry in fs::read_dir(&gallery_dir)? {
                 let entry = entry?;
                 let path = entry.path();
-                if path.is_dir() {
-                    if let Some(name) = path.file_name() {
-                        let name_str = name.to_string_lossy().to_string();
-                        // 检查是否是日期格式的文件夹（YYYY-MM-DD）
-                        if name_str.len() == 10 && name_str.chars().nth(4) == Some('-') {
-                            // 只包含今天之前的文件夹
-                            if name_str.as_str() < today.as_str() {
-                                // 统计文件数量和总大小
-                                let mut image_count = 0;
-                                let mut total_size = 0u64;
-                                if let Ok(dir_entries) = fs::read_dir(&path) {
-                                    for file_entry in dir_entries.flatten() {
-                                        if file_entry.path().is_file() {
-                                            image_count += 1;
-                                            if let Ok(meta) = file_entry.metadata() {
-                                                total_size += meta.len();
-                                            }
+                if path.is_dir()
+                    && let Some(name) = path.file_name()
+                {
+                    let name_str = name.to_string_lossy().to_string();
+                    // 检查是否是日期格式的文件夹（YYYY-MM-DD）
+                    if is_valid_date(&name_str) {
+                        // 只包含今天之前的文件夹
+                        if name_str.as_str() < today.as_str() {
+                            // 统计文件数量和总大小
+                            let mut image_count = 0;
+                            let mut total_size = 0u64;
+                            if let Ok(dir_entries) = fs::read_dir(&path) {
+                                for file_entry in dir_entries.flatten() {
+                                    if file_entry.path().is_file() {
+                                        image_count += 1;
+                                        if let Ok(meta) = file_entry.metadata() {
+                                            total_size += meta.len();
                                         }
                                     }
                                 }
-                                dates.push(ArchivableDate {
-                                    date: name_str,
-                                    image_count,
-                                    total_size,
-                                });
                             }
+                            dates.push(ArchivableDate {
+                                date: name_str,
+                                image_count,
+                                total_size,
+                            });
                         }
                     }
                 }
             }
 
-            // 按日期降序排列（最新的在前）
+            // 按日期降序排列（最新的在前），跳过需要保留的最近几天
             dates.sort_by(|a, b| b.date.cmp(&a.date));
+            dates.drain(..dates.len().min(keep_recent_days as usize));
             Ok(dates)
         })
-        .await
-        .map_err(|e| anyhow!("join error: {e}"))?
+        .await?
     }
 
-    /// 创建归档：归档所有今天之前的日期
+    /// 创建归档：归档所有可归档的日期（见 [`Self::list_archivable_dates`]）
     pub async fn create_archives(&self) -> CoreResult<ArchiveResult> {
         let archivable = self.list_archivable_dates().await?;
         let dates: Vec<String> = archivable.into_iter().map(|d| d.date).collect();
         if dates.is_empty() {
-            return Err(anyhow!(
-                "no directories to archive (only today's images exist)"
+            return Err(CoreError::invalid(
+                "no directories to archive (only today's images exist)",
             ));
         }
         self.create_archives_for_dates(&dates).await
@@ -155,32 +328,32 @@ impl<'a> ArchiveManager<'a> {
 
     /// 创建归档：仅归档指定的日期
     pub async fn create_archives_for_dates(&self, dates: &[String]) -> CoreResult<ArchiveResult> {
-        use zip::write::SimpleFileOptions;
-
         if dates.is_empty() {
-            return Err(anyhow!("no dates specified for archiving"));
+            return Err(CoreError::invalid("no dates specified for archiving"));
         }
 
-        let today = Local::now().format("%Y-%m-%d").to_string();
+        let today = self.timezone.today();
         let gallery_dir = self.gallery_dir.to_path_buf();
         let dates = dates.to_vec();
+        let archive_options = self.options;
+        let cancel = self.cancel.clone();
 
         // 在阻塞线程中执行压缩操作
-        let (created_archives, archived_dates, skipped_existing) = tokio::task::spawn_blocking(move || {
+        let (created_archives, archived_dates, skipped_existing, remaining_dates) = tokio::task::spawn_blocking(move || {
             // 验证并收集需要归档的日期文件夹
             let mut dirs_to_archive: Vec<PathBuf> = Vec::new();
             if !gallery_dir.exists() {
-                return Err(anyhow!("gallery directory does not exist"));
+                return Err(CoreError::not_found("gallery directory"));
             }
 
             for date in &dates {
                 // 验证日期格式
-                if date.len() != 10 || date.chars().nth(4) != Some('-') {
-                    return Err(anyhow!("invalid date format: {}", date));
+                if !is_valid_date(date) {
+                    return Err(CoreError::invalid(format!("invalid date (expected YYYY-MM-DD): {}", date)));
                 }
                 // 不能归档今天的
                 if date.as_str() >= today.as_str() {
-                    return Err(anyhow!("cannot archive today's or future dates: {}", date));
+                    return Err(CoreError::invalid(format!("cannot archive today's or future dates: {}", date)));
                 }
                 let dir_path = gallery_dir.join(date);
                 if dir_path.exists() && dir_path.is_dir() {
@@ -189,8 +362,8 @@ impl<'a> ArchiveManager<'a> {
             }
 
             if dirs_to_archive.is_empty() {
-                return Err(anyhow!(
-                    "no valid directories found for the specified dates"
+                return Err(CoreError::not_found(
+                    "directories for the specified dates",
                 ));
             }
 
@@ -201,9 +374,19 @@ impl<'a> ArchiveManager<'a> {
             let mut created_archives = Vec::new();
             let mut archived_dates = Vec::new();
             let mut skipped_existing = Vec::new();
+            let mut skipped_nested = Vec::new();
+            let mut remaining_dates = Vec::new();
 
             // 为每个日期创建单独的压缩包
-            for dir in &dirs_to_archive {
+            for (i, dir) in dirs_to_archive.iter().enumerate() {
+                if cancel.as_ref().is_some_and(|c| c.load(Ordering::SeqCst)) {
+                    remaining_dates = dirs_to_archive[i..]
+                        .iter()
+                        .map(|d| d.file_name().unwrap().to_string_lossy().to_string())
+                        .collect();
+                    info!(remaining=?remaining_dates, "archive cancelled");
+                    break;
+                }
                 let date_str = dir.file_name().unwrap().to_string_lossy().to_string();
                 let archive_name = format!("archive_{}.zip", date_str);
                 let archive_path = gallery_dir.join(&archive_name);
@@ -215,13 +398,19 @@ impl<'a> ArchiveManager<'a> {
                     continue;
                 }
 
+                // 日期文件夹里只应有图片；含子目录时（如旧版本与日期同名的标签目录）
+                // 打包后删除会连带删掉子目录，因此跳过
+                if has_subdirectories(dir)? {
+                    warn!(date=%date_str, "date folder contains subdirectories, skipping");
+                    skipped_nested.push(date_str);
+                    continue;
+                }
+
                 // 创建 zip 文件
                 let file = fs::File::create(&archive_path)?;
                 let mut zip = zip::ZipWriter::new(file);
 
-                let options = SimpleFileOptions::default()
-                    .compression_method(zip::CompressionMethod::Zstd)
-                    .compression_level(Some(19));
+                let options = archive_options.compression.file_options();
 
                 // 添加该日期文件夹中的所有文件
                 for entry in fs::read_dir(dir)? {
@@ -232,16 +421,15 @@ impl<'a> ArchiveManager<'a> {
                         let zip_path = format!("{}/{}", date_str, file_name);
 
                         zip.start_file(&zip_path, options)?;
-                        let f = fs::File::open(&file_path)?;
-                        let mut reader = std::io::BufReader::with_capacity(128 * 1024, f);
-                        std::io::copy(&mut reader, &mut zip)?;
+                        copy_file(&file_path, &mut zip, archive_options.buffer_size)?;
                     }
                 }
 
                 zip.finish()?;
 
-                // 删除已归档的文件夹
+                // 删除已归档的文件夹及其缩略图缓存
                 fs::remove_dir_all(dir)?;
+                crate::imaging::remove_cached_thumbnails(&gallery_dir, Path::new(&date_str));
 
                 // 记录归档信息
                 let metadata = fs::metadata(&archive_path)?;
@@ -257,10 +445,17 @@ impl<'a> ArchiveManager<'a> {
                 info!(date=%date_str, "archived date folder");
             }
 
-            Ok::<_, anyhow::Error>((created_archives, archived_dates, skipped_existing))
+            if !skipped_nested.is_empty() {
+                warn!(
+                    skipped=?skipped_nested,
+                    "archives skipped because the date folders contain subdirectories"
+                );
+            }
+
+            Ok::<_, CoreError>((created_archives, archived_dates, skipped_existing, remaining_dates))
         })
         .await
-        .map_err(|e| anyhow!("join error: {e}"))??;
+        ??;
 
         if !skipped_existing.is_empty() {
             info!(
@@ -276,6 +471,8 @@ impl<'a> ArchiveManager<'a> {
         Ok(ArchiveResult {
             archives: created_archives,
             deleted_records,
+            cancelled: !remaining_dates.is_empty(),
+            remaining_dates,
         })
     }
 
@@ -283,15 +480,17 @@ impl<'a> ArchiveManager<'a> {
     pub async fn delete_archive(&self, name: &str) -> CoreResult<bool> {
         // 安全检查：防止路径遍历攻击
         if name.contains("..") || name.contains('/') || name.contains('\\') {
-            return Err(anyhow!("invalid archive name"));
+            return Err(CoreError::invalid("invalid archive name"));
         }
 
         // 确保是 .zip 文件
         if !name.ends_with(".zip") {
-            return Err(anyhow!("invalid archive name"));
+            return Err(CoreError::invalid("invalid archive name"));
         }
 
         let archive_path = self.gallery_dir.join(name);
+        let hash_key = crate::gallery_hash_key(self.gallery_dir, Path::new(name));
+        let storage = self.storage.clone();
         let name = name.to_string();
         tokio::task::spawn_blocking(move || {
             if !archive_path.exists() {
@@ -299,45 +498,98 @@ impl<'a> ArchiveManager<'a> {
             }
 
             fs::remove_file(&archive_path)?;
+            storage.remove_content_hashes(&Vec::from_iter(hash_key))?;
             info!(name=%name, "archive deleted");
             Ok(true)
         })
-        .await
-        .map_err(|e| anyhow!("join error: {e}"))?
+        .await?
     }
 
     /// 获取归档文件路径
     pub fn get_archive_path(&self, name: &str) -> CoreResult<PathBuf> {
         // 安全检查：防止路径遍历攻击
         if name.contains("..") || name.contains('/') || name.contains('\\') {
-            return Err(anyhow!("invalid archive name"));
+            return Err(CoreError::invalid("invalid archive name"));
         }
 
         // 确保是 .zip 文件
         if !name.ends_with(".zip") {
-            return Err(anyhow!("invalid archive name"));
+            return Err(CoreError::invalid("invalid archive name"));
         }
 
         let archive_path = self.gallery_dir.join(name);
         if !archive_path.exists() {
-            return Err(anyhow!("archive not found"));
+            return Err(CoreError::not_found("archive"));
         }
 
         Ok(archive_path)
     }
 
+    /// 列出归档内的文件（只读取 zip 中央目录，不解压），目录条目不计入
+    pub async fn list_archive_contents(&self, name: &str) -> CoreResult<Vec<ArchiveEntry>> {
+        let archive_path = self.get_archive_path(name)?;
+        tokio::task::spawn_blocking(move || {
+            let mut zip = zip::ZipArchive::new(fs::File::open(&archive_path)?)?;
+            let mut entries = Vec::with_capacity(zip.len());
+            for i in 0..zip.len() {
+                let file = zip.by_index_raw(i)?;
+                if file.is_dir() {
+                    continue;
+                }
+                entries.push(ArchiveEntry {
+                    name: file.name().to_string(),
+                    size: file.size(),
+                    compressed_size: file.compressed_size(),
+                });
+            }
+            Ok(entries)
+        })
+        .await?
+    }
+
+    /// 读取归档内单个文件的内容，`entry_name` 为 zip 内路径，拒绝路径遍历
+    pub async fn extract_file(&self, archive: &str, entry_name: &str) -> CoreResult<Vec<u8>> {
+        if !imaging::is_safe_relative(entry_name) {
+            return Err(CoreError::invalid("invalid archive entry name"));
+        }
+        let archive_path = self.get_archive_path(archive)?;
+        let entry_name = entry_name.to_string();
+        tokio::task::spawn_blocking(move || {
+            let mut zip = zip::ZipArchive::new(fs::File::open(&archive_path)?)?;
+            let mut file = match zip.by_name(&entry_name) {
+                Ok(file) if file.is_file() => file,
+                Ok(_) | Err(zip::result::ZipError::FileNotFound) => {
+                    return Err(CoreError::not_found(format!("archive entry {entry_name}")));
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let mut bytes = Vec::with_capacity(file.size() as usize);
+            io::Read::read_to_end(&mut file, &mut bytes)?;
+            Ok(bytes)
+        })
+        .await?
+    }
+
     /// 删除指定日期范围内的所有记录（仅删除数据库记录）
     async fn delete_records_by_dates(&self, dates: &[String]) -> CoreResult<usize> {
         if dates.is_empty() {
             return Ok(0);
         }
+        if let Some(date) = dates.iter().find(|d| !is_valid_date(d)) {
+            return Err(CoreError::invalid(format!(
+                "invalid date (expected YYYY-MM-DD): {}",
+                date
+            )));
+        }
 
         let storage = self.storage.clone();
+        let gallery_dir = self.gallery_dir.to_path_buf();
+        let timezone = self.timezone;
         let dates_set: HashSet<String> = dates.iter().cloned().collect();
 
         tokio::task::spawn_blocking(move || {
             // 获取所有记录
-            let ids_to_delete = storage.list_record_ids_by_dates(&dates_set)?;
+            let ids_to_delete = storage.list_record_ids_by_dates(&dates_set, timezone)?;
 
             // 找出需要删除的记录 ID
 
@@ -348,10 +600,297 @@ impl<'a> ArchiveManager<'a> {
                     deleted += 1;
                 }
             }
+            // 日期文件夹已删除，其中图片的内容哈希缓存随之失效
+            let hash_keys: Vec<String> = dates_set
+                .iter()
+                .filter_map(|date| crate::gallery_hash_key(&gallery_dir, Path::new(date)))
+                .collect();
+            storage.remove_content_hashes(&hash_keys)?;
 
             Ok(deleted)
         })
-        .await
-        .map_err(|e| anyhow!("join error: {e}"))?
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{TestDir, TestStorage};
+
+    #[test]
+    fn test_is_valid_date() {
+        assert!(is_valid_date("2024-02-29"));
+        assert!(!is_valid_date("2023-02-29"));
+        assert!(!is_valid_date("2024-99-99"));
+        assert!(!is_valid_date("abcd-ef-gh"));
+        assert!(!is_valid_date("2024-1-011"));
+        assert!(!is_valid_date("2024-01-1"));
+    }
+
+    #[test]
+    fn test_write_date_zip_leaves_folder_intact() {
+        let dir = TestDir::new();
+        fs::create_dir_all(dir.join("2024-03-01")).unwrap();
+        fs::write(dir.join("2024-03-01/a.png"), b"aaa").unwrap();
+        fs::write(dir.join("2024-03-01/b.png"), b"bbbb").unwrap();
+
+        let mut buf = Vec::new();
+        write_date_zip(&dir, "2024-03-01", &mut buf, DEFAULT_ARCHIVE_BUFFER_SIZE).unwrap();
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(buf)).unwrap();
+        assert_eq!(zip.len(), 2);
+        let mut content = String::new();
+        std::io::Read::read_to_string(&mut zip.by_name("2024-03-01/b.png").unwrap(), &mut content)
+            .unwrap();
+        assert_eq!(content, "bbbb");
+        assert!(dir.join("2024-03-01/a.png").exists());
+
+        assert!(write_date_zip(&dir, "2024-03-02", Vec::new(), 1024).is_err());
+        assert!(write_date_zip(&dir, "../2024-03-01", Vec::new(), 1024).is_err());
+    }
+
+    #[test]
+    fn test_archive_compression_parse() {
+        assert_eq!(
+            ArchiveCompression::parse("Stored").unwrap(),
+            ArchiveCompression::Stored
+        );
+        assert_eq!(
+            ArchiveCompression::parse("zstd").unwrap(),
+            ArchiveCompression::default()
+        );
+        assert_eq!(
+            ArchiveCompression::parse("zstd:3").unwrap(),
+            ArchiveCompression::Zstd { level: 3 }
+        );
+        assert!(ArchiveCompression::parse("zstd:99").is_err());
+        assert!(ArchiveCompression::parse("deflate").is_err());
+    }
+
+    /// 记录单次写入的最大长度
+    struct MaxChunk {
+        max: usize,
+        total: usize,
+    }
+
+    impl Write for MaxChunk {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.max = self.max.max(buf.len());
+            self.total += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_archive_large_file_streams_in_chunks() {
+        let TestStorage { dir, storage } = TestStorage::new();
+        let gallery = dir.join("gallery");
+        fs::create_dir_all(gallery.join("2024-03-01")).unwrap();
+        let big = gallery.join("2024-03-01/big.png");
+        let data: Vec<u8> = (0..8 * 1024 * 1024u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&big, &data).unwrap();
+
+        let mut sink = MaxChunk { max: 0, total: 0 };
+        copy_file(&big, &mut sink, 16 * 1024).unwrap();
+        assert_eq!(sink.total, data.len());
+        assert!(sink.max <= 16 * 1024, "chunk of {} bytes", sink.max);
+
+        let manager = ArchiveManager::new(&gallery, &storage).with_options(ArchiveOptions {
+            compression: ArchiveCompression::Stored,
+            buffer_size: 16 * 1024,
+            ..Default::default()
+        });
+        let result = manager
+            .create_archives_for_dates(&["2024-03-01".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(result.archives.len(), 1);
+        let file = fs::File::open(gallery.join("archive_2024-03-01.zip")).unwrap();
+        let mut zip = zip::ZipArchive::new(file).unwrap();
+        let mut entry = zip.by_name("2024-03-01/big.png").unwrap();
+        assert_eq!(entry.compression(), zip::CompressionMethod::Stored);
+        let mut content = Vec::new();
+        std::io::Read::read_to_end(&mut entry, &mut content).unwrap();
+        assert!(content == data);
+    }
+
+    #[tokio::test]
+    async fn test_list_and_extract_archive_contents() {
+        let TestStorage { dir, storage } = TestStorage::new();
+        let gallery = dir.join("gallery");
+        fs::create_dir_all(gallery.join("2024-03-01")).unwrap();
+        fs::write(gallery.join("2024-03-01/a.png"), b"aaa").unwrap();
+        fs::write(gallery.join("2024-03-01/b.png"), b"bbbb").unwrap();
+        let file = fs::File::create(gallery.join("archive_2024-03-01.zip")).unwrap();
+        write_date_zip(&gallery, "2024-03-01", file, DEFAULT_ARCHIVE_BUFFER_SIZE).unwrap();
+
+        let manager = ArchiveManager::new(&gallery, &storage);
+        let mut entries = manager
+            .list_archive_contents("archive_2024-03-01.zip")
+            .await
+            .unwrap();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        let listed: Vec<_> = entries.iter().map(|e| (e.name.as_str(), e.size)).collect();
+        assert_eq!(
+            listed,
+            vec![("2024-03-01/a.png", 3), ("2024-03-01/b.png", 4)]
+        );
+
+        assert!(
+            manager
+                .list_archive_contents("../archive_2024-03-01.zip")
+                .await
+                .is_err()
+        );
+        assert!(matches!(
+            manager.list_archive_contents("missing.zip").await,
+            Err(CoreError::NotFound { .. })
+        ));
+
+        let bytes = manager
+            .extract_file("archive_2024-03-01.zip", "2024-03-01/b.png")
+            .await
+            .unwrap();
+        assert_eq!(bytes, b"bbbb");
+        assert!(matches!(
+            manager
+                .extract_file("archive_2024-03-01.zip", "2024-03-01/c.png")
+                .await,
+            Err(CoreError::NotFound { .. })
+        ));
+        for entry in [
+            "../2024-03-01/a.png",
+            "/2024-03-01/a.png",
+            "2024-03-01\\a.png",
+        ] {
+            assert!(matches!(
+                manager.extract_file("archive_2024-03-01.zip", entry).await,
+                Err(CoreError::Validation(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keep_recent_days() {
+        let TestStorage { dir, storage } = TestStorage::new();
+        let gallery = dir.join("gallery");
+        let today = GalleryTimezone::default().today();
+        for date in [
+            "2024-03-01",
+            "2024-03-02",
+            "2024-03-05",
+            "2024-03-09",
+            &today,
+        ] {
+            fs::create_dir_all(gallery.join(date)).unwrap();
+            fs::write(gallery.join(date).join("a.png"), b"a").unwrap();
+        }
+        let archivable = |keep_recent_days| {
+            let manager = ArchiveManager::new(&gallery, &storage).with_options(ArchiveOptions {
+                keep_recent_days,
+                ..Default::default()
+            });
+            async move {
+                manager
+                    .list_archivable_dates()
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|d| d.date)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            archivable(0).await,
+            vec!["2024-03-09", "2024-03-05", "2024-03-02", "2024-03-01"]
+        );
+        // 按文件夹计数，与日期间隔无关；今天不计入
+        assert_eq!(archivable(2).await, vec!["2024-03-02", "2024-03-01"]);
+        assert!(archivable(10).await.is_empty());
+
+        let manager = ArchiveManager::new(&gallery, &storage).with_options(ArchiveOptions {
+            compression: ArchiveCompression::Stored,
+            keep_recent_days: 3,
+            ..Default::default()
+        });
+        let result = manager.create_archives().await.unwrap();
+        assert_eq!(result.archives.len(), 1);
+        assert!(gallery.join("archive_2024-03-01.zip").exists());
+        assert!(!gallery.join("2024-03-01").exists());
+        assert!(gallery.join("2024-03-02").exists());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_archive_leaves_remaining_dates() {
+        let TestStorage { dir, storage } = TestStorage::new();
+        let gallery = dir.join("gallery");
+        for date in ["2024-03-01", "2024-03-02"] {
+            fs::create_dir_all(gallery.join(date)).unwrap();
+            fs::write(gallery.join(date).join("a.png"), b"a").unwrap();
+        }
+        let cancel = Arc::new(AtomicBool::new(true));
+        let manager = ArchiveManager::new(&gallery, &storage)
+            .with_options(ArchiveOptions {
+                compression: ArchiveCompression::Stored,
+                ..Default::default()
+            })
+            .with_cancel_flag(Arc::clone(&cancel));
+
+        let dates = ["2024-03-01".to_string(), "2024-03-02".to_string()];
+        let result = manager.create_archives_for_dates(&dates).await.unwrap();
+        assert!(result.cancelled);
+        assert!(result.archives.is_empty());
+        assert_eq!(result.remaining_dates, dates);
+        assert!(gallery.join("2024-03-01/a.png").exists());
+        assert!(!gallery.join("archive_2024-03-01.zip").exists());
+
+        cancel.store(false, Ordering::SeqCst);
+        let result = manager.create_archives_for_dates(&dates).await.unwrap();
+        assert!(!result.cancelled);
+        assert_eq!(result.archives.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_create_archives_rejects_invalid_dates() {
+        let TestStorage { dir, storage } = TestStorage::new();
+        fs::create_dir_all(dir.join("gallery/2024-99-99")).unwrap();
+        let gallery = dir.join("gallery");
+        let manager = ArchiveManager::new(&gallery, &storage);
+
+        let err = manager
+            .create_archives_for_dates(&["2024-99-99".to_string()])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("invalid date"));
+        assert!(manager.list_archivable_dates().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_archive_skips_date_folder_with_subdirectories() {
+        let TestStorage { dir, storage } = TestStorage::new();
+        let gallery = dir.join("gallery");
+        // 旧版本中与日期同名的标签目录：`2024-03-01/2024-03-02/`
+        fs::create_dir_all(gallery.join("2024-03-01/2024-03-02")).unwrap();
+        fs::write(gallery.join("2024-03-01/a.png"), b"a").unwrap();
+        fs::write(gallery.join("2024-03-01/2024-03-02/b.png"), b"b").unwrap();
+        fs::create_dir_all(gallery.join("2024-03-03")).unwrap();
+        fs::write(gallery.join("2024-03-03/c.png"), b"c").unwrap();
+        let manager = ArchiveManager::new(&gallery, &storage);
+
+        let result = manager
+            .create_archives_for_dates(&["2024-03-01".to_string(), "2024-03-03".to_string()])
+            .await
+            .unwrap();
+        let names: Vec<_> = result.archives.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, vec!["archive_2024-03-03.zip"]);
+        assert!(!gallery.join("archive_2024-03-01.zip").exists());
+        assert!(gallery.join("2024-03-01/a.png").exists());
+        assert!(gallery.join("2024-03-01/2024-03-02/b.png").exists());
+        assert!(!gallery.join("2024-03-03").exists());
     }
 }
@@ -0,0 +1,187 @@
+//! 标签屏蔽列表
+
+use std::collections::HashSet;
+
+use chrono::Utc;
+use redb::{ReadableDatabase, ReadableTable, TableHandle};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::info;
+
+use crate::{CoreResult, CoreStorage, TABLE_BLOCKLIST, decode_row, lexicon};
+
+/// 全局屏蔽的标签
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockedTag {
+    pub tag: String,
+    pub created_at: chrono::DateTime<Utc>,
+}
+
+/// 屏蔽标签校验错误
+#[derive(Debug, Clone, PartialEq, Eq, Error, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum BlockedTagError {
+    #[error("屏蔽标签不能为空")]
+    Empty,
+    #[error("屏蔽标签只能是单个标签，不能包含逗号")]
+    Comma,
+}
+
+/// 校验屏蔽标签（调用方应先去除首尾空白）
+pub fn validate_blocked_tag(tag: &str) -> Result<(), BlockedTagError> {
+    if tag.is_empty() {
+        return Err(BlockedTagError::Empty);
+    }
+    if tag.contains(',') {
+        return Err(BlockedTagError::Comma);
+    }
+    Ok(())
+}
+
+impl CoreStorage {
+    /// 添加屏蔽标签（忽略大小写，下划线视同空格）；已存在时保持原样
+    pub fn add_blocked_tag(&self, tag: &str) -> CoreResult<BlockedTag> {
+        let tag = tag.trim();
+        validate_blocked_tag(tag)?;
+        let key = lexicon::normalize_tag(tag);
+        let write_txn = self.begin_write_with_retry()?;
+        let blocked = {
+            let mut table = write_txn.open_table(TABLE_BLOCKLIST)?;
+            let existing = table
+                .get(key.as_str())?
+                .map(|value| serde_json::from_str::<BlockedTag>(&value.value()))
+                .transpose()?;
+            match existing {
+                Some(blocked) => blocked,
+                None => {
+                    let blocked = BlockedTag {
+                        tag: tag.to_string(),
+                        created_at: Utc::now(),
+                    };
+                    table.insert(key.as_str(), serde_json::to_string(&blocked)?)?;
+                    blocked
+                }
+            }
+        };
+        write_txn.commit()?;
+        info!(tag=%blocked.tag, "tag blocked");
+        Ok(blocked)
+    }
+
+    /// 列出屏蔽标签，按标签排序
+    pub fn list_blocked_tags(&self) -> CoreResult<Vec<BlockedTag>> {
+        let read_txn = self.db()?.begin_read()?;
+        let table = read_txn.open_table(TABLE_BLOCKLIST)?;
+        let mut tags = Vec::new();
+        for entry in table.iter()? {
+            let (key, value) = entry?;
+            tags.extend(decode_row::<BlockedTag>(
+                TABLE_BLOCKLIST.name(),
+                key.value(),
+                &value.value(),
+            ));
+        }
+        Ok(tags)
+    }
+
+    /// 规范化后的屏蔽标签集合
+    pub fn blocked_tag_set(&self) -> CoreResult<HashSet<String>> {
+        let read_txn = self.db()?.begin_read()?;
+        let table = read_txn.open_table(TABLE_BLOCKLIST)?;
+        let mut tags = HashSet::new();
+        for entry in table.iter()? {
+            let (key, _) = entry?;
+            tags.insert(key.value().to_string());
+        }
+        Ok(tags)
+    }
+
+    /// 取消屏蔽标签
+    pub fn remove_blocked_tag(&self, tag: &str) -> CoreResult<bool> {
+        let key = lexicon::normalize_tag(tag);
+        let write_txn = self.begin_write_with_retry()?;
+        let removed = {
+            let mut table = write_txn.open_table(TABLE_BLOCKLIST)?;
+            table.remove(key.as_str())?.is_some()
+        };
+        write_txn.commit()?;
+        if removed {
+            info!(tag=%key, "tag unblocked");
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use codex_api::CharacterPrompt;
+
+    use super::*;
+    use crate::{
+        CharacterSlotSettings, CoreError, GenerateTaskRequest, MainPresetSettings, PromptProcessor,
+        Snippet, ValidationError, test_support::TestStorage,
+    };
+
+    #[test]
+    fn test_blocklist_applied_in_dry_run() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        storage.add_blocked_tag(" Blue_Hair ").unwrap();
+        let again = storage.add_blocked_tag("blue hair").unwrap();
+        assert_eq!(again.tag, "Blue_Hair");
+        assert_eq!(storage.list_blocked_tags().unwrap().len(), 1);
+        let err = storage.add_blocked_tag("a, b").unwrap_err();
+        assert!(matches!(
+            err,
+            CoreError::Validation(ValidationError::BlockedTag(BlockedTagError::Comma))
+        ));
+
+        let snippet = Snippet::new("hair".into(), "char".into(), "blue hair".into()).unwrap();
+        storage.upsert_snippet(snippet, None).unwrap();
+        let result = PromptProcessor::new(Arc::clone(&storage))
+            .dry_run(
+                "1girl, <snippet:hair>, smile",
+                "",
+                &MainPresetSettings::default(),
+                &[],
+            )
+            .unwrap();
+        assert_eq!(result.final_positive, "1girl, smile");
+        assert_eq!(result.blocked_tags, vec!["blue hair"]);
+
+        // 角色提示词同样删除屏蔽标签，负面提示词保留
+        storage.add_blocked_tag("red eyes").unwrap();
+        let slot = CharacterSlotSettings {
+            prompt: "1boy, red eyes, <snippet:hair>".into(),
+            uc: "blue hair".into(),
+            enabled: true,
+            preset_id: None,
+        };
+        let processor = PromptProcessor::new(Arc::clone(&storage));
+        let result = processor
+            .dry_run("1girl", "", &MainPresetSettings::default(), &[slot])
+            .unwrap();
+        assert_eq!(result.character_prompts[0].final_prompt, "1boy");
+        assert_eq!(result.character_prompts[0].final_uc, "blue hair");
+        assert_eq!(result.blocked_tags, vec!["red eyes", "blue hair"]);
+
+        let mut task = GenerateTaskRequest::new("1girl".into(), String::new());
+        task.params.character_prompts = Some(vec![CharacterPrompt {
+            prompt: "1boy, red eyes, <snippet:hair>".into(),
+            uc: "blue hair".into(),
+            center: Default::default(),
+            enabled: true,
+            add_quality_tags: false,
+            inherit_uc: false,
+        }]);
+        processor.process_task(&mut task).unwrap();
+        let chars = task.params.character_prompts.unwrap();
+        assert_eq!(chars[0].prompt, "1boy");
+        assert_eq!(chars[0].uc, "blue hair");
+        assert!(storage.remove_blocked_tag("red eyes").unwrap());
+
+        assert!(storage.remove_blocked_tag("BLUE HAIR").unwrap());
+        assert!(storage.blocked_tag_set().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,207 @@
+//! 文件内容哈希缓存 - 文件修改时间或大小变化时重新计算
+
+use std::{fs, path::Path};
+
+use redb::{ReadableDatabase, ReadableTable, WriteTransaction};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{CoreResult, CoreStorage, TABLE_CONTENT_HASHES, imaging};
+
+/// 内容哈希缓存键的命名空间：gallery 图片
+pub const CONTENT_HASH_GALLERY: &str = "gallery";
+
+/// 内容哈希缓存键的命名空间：snippet / preset 预览图
+pub const CONTENT_HASH_PREVIEWS: &str = "previews";
+
+/// gallery 内文件或目录的内容哈希缓存键 `gallery/{相对路径}`
+///
+/// `path` 可以是相对路径，也可以是位于 `gallery_root` 下的绝对路径；不在 gallery 内时返回 None
+pub fn gallery_hash_key(gallery_root: &Path, path: &Path) -> Option<String> {
+    let rel_path = path.strip_prefix(gallery_root).unwrap_or(path).to_str()?;
+    imaging::is_safe_relative(rel_path).then(|| format!("{CONTENT_HASH_GALLERY}/{rel_path}"))
+}
+
+/// 删除键为 `key`，或位于 `key/` 之下的内容哈希缓存，返回删除条数
+pub(crate) fn remove_content_hashes_in(txn: &WriteTransaction, key: &str) -> CoreResult<usize> {
+    let mut table = txn.open_table(TABLE_CONTENT_HASHES)?;
+    let dir_prefix = format!("{key}/");
+    let mut stale = Vec::new();
+    for entry in table.range(key..)? {
+        let (k, _) = entry?;
+        let k = k.value();
+        if !k.starts_with(key) {
+            break;
+        }
+        if k == key || k.starts_with(&dir_prefix) {
+            stale.push(k.to_string());
+        }
+    }
+    for k in &stale {
+        table.remove(k.as_str())?;
+    }
+    Ok(stale.len())
+}
+
+/// 缓存的文件内容哈希，以修改时间和大小判断是否失效
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ContentHash {
+    mtime_ms: u64,
+    len: u64,
+    hash: String,
+}
+
+impl CoreStorage {
+    /// 获取文件内容哈希（sha256 十六进制），按 `key` 缓存；文件修改时间或大小变化时重新计算
+    ///
+    /// 缓存未命中时（如图片第一次被访问）需要一次写事务保存结果。
+    /// 文件被删除时由删除方调用 [`Self::remove_content_hashes`] 清理缓存。
+    /// 文件不存在时返回 `None`
+    pub fn file_content_hash(&self, key: &str, path: &Path) -> CoreResult<Option<String>> {
+        let metadata = match fs::metadata(path) {
+            Ok(m) if m.is_file() => m,
+            Ok(_) => return Ok(None),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mtime_ms = metadata
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let len = metadata.len();
+
+        {
+            let read_txn = self.db()?.begin_read()?;
+            let table = read_txn.open_table(TABLE_CONTENT_HASHES)?;
+            if let Some(value) = table.get(key)? {
+                let cached: ContentHash = serde_json::from_str(&value.value())?;
+                if cached.mtime_ms == mtime_ms && cached.len == len {
+                    return Ok(Some(cached.hash));
+                }
+            }
+        }
+
+        let mut hasher = Sha256::new();
+        let mut file = fs::File::open(path)?;
+        std::io::copy(&mut file, &mut hasher)?;
+        let hash: String = hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+
+        let entry = ContentHash {
+            mtime_ms,
+            len,
+            hash,
+        };
+        let serialized = serde_json::to_string(&entry)?;
+        let write_txn = self.begin_write_with_retry()?;
+        {
+            let mut table = write_txn.open_table(TABLE_CONTENT_HASHES)?;
+            table.insert(key, serialized)?;
+        }
+        write_txn.commit()?;
+        Ok(Some(entry.hash))
+    }
+
+    /// 删除 `keys` 对应的内容哈希缓存，键为目录时连同其下所有文件，返回删除条数
+    pub fn remove_content_hashes(&self, keys: &[String]) -> CoreResult<usize> {
+        if keys.is_empty() {
+            return Ok(0);
+        }
+        let write_txn = self.begin_write_with_retry()?;
+        let mut removed = 0;
+        for key in keys {
+            removed += remove_content_hashes_in(&write_txn, key)?;
+        }
+        write_txn.commit()?;
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestStorage;
+
+    #[test]
+    fn test_file_content_hash_cached_until_modified() {
+        let TestStorage { dir, storage } = TestStorage::new();
+        let path = dir.join("a.png");
+
+        assert_eq!(
+            storage.file_content_hash("gallery/a.png", &path).unwrap(),
+            None
+        );
+        std::fs::write(&path, b"abc").unwrap();
+        let first = storage
+            .file_content_hash("gallery/a.png", &path)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            first,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            storage.file_content_hash("gallery/a.png", &path).unwrap(),
+            Some(first.clone())
+        );
+
+        std::fs::write(&path, b"abcd").unwrap();
+        let second = storage
+            .file_content_hash("gallery/a.png", &path)
+            .unwrap()
+            .unwrap();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_remove_content_hashes_by_file_and_dir() {
+        let TestStorage { dir, storage } = TestStorage::new();
+        let root = dir.join("gallery");
+        let files = [
+            "2024-03-01/a.png",
+            "2024-03-01/b.png",
+            "2024-03-01x/c.png",
+            "2024-03-02/d.png",
+        ];
+        for rel in files {
+            let path = root.join(rel);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, rel).unwrap();
+            let key = gallery_hash_key(&root, &path).unwrap();
+            assert_eq!(key, format!("gallery/{rel}"));
+            storage.file_content_hash(&key, &path).unwrap();
+        }
+        let cached_keys = || {
+            let read_txn = storage.db().unwrap().begin_read().unwrap();
+            let table = read_txn.open_table(TABLE_CONTENT_HASHES).unwrap();
+            table
+                .iter()
+                .unwrap()
+                .map(|entry| entry.unwrap().0.value().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // 目录键只匹配目录下的文件，不匹配同前缀的兄弟目录
+        let removed = storage
+            .remove_content_hashes(&[gallery_hash_key(&root, Path::new("2024-03-01")).unwrap()])
+            .unwrap();
+        assert_eq!(removed, 2);
+        assert_eq!(
+            cached_keys(),
+            vec!["gallery/2024-03-01x/c.png", "gallery/2024-03-02/d.png"]
+        );
+
+        let removed = storage
+            .remove_content_hashes(&[
+                gallery_hash_key(&root, &root.join("2024-03-02/d.png")).unwrap()
+            ])
+            .unwrap();
+        assert_eq!(removed, 1);
+        assert_eq!(cached_keys(), vec!["gallery/2024-03-01x/c.png"]);
+        assert_eq!(gallery_hash_key(&root, Path::new("../x.png")), None);
+    }
+}
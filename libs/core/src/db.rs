@@ -0,0 +1,380 @@
+//! 数据库句柄 - I/O 失败后重新打开数据库，写事务遇到暂时性错误时重试
+
+use std::{
+    cell::Cell,
+    fmt,
+    ops::Deref,
+    sync::{PoisonError, RwLock, RwLockReadGuard},
+    time::Duration,
+};
+
+use rand::{Rng, rng};
+use redb::{Database, DatabaseError, WriteTransaction};
+use tracing::warn;
+
+use crate::{CoreError, CoreResult};
+
+type Opener = Box<dyn Fn() -> Result<Database, DatabaseError> + Send + Sync>;
+
+/// 可重新打开的数据库
+///
+/// redb 在任何一次后端 I/O 失败后都会拒绝之后的所有操作（`PreviousIo`），
+/// 直到数据库被关闭并重新打开；这里在检测到这种状态时丢弃旧实例并重新打开。
+pub(crate) struct DbHandle {
+    /// 压缩与重新打开需要独占数据库，其余操作只在开启事务时短暂持有读锁
+    slot: RwLock<DbSlot>,
+    open: Opener,
+}
+
+struct DbSlot {
+    /// 重新打开失败时为空，下次使用时再尝试打开
+    db: Option<Database>,
+    /// 每次重新打开后递增，避免并发的多个失败方重复打开
+    generation: u64,
+}
+
+/// 持有读锁的数据库引用
+pub(crate) struct DbGuard<'a>(RwLockReadGuard<'a, DbSlot>);
+
+impl Deref for DbGuard<'_> {
+    type Target = Database;
+
+    fn deref(&self) -> &Database {
+        self.0
+            .db
+            .as_ref()
+            .expect("DbGuard always holds an open database")
+    }
+}
+
+impl fmt::Debug for DbHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let slot = self.slot.read().unwrap_or_else(PoisonError::into_inner);
+        f.debug_struct("DbHandle")
+            .field("open", &slot.db.is_some())
+            .field("generation", &slot.generation)
+            .finish()
+    }
+}
+
+impl DbHandle {
+    /// 用 `open` 打开数据库；之后每次重新打开都会再次调用它
+    pub(crate) fn new(
+        open: impl Fn() -> Result<Database, DatabaseError> + Send + Sync + 'static,
+    ) -> CoreResult<Self> {
+        let db = open()?;
+        Ok(Self {
+            slot: RwLock::new(DbSlot {
+                db: Some(db),
+                generation: 0,
+            }),
+            open: Box::new(open),
+        })
+    }
+
+    /// 获取数据库；上次重新打开失败时先尝试重新打开
+    pub(crate) fn get(&self) -> CoreResult<DbGuard<'_>> {
+        loop {
+            let slot = self.slot.read().unwrap_or_else(PoisonError::into_inner);
+            if slot.db.is_some() {
+                return Ok(DbGuard(slot));
+            }
+            let generation = slot.generation;
+            drop(slot);
+            self.reopen(generation)?;
+        }
+    }
+
+    /// 丢弃第 `generation` 代数据库实例并重新打开；其他调用方已经重新打开时直接返回
+    fn reopen(&self, generation: u64) -> CoreResult<()> {
+        let mut slot = self.slot.write().unwrap_or_else(PoisonError::into_inner);
+        if slot.generation != generation {
+            return Ok(());
+        }
+        // 先释放旧实例持有的文件锁，否则无法再次打开
+        slot.db = None;
+        slot.db = Some((self.open)()?);
+        slot.generation += 1;
+        warn!(generation = slot.generation, "database reopened");
+        Ok(())
+    }
+
+    /// 独占数据库并压缩
+    pub(crate) fn compact(&self) -> CoreResult<()> {
+        let mut slot = self.slot.write().unwrap_or_else(PoisonError::into_inner);
+        if slot.db.is_none() {
+            slot.db = Some((self.open)()?);
+            slot.generation += 1;
+        }
+        if let Some(db) = slot.db.as_mut() {
+            db.compact()?;
+        }
+        Ok(())
+    }
+
+    /// 开启写事务；遇到暂时性错误时重新打开数据库并带随机抖动重试，最多 `retries` 次
+    pub(crate) fn begin_write(&self, retries: u32) -> CoreResult<WriteTransaction> {
+        let seen = Cell::new(None);
+        retry_transient(
+            retries,
+            || {
+                let db = self.get()?;
+                seen.set(Some(db.0.generation));
+                Ok(db.begin_write()?)
+            },
+            || match seen.get() {
+                Some(generation) => self.reopen(generation),
+                // 连数据库都没拿到，`get` 下次会自己重新打开
+                None => Ok(()),
+            },
+        )
+    }
+}
+
+/// 执行 `op`，遇到暂时性错误时先调用 `recover`，再等待随尝试次数增长的随机延迟后重试，
+/// 最多重试 `retries` 次
+pub(crate) fn retry_transient<T>(
+    retries: u32,
+    mut op: impl FnMut() -> CoreResult<T>,
+    mut recover: impl FnMut() -> CoreResult<()>,
+) -> CoreResult<T> {
+    let mut attempt = 0;
+    loop {
+        let err = match op() {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt < retries && is_transient_error(&err) => err,
+            Err(err) => return Err(err),
+        };
+        attempt += 1;
+        match recover() {
+            Ok(()) => {}
+            Err(recover_err) if is_transient_error(&recover_err) => {
+                warn!(attempt, error = %recover_err, "database recovery failed");
+            }
+            Err(recover_err) => return Err(recover_err),
+        }
+        let delay = Duration::from_millis(rng().random_range(10..=50) * attempt as u64);
+        warn!(attempt, error = %err, "begin write failed, retrying in {:?}", delay);
+        std::thread::sleep(delay);
+    }
+}
+
+/// 是否为重新打开数据库或稍后重试即可恢复的错误
+///
+/// - `PreviousIo`：之前的 I/O 失败使数据库进入只能重新打开的状态
+/// - `DatabaseAlreadyOpen`：重新打开时旧实例的事务尚未结束，文件锁还未释放
+/// - 被中断或超时的 I/O
+pub(crate) fn is_transient_error(err: &CoreError) -> bool {
+    match err {
+        CoreError::Db(redb::Error::PreviousIo | redb::Error::DatabaseAlreadyOpen) => true,
+        CoreError::Db(redb::Error::Io(io)) => matches!(
+            io.kind(),
+            std::io::ErrorKind::Interrupted
+                | std::io::ErrorKind::WouldBlock
+                | std::io::ErrorKind::TimedOut
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::OpenOptions,
+        io,
+        sync::{
+            Arc,
+            atomic::{AtomicBool, Ordering},
+        },
+    };
+
+    use chrono::Utc;
+    use redb::{StorageBackend, backends::FileBackend};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{CoreStorage, GenerationRecord, test_support::TestDir};
+
+    /// 在 `fail` 置位时写入与同步都返回 I/O 错误的文件后端
+    #[derive(Debug)]
+    struct FlakyBackend {
+        inner: FileBackend,
+        fail: Arc<AtomicBool>,
+    }
+
+    impl FlakyBackend {
+        fn check(&self) -> io::Result<()> {
+            if self.fail.load(Ordering::SeqCst) {
+                Err(io::Error::other("injected failure"))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl StorageBackend for FlakyBackend {
+        fn len(&self) -> io::Result<u64> {
+            self.inner.len()
+        }
+
+        fn read(&self, offset: u64, out: &mut [u8]) -> io::Result<()> {
+            self.inner.read(offset, out)
+        }
+
+        fn set_len(&self, len: u64) -> io::Result<()> {
+            self.check()?;
+            self.inner.set_len(len)
+        }
+
+        fn sync_data(&self) -> io::Result<()> {
+            self.check()?;
+            self.inner.sync_data()
+        }
+
+        fn write(&self, offset: u64, data: &[u8]) -> io::Result<()> {
+            self.check()?;
+            self.inner.write(offset, data)
+        }
+
+        fn close(&self) -> io::Result<()> {
+            self.inner.close()
+        }
+    }
+
+    fn open_flaky(dir: &TestDir, fail: &Arc<AtomicBool>) -> CoreStorage {
+        let path = dir.join("codex.redb");
+        let fail = Arc::clone(fail);
+        CoreStorage::open_with(path.clone(), dir.join("previews"), move || {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)?;
+            redb::Builder::new().create_with_backend(FlakyBackend {
+                inner: FileBackend::new(file)?,
+                fail: Arc::clone(&fail),
+            })
+        })
+        .unwrap()
+    }
+
+    fn record() -> GenerationRecord {
+        GenerationRecord {
+            id: Uuid::new_v4(),
+            task_id: Uuid::new_v4(),
+            created_at: Utc::now(),
+            raw_prompt: "1girl".to_string(),
+            expanded_prompt: "1girl".to_string(),
+            negative_prompt: String::new(),
+            label: None,
+            raw_negative_prompt: None,
+            main_preset: None,
+            images: Vec::new(),
+            params: None,
+        }
+    }
+
+    #[test]
+    fn test_write_recovers_after_io_failure() {
+        let dir = TestDir::new();
+        let fail = Arc::new(AtomicBool::new(false));
+        let storage = open_flaky(&dir, &fail);
+        let first = record();
+        storage.append_record(&first).unwrap();
+
+        fail.store(true, Ordering::SeqCst);
+        assert!(storage.append_record(&record()).is_err());
+        fail.store(false, Ordering::SeqCst);
+
+        // 数据库已处于 PreviousIo 状态，写入前重新打开
+        let second = record();
+        storage.append_record(&second).unwrap();
+        let ids: Vec<_> = storage
+            .list_recent_records(10)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.id)
+            .collect();
+        assert_eq!(ids, vec![second.id, first.id]);
+    }
+
+    #[test]
+    fn test_write_without_retries_stays_failed() {
+        let dir = TestDir::new();
+        let fail = Arc::new(AtomicBool::new(false));
+        let storage = open_flaky(&dir, &fail).with_write_retries(0);
+
+        fail.store(true, Ordering::SeqCst);
+        assert!(storage.append_record(&record()).is_err());
+        fail.store(false, Ordering::SeqCst);
+
+        let err = storage.append_record(&record()).unwrap_err();
+        assert!(matches!(err, CoreError::Db(redb::Error::PreviousIo)));
+    }
+
+    #[test]
+    fn test_retry_transient_recovers_between_attempts() {
+        let previous_io = || CoreError::Db(redb::Error::PreviousIo);
+        assert!(is_transient_error(&previous_io()));
+        assert!(is_transient_error(&CoreError::Db(redb::Error::Io(
+            io::ErrorKind::Interrupted.into()
+        ))));
+        assert!(!is_transient_error(&CoreError::Db(redb::Error::Io(
+            io::ErrorKind::PermissionDenied.into()
+        ))));
+        assert!(!is_transient_error(&CoreError::invalid("bad")));
+
+        // 每次失败后都先恢复再重试
+        let (mut calls, mut recovered) = (0, 0);
+        let result = retry_transient(
+            2,
+            || {
+                calls += 1;
+                if calls <= 2 {
+                    Err(previous_io())
+                } else {
+                    Ok(calls)
+                }
+            },
+            || {
+                recovered += 1;
+                Ok(())
+            },
+        );
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(recovered, 2);
+
+        // 超过重试次数后返回最后的错误
+        let mut calls = 0;
+        let result: CoreResult<()> = retry_transient(
+            2,
+            || {
+                calls += 1;
+                Err(previous_io())
+            },
+            || Ok(()),
+        );
+        assert!(matches!(
+            result,
+            Err(CoreError::Db(redb::Error::PreviousIo))
+        ));
+        assert_eq!(calls, 3);
+
+        // 非暂时性错误不重试；恢复本身的非暂时性错误直接返回
+        let mut calls = 0;
+        let result: CoreResult<()> = retry_transient(
+            5,
+            || {
+                calls += 1;
+                Err(CoreError::invalid("bad"))
+            },
+            || Ok(()),
+        );
+        assert!(matches!(result, Err(CoreError::Validation(_))));
+        assert_eq!(calls, 1);
+        let result: CoreResult<()> =
+            retry_transient(5, || Err(previous_io()), || Err(CoreError::invalid("bad")));
+        assert!(matches!(result, Err(CoreError::Validation(_))));
+    }
+}
@@ -0,0 +1,109 @@
+//! core 错误类型
+//!
+//! 存储、snippet 展开与提示词处理返回 [`CoreError`]，调用方可按变体映射 HTTP 状态；
+//! 需要 `anyhow` 的调用方可直接用 `?` 转换。
+
+use codex_api::LimitExceeded;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    BlockedTagError, PresetMergeError, ProfileNameError, SeedLabelError, SnippetContentError,
+    SnippetExpandError, SnippetNameError,
+};
+
+pub type CoreResult<T> = Result<T, CoreError>;
+
+#[derive(Debug, Error)]
+pub enum CoreError {
+    /// 要操作的对象不存在，`what` 描述对象，如 `snippet`、`preset <id>`
+    #[error("{what} not found")]
+    NotFound { what: String },
+    #[error("snippet name already exists: {name}")]
+    NameTaken { name: String },
+    #[error(transparent)]
+    InvalidName(#[from] SnippetNameError),
+    /// 提示词引用了不存在的 snippet
+    #[error(transparent)]
+    SnippetNotFound(#[from] SnippetExpandError),
+    /// snippet 循环引用，`chain` 为从入口到重复出现的 snippet 的引用链
+    #[error("snippet reference cycle: {}", chain.join(" -> "))]
+    SnippetCycle { chain: Vec<String> },
+    /// 请求内容校验失败
+    #[error(transparent)]
+    Validation(#[from] ValidationError),
+    #[error("database error: {0}")]
+    Db(#[from] redb::Error),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("serialization error: {0}")]
+    Serde(#[from] serde_json::Error),
+    #[error("zip error: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("image error: {0}")]
+    Image(#[from] image::ImageError),
+    #[error(transparent)]
+    Nai(#[from] codex_api::NaiError),
+    /// 后台任务被取消或 panic 等内部错误
+    #[error("internal error: {0}")]
+    Internal(String),
+}
+
+impl CoreError {
+    pub fn not_found(what: impl Into<String>) -> Self {
+        Self::NotFound { what: what.into() }
+    }
+
+    pub fn invalid(message: impl Into<String>) -> Self {
+        Self::Validation(ValidationError::Message(message.into()))
+    }
+}
+
+/// 校验错误；结构化的原因序列化为对应类型本身，其余为字符串
+#[derive(Debug, Clone, PartialEq, Eq, Error, Serialize)]
+#[serde(untagged)]
+pub enum ValidationError {
+    #[error(transparent)]
+    SnippetContent(#[from] SnippetContentError),
+    #[error(transparent)]
+    BlockedTag(#[from] BlockedTagError),
+    #[error(transparent)]
+    SeedLabel(#[from] SeedLabelError),
+    #[error(transparent)]
+    ProfileName(#[from] ProfileNameError),
+    #[error(transparent)]
+    PresetMerge(#[from] PresetMergeError),
+    #[error(transparent)]
+    Limit(#[from] LimitExceeded),
+    #[error("{0}")]
+    Message(String),
+}
+
+macro_rules! via {
+    ($inner:ty => $mid:ty) => {
+        impl From<$inner> for CoreError {
+            fn from(err: $inner) -> Self {
+                <$mid>::from(err).into()
+            }
+        }
+    };
+}
+
+via!(SnippetContentError => ValidationError);
+via!(BlockedTagError => ValidationError);
+via!(SeedLabelError => ValidationError);
+via!(ProfileNameError => ValidationError);
+via!(PresetMergeError => ValidationError);
+via!(LimitExceeded => ValidationError);
+via!(redb::DatabaseError => redb::Error);
+via!(redb::TransactionError => redb::Error);
+via!(redb::TableError => redb::Error);
+via!(redb::StorageError => redb::Error);
+via!(redb::CommitError => redb::Error);
+via!(redb::CompactionError => redb::Error);
+
+impl From<tokio::task::JoinError> for CoreError {
+    fn from(err: tokio::task::JoinError) -> Self {
+        Self::Internal(format!("join error: {err}"))
+    }
+}
@@ -0,0 +1,866 @@
+//! 生成任务执行 - 请求 NovelAI、写入图片并保存生成记录
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::Utc;
+use codex_api::{
+    CharacterPrompt, GenerationLimits, LimitExceeded, NaiClient, NaiError, WeightRange,
+};
+use rand::{Rng, rng};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{
+    CharacterPreset, CoreError, CoreResult, CoreStorage, Diagnostic, GalleryImage, GalleryPaths,
+    GenerationParams, GenerationRecord, GlobalAffix, MainPresetSettings, PngCompression,
+    PromptProcessor, imaging, params::to_nai_request, sanitize_label,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenerateTaskRequest {
+    pub id: Uuid,
+    pub raw_prompt: String,
+    pub negative_prompt: String,
+    /// How many images to generate sequentially.
+    pub count: u32,
+    pub params: GenerationParams,
+    /// 角色预设（应用于角色槽）
+    pub preset: Option<CharacterPreset>,
+    /// 主提示词预设设置
+    #[serde(default)]
+    pub main_preset: MainPresetSettings,
+    /// 被内容过滤拒绝时换下一个种子重试（最多 `MAX_FILTER_RETRIES` 次）
+    #[serde(default)]
+    pub retry_on_filter: bool,
+    /// 输出标签：设置后图片保存到 `{label}/{date}/` 而非 `{date}/`，见 [`sanitize_label`]
+    #[serde(default)]
+    pub label: Option<String>,
+    /// 提示词已是展开后的最终结果：处理时跳过全局前缀 / 后缀、主预设与 snippet 展开
+    #[serde(default)]
+    pub pre_expanded: bool,
+}
+
+impl GenerateTaskRequest {
+    pub fn new(raw_prompt: String, negative_prompt: String) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            raw_prompt,
+            negative_prompt,
+            count: 1,
+            params: GenerationParams::default(),
+            preset: None,
+            main_preset: MainPresetSettings::default(),
+            retry_on_filter: false,
+            label: None,
+            pre_expanded: false,
+        }
+    }
+
+    /// 按记录保存的展开结果与参数重新生成；提示词不再叠加全局前缀 / 后缀与主预设
+    pub fn from_record(record: &GenerationRecord) -> Self {
+        let mut task = Self::new(
+            record.expanded_prompt.clone(),
+            record.negative_prompt.clone(),
+        );
+        task.params = record.params.clone().unwrap_or_default();
+        task.pre_expanded = true;
+        task
+    }
+
+    /// 检查生成参数的宽高与步数是否超出上限
+    pub fn check_limits(&self, limits: GenerationLimits) -> Result<(), LimitExceeded> {
+        limits.check(self.params.width, self.params.height, self.params.steps)
+    }
+}
+
+/// 任务预览结果
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskPreview {
+    /// 修正后实际发送的正面提示词
+    pub final_positive: String,
+    /// 修正后实际发送的负面提示词
+    pub final_negative: String,
+    /// 处理后的角色提示词
+    pub character_prompts: Option<Vec<CharacterPrompt>>,
+    /// 发送前对请求参数所做的修正
+    pub warnings: Vec<String>,
+    /// 原始提示词的检查结果
+    pub positive_diagnostics: Vec<Diagnostic>,
+    pub negative_diagnostics: Vec<Diagnostic>,
+    pub estimated_anlas: AnlasEstimate,
+}
+
+/// 预计 Anlas 消耗
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct AnlasEstimate {
+    /// 单张图片
+    pub per_image: u32,
+    /// 整个任务
+    pub total: u32,
+}
+
+/// 任务执行结果
+#[derive(Debug, Clone)]
+pub enum TaskOutcome {
+    /// 所有图片均生成成功
+    Completed(GenerationRecord),
+    /// 部分图片生成失败；成功的图片已保存在 `record` 中
+    PartiallyCompleted {
+        record: GenerationRecord,
+        /// 未生成的图片数量
+        failed: u32,
+        /// 导致中断的错误信息
+        error: String,
+    },
+}
+
+/// 记录保存后的回调，用于外部集成（如 webhook）
+///
+/// 回调在执行器所在的异步上下文中同步调用，耗时操作应自行 spawn
+#[derive(Clone)]
+pub struct RecordHook(Arc<dyn Fn(&GenerationRecord) + Send + Sync>);
+
+impl RecordHook {
+    pub fn new(f: impl Fn(&GenerationRecord) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(f))
+    }
+
+    pub fn call(&self, record: &GenerationRecord) {
+        (self.0)(record)
+    }
+}
+
+impl std::fmt::Debug for RecordHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RecordHook")
+    }
+}
+
+/// 单张图片因内容过滤换种子重试的最大次数
+pub const MAX_FILTER_RETRIES: u32 = 3;
+
+/// 任务执行器配置
+#[derive(Debug, Clone, Default)]
+pub struct ExecutorConfig {
+    /// 保存时的最长边上限；超出则缩小后再写入，原图不保留。
+    ///
+    /// 用于限制磁盘占用，代价是丢失原始分辨率和 PNG 内嵌的生成参数。
+    pub store_max_dimension: Option<u32>,
+    /// 因 `store_max_dimension` 缩小图片而重新编码时的 PNG 压缩级别；
+    /// 不需要缩小时原样写入，不受影响
+    pub png_compression: PngCompression,
+    /// 记录保存成功后调用
+    pub on_record_appended: Option<RecordHook>,
+    /// 单个任务中已生成但尚未写入磁盘的图片上限（0 视为 1）
+    pub max_pending_writes: usize,
+    /// 冒号权重允许范围，超出的在发送请求前被修正
+    pub weight_range: WeightRange,
+    /// 宽高与步数上限，超出的任务直接失败
+    pub limits: GenerationLimits,
+    /// 多图任务中图片之间额外等待的时间（叠加在内置的随机延迟之上）
+    pub inter_image_delay: Duration,
+    /// 在主预设之前加到每个正面提示词上的全局前缀 / 后缀
+    pub global_affix: GlobalAffix,
+    /// 写入后重新读取并解码文件头校验图片；失败时重写一次，仍失败则该图片记为失败
+    pub verify_writes: bool,
+    /// 任务未指定 UC 预设时使用的默认值（按模型范围截断）
+    pub default_uc_preset: Option<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TaskExecutor {
+    client: Arc<NaiClient>,
+    storage: Arc<CoreStorage>,
+    gallery: GalleryPaths,
+    config: ExecutorConfig,
+}
+
+impl TaskExecutor {
+    pub fn new(
+        client: Arc<NaiClient>,
+        storage: Arc<CoreStorage>,
+        gallery: GalleryPaths,
+        config: ExecutorConfig,
+    ) -> Self {
+        Self {
+            client,
+            storage,
+            gallery,
+            config,
+        }
+    }
+
+    pub async fn execute(&self, task: GenerateTaskRequest) -> CoreResult<TaskOutcome> {
+        if task.count == 0 {
+            return Err(CoreError::invalid("task count must be at least 1"));
+        }
+        info!(task_id=%task.id, count=task.count, "task started");
+        self.run(task, None).await
+    }
+
+    /// 重新生成部分完成任务中失败的图片，结果追加到原记录中
+    ///
+    /// 提示词与参数取自原记录，与已生成的图片保持一致
+    pub async fn retry_failed(
+        &self,
+        mut task: GenerateTaskRequest,
+        record: GenerationRecord,
+        failed: u32,
+    ) -> CoreResult<TaskOutcome> {
+        info!(task_id=%task.id, record_id=%record.id, failed, "retrying failed images");
+        task.count = failed;
+        self.run(task, Some(record)).await
+    }
+
+    /// 生成 `task.count` 张图片；`existing` 不为空时追加到已有记录
+    ///
+    /// 中途失败时，已成功的图片仍会写入记录并返回 `PartiallyCompleted`；
+    /// 只有在没有任何可保存的图片时才返回错误。
+    async fn run(
+        &self,
+        mut task: GenerateTaskRequest,
+        existing: Option<GenerationRecord>,
+    ) -> CoreResult<TaskOutcome> {
+        task.check_limits(self.config.limits)?;
+        task.params
+            .apply_default_uc_preset(self.config.default_uc_preset);
+
+        let (task, expanded_prompt, expanded_negative) = match existing
+            .as_ref()
+            .and_then(|record| Some((record, record.params.clone()?)))
+        {
+            // 重试沿用原记录的展开结果（含角色提示词），不按 snippet 的当前内容重新展开
+            Some((record, params)) => {
+                task.params = params;
+                (
+                    task,
+                    record.expanded_prompt.clone(),
+                    record.negative_prompt.clone(),
+                )
+            }
+            None => {
+                // 使用 PromptProcessor 处理提示词，角色提示词替换为展开后的版本
+                // 处理链：剥离注释 -> 注入主预设 -> 展开 snippet
+                let processor = PromptProcessor::new(Arc::clone(&self.storage))
+                    .with_global_affix(self.config.global_affix.clone());
+                tokio::task::spawn_blocking(move || {
+                    let (positive, negative) = processor.process_task(&mut task)?;
+                    Ok::<_, CoreError>((task, positive, negative))
+                })
+                .await??
+            }
+        };
+
+        // 重试时文件序号接在已有图片之后
+        let start_index = existing.as_ref().map_or(0, |r| r.images.len() as u32);
+        let mut failure: Option<(u32, CoreError)> = None;
+
+        // 写入在独立任务中按顺序进行，与下一张图片的生成重叠
+        let (write_tx, write_rx) = mpsc::channel(self.config.max_pending_writes.max(1));
+        let writer = tokio::spawn(write_images(
+            write_rx,
+            task.id,
+            self.config
+                .store_max_dimension
+                .map(|max| (max, self.config.png_compression)),
+            (task.params.width, task.params.height),
+            self.config.verify_writes,
+        ));
+
+        // 固定种子、主种子派生或随机
+        let seeds = task.params.image_seeds(start_index, task.count);
+        let label = task.label.as_deref().and_then(sanitize_label);
+
+        for offset in 0..task.count {
+            let idx = start_index + offset;
+            // 图片之间添加随机延迟（首张图片除外）
+            if offset > 0 {
+                let delay = random_delay() + self.config.inter_image_delay;
+                info!(task_id=%task.id, idx, "waiting {:?} before next image", delay);
+                tokio::time::sleep(delay).await;
+            }
+
+            let mut seed = seeds[offset as usize];
+            let mut filter_retries = 0;
+            let result = loop {
+                info!(task_id=%task.id, idx, seed, "generating image");
+                match self
+                    .request_image(&task, &expanded_prompt, &expanded_negative, seed)
+                    .await
+                {
+                    Err(CoreError::Nai(NaiError::ContentFiltered { .. }))
+                        if task.retry_on_filter && filter_retries < MAX_FILTER_RETRIES =>
+                    {
+                        filter_retries += 1;
+                        seed = seed.wrapping_add(1);
+                        tracing::warn!(task_id=%task.id, idx, filter_retries, "rejected by content filter, retrying with next seed");
+                        tokio::time::sleep(random_delay()).await;
+                    }
+                    other => break other,
+                }
+            };
+            match result {
+                Ok(bytes) => {
+                    let write = PendingWrite {
+                        offset,
+                        path: self.gallery.image_path(idx, seed, label.as_deref()),
+                        seed,
+                        filter_retries,
+                        bytes,
+                    };
+                    // 写入任务已因错误退出，剩余图片计为失败
+                    if write_tx.send(write).await.is_err() {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    let failed = task.count - offset;
+                    tracing::warn!(task_id=%task.id, idx, failed, error=%err, "image generation failed");
+                    failure = Some((failed, err));
+                    break;
+                }
+            }
+        }
+        drop(write_tx);
+
+        let (images, write_failure) = writer.await?;
+        // 写入失败的图片一定早于生成失败的图片
+        if let Some((offset, err)) = write_failure {
+            failure = Some((task.count - offset, err));
+        }
+
+        let new_task = existing.is_none();
+        let mut record = match existing {
+            Some(record) => record,
+            None => {
+                // 一张都没有成功，且没有可追加的记录：整个任务失败
+                if images.is_empty()
+                    && let Some((_, err)) = failure
+                {
+                    return Err(err);
+                }
+                GenerationRecord {
+                    id: Uuid::new_v4(),
+                    task_id: task.id,
+                    created_at: Utc::now(),
+                    raw_prompt: task.raw_prompt.clone(),
+                    expanded_prompt,
+                    negative_prompt: expanded_negative,
+                    label: label.clone(),
+                    raw_negative_prompt: Some(task.negative_prompt.clone()),
+                    main_preset: (!task.main_preset.is_empty()).then(|| task.main_preset.clone()),
+                    images: Vec::new(),
+                    params: Some(task.params.clone()),
+                }
+            }
+        };
+        let new_images = images.len();
+        record.images.extend(images);
+
+        if new_images > 0 {
+            let storage_for_record = Arc::clone(&self.storage);
+            let append = record.clone();
+            tokio::task::spawn_blocking(move || {
+                storage_for_record.append_generated_record(&append, new_images as u64, new_task)
+            })
+            .await??;
+
+            if let Some(hook) = &self.config.on_record_appended {
+                hook.call(&record);
+            }
+        }
+
+        match failure {
+            Some((failed, err)) => {
+                info!(
+                    task_id=%task.id,
+                    record_id=%record.id,
+                    succeeded=%record.images.len(),
+                    failed,
+                    "task partially completed"
+                );
+                Ok(TaskOutcome::PartiallyCompleted {
+                    record,
+                    failed,
+                    error: err.to_string(),
+                })
+            }
+            None => {
+                info!(task_id=%task.id, record_id=%record.id, images=%record.images.len(), "task completed");
+                Ok(TaskOutcome::Completed(record))
+            }
+        }
+    }
+
+    /// 请求生成单张图片，返回 PNG 字节
+    async fn request_image(
+        &self,
+        task: &GenerateTaskRequest,
+        prompt: &str,
+        negative: &str,
+        seed: u64,
+    ) -> CoreResult<Vec<u8>> {
+        let mut req = to_nai_request(&task.params, prompt, negative, seed);
+        for warning in req.validate(self.config.weight_range) {
+            tracing::warn!(task_id=%task.id, "{}", warning);
+        }
+        Ok(self.client.generate_image(&req).await?)
+    }
+}
+
+/// 等待写入 gallery 的图片
+struct PendingWrite {
+    offset: u32,
+    path: PathBuf,
+    seed: u64,
+    filter_retries: u32,
+    bytes: Vec<u8>,
+}
+
+/// 按接收顺序写入图片；遇到第一个错误即停止，并返回出错图片的偏移
+async fn write_images(
+    mut rx: mpsc::Receiver<PendingWrite>,
+    task_id: Uuid,
+    max_dimension: Option<(u32, PngCompression)>,
+    (req_width, req_height): (u32, u32),
+    verify: bool,
+) -> (Vec<GalleryImage>, Option<(u32, CoreError)>) {
+    let mut images = Vec::new();
+    while let Some(write) = rx.recv().await {
+        let offset = write.offset;
+        let result = tokio::task::spawn_blocking(move || -> CoreResult<GalleryImage> {
+            let (bytes, width, height) = match max_dimension {
+                Some((max, compression)) => {
+                    let scaled = imaging::downscale_png_with(write.bytes, max, compression)?;
+                    (scaled.bytes, scaled.width, scaled.height)
+                }
+                None => (write.bytes, req_width, req_height),
+            };
+            if let Some(parent) = write.path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let path = write_new_file(&write.path, &bytes)?;
+            if verify && let Err(err) = imaging::verify_written_image(&path, &bytes) {
+                tracing::warn!(%task_id, ?path, error=%err, "image failed verification, rewriting");
+                fs::write(&path, &bytes)?;
+                imaging::verify_written_image(&path, &bytes)?;
+            }
+            Ok(GalleryImage {
+                path,
+                seed: write.seed,
+                width,
+                height,
+                filter_retries: write.filter_retries,
+            })
+        })
+        .await
+        .map_err(CoreError::from)
+        .and_then(|r| r);
+        match result {
+            Ok(image) => images.push(image),
+            Err(err) => {
+                tracing::warn!(%task_id, offset, error=%err, "image write failed");
+                return (images, Some((offset, err)));
+            }
+        }
+    }
+    (images, None)
+}
+
+/// 同名图片的最大消歧后缀
+const MAX_COLLISION_SUFFIX: u32 = 100;
+
+/// 写入新文件而不覆盖已有图片：目标已存在时依次尝试 `{stem}_1.{ext}`、`{stem}_2.{ext}`……
+///
+/// 返回实际写入的路径
+fn write_new_file(path: &Path, bytes: &[u8]) -> CoreResult<PathBuf> {
+    use std::io::Write;
+
+    for suffix in 0..=MAX_COLLISION_SUFFIX {
+        let candidate = match suffix {
+            0 => path.to_path_buf(),
+            n => {
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                let name = match path.extension() {
+                    Some(ext) => format!("{stem}_{n}.{}", ext.to_string_lossy()),
+                    None => format!("{stem}_{n}"),
+                };
+                path.with_file_name(name)
+            }
+        };
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+        {
+            Ok(mut file) => {
+                file.write_all(bytes)?;
+                if suffix > 0 {
+                    tracing::warn!(path=?candidate, "image path collided, wrote with suffix");
+                }
+                return Ok(candidate);
+            }
+            // 只对已有文件消歧；目录等异常情况直接报错
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && candidate.is_file() => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Err(CoreError::Internal(format!(
+        "too many images named like {}",
+        path.display()
+    )))
+}
+
+/// 生成随机延迟时间，基准3秒，有0.5秒的波动范围
+fn random_delay() -> Duration {
+    let mut rng = rng();
+    let base_ms = 3000i32;
+    let bounce_ms = rng.random_range(-500..=500);
+    Duration::from_millis((base_ms + bounce_ms) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Snippet,
+        test_support::{MockNai, TestDir, TestStorage},
+    };
+
+    #[tokio::test]
+    async fn test_write_images_keeps_order_and_stops_on_error() {
+        let dir = TestDir::new();
+        let (tx, rx) = mpsc::channel(1);
+        let writer = tokio::spawn(write_images(rx, Uuid::new_v4(), None, (64, 64), false));
+
+        for offset in 0..2u32 {
+            let path = dir.join(format!("{offset}.png"));
+            tx.send(PendingWrite {
+                offset,
+                path,
+                seed: offset as u64,
+                filter_retries: 0,
+                bytes: vec![0],
+            })
+            .await
+            .unwrap();
+        }
+        // 目标路径是已存在的目录，写入必然失败
+        let blocked = dir.join("blocked");
+        std::fs::create_dir_all(&blocked).unwrap();
+        tx.send(PendingWrite {
+            offset: 2,
+            path: blocked,
+            seed: 2,
+            filter_retries: 0,
+            bytes: vec![0],
+        })
+        .await
+        .unwrap();
+        drop(tx);
+
+        let (images, failure) = writer.await.unwrap();
+        let seeds: Vec<u64> = images.iter().map(|i| i.seed).collect();
+        assert_eq!(seeds, vec![0, 1]);
+        assert_eq!(failure.map(|(offset, _)| offset), Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_write_images_verification() {
+        let dir = TestDir::new();
+        let mut valid = std::io::Cursor::new(Vec::new());
+        image::DynamicImage::new_rgb8(4, 4)
+            .write_to(&mut valid, image::ImageFormat::Png)
+            .unwrap();
+        let pending = |offset: u32, bytes: Vec<u8>| PendingWrite {
+            offset,
+            path: dir.join(format!("{offset}.png")),
+            seed: offset as u64,
+            filter_retries: 0,
+            bytes,
+        };
+
+        // 模拟损坏的图片字节：不校验时照常写入
+        let (tx, rx) = mpsc::channel(2);
+        let writer = tokio::spawn(write_images(rx, Uuid::new_v4(), None, (4, 4), false));
+        tx.send(pending(0, vec![0x89, b'P', b'N'])).await.unwrap();
+        drop(tx);
+        let (images, failure) = writer.await.unwrap();
+        assert_eq!(images.len(), 1);
+        assert!(failure.is_none());
+
+        // 开启校验后损坏的图片记为失败
+        let (tx, rx) = mpsc::channel(2);
+        let writer = tokio::spawn(write_images(rx, Uuid::new_v4(), None, (4, 4), true));
+        tx.send(pending(1, valid.into_inner())).await.unwrap();
+        tx.send(pending(2, vec![0x89, b'P', b'N'])).await.unwrap();
+        drop(tx);
+        let (images, failure) = writer.await.unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(failure.map(|(offset, _)| offset), Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_write_images_disambiguates_collisions() {
+        let dir = TestDir::new();
+        let (tx, rx) = mpsc::channel(1);
+        let writer = tokio::spawn(write_images(rx, Uuid::new_v4(), None, (64, 64), false));
+
+        let path = dir.join("2024-03-01").join("100000000_0_7.png");
+        for offset in 0..3u32 {
+            tx.send(PendingWrite {
+                offset,
+                path: path.clone(),
+                seed: 7,
+                filter_retries: 0,
+                bytes: vec![offset as u8],
+            })
+            .await
+            .unwrap();
+        }
+        drop(tx);
+
+        let (images, failure) = writer.await.unwrap();
+        assert!(failure.is_none());
+        let names: Vec<_> = images
+            .iter()
+            .map(|i| i.path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "100000000_0_7.png",
+                "100000000_0_7_1.png",
+                "100000000_0_7_2.png"
+            ]
+        );
+        for (offset, image) in images.iter().enumerate() {
+            assert_eq!(std::fs::read(&image.path).unwrap(), vec![offset as u8]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execute_rejects_zero_count() {
+        let TestStorage { dir, storage } = TestStorage::new();
+        let executor = TaskExecutor::new(
+            Arc::new(NaiClient::new("token".to_string()).unwrap()),
+            storage,
+            GalleryPaths::new(dir.join("gallery")),
+            ExecutorConfig::default(),
+        );
+
+        let mut task = GenerateTaskRequest::new("1girl".to_string(), String::new());
+        task.count = 0;
+        let err = executor.execute(task).await.unwrap_err();
+        assert!(err.to_string().contains("count"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_partial_failure_then_retry_reuses_expansion() {
+        let TestStorage { dir, storage } = TestStorage::new();
+        let hair = storage
+            .upsert_snippet(
+                Snippet::new("hair".into(), "cat".into(), "red hair".into()).unwrap(),
+                None,
+            )
+            .unwrap();
+        // 第三张失败，其余成功
+        let nai = MockNai::start(|n| match n {
+            2 => (500, b"server error".to_vec()),
+            _ => MockNai::image_response(),
+        })
+        .await;
+        let executor = TaskExecutor::new(
+            nai.client(),
+            Arc::clone(&storage),
+            GalleryPaths::new(dir.join("gallery")),
+            ExecutorConfig::default(),
+        );
+
+        let mut task = GenerateTaskRequest::new("1girl, <snippet:hair>".into(), String::new());
+        task.count = 3;
+        let TaskOutcome::PartiallyCompleted {
+            record,
+            failed,
+            error,
+        } = executor.execute(task.clone()).await.unwrap()
+        else {
+            panic!("expected a partial result");
+        };
+        assert_eq!(failed, 1);
+        assert_eq!(record.images.len(), 2);
+        assert!(error.contains("500"));
+        assert_eq!(
+            storage.get_record(record.id).unwrap().unwrap().images.len(),
+            2
+        );
+
+        // 重试前修改 snippet，重试仍使用原记录的展开结果
+        let mut changed = hair;
+        changed.content = "blue hair".into();
+        storage.upsert_snippet(changed, None).unwrap();
+        let TaskOutcome::Completed(retried) = executor
+            .retry_failed(task, record.clone(), failed)
+            .await
+            .unwrap()
+        else {
+            panic!("expected the retry to complete");
+        };
+        assert_eq!(retried.id, record.id);
+        assert_eq!(retried.images.len(), 3);
+        assert_eq!(retried.expanded_prompt, record.expanded_prompt);
+        assert!(nai.input(3).starts_with("1girl, red hair"));
+        assert_eq!(
+            storage.get_record(record.id).unwrap().unwrap().images.len(),
+            3
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_content_filter_retries_with_next_seed() {
+        const FILTERED: &[u8] = br#"{"statusCode":400,"message":"rejected by the content filter"}"#;
+        let TestStorage { dir, storage } = TestStorage::new();
+        let executor = |nai: &MockNai| {
+            TaskExecutor::new(
+                nai.client(),
+                Arc::clone(&storage),
+                GalleryPaths::new(dir.join("gallery")),
+                ExecutorConfig::default(),
+            )
+        };
+        let mut task = GenerateTaskRequest::new("1girl".into(), String::new());
+        task.params.seed = Some(100);
+        task.retry_on_filter = true;
+        let seeds = |nai: &MockNai| {
+            nai.requests
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|req| req["parameters"]["seed"].as_u64().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        // 前两次被过滤，第三次用 seed + 2 成功
+        let nai = MockNai::start(|n| match n {
+            0 | 1 => (400, FILTERED.to_vec()),
+            _ => MockNai::image_response(),
+        })
+        .await;
+        let TaskOutcome::Completed(record) = executor(&nai).execute(task.clone()).await.unwrap()
+        else {
+            panic!("expected the task to complete");
+        };
+        assert_eq!(seeds(&nai), vec![100, 101, 102]);
+        assert_eq!(record.images[0].seed, 102);
+        assert_eq!(record.images[0].filter_retries, 2);
+
+        // 超过重试上限后放弃
+        let nai = MockNai::start(|_| (400, FILTERED.to_vec())).await;
+        let err = executor(&nai).execute(task.clone()).await.unwrap_err();
+        assert!(matches!(
+            err,
+            CoreError::Nai(NaiError::ContentFiltered { .. })
+        ));
+        assert_eq!(seeds(&nai).len(), 1 + MAX_FILTER_RETRIES as usize);
+
+        // 未开启重试时只请求一次；其他 400 错误从不重试
+        task.retry_on_filter = false;
+        let nai = MockNai::start(|_| (400, FILTERED.to_vec())).await;
+        assert!(executor(&nai).execute(task.clone()).await.is_err());
+        assert_eq!(seeds(&nai), vec![100]);
+        task.retry_on_filter = true;
+        let nai = MockNai::start(|_| (400, br#"{"message":"invalid width"}"#.to_vec())).await;
+        assert!(executor(&nai).execute(task).await.is_err());
+        assert_eq!(seeds(&nai), vec![100]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_regenerate_from_record_applies_affix_once() {
+        let TestStorage { dir, storage } = TestStorage::new();
+        let nai = MockNai::start(|_| MockNai::image_response()).await;
+        let executor = TaskExecutor::new(
+            nai.client(),
+            Arc::clone(&storage),
+            GalleryPaths::new(dir.join("gallery")),
+            ExecutorConfig {
+                global_affix: GlobalAffix {
+                    prefix: Some("house style".into()),
+                    suffix: Some("signature".into()),
+                },
+                ..ExecutorConfig::default()
+            },
+        );
+
+        let mut task = GenerateTaskRequest::new("1girl".into(), String::new());
+        task.main_preset.before = Some("best quality".into());
+        let TaskOutcome::Completed(record) = executor.execute(task).await.unwrap() else {
+            panic!("expected the task to complete");
+        };
+        assert_eq!(
+            record.expanded_prompt,
+            "best quality, house style, 1girl, signature"
+        );
+
+        let TaskOutcome::Completed(regenerated) = executor
+            .execute(GenerateTaskRequest::from_record(&record))
+            .await
+            .unwrap()
+        else {
+            panic!("expected the regenerate to complete");
+        };
+        assert_eq!(regenerated.expanded_prompt, record.expanded_prompt);
+        assert_eq!(nai.input(1), nai.input(0));
+        assert_eq!(nai.input(1).matches("house style").count(), 1);
+        assert_eq!(nai.input(1).matches("best quality").count(), 1);
+    }
+
+    #[test]
+    fn test_preview_task() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        let snippet = Snippet::new("hair".into(), "cat".into(), "blue hair".into()).unwrap();
+        storage.upsert_snippet(snippet, None).unwrap();
+
+        let mut task = GenerateTaskRequest::new(
+            "1girl, <snippet:hair>, 5::smile:: //note//".into(),
+            "blurry".into(),
+        );
+        task.count = 3;
+        task.params.width = 832;
+        task.params.height = 1216;
+        task.params.steps = 28;
+
+        let preview = PromptProcessor::new(Arc::clone(&storage))
+            .preview_task(&task, WeightRange::default(), false)
+            .unwrap();
+        assert_eq!(preview.final_positive, "1girl, blue hair, 2::smile::");
+        assert_eq!(preview.warnings.len(), 1);
+        assert_eq!(preview.positive_diagnostics.len(), 1);
+        assert_eq!(preview.positive_diagnostics[0].code, "weight_out_of_range");
+        assert_eq!(preview.estimated_anlas.per_image, 20);
+        assert_eq!(preview.estimated_anlas.total, 60);
+    }
+
+    #[test]
+    fn test_debug_payload_uses_processed_prompt_and_seed() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        let snippet = Snippet::new("hair".into(), "cat".into(), "blue hair".into()).unwrap();
+        storage.upsert_snippet(snippet, None).unwrap();
+
+        let mut task = GenerateTaskRequest::new("1girl, <snippet:hair>".into(), "blurry".into());
+        task.params.add_quality_tags = false;
+        task.params.seed = Some(42);
+        let payload = PromptProcessor::new(Arc::clone(&storage))
+            .debug_payload(&task, WeightRange::default())
+            .unwrap();
+        assert_eq!(payload["input"], "1girl, blue hair");
+        assert_eq!(payload["parameters"]["seed"], 42);
+        assert_eq!(payload["parameters"]["negative_prompt"], "blurry");
+    }
+}
@@ -0,0 +1,152 @@
+//! 收藏种子
+
+use chrono::Utc;
+use redb::{ReadableDatabase, ReadableTable, TableHandle};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::info;
+
+use crate::{CoreResult, CoreStorage, TABLE_FAVORITE_SEEDS, decode_row};
+
+/// 收藏的种子
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FavoriteSeed {
+    pub seed: u64,
+    pub label: String,
+    pub created_at: chrono::DateTime<Utc>,
+}
+
+/// 种子收藏标签的最大字符数
+pub const MAX_SEED_LABEL_CHARS: usize = 64;
+
+/// 种子收藏标签校验错误
+#[derive(Debug, Clone, PartialEq, Eq, Error, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum SeedLabelError {
+    #[error("种子标签不能为空")]
+    Empty,
+    #[error("种子标签不能超过 {max} 个字符")]
+    TooLong { max: usize },
+    #[error("种子标签不能包含控制字符（位置 {position}）")]
+    ControlChar { position: usize },
+}
+
+/// 校验种子收藏标签（调用方应先去除首尾空白）
+pub fn validate_seed_label(label: &str) -> Result<(), SeedLabelError> {
+    if label.is_empty() {
+        return Err(SeedLabelError::Empty);
+    }
+    if let Some(position) = label.chars().position(char::is_control) {
+        return Err(SeedLabelError::ControlChar { position });
+    }
+    if label.chars().count() > MAX_SEED_LABEL_CHARS {
+        return Err(SeedLabelError::TooLong {
+            max: MAX_SEED_LABEL_CHARS,
+        });
+    }
+    Ok(())
+}
+
+impl CoreStorage {
+    /// 收藏种子；同一种子已存在时只更新标签
+    pub fn add_favorite_seed(&self, seed: u64, label: &str) -> CoreResult<FavoriteSeed> {
+        let label = label.trim();
+        validate_seed_label(label)?;
+        let write_txn = self.begin_write_with_retry()?;
+        let favorite = {
+            let mut table = write_txn.open_table(TABLE_FAVORITE_SEEDS)?;
+            let existing = table
+                .get(seed)?
+                .map(|value| serde_json::from_str::<FavoriteSeed>(&value.value()))
+                .transpose()?;
+            let favorite = FavoriteSeed {
+                seed,
+                label: label.to_string(),
+                created_at: existing.map_or_else(Utc::now, |f| f.created_at),
+            };
+            table.insert(seed, serde_json::to_string(&favorite)?)?;
+            favorite
+        };
+        write_txn.commit()?;
+        info!(seed=%seed, label=%favorite.label, "favorite seed saved");
+        Ok(favorite)
+    }
+
+    /// 列出收藏的种子，最新收藏的在前
+    pub fn list_favorite_seeds(&self) -> CoreResult<Vec<FavoriteSeed>> {
+        let read_txn = self.db()?.begin_read()?;
+        let table = read_txn.open_table(TABLE_FAVORITE_SEEDS)?;
+        let mut seeds = Vec::new();
+        for entry in table.iter()? {
+            let (key, value) = entry?;
+            seeds.extend(decode_row::<FavoriteSeed>(
+                TABLE_FAVORITE_SEEDS.name(),
+                key.value(),
+                &value.value(),
+            ));
+        }
+        seeds.sort_by_key(|f| std::cmp::Reverse(f.created_at));
+        Ok(seeds)
+    }
+
+    /// 取消收藏种子
+    pub fn remove_favorite_seed(&self, seed: u64) -> CoreResult<bool> {
+        let write_txn = self.begin_write_with_retry()?;
+        let removed = {
+            let mut table = write_txn.open_table(TABLE_FAVORITE_SEEDS)?;
+            table.remove(seed)?.is_some()
+        };
+        write_txn.commit()?;
+        if removed {
+            info!(seed=%seed, "favorite seed removed");
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CoreError, ValidationError, test_support::TestStorage};
+
+    #[test]
+    fn test_favorite_seeds_dedupe_and_remove() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+
+        let first = storage.add_favorite_seed(42, " sunset ").unwrap();
+        assert_eq!(first.label, "sunset");
+        storage.add_favorite_seed(7, "portrait").unwrap();
+        let renamed = storage.add_favorite_seed(42, "golden hour").unwrap();
+        assert_eq!(renamed.created_at, first.created_at);
+
+        let seeds = storage.list_favorite_seeds().unwrap();
+        assert_eq!(seeds.len(), 2);
+        let labels: Vec<_> = seeds.iter().map(|f| (f.seed, f.label.as_str())).collect();
+        assert_eq!(labels, vec![(7, "portrait"), (42, "golden hour")]);
+
+        let err = storage.add_favorite_seed(1, "   ").unwrap_err();
+        assert!(matches!(
+            err,
+            CoreError::Validation(ValidationError::SeedLabel(SeedLabelError::Empty))
+        ));
+
+        assert!(storage.remove_favorite_seed(42).unwrap());
+        assert!(!storage.remove_favorite_seed(42).unwrap());
+        assert_eq!(storage.list_favorite_seeds().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_validate_seed_label() {
+        assert_eq!(validate_seed_label("好图"), Ok(()));
+        assert_eq!(
+            validate_seed_label("a\nb"),
+            Err(SeedLabelError::ControlChar { position: 1 })
+        );
+        assert_eq!(
+            validate_seed_label(&"x".repeat(MAX_SEED_LABEL_CHARS + 1)),
+            Err(SeedLabelError::TooLong {
+                max: MAX_SEED_LABEL_CHARS
+            })
+        );
+    }
+}
@@ -0,0 +1,461 @@
+//! 图片处理 - 生成结果的缩放与重新编码
+//!
+//! 注意：重新编码会丢弃 NovelAI 写入 PNG 的元数据块（生成参数等）。
+//!
+//! 只有缩小图片时才会重新编码：保存时超过 `store_max_dimension`、
+//! 生成预览图和缩略图。其余情况原样写入 NovelAI 返回的字节。
+
+use std::{
+    fs,
+    io::Cursor,
+    path::{Component, Path, PathBuf},
+};
+
+use image::{
+    DynamicImage, ImageFormat,
+    codecs::png::{CompressionType, FilterType as PngFilterType, PngEncoder},
+    imageops::FilterType,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{CoreError, CoreResult};
+
+/// 重新编码 PNG 时的压缩级别
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PngCompression {
+    /// 速度最快，文件较大（image 库的默认行为）
+    #[default]
+    Fast,
+    /// zlib 默认级别
+    Default,
+    /// 文件最小，编码最慢
+    Best,
+}
+
+impl PngCompression {
+    /// 解析 `fast`、`default` 或 `best`
+    pub fn parse(value: &str) -> CoreResult<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "fast" => Ok(Self::Fast),
+            "default" => Ok(Self::Default),
+            "best" => Ok(Self::Best),
+            _ => Err(CoreError::invalid(format!(
+                "invalid png compression: {}",
+                value
+            ))),
+        }
+    }
+
+    fn compression_type(self) -> CompressionType {
+        match self {
+            Self::Fast => CompressionType::Fast,
+            Self::Default => CompressionType::Default,
+            Self::Best => CompressionType::Best,
+        }
+    }
+}
+
+/// 按指定压缩级别编码 PNG
+pub fn encode_png(img: &DynamicImage, compression: PngCompression) -> CoreResult<Vec<u8>> {
+    let mut out = Vec::new();
+    let encoder = PngEncoder::new_with_quality(
+        &mut out,
+        compression.compression_type(),
+        PngFilterType::Adaptive,
+    );
+    img.write_with_encoder(encoder)?;
+    Ok(out)
+}
+
+/// 缩放后的图片
+#[derive(Debug, Clone)]
+pub struct ScaledImage {
+    pub bytes: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// 将 PNG 按比例缩小，使最长边不超过 `max_dimension`
+///
+/// 原图已满足限制时原样返回（不重新编码，保留元数据）。
+pub fn downscale_png(bytes: Vec<u8>, max_dimension: u32) -> CoreResult<ScaledImage> {
+    downscale_png_with(bytes, max_dimension, PngCompression::default())
+}
+
+/// 同 [`downscale_png`]，缩小后按 `compression` 重新编码
+pub fn downscale_png_with(
+    bytes: Vec<u8>,
+    max_dimension: u32,
+    compression: PngCompression,
+) -> CoreResult<ScaledImage> {
+    let img = image::load_from_memory_with_format(&bytes, ImageFormat::Png)?;
+    downscale_decoded(img, bytes, max_dimension, compression)
+}
+
+/// 同 [`downscale_png_with`]，但接受任意可解码的格式（见 [`can_decode`]）
+///
+/// 缩小后统一编码为 PNG；无需缩小时原样返回。
+pub fn downscale_image_with(
+    bytes: Vec<u8>,
+    max_dimension: u32,
+    compression: PngCompression,
+) -> CoreResult<ScaledImage> {
+    let img = image::load_from_memory(&bytes)?;
+    downscale_decoded(img, bytes, max_dimension, compression)
+}
+
+/// 根据文件头判断是否为已启用解码器的图片格式（PNG / JPEG / WebP）
+pub fn can_decode(bytes: &[u8]) -> bool {
+    image::guess_format(bytes).is_ok_and(|format| format.reading_enabled())
+}
+
+fn downscale_decoded(
+    img: DynamicImage,
+    bytes: Vec<u8>,
+    max_dimension: u32,
+    compression: PngCompression,
+) -> CoreResult<ScaledImage> {
+    let (width, height) = (img.width(), img.height());
+    if width.max(height) <= max_dimension || max_dimension == 0 {
+        return Ok(ScaledImage {
+            bytes,
+            width,
+            height,
+        });
+    }
+
+    // resize 保持宽高比，结果落在 max_dimension x max_dimension 之内
+    let resized = img.resize(max_dimension, max_dimension, FilterType::Lanczos3);
+    Ok(ScaledImage {
+        bytes: encode_png(&resized, compression)?,
+        width: resized.width(),
+        height: resized.height(),
+    })
+}
+
+/// 从 gallery 图片生成 snippet / preset 预览图时的最长边
+pub const PREVIEW_MAX_DIMENSION: u32 = 512;
+
+/// 确认 `path` 位于 `dir` 之内（解析符号链接后比较），返回规范化后的路径
+pub fn ensure_within(dir: &Path, path: &Path) -> CoreResult<PathBuf> {
+    let dir = dir.canonicalize()?;
+    let path = path
+        .canonicalize()
+        .map_err(|_| CoreError::not_found("image"))?;
+    if !path.starts_with(&dir) {
+        return Err(CoreError::invalid("image is outside the gallery"));
+    }
+    Ok(path)
+}
+
+/// 缩略图缓存目录（位于 gallery 目录下）
+pub const THUMBNAIL_DIR: &str = ".thumbs";
+
+/// 相对路径只能由普通路径段组成（无 `..`、绝对路径或反斜杠）
+pub(crate) fn is_safe_relative(rel_path: &str) -> bool {
+    !rel_path.is_empty()
+        && !rel_path.contains('\\')
+        && Path::new(rel_path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// 将 gallery 内的相对路径解析为绝对路径，拒绝路径遍历
+pub fn resolve_gallery_path(gallery_dir: &Path, rel_path: &str) -> CoreResult<PathBuf> {
+    if !is_safe_relative(rel_path) || Path::new(rel_path).starts_with(THUMBNAIL_DIR) {
+        return Err(CoreError::invalid("invalid gallery path"));
+    }
+    Ok(gallery_dir.join(rel_path))
+}
+
+/// 将 preview 目录内的相对路径解析为绝对路径，拒绝路径遍历
+pub fn resolve_preview_path(preview_dir: &Path, rel_path: &str) -> CoreResult<PathBuf> {
+    if !is_safe_relative(rel_path) {
+        return Err(CoreError::invalid("invalid preview path"));
+    }
+    Ok(preview_dir.join(rel_path))
+}
+
+/// 根据文件头判断图片的 MIME 类型，无法识别时返回 `application/octet-stream`
+pub fn sniff_content_type(bytes: &[u8]) -> &'static str {
+    image::guess_format(bytes)
+        .map(|format| format.to_mime_type())
+        .unwrap_or("application/octet-stream")
+}
+
+/// 校验已写入的图片：内容与预期字节一致，且文件头可解码出尺寸
+pub fn verify_written_image(path: &Path, expected: &[u8]) -> CoreResult<(u32, u32)> {
+    let written = fs::read(path)?;
+    if written != expected {
+        return Err(CoreError::Internal(format!(
+            "written image {} differs from generated bytes ({} of {} bytes)",
+            path.display(),
+            written.len(),
+            expected.len()
+        )));
+    }
+    let dimensions = image::ImageReader::new(Cursor::new(&written))
+        .with_guessed_format()?
+        .into_dimensions()?;
+    Ok(dimensions)
+}
+
+/// 获取（必要时生成并缓存）gallery 图片的缩略图，返回缓存文件路径
+///
+/// 缓存位于 `{gallery_dir}/.thumbs/{size}/{rel_path}`，以路径与尺寸为键；
+/// 新生成的缩略图按 `compression` 编码。原图比缓存新（被替换）时重新生成，
+/// 原图已不存在时顺带删除各尺寸的缓存。
+pub fn cached_thumbnail(
+    gallery_dir: &Path,
+    rel_path: &str,
+    size: u32,
+    compression: PngCompression,
+) -> CoreResult<PathBuf> {
+    let source = resolve_gallery_path(gallery_dir, rel_path)?;
+    let source = match ensure_within(gallery_dir, &source) {
+        Ok(source) if source.is_file() => source,
+        Ok(_) => return Err(CoreError::not_found("image")),
+        Err(err) => {
+            if !source.exists() {
+                remove_cached_thumbnails(gallery_dir, Path::new(rel_path));
+            }
+            return Err(err);
+        }
+    };
+    let cached = gallery_dir
+        .join(THUMBNAIL_DIR)
+        .join(size.to_string())
+        .join(rel_path);
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    if let Some(cached_at) = modified(&cached)
+        && modified(&source).is_some_and(|source_at| source_at <= cached_at)
+    {
+        return Ok(cached);
+    }
+
+    let bytes = fs::read(&source)?;
+    let scaled = downscale_png_with(bytes, size, compression)?;
+    if let Some(parent) = cached.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // 先写临时文件再重命名，避免并发请求读到写了一半的缓存
+    let tmp = cached.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+    fs::write(&tmp, &scaled.bytes)?;
+    fs::rename(&tmp, &cached)?;
+    Ok(cached)
+}
+
+/// 删除 gallery 图片或目录在各尺寸下的缓存缩略图（尽力而为，忽略错误）
+///
+/// `path` 可以是 gallery 内的相对路径，也可以是位于 `gallery_dir` 下的绝对路径。
+pub fn remove_cached_thumbnails(gallery_dir: &Path, path: &Path) {
+    let rel_path = path.strip_prefix(gallery_dir).unwrap_or(path);
+    if !rel_path.to_str().is_some_and(is_safe_relative) {
+        return;
+    }
+    let Ok(sizes) = fs::read_dir(gallery_dir.join(THUMBNAIL_DIR)) else {
+        return;
+    };
+    for size in sizes.flatten() {
+        let cached = size.path().join(rel_path);
+        if cached.is_dir() {
+            let _ = fs::remove_dir_all(&cached);
+        } else {
+            let _ = fs::remove_file(&cached);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestDir;
+    use image::{DynamicImage, RgbImage};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(width, height));
+        let mut out = Cursor::new(Vec::new());
+        img.write_to(&mut out, ImageFormat::Png).unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn test_downscale_keeps_aspect_ratio() {
+        let scaled = downscale_png(png(64, 32), 16).unwrap();
+        assert_eq!((scaled.width, scaled.height), (16, 8));
+    }
+
+    #[test]
+    fn test_downscale_skips_small_image() {
+        let original = png(20, 10);
+        let scaled = downscale_png(original.clone(), 32).unwrap();
+        assert_eq!((scaled.width, scaled.height), (20, 10));
+        assert_eq!(scaled.bytes, original);
+    }
+
+    #[test]
+    fn test_png_compression_levels() {
+        // 带渐变的图片才能体现压缩级别的差异
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(256, 256, |x, y| {
+            image::Rgb([x as u8, y as u8, (x * y / 256) as u8])
+        }));
+        let mut source = Cursor::new(Vec::new());
+        img.write_to(&mut source, ImageFormat::Png).unwrap();
+        let source = source.into_inner();
+
+        let size = |compression| {
+            downscale_png_with(source.clone(), 128, compression)
+                .unwrap()
+                .bytes
+                .len()
+        };
+        let (fast, best) = (size(PngCompression::Fast), size(PngCompression::Best));
+        assert!(
+            best < fast,
+            "best {best} should be smaller than fast {fast}"
+        );
+        assert!(size(PngCompression::Default) <= fast);
+
+        // 不需要缩放时原样返回，不受压缩级别影响
+        let untouched = downscale_png_with(source.clone(), 512, PngCompression::Best).unwrap();
+        assert_eq!(untouched.bytes, source);
+        assert_eq!(
+            PngCompression::parse(" Best ").unwrap(),
+            PngCompression::Best
+        );
+        assert!(PngCompression::parse("max").is_err());
+    }
+
+    #[test]
+    fn test_downscale_image_accepts_other_formats() {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(64, 32));
+        for format in [ImageFormat::Jpeg, ImageFormat::WebP] {
+            let mut source = Cursor::new(Vec::new());
+            img.write_to(&mut source, format).unwrap();
+            let source = source.into_inner();
+            assert!(can_decode(&source));
+
+            let scaled = downscale_image_with(source, 16, PngCompression::Fast).unwrap();
+            assert_eq!((scaled.width, scaled.height), (16, 8));
+            assert_eq!(sniff_content_type(&scaled.bytes), "image/png");
+        }
+        assert!(!can_decode(b"GIF89a"));
+        assert!(!can_decode(b"not an image"));
+    }
+
+    #[test]
+    fn test_resolve_gallery_path_rejects_traversal() {
+        let root = Path::new("/data/gallery");
+        assert!(resolve_gallery_path(root, "2024-01-01/a.png").is_ok());
+        assert!(resolve_gallery_path(root, "../secret.png").is_err());
+        assert!(resolve_gallery_path(root, "2024-01-01/../../x").is_err());
+        assert!(resolve_gallery_path(root, "/etc/passwd").is_err());
+        assert!(resolve_gallery_path(root, ".thumbs/256/a.png").is_err());
+    }
+
+    #[test]
+    fn test_sniff_content_type() {
+        assert_eq!(sniff_content_type(&png(4, 4)), "image/png");
+        assert_eq!(sniff_content_type(b"\xFF\xD8\xFF\xE0rest"), "image/jpeg");
+        assert_eq!(sniff_content_type(b"RIFF\0\0\0\0WEBPVP8 "), "image/webp");
+        assert_eq!(
+            sniff_content_type(b"not an image"),
+            "application/octet-stream"
+        );
+    }
+
+    #[test]
+    fn test_verify_written_image() {
+        let dir = TestDir::new();
+        let good = png(8, 4);
+        fs::write(dir.join("good.png"), &good).unwrap();
+        assert_eq!(
+            verify_written_image(&dir.join("good.png"), &good).unwrap(),
+            (8, 4)
+        );
+
+        // 截断的写入
+        fs::write(dir.join("short.png"), &good[..good.len() / 2]).unwrap();
+        assert!(verify_written_image(&dir.join("short.png"), &good).is_err());
+        // 字节完整但本身不是图片
+        fs::write(dir.join("junk.png"), b"not an image").unwrap();
+        assert!(verify_written_image(&dir.join("junk.png"), b"not an image").is_err());
+    }
+
+    #[test]
+    fn test_cached_thumbnail() {
+        let dir = TestDir::new();
+        fs::create_dir_all(dir.join("2024-01-01")).unwrap();
+        fs::write(dir.join("2024-01-01/a.png"), png(64, 32)).unwrap();
+
+        let thumb = cached_thumbnail(&dir, "2024-01-01/a.png", 16, PngCompression::Fast).unwrap();
+        assert_eq!(thumb, dir.join(".thumbs/16/2024-01-01/a.png"));
+        let img = image::open(&thumb).unwrap();
+        assert_eq!((img.width(), img.height()), (16, 8));
+
+        // 第二次直接命中缓存
+        let modified = fs::metadata(&thumb).unwrap().modified().unwrap();
+        cached_thumbnail(&dir, "2024-01-01/a.png", 16, PngCompression::Fast).unwrap();
+        assert_eq!(fs::metadata(&thumb).unwrap().modified().unwrap(), modified);
+
+        assert!(
+            cached_thumbnail(&dir, "2024-01-01/missing.png", 16, PngCompression::Fast).is_err()
+        );
+
+        // 原图被替换后重新生成
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        fs::write(dir.join("2024-01-01/a.png"), png(32, 64)).unwrap();
+        let thumb = cached_thumbnail(&dir, "2024-01-01/a.png", 16, PngCompression::Fast).unwrap();
+        let img = image::open(&thumb).unwrap();
+        assert_eq!((img.width(), img.height()), (8, 16));
+
+        // 原图删除后各尺寸的缓存随之删除
+        cached_thumbnail(&dir, "2024-01-01/a.png", 32, PngCompression::Fast).unwrap();
+        fs::remove_file(dir.join("2024-01-01/a.png")).unwrap();
+        assert!(matches!(
+            cached_thumbnail(&dir, "2024-01-01/a.png", 16, PngCompression::Fast),
+            Err(CoreError::NotFound { .. })
+        ));
+        assert!(!dir.join(".thumbs/16/2024-01-01/a.png").exists());
+        assert!(!dir.join(".thumbs/32/2024-01-01/a.png").exists());
+
+        // 按目录删除
+        fs::write(dir.join("2024-01-01/b.png"), png(64, 32)).unwrap();
+        cached_thumbnail(&dir, "2024-01-01/b.png", 16, PngCompression::Fast).unwrap();
+        remove_cached_thumbnails(&dir, &dir.join("2024-01-01"));
+        assert!(!dir.join(".thumbs/16/2024-01-01").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cached_thumbnail_rejects_symlink_escape() {
+        let dir = TestDir::new();
+        fs::create_dir_all(dir.join("gallery/2024-01-01")).unwrap();
+        fs::write(dir.join("secret.png"), png(8, 8)).unwrap();
+        std::os::unix::fs::symlink(dir.join("secret.png"), dir.join("gallery/2024-01-01/a.png"))
+            .unwrap();
+        assert!(matches!(
+            cached_thumbnail(
+                &dir.join("gallery"),
+                "2024-01-01/a.png",
+                16,
+                PngCompression::Fast
+            ),
+            Err(CoreError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn test_ensure_within() {
+        let dir = TestDir::new();
+        fs::create_dir_all(dir.join("gallery/2024-01-01")).unwrap();
+        fs::write(dir.join("gallery/2024-01-01/a.png"), b"x").unwrap();
+        fs::write(dir.join("secret.png"), b"x").unwrap();
+        let gallery = dir.join("gallery");
+
+        assert!(ensure_within(&gallery, &gallery.join("2024-01-01/a.png")).is_ok());
+        assert!(ensure_within(&gallery, &gallery.join("../secret.png")).is_err());
+        assert!(ensure_within(&gallery, &gallery.join("2024-01-01/missing.png")).is_err());
+    }
+}
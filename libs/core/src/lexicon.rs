@@ -2,6 +2,8 @@ use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+use crate::Page;
+
 // 编译时嵌入单个词库文件
 const EMBEDDED_LEXICON: &str = include_str!("../../../assets/lexicon.json");
 
@@ -9,7 +11,8 @@ const EMBEDDED_LEXICON: &str = include_str!("../../../assets/lexicon.json");
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LexiconEntry {
     pub tag: String,
-    pub zh: String,
+    /// 语言代码 -> 译文，如 `zh`、`ja`、`ko`
+    pub translations: HashMap<String, String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub weight: Option<u64>,
     pub category: String,
@@ -45,13 +48,31 @@ pub struct CategoryData {
     pub subcategories: HashMap<String, Vec<LexiconEntry>>,
 }
 
+impl LexiconEntry {
+    /// 获取指定语言的译文
+    pub fn translation(&self, lang: &str) -> Option<&str> {
+        self.translations.get(lang).map(String::as_str)
+    }
+}
+
 /// 搜索结果
 #[derive(Debug, Clone, Serialize)]
 pub struct SearchResult {
-    pub entries: Vec<LexiconEntry>,
+    pub entries: Vec<SearchHit>,
     pub total: usize,
 }
 
+/// 单条搜索命中
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub entry: LexiconEntry,
+    /// 命中的译文语言；为空表示命中的是标签本身
+    pub matched_lang: Option<String>,
+    /// 用于显示的译文：优先请求的语言，其次命中的语言，最后为中文
+    pub display: Option<String>,
+}
+
 /// 嵌入的 JSON 结构
 #[derive(Debug, Deserialize)]
 struct EmbeddedLexicon {
@@ -74,7 +95,11 @@ struct EmbeddedSubcategory {
 #[derive(Debug, Deserialize)]
 struct EmbeddedTag {
     tag: String,
-    zh: String,
+    /// 旧格式的中文译文，加载时并入 `translations["zh"]`
+    #[serde(default)]
+    zh: Option<String>,
+    #[serde(default)]
+    translations: HashMap<String, String>,
     #[serde(default)]
     weight: Option<u64>,
 }
@@ -85,6 +110,8 @@ pub struct Lexicon {
     categories: HashMap<String, CategoryData>,
     /// 所有条目的平面列表，用于搜索
     all_entries: Vec<LexiconEntry>,
+    /// 规范化标签 -> all_entries 下标，用于精确查找
+    by_tag: HashMap<String, usize>,
     /// 索引信息
     index: LexiconIndex,
 }
@@ -108,11 +135,14 @@ impl Lexicon {
                 let entries: Vec<LexiconEntry> = subcat
                     .tags
                     .into_iter()
-                    .map(|t| {
+                    .map(|mut t| {
                         tag_count += 1;
+                        if let Some(zh) = t.zh {
+                            t.translations.entry("zh".to_string()).or_insert(zh);
+                        }
                         LexiconEntry {
                             tag: t.tag,
-                            zh: t.zh,
+                            translations: t.translations,
                             weight: t.weight,
                             category: cat.name.clone(),
                             subcategory: subcat.name.clone(),
@@ -140,7 +170,13 @@ impl Lexicon {
         }
 
         // 预排序所有条目（按权重高到低）
-        all_entries.sort_by(|a, b| b.weight.unwrap_or(0).cmp(&a.weight.unwrap_or(0)));
+        all_entries.sort_by_key(|e| std::cmp::Reverse(e.weight.unwrap_or(0)));
+
+        let mut by_tag = HashMap::new();
+        for (i, entry) in all_entries.iter().enumerate() {
+            // 重复标签保留权重最高的条目
+            by_tag.entry(normalize_tag(&entry.tag)).or_insert(i);
+        }
 
         let index = LexiconIndex {
             categories: index_categories,
@@ -150,6 +186,7 @@ impl Lexicon {
         Ok(Self {
             categories,
             all_entries,
+            by_tag,
             index,
         })
     }
@@ -169,54 +206,262 @@ impl Lexicon {
         self.categories.get(name)
     }
 
+    /// 分页获取某个分类的条目
+    /// 指定 subcategory 时仅返回该子分类；否则按子分类顺序返回整个分类
+    /// 分类或子分类不存在时返回 None
+    pub fn get_category_paged(
+        &self,
+        name: &str,
+        subcategory: Option<&str>,
+        offset: usize,
+        limit: usize,
+    ) -> Option<Page<LexiconEntry>> {
+        let category = self.categories.get(name)?;
+
+        let entries: Vec<&LexiconEntry> = match subcategory {
+            Some(sub) => category.subcategories.get(sub)?.iter().collect(),
+            None => {
+                // HashMap 无序，按索引中的子分类顺序拼接
+                let info = self.index.categories.iter().find(|c| c.name == name)?;
+                info.subcategories
+                    .iter()
+                    .filter_map(|sub| category.subcategories.get(sub))
+                    .flatten()
+                    .collect()
+            }
+        };
+
+        let total = entries.len();
+        let items = entries
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect();
+        Some(Page::new(items, total, offset))
+    }
+
+    /// 按标签精确查找（忽略大小写，下划线视同空格）
+    pub fn lookup(&self, tag: &str) -> Option<&LexiconEntry> {
+        self.by_tag
+            .get(&normalize_tag(tag))
+            .map(|&i| &self.all_entries[i])
+    }
+
     /// 搜索标签
-    /// 支持中英文搜索，返回匹配结果（按权重排序）
-    pub fn search(&self, query: &str, limit: usize, offset: usize) -> SearchResult {
+    /// 匹配标签及任意语言的译文，精确匹配优先、前缀匹配次之，最后按权重排序
+    /// `lang` 指定优先显示的译文语言；`category` / `subcategory` 非空时只在该分类内搜索
+    pub fn search(
+        &self,
+        query: &str,
+        limit: usize,
+        offset: usize,
+        lang: Option<&str>,
+        category: Option<&str>,
+        subcategory: Option<&str>,
+    ) -> SearchResult {
         let query_lower = query.to_lowercase();
         let query_normalized = query_lower.replace('_', " ");
 
-        let mut matches: Vec<&LexiconEntry> = self
+        // 已按权重预排序，稳定排序保证同一匹配等级内仍按权重
+        let mut matches: Vec<(MatchRank, Option<&str>, &LexiconEntry)> = self
             .all_entries
             .iter()
             .filter(|entry| {
-                let tag_normalized = entry.tag.to_lowercase().replace('_', " ");
-                tag_normalized.contains(&query_normalized) || entry.zh.contains(&query_lower)
+                category.is_none_or(|c| entry.category == c)
+                    && subcategory.is_none_or(|s| entry.subcategory == s)
+            })
+            .filter_map(|entry| {
+                let tag_rank = MatchRank::of(
+                    &entry.tag.to_lowercase().replace('_', " "),
+                    &query_normalized,
+                )
+                .map(|rank| (rank, None));
+                let lang_rank = entry
+                    .translations
+                    .iter()
+                    .filter_map(|(code, text)| {
+                        MatchRank::of(&text.to_lowercase(), &query_lower)
+                            .map(|rank| (rank, Some(code.as_str())))
+                    })
+                    .min();
+                // 同等级时优先标签本身
+                let (rank, matched) = match (tag_rank, lang_rank) {
+                    (Some(t), Some(l)) if l.0 < t.0 => l,
+                    (Some(t), _) => t,
+                    (None, l) => l?,
+                };
+                Some((rank, matched, entry))
             })
             .collect();
-
-        // 已按权重预排序，但精确匹配应优先
-        matches.sort_by(|a, b| {
-            let a_tag = a.tag.to_lowercase().replace('_', " ");
-            let b_tag = b.tag.to_lowercase().replace('_', " ");
-
-            // 精确匹配优先
-            let a_exact = a_tag == query_normalized || a.zh == query_lower;
-            let b_exact = b_tag == query_normalized || b.zh == query_lower;
-
-            if a_exact != b_exact {
-                return b_exact.cmp(&a_exact);
-            }
-
-            // 前缀匹配次之
-            let a_prefix = a_tag.starts_with(&query_normalized) || a.zh.starts_with(&query_lower);
-            let b_prefix = b_tag.starts_with(&query_normalized) || b.zh.starts_with(&query_lower);
-
-            if a_prefix != b_prefix {
-                return b_prefix.cmp(&a_prefix);
-            }
-
-            // 最后按权重
-            b.weight.unwrap_or(0).cmp(&a.weight.unwrap_or(0))
-        });
+        matches.sort_by_key(|(rank, _, _)| *rank);
 
         let total = matches.len();
-        let entries: Vec<LexiconEntry> = matches
+        let entries = matches
             .into_iter()
             .skip(offset)
             .take(limit)
-            .cloned()
+            .map(|(_, matched, entry)| {
+                let display = lang
+                    .and_then(|l| entry.translation(l))
+                    .or_else(|| matched.and_then(|l| entry.translation(l)))
+                    .or_else(|| entry.translation("zh"))
+                    .map(str::to_string);
+                SearchHit {
+                    entry: entry.clone(),
+                    matched_lang: matched.map(str::to_string),
+                    display,
+                }
+            })
             .collect();
 
         SearchResult { entries, total }
     }
 }
+
+/// 搜索匹配等级，越小越优先
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum MatchRank {
+    Exact,
+    Prefix,
+    Contains,
+}
+
+impl MatchRank {
+    fn of(text: &str, query: &str) -> Option<Self> {
+        if text == query {
+            Some(Self::Exact)
+        } else if text.starts_with(query) {
+            Some(Self::Prefix)
+        } else if text.contains(query) {
+            Some(Self::Contains)
+        } else {
+            None
+        }
+    }
+}
+
+/// 标签规范化：小写，下划线视同空格
+pub(crate) fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase().replace('_', " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_normalizes_tag() {
+        let lexicon = Lexicon::load_embedded().unwrap();
+        let entry = lexicon.get_index().categories[0].name.clone();
+        let first = lexicon
+            .get_category_paged(&entry, None, 0, 1)
+            .unwrap()
+            .items
+            .remove(0);
+        let query = first.tag.to_uppercase().replace(' ', "_");
+        assert_eq!(
+            lexicon.lookup(&query).map(|e| normalize_tag(&e.tag)),
+            Some(normalize_tag(&first.tag))
+        );
+        assert!(lexicon.lookup("__no_such_tag__").is_none());
+    }
+
+    #[test]
+    fn test_embedded_zh_becomes_translation() {
+        let tag: EmbeddedTag = serde_json::from_str(
+            r#"{"tag": "smile", "zh": "微笑", "translations": {"ja": "笑顔"}}"#,
+        )
+        .unwrap();
+        assert_eq!(tag.zh.as_deref(), Some("微笑"));
+        let lexicon = Lexicon::load_embedded().unwrap();
+        assert!(
+            lexicon
+                .all_entries
+                .iter()
+                .any(|e| e.translation("zh").is_some())
+        );
+    }
+
+    #[test]
+    fn test_search_matches_any_translation() {
+        let entry = |tag: &str, translations: &[(&str, &str)], weight| LexiconEntry {
+            category: "c".to_string(),
+            subcategory: if weight > 60 { "s" } else { "t" }.to_string(),
+            tag: tag.to_string(),
+            translations: translations
+                .iter()
+                .map(|(l, t)| (l.to_string(), t.to_string()))
+                .collect(),
+            weight: Some(weight),
+        };
+        let lexicon = Lexicon {
+            categories: HashMap::new(),
+            all_entries: vec![
+                entry("smile", &[("zh", "微笑"), ("ja", "笑顔")], 100),
+                entry("grin", &[("zh", "露齿笑")], 50),
+            ],
+            by_tag: HashMap::new(),
+            index: LexiconIndex {
+                categories: Vec::new(),
+                stats: LexiconStats {
+                    total_tags: 2,
+                    categorized_tags: 2,
+                    uncategorized_tags: 0,
+                    matched_weights: 2,
+                },
+            },
+        };
+
+        let result = lexicon.search("笑顔", 10, 0, None, None, None);
+        assert_eq!(result.total, 1);
+        assert_eq!(result.entries[0].matched_lang.as_deref(), Some("ja"));
+        assert_eq!(result.entries[0].display.as_deref(), Some("笑顔"));
+
+        let result = lexicon.search("smile", 10, 0, Some("ja"), None, None);
+        assert_eq!(result.entries[0].matched_lang, None);
+        assert_eq!(result.entries[0].display.as_deref(), Some("笑顔"));
+
+        // 同一条目取最优的匹配等级：「笑顔」前缀匹配优于「微笑」包含匹配
+        let result = lexicon.search("笑", 10, 0, None, None, None);
+        assert_eq!(result.total, 2);
+        assert_eq!(result.entries[0].entry.tag, "smile");
+        assert_eq!(result.entries[0].matched_lang.as_deref(), Some("ja"));
+        assert_eq!(result.entries[1].matched_lang.as_deref(), Some("zh"));
+
+        // 限定分类：先过滤再排序
+        let result = lexicon.search("笑", 10, 0, None, Some("c"), Some("t"));
+        assert_eq!(result.total, 1);
+        assert_eq!(result.entries[0].entry.tag, "grin");
+        assert_eq!(lexicon.search("笑", 10, 0, None, Some("c"), None).total, 2);
+        assert_eq!(lexicon.search("笑", 10, 0, None, Some("x"), None).total, 0);
+    }
+
+    #[test]
+    fn test_get_category_paged() {
+        let lexicon = Lexicon::load_embedded().unwrap();
+        let info = &lexicon.get_index().categories[0];
+
+        let page = lexicon.get_category_paged(&info.name, None, 0, 5).unwrap();
+        assert_eq!(page.total, info.tag_count);
+        assert!(page.items.len() <= 5);
+
+        let sub = &info.subcategories[0];
+        let sub_page = lexicon
+            .get_category_paged(&info.name, Some(sub), 0, usize::MAX)
+            .unwrap();
+        assert!(sub_page.items.iter().all(|e| &e.subcategory == sub));
+        assert_eq!(sub_page.items.len(), sub_page.total);
+
+        assert!(
+            lexicon
+                .get_category_paged("__missing__", None, 0, 5)
+                .is_none()
+        );
+        assert!(
+            lexicon
+                .get_category_paged(&info.name, Some("__missing__"), 0, 5)
+                .is_none()
+        );
+    }
+}
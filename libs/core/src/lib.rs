@@ -1,34 +1,100 @@
 use std::{
-    collections::HashSet,
     fs,
     path::{Path, PathBuf},
     sync::Arc,
-    time::Duration,
 };
 
-use anyhow::{Context, Result, anyhow};
-use chrono::{Datelike, Local, Timelike, Utc};
-use codex_api::{CharacterPrompt, ImageGenerationRequest, Model, NaiClient, Noise, Sampler};
-use rand::{Rng, rng};
-use redb::{Database, ReadableDatabase, ReadableTable, TableDefinition};
+use chrono::Utc;
+use redb::{Database, ReadableDatabase, TableDefinition, WriteTransaction};
 use serde::{Deserialize, Serialize};
 use tracing::info;
 use uuid::Uuid;
 
+mod error;
+pub use error::{CoreError, CoreResult, ValidationError};
+
 pub mod prompt_parser;
-pub use prompt_parser::{CommentSpan, HighlightSpan, ParseError, ParseResult, PromptParser, Token};
+pub use prompt_parser::{
+    CHUNK_TOKENS, CharacterSplit, ChunkEstimate, ChunkedTag, CommentSpan, Diagnostic,
+    FormatOptions, HighlightSpan, ParseError, ParseResult, PromptParser, Severity, SnippetWeight,
+    TagWeight, Token, TokenExplanation,
+};
 
 pub mod lexicon;
 pub use lexicon::{
     CategoryData, CategoryInfo, Lexicon, LexiconEntry, LexiconIndex, LexiconStats,
-    SearchResult as LexiconSearchResult,
+    SearchHit as LexiconSearchHit, SearchResult as LexiconSearchResult,
 };
 
 pub mod preset;
-pub use preset::{CharacterPreset, MainPreset, MainPresetSettings};
+pub use preset::{
+    CharacterPreset, GlobalAffix, MainPreset, MainPresetSettings, MergeOrder, PresetFieldDiff,
+    PresetMergeError, PresetMergeStrategy, ReplaceConflict,
+};
 
 pub mod archive;
-pub use archive::{ArchiveInfo, ArchiveManager};
+pub use archive::{ArchiveCompression, ArchiveEntry, ArchiveInfo, ArchiveManager, ArchiveOptions};
+
+pub mod imaging;
+pub use imaging::PngCompression;
+
+pub mod tag_usage;
+pub use tag_usage::{COOCCURRENCE_SCAN_LIMIT, MAX_TRACKED_TAGS, TagCount, TagStats, TagUsage};
+
+pub mod template;
+pub use template::PromptTemplate;
+
+pub mod recipe;
+pub use recipe::{
+    ImportConflict, RECIPE_VERSION, Recipe, RecipeImportResult, RecipeTask, RenamedSnippet,
+};
+
+mod db;
+use db::{DbGuard, DbHandle};
+
+mod params;
+pub use params::{GenerationParams, PartialGenerationParams};
+
+mod record;
+pub use record::{
+    DeletedByDate, GalleryImage, GalleryPaths, GalleryTimezone, GenerationRecord, MAX_LABEL_LEN,
+    MissingImages, RepairReport, UsageStats, VerifyReport, is_zero, sanitize_label,
+};
+
+mod snippet;
+pub use snippet::{
+    DanglingRef, NameConflict, RebuildIndexReport, RefLocation, RenameSnippetResult, Snippet,
+    SnippetContentError, SnippetExpandError, SnippetName, SnippetNameError, SnippetResolver,
+    validate_snippet_content, validate_snippet_name,
+};
+
+mod settings;
+pub use settings::{
+    CharacterSlotSettings, GenerationProfile, LastGenerationSettings, MAX_PROFILE_NAME_CHARS,
+    ProfileNameError, ResolvedCharacterSlot, ResolvedGenerationSettings, ResolvedPreset,
+    validate_profile_name,
+};
+
+mod content_hash;
+pub use content_hash::{CONTENT_HASH_GALLERY, CONTENT_HASH_PREVIEWS, gallery_hash_key};
+
+mod blocklist;
+pub use blocklist::{BlockedTag, BlockedTagError, validate_blocked_tag};
+
+mod favorite_seed;
+pub use favorite_seed::{FavoriteSeed, MAX_SEED_LABEL_CHARS, SeedLabelError, validate_seed_label};
+
+mod processor;
+pub use processor::{DryRunResult, ProcessedCharacterPrompt, PromptProcessor};
+
+mod executor;
+pub use executor::{
+    AnlasEstimate, ExecutorConfig, GenerateTaskRequest, MAX_FILTER_RETRIES, RecordHook,
+    TaskExecutor, TaskOutcome, TaskPreview,
+};
+
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
 
 const TABLE_SNIPPETS: TableDefinition<Uuid, String> = TableDefinition::new("snippets");
 const TABLE_SNIPPET_NAME_INDEX: TableDefinition<String, Uuid> =
@@ -36,231 +102,130 @@ const TABLE_SNIPPET_NAME_INDEX: TableDefinition<String, Uuid> =
 const TABLE_PRESETS: TableDefinition<Uuid, String> = TableDefinition::new("character_presets");
 const TABLE_MAIN_PRESETS: TableDefinition<Uuid, String> = TableDefinition::new("main_presets");
 const TABLE_RECORDS: TableDefinition<Uuid, String> = TableDefinition::new("generation_records");
+/// 记录的时间索引，键为 [`record::record_time_key`]，按创建时间排序
+const TABLE_RECORDS_BY_TIME: TableDefinition<&[u8], Uuid> = TableDefinition::new("records_by_time");
 const TABLE_SETTINGS: TableDefinition<&str, String> = TableDefinition::new("settings");
+const TABLE_FAVORITE_SEEDS: TableDefinition<u64, String> = TableDefinition::new("favorite_seeds");
+/// 屏蔽标签，键为规范化后的标签
+const TABLE_BLOCKLIST: TableDefinition<&str, String> = TableDefinition::new("tag_blocklist");
+/// 图片内容哈希缓存，键为 `命名空间/相对路径`
+const TABLE_CONTENT_HASHES: TableDefinition<&str, String> = TableDefinition::new("content_hashes");
+/// 标签使用统计，键为规范化后的标签
+const TABLE_TAG_USAGE: TableDefinition<&str, String> = TableDefinition::new("tag_usage");
+/// 命名的生成设置配置，键为配置名称
+const TABLE_GENERATION_PROFILES: TableDefinition<&str, String> =
+    TableDefinition::new("generation_profiles");
 const SETTINGS_KEY_LAST_GENERATION: &str = "last_generation";
+const SETTINGS_KEY_SCHEMA_VERSION: &str = "schema_version";
+/// 默认主预设的 ID，新会话没有保存的设置时预选它
+const SETTINGS_KEY_DEFAULT_MAIN_PRESET: &str = "default_main_preset";
+/// 本地生成用量计数
+const SETTINGS_KEY_USAGE_STATS: &str = "usage_stats";
 
-pub type CoreResult<T> = Result<T>;
+/// 数据库结构版本；低于此版本的数据库在打开时会重建 snippet 名称索引与记录时间索引
+const SCHEMA_VERSION: u32 = 2;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Page<T> {
     pub items: Vec<T>,
     pub total: usize,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Snippet {
-    pub id: Uuid,
-    pub name: String,
-    pub category: String,
-    pub tags: Vec<String>,
-    pub description: Option<String>,
-    /// 预览图文件名（存储在 preview_dir 中）
-    pub preview_path: Option<String>,
-    pub content: String,
-    pub created_at: chrono::DateTime<Utc>,
-    pub updated_at: chrono::DateTime<Utc>,
-}
-
-impl Snippet {
-    pub fn new(name: String, category: String, content: String) -> CoreResult<Self> {
-        validate_snippet_name(&name)?;
-        let now = Utc::now();
-        Ok(Self {
-            id: Uuid::new_v4(),
-            name,
-            category,
-            tags: Vec::new(),
-            description: None,
-            preview_path: None,
-            content,
-            created_at: now,
-            updated_at: now,
-        })
-    }
-}
-
-/// Snippet 重命名结果，包含更新统计
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct RenameSnippetResult {
-    pub snippet: Snippet,
-    pub updated_presets: usize,
-    pub updated_settings: bool,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GalleryImage {
-    pub path: PathBuf,
-    pub seed: u64,
-    pub width: u32,
-    pub height: u32,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GenerationRecord {
-    pub id: Uuid,
-    pub task_id: Uuid,
-    pub created_at: chrono::DateTime<Utc>,
-    /// Raw prompt before snippet injection.
-    pub raw_prompt: String,
-    /// Prompt after preset + snippet expansion (for debug only).
-    pub expanded_prompt: String,
-    pub negative_prompt: String,
-    pub images: Vec<GalleryImage>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(default)]
-pub struct GenerationParams {
-    pub model: Model,
-    pub width: u32,
-    pub height: u32,
-    pub steps: u32,
-    pub scale: f32,
-    pub sampler: Sampler,
-    pub noise: Noise,
-    pub cfg_rescale: f32,
-    pub undesired_content_preset: Option<u8>,
-    pub add_quality_tags: bool,
-    pub character_prompts: Option<Vec<CharacterPrompt>>,
-    /// Fixed seed for reproducibility. None or negative means random.
-    pub seed: Option<i64>,
-    /// Variety+ mode for dynamic variation
-    pub variety_plus: bool,
-}
-
-impl Default for GenerationParams {
-    fn default() -> Self {
-        Self {
-            model: Model::default(),
-            width: 1024,
-            height: 1024,
-            steps: 28,
-            scale: 5.0,
-            sampler: Sampler::default(),
-            noise: Noise::default(),
-            cfg_rescale: 0.0,
-            undesired_content_preset: None,
-            add_quality_tags: true,
-            character_prompts: None,
-            seed: None,
-            variety_plus: false,
-        }
-    }
-}
-
-/// 角色槽设置，用于保存角色提示词
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct CharacterSlotSettings {
-    pub prompt: String,
-    pub uc: String,
-    pub enabled: bool,
-    pub preset_id: Option<Uuid>,
-}
-
-/// 保存上次生成页面的设置，用于下次打开时恢复
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct LastGenerationSettings {
-    pub prompt: String,
-    pub negative_prompt: String,
-    pub count: u32,
-    pub params: GenerationParams,
+    /// 因数据损坏无法反序列化而被跳过的行数
     #[serde(default)]
-    pub character_slots: Vec<CharacterSlotSettings>,
-    /// 主提示词预设ID（替代之前的内联设置）
+    pub skipped: usize,
+    /// 当前页之后是否还有数据
     #[serde(default)]
-    pub main_preset_id: Option<Uuid>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GenerateTaskRequest {
-    pub id: Uuid,
-    pub raw_prompt: String,
-    pub negative_prompt: String,
-    /// How many images to generate sequentially.
-    pub count: u32,
-    pub params: GenerationParams,
-    /// 角色预设（应用于角色槽）
-    pub preset: Option<CharacterPreset>,
-    /// 主提示词预设设置
+    pub has_more: bool,
+    /// 下一页的 offset，没有更多数据时为 None
     #[serde(default)]
-    pub main_preset: MainPresetSettings,
+    pub next_offset: Option<usize>,
 }
 
-impl GenerateTaskRequest {
-    pub fn new(raw_prompt: String, negative_prompt: String) -> Self {
+impl<T> Page<T> {
+    /// 以从 `offset` 开始的一页数据构造分页结果
+    pub fn new(items: Vec<T>, total: usize, offset: usize) -> Self {
+        let end = offset.saturating_add(items.len());
+        let has_more = end < total;
         Self {
-            id: Uuid::new_v4(),
-            raw_prompt,
-            negative_prompt,
-            count: 1,
-            params: GenerationParams::default(),
-            preset: None,
-            main_preset: MainPresetSettings::default(),
+            items,
+            total,
+            skipped: 0,
+            has_more,
+            next_offset: has_more.then_some(end),
         }
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct GalleryPaths {
-    pub root: PathBuf,
-}
-
-impl GalleryPaths {
-    pub fn new(root: impl AsRef<Path>) -> Self {
-        Self {
-            root: root.as_ref().to_path_buf(),
+/// 反序列化列表中的一行；损坏的行记录警告后返回 `None`，由调用方跳过
+fn decode_row<T: serde::de::DeserializeOwned>(
+    table: &str,
+    key: impl std::fmt::Display,
+    raw: &str,
+) -> Option<T> {
+    match serde_json::from_str(raw) {
+        Ok(value) => Some(value),
+        Err(err) => {
+            tracing::warn!(table, key = %key, error = %err, "skipping corrupt row");
+            None
         }
     }
-
-    /// Build path as YYYY-MM-DD/{time_index}_{index}_{seed}.png
-    /// time_index format: HHMMSSmmm (hour, minute, second, millisecond)
-    /// This ensures filename sorting equals time sorting
-    pub fn image_path(&self, index: u32, seed: u64) -> PathBuf {
-        let now = Local::now();
-        let date_dir = format!("{:04}-{:02}-{:02}", now.year(), now.month(), now.day());
-        // Time index: HHMMSSmmm format for sorting
-        let time_index = format!(
-            "{:02}{:02}{:02}{:03}",
-            now.hour(),
-            now.minute(),
-            now.second(),
-            now.timestamp_subsec_millis()
-        );
-        self.root
-            .join(date_dir)
-            .join(format!("{}_{}_{}.png", time_index, index, seed))
-    }
 }
 
+/// 开启写事务遇到暂时性错误（如之前的 I/O 失败使数据库需要重新打开）时的默认重试次数
+pub const DEFAULT_WRITE_RETRIES: u32 = 3;
+
+/// 默认 snippet 内容大小上限（字节）
+pub const DEFAULT_MAX_SNIPPET_CONTENT_BYTES: usize = 16 * 1024;
+
 #[derive(Debug, Clone)]
 pub struct CoreStorage {
-    db: Arc<Database>,
+    db: Arc<DbHandle>,
+    db_path: PathBuf,
     preview_dir: PathBuf,
+    write_retries: u32,
+    max_snippet_content_bytes: usize,
+    preview_max_dimension: u32,
+    png_compression: PngCompression,
 }
 
 impl CoreStorage {
     pub fn open(db_path: impl AsRef<Path>, preview_dir: impl AsRef<Path>) -> CoreResult<Self> {
+        let path = db_path.as_ref().to_path_buf();
+        Self::open_with(db_path, preview_dir, move || Database::create(&path))
+    }
+
+    /// 用 `open` 打开数据库；I/O 失败后重新打开时也调用它
+    pub(crate) fn open_with(
+        db_path: impl AsRef<Path>,
+        preview_dir: impl AsRef<Path>,
+        open: impl Fn() -> Result<Database, redb::DatabaseError> + Send + Sync + 'static,
+    ) -> CoreResult<Self> {
         let db_path = db_path.as_ref();
         if let Some(parent) = db_path.parent() {
-            fs::create_dir_all(parent).context("create db parent dir")?;
+            fs::create_dir_all(parent)?;
         }
         let preview_dir = preview_dir.as_ref().to_path_buf();
-        fs::create_dir_all(&preview_dir).context("create preview dir")?;
+        fs::create_dir_all(&preview_dir)?;
         // 创建子目录
-        fs::create_dir_all(preview_dir.join("snippets")).context("create snippets preview dir")?;
-        fs::create_dir_all(preview_dir.join("presets")).context("create presets preview dir")?;
-        let db = Database::create(db_path).context("open redb database")?;
+        fs::create_dir_all(preview_dir.join("snippets"))?;
+        fs::create_dir_all(preview_dir.join("presets"))?;
+        let db = DbHandle::new(open)?;
 
         // Ensure all tables exist so read transactions never fail on first use
         {
-            let write_txn = db.begin_write()?;
+            let write_txn = db.begin_write(DEFAULT_WRITE_RETRIES)?;
             {
                 write_txn.open_table(TABLE_SNIPPETS)?;
                 write_txn.open_table(TABLE_SNIPPET_NAME_INDEX)?;
                 write_txn.open_table(TABLE_PRESETS)?;
                 write_txn.open_table(TABLE_MAIN_PRESETS)?;
                 write_txn.open_table(TABLE_RECORDS)?;
+                write_txn.open_table(TABLE_RECORDS_BY_TIME)?;
                 write_txn.open_table(TABLE_SETTINGS)?;
+                write_txn.open_table(TABLE_FAVORITE_SEEDS)?;
+                write_txn.open_table(TABLE_CONTENT_HASHES)?;
+                write_txn.open_table(TABLE_BLOCKLIST)?;
+                write_txn.open_table(TABLE_TAG_USAGE)?;
+                write_txn.open_table(TABLE_GENERATION_PROFILES)?;
             }
             write_txn.commit()?;
         }
@@ -268,1165 +233,377 @@ impl CoreStorage {
         let str_db_path = db_path.to_str().unwrap_or("unknown");
         let str_preview_dir = preview_dir.to_str().unwrap_or("unknown");
         info!(?str_db_path, ?str_preview_dir, "core storage opened");
-        Ok(Self {
+        let storage = Self {
             db: Arc::new(db),
+            db_path: db_path.to_path_buf(),
             preview_dir,
-        })
-    }
-
-    /// 生成带时间戳的预览图文件名，解决浏览器缓存问题
-    fn generate_preview_filename(id: Uuid, subdir: &str) -> String {
-        let ts = Utc::now().timestamp_millis();
-        format!("{}/{}_{}.png", subdir, id, ts)
-    }
-
-    /// 删除旧的预览图文件（如果存在）
-    fn remove_old_preview(&self, old_path: Option<&str>) {
-        if let Some(path) = old_path {
-            let full_path = self.preview_dir.join(path);
-            let _ = fs::remove_file(full_path);
-        }
-    }
-
-    pub fn upsert_snippet(
-        &self,
-        mut snippet: Snippet,
-        preview_bytes: Option<&[u8]>,
-    ) -> CoreResult<Snippet> {
-        validate_snippet_name(&snippet.name)?;
-        snippet.updated_at = Utc::now();
-
-        // 获取旧的信息以便更新索引和清理旧预览图
-        let old_data = {
-            let read_txn = self.db.begin_read()?;
-            let table = read_txn.open_table(TABLE_SNIPPETS)?;
-            if let Some(value) = table.get(snippet.id)? {
-                let old: Snippet = serde_json::from_str(&value.value())?;
-                Some((old.name, old.preview_path))
-            } else {
-                None
-            }
+            write_retries: DEFAULT_WRITE_RETRIES,
+            max_snippet_content_bytes: DEFAULT_MAX_SNIPPET_CONTENT_BYTES,
+            preview_max_dimension: imaging::PREVIEW_MAX_DIMENSION,
+            png_compression: PngCompression::default(),
         };
-
-        // 处理预览图
-        if let Some(bytes) = preview_bytes {
-            // 删除旧的预览图
-            if let Some((_, ref old_preview)) = old_data {
-                self.remove_old_preview(old_preview.as_deref());
-            }
-            // 保存新的预览图（带时间戳）
-            let preview_filename = Self::generate_preview_filename(snippet.id, "snippets");
-            let preview_path = self.preview_dir.join(&preview_filename);
-            fs::write(&preview_path, bytes).context("write snippet preview")?;
-            snippet.preview_path = Some(preview_filename);
-        }
-
-        let serialized = serde_json::to_string(&snippet)?;
-        let write_txn = self.db.begin_write()?;
-        {
-            let mut table = write_txn.open_table(TABLE_SNIPPETS)?;
-            table.insert(snippet.id, serialized)?;
-
-            let mut index = write_txn.open_table(TABLE_SNIPPET_NAME_INDEX)?;
-
-            // 检查新名称是否已被其他 snippet 使用
-            if let Some(existing) = index.get(snippet.name.clone())? {
-                let existing_id = existing.value();
-                if existing_id != snippet.id {
-                    return Err(anyhow!("snippet name already exists"));
-                }
-            }
-
-            // 如果是重命名，删除旧的索引条目
-            if let Some((ref old_name, _)) = old_data {
-                if old_name != &snippet.name {
-                    index.remove(old_name.clone())?;
-                }
-            }
-
-            index.insert(snippet.name.clone(), snippet.id)?;
-        }
-        write_txn.commit()?;
-        info!(id=%snippet.id, name=%snippet.name, "snippet upserted");
-        Ok(snippet)
-    }
-
-    /// 重命名 snippet，并更新所有引用该 snippet 的 preset 和 LastGenerationSettings
-    pub fn rename_snippet(&self, id: Uuid, new_name: String) -> CoreResult<RenameSnippetResult> {
-        validate_snippet_name(&new_name)?;
-
-        let mut snippet = self
-            .get_snippet(id)?
-            .ok_or_else(|| anyhow!("snippet not found"))?;
-
-        let old_name = snippet.name.clone();
-
-        // 如果名称没变，直接返回
-        if old_name == new_name {
-            return Ok(RenameSnippetResult {
-                snippet,
-                updated_presets: 0,
-                updated_settings: false,
-            });
-        }
-
-        snippet.name = new_name.clone();
-        snippet.updated_at = Utc::now();
-
-        let serialized = serde_json::to_string(&snippet)?;
-        let write_txn = self.db.begin_write()?;
-        {
-            let mut table = write_txn.open_table(TABLE_SNIPPETS)?;
-            let mut index = write_txn.open_table(TABLE_SNIPPET_NAME_INDEX)?;
-
-            // 检查新名称是否已被使用
-            if let Some(existing) = index.get(new_name.clone())? {
-                let existing_id = existing.value();
-                if existing_id != snippet.id {
-                    return Err(anyhow!("snippet name already exists"));
-                }
-            }
-
-            // 更新数据和索引
-            table.insert(snippet.id, serialized)?;
-            index.remove(old_name.clone())?;
-            index.insert(new_name.clone(), snippet.id)?;
+        storage.migrate()?;
+        Ok(storage)
+    }
+
+    /// 按存储的结构版本执行升级，完成后写入当前版本
+    fn migrate(&self) -> CoreResult<()> {
+        let stored = {
+            let read_txn = self.db()?.begin_read()?;
+            let table = read_txn.open_table(TABLE_SETTINGS)?;
+            table
+                .get(SETTINGS_KEY_SCHEMA_VERSION)?
+                .and_then(|v| v.value().parse::<u32>().ok())
+                .unwrap_or(0)
+        };
+        if stored >= SCHEMA_VERSION {
+            return Ok(());
         }
-        write_txn.commit()?;
-        info!(id=%snippet.id, old_name=%old_name, new_name=%new_name, "snippet renamed");
-
-        // 更新所有引用该 snippet 的 preset 和 settings
-        let (updated_presets, updated_settings) =
-            self.update_snippet_references(&old_name, &new_name)?;
 
+        let report = self.rebuild_name_index()?;
+        let records = self.rebuild_record_time_index()?;
         info!(
-            old_name=%old_name,
-            new_name=%new_name,
-            updated_presets=%updated_presets,
-            updated_settings=%updated_settings,
-            "snippet references updated"
+            from = stored,
+            to = SCHEMA_VERSION,
+            indexed = report.indexed,
+            records,
+            "database schema upgraded"
         );
 
-        Ok(RenameSnippetResult {
-            snippet,
-            updated_presets,
-            updated_settings,
-        })
-    }
-
-    /// 更新所有引用旧 snippet 名称的地方
-    fn update_snippet_references(
-        &self,
-        old_name: &str,
-        new_name: &str,
-    ) -> CoreResult<(usize, bool)> {
-        let old_tag = format!("<snippet:{}>", old_name);
-        let new_tag = format!("<snippet:{}>", new_name);
-
-        // 更新所有 presets
-        let mut updated_presets = 0;
-        let presets = {
-            let read_txn = self.db.begin_read()?;
-            let table = read_txn.open_table(TABLE_PRESETS)?;
-            let mut list = Vec::new();
-            for entry in table.iter()? {
-                let (_, value) = entry?;
-                let preset: CharacterPreset = serde_json::from_str(&value.value())?;
-                list.push(preset);
-            }
-            list
-        };
-
-        for mut preset in presets {
-            let mut changed = false;
-
-            if let Some(ref mut before) = preset.before {
-                if before.contains(&old_tag) {
-                    *before = before.replace(&old_tag, &new_tag);
-                    changed = true;
-                }
-            }
-            if let Some(ref mut after) = preset.after {
-                if after.contains(&old_tag) {
-                    *after = after.replace(&old_tag, &new_tag);
-                    changed = true;
-                }
-            }
-            if let Some(ref mut replace) = preset.replace {
-                if replace.contains(&old_tag) {
-                    *replace = replace.replace(&old_tag, &new_tag);
-                    changed = true;
-                }
-            }
-            if let Some(ref mut uc_before) = preset.uc_before {
-                if uc_before.contains(&old_tag) {
-                    *uc_before = uc_before.replace(&old_tag, &new_tag);
-                    changed = true;
-                }
-            }
-            if let Some(ref mut uc_after) = preset.uc_after {
-                if uc_after.contains(&old_tag) {
-                    *uc_after = uc_after.replace(&old_tag, &new_tag);
-                    changed = true;
-                }
-            }
-            if let Some(ref mut uc_replace) = preset.uc_replace {
-                if uc_replace.contains(&old_tag) {
-                    *uc_replace = uc_replace.replace(&old_tag, &new_tag);
-                    changed = true;
-                }
-            }
-
-            if changed {
-                preset.updated_at = Utc::now();
-                self.upsert_preset(preset)?;
-                updated_presets += 1;
-            }
-        }
-
-        // 更新 LastGenerationSettings
-        let mut updated_settings = false;
-        if let Some(mut settings) = self.load_last_generation_settings()? {
-            let mut changed = false;
-
-            if settings.prompt.contains(&old_tag) {
-                settings.prompt = settings.prompt.replace(&old_tag, &new_tag);
-                changed = true;
-            }
-            if settings.negative_prompt.contains(&old_tag) {
-                settings.negative_prompt = settings.negative_prompt.replace(&old_tag, &new_tag);
-                changed = true;
-            }
-
-            for slot in &mut settings.character_slots {
-                if slot.prompt.contains(&old_tag) {
-                    slot.prompt = slot.prompt.replace(&old_tag, &new_tag);
-                    changed = true;
-                }
-                if slot.uc.contains(&old_tag) {
-                    slot.uc = slot.uc.replace(&old_tag, &new_tag);
-                    changed = true;
-                }
-            }
-
-            if changed {
-                self.save_last_generation_settings(&settings)?;
-                updated_settings = true;
-            }
-        }
-
-        Ok((updated_presets, updated_settings))
-    }
-
-    pub fn get_snippet_by_name(&self, name: &str) -> CoreResult<Option<Snippet>> {
-        let read_txn = self.db.begin_read()?;
-        let index = read_txn.open_table(TABLE_SNIPPET_NAME_INDEX)?;
-        if let Some(id) = index.get(name.to_string())? {
-            let id = id.value();
-            let table = read_txn.open_table(TABLE_SNIPPETS)?;
-            if let Some(value) = table.get(id)? {
-                let snippet: Snippet = serde_json::from_str(&value.value())?;
-                return Ok(Some(snippet));
-            }
-        }
-        Ok(None)
-    }
-
-    pub fn upsert_preset(&self, preset: CharacterPreset) -> CoreResult<CharacterPreset> {
-        let serialized = serde_json::to_string(&preset)?;
-        let write_txn = self.db.begin_write()?;
+        let write_txn = self.begin_write_with_retry()?;
         {
-            let mut table = write_txn.open_table(TABLE_PRESETS)?;
-            table.insert(preset.id, serialized)?;
+            let mut table = write_txn.open_table(TABLE_SETTINGS)?;
+            table.insert(SETTINGS_KEY_SCHEMA_VERSION, SCHEMA_VERSION.to_string())?;
         }
         write_txn.commit()?;
-        info!(id=%preset.id, name=%preset.name, "preset upserted");
-        Ok(preset)
+        Ok(())
     }
 
-    /// 创建或更新 preset 并可选保存预览图
-    pub fn upsert_preset_with_preview(
-        &self,
-        mut preset: CharacterPreset,
-        preview_bytes: Option<&[u8]>,
-    ) -> CoreResult<CharacterPreset> {
-        // 处理预览图
-        if let Some(bytes) = preview_bytes {
-            // 获取旧的预览图路径以便删除
-            if let Some(old_preset) = self.get_preset(preset.id)? {
-                self.remove_old_preview(old_preset.preview_path.as_deref());
-            }
-            // 保存新的预览图（带时间戳）
-            let preview_filename = Self::generate_preview_filename(preset.id, "presets");
-            let preview_path = self.preview_dir.join(&preview_filename);
-            fs::write(&preview_path, bytes).context("write preset preview")?;
-            preset.preview_path = Some(preview_filename);
-        }
-
-        let serialized = serde_json::to_string(&preset)?;
-        let write_txn = self.db.begin_write()?;
-        {
-            let mut table = write_txn.open_table(TABLE_PRESETS)?;
-            table.insert(preset.id, serialized)?;
-        }
-        write_txn.commit()?;
-        info!(id=%preset.id, name=%preset.name, "preset upserted");
-        Ok(preset)
+    /// 设置开启写事务时的最大重试次数
+    pub fn with_write_retries(mut self, retries: u32) -> Self {
+        self.write_retries = retries;
+        self
     }
 
-    /// 重命名 preset
-    pub fn rename_preset(&self, id: Uuid, new_name: String) -> CoreResult<CharacterPreset> {
-        let mut preset = self
-            .get_preset(id)?
-            .ok_or_else(|| anyhow!("preset not found"))?;
-
-        let old_name = preset.name.clone();
-        preset.name = new_name.clone();
-        preset.updated_at = Utc::now();
-
-        let serialized = serde_json::to_string(&preset)?;
-        let write_txn = self.db.begin_write()?;
-        {
-            let mut table = write_txn.open_table(TABLE_PRESETS)?;
-            table.insert(preset.id, serialized)?;
-        }
-        write_txn.commit()?;
-        info!(id=%preset.id, old_name=%old_name, new_name=%new_name, "preset renamed");
-        Ok(preset)
+    /// 设置 snippet 内容大小上限（字节）
+    pub fn with_max_snippet_content(mut self, bytes: usize) -> Self {
+        self.max_snippet_content_bytes = bytes;
+        self
     }
 
-    pub fn get_preset(&self, id: Uuid) -> CoreResult<Option<CharacterPreset>> {
-        let read_txn = self.db.begin_read()?;
-        let table = read_txn.open_table(TABLE_PRESETS)?;
-        if let Some(value) = table.get(id)? {
-            let preset: CharacterPreset = serde_json::from_str(&value.value())?;
-            return Ok(Some(preset));
-        }
-        Ok(None)
+    /// 设置上传预览图的最长边，超出时等比缩小；0 表示不缩放
+    pub fn with_preview_max_dimension(mut self, max_dimension: u32) -> Self {
+        self.preview_max_dimension = max_dimension;
+        self
     }
 
-    pub fn delete_preset(&self, id: Uuid) -> CoreResult<bool> {
-        // First read the preset to get its preview path
-        let preview_path = {
-            let read_txn = self.db.begin_read()?;
-            let table = read_txn.open_table(TABLE_PRESETS)?;
-            if let Some(value) = table.get(id)? {
-                let preset: CharacterPreset = serde_json::from_str(&value.value())?;
-                preset.preview_path
-            } else {
-                return Ok(false);
-            }
-        };
-
-        let write_txn = self.db.begin_write()?;
-        let removed = {
-            let mut table = write_txn.open_table(TABLE_PRESETS)?;
-            table.remove(id)?.is_some()
-        };
-        write_txn.commit()?;
-
-        // Remove preview file if exists
-        if let Some(path) = preview_path {
-            let full_path = self.preview_dir.join(path);
-            let _ = fs::remove_file(full_path);
-        }
-
-        if removed {
-            info!(id=%id, "preset deleted");
-        }
-        Ok(removed)
+    /// 设置缩小预览图 / 缩略图后重新编码使用的 PNG 压缩级别
+    pub fn with_png_compression(mut self, compression: PngCompression) -> Self {
+        self.png_compression = compression;
+        self
     }
 
-    /// 更新 preset 的预览图
-    pub fn update_preset_preview(
-        &self,
-        id: Uuid,
-        preview_bytes: &[u8],
-    ) -> CoreResult<CharacterPreset> {
-        let mut preset = self
-            .get_preset(id)?
-            .ok_or_else(|| anyhow!("preset not found"))?;
-
-        // 删除旧的预览图
-        self.remove_old_preview(preset.preview_path.as_deref());
-
-        // 保存新的预览图（带时间戳）
-        let preview_filename = Self::generate_preview_filename(preset.id, "presets");
-        let preview_path = self.preview_dir.join(&preview_filename);
-        fs::write(&preview_path, preview_bytes).context("write preset preview")?;
-        preset.preview_path = Some(preview_filename);
-        preset.updated_at = Utc::now();
-
-        let serialized = serde_json::to_string(&preset)?;
-        let write_txn = self.db.begin_write()?;
-        {
-            let mut table = write_txn.open_table(TABLE_PRESETS)?;
-            table.insert(preset.id, serialized)?;
-        }
-        write_txn.commit()?;
-        info!(id=%preset.id, "preset preview updated");
-        Ok(preset)
+    /// 缩小预览图 / 缩略图后重新编码使用的 PNG 压缩级别
+    pub fn png_compression(&self) -> PngCompression {
+        self.png_compression
     }
 
-    /// 删除 preset 的预览图
-    pub fn delete_preset_preview(&self, id: Uuid) -> CoreResult<CharacterPreset> {
-        let mut preset = self
-            .get_preset(id)?
-            .ok_or_else(|| anyhow!("preset not found"))?;
-
-        if let Some(path) = &preset.preview_path {
-            let full_path = self.preview_dir.join(path);
-            let _ = fs::remove_file(full_path);
-        }
-        preset.preview_path = None;
-        preset.updated_at = Utc::now();
-
-        let serialized = serde_json::to_string(&preset)?;
-        let write_txn = self.db.begin_write()?;
-        {
-            let mut table = write_txn.open_table(TABLE_PRESETS)?;
-            table.insert(preset.id, serialized)?;
-        }
-        write_txn.commit()?;
-        info!(id=%preset.id, "preset preview deleted");
-        Ok(preset)
+    fn db(&self) -> CoreResult<DbGuard<'_>> {
+        self.db.get()
     }
 
-    pub fn get_snippet(&self, id: Uuid) -> CoreResult<Option<Snippet>> {
-        let read_txn = self.db.begin_read()?;
-        let table = read_txn.open_table(TABLE_SNIPPETS)?;
-        if let Some(value) = table.get(id)? {
-            let snippet: Snippet = serde_json::from_str(&value.value())?;
-            return Ok(Some(snippet));
-        }
-        Ok(None)
+    /// 压缩数据库文件，回收删除数据后留下的空闲页，返回减少的字节数
+    ///
+    /// 等待所有事务开启完成后独占数据库；仍有读事务存活时返回错误。
+    pub fn compact(&self) -> CoreResult<u64> {
+        let before = fs::metadata(&self.db_path)?.len();
+        self.db.compact()?;
+        let after = fs::metadata(&self.db_path)?.len();
+        info!(before, after, "database compacted");
+        Ok(before.saturating_sub(after))
     }
 
-    pub fn delete_snippet(&self, id: Uuid) -> CoreResult<bool> {
-        // First read the snippet to get its name and preview path
-        let snippet_data = {
-            let read_txn = self.db.begin_read()?;
-            let table = read_txn.open_table(TABLE_SNIPPETS)?;
-            if let Some(value) = table.get(id)? {
-                let snippet: Snippet = serde_json::from_str(&value.value())?;
-                Some((snippet.name, snippet.preview_path))
-            } else {
-                None
-            }
-        };
-
-        let Some((name, preview_path)) = snippet_data else {
-            return Ok(false);
-        };
-
-        // Now delete from tables
-        let write_txn = self.db.begin_write()?;
-        {
-            let mut table = write_txn.open_table(TABLE_SNIPPETS)?;
-            table.remove(id)?;
-            let mut index = write_txn.open_table(TABLE_SNIPPET_NAME_INDEX)?;
-            index.remove(name)?;
-        }
-        write_txn.commit()?;
-
-        // Remove preview file if exists
-        if let Some(path) = preview_path {
-            let full_path = self.preview_dir.join(path);
-            let _ = fs::remove_file(full_path);
-        }
-
-        info!(id=%id, "snippet deleted");
-        Ok(true)
+    /// 开启写事务；遇到暂时性错误时重新打开数据库并带随机抖动重试
+    fn begin_write_with_retry(&self) -> CoreResult<WriteTransaction> {
+        self.db.begin_write(self.write_retries)
     }
 
-    /// 更新 snippet 的预览图
-    pub fn update_snippet_preview(&self, id: Uuid, preview_bytes: &[u8]) -> CoreResult<Snippet> {
-        let mut snippet = self
-            .get_snippet(id)?
-            .ok_or_else(|| anyhow!("snippet not found"))?;
-
-        // 删除旧的预览图
-        self.remove_old_preview(snippet.preview_path.as_deref());
+    /// 生成带时间戳的预览图文件名，解决浏览器缓存问题
+    fn generate_preview_filename(id: Uuid, subdir: &str) -> String {
+        let ts = Utc::now().timestamp_millis();
+        format!("{}/{}_{}.png", subdir, id, ts)
+    }
 
-        // 保存新的预览图（带时间戳）
-        let preview_filename = Self::generate_preview_filename(snippet.id, "snippets");
+    /// 写入预览图文件（带时间戳），返回相对 `preview_dir` 的路径
+    ///
+    /// 可解码的图片（PNG / JPEG / WebP）超出 `preview_max_dimension` 时等比缩小并编码为 PNG；
+    /// 其他格式原样保存。
+    fn write_preview(&self, id: Uuid, subdir: &str, bytes: &[u8]) -> CoreResult<String> {
+        let preview_filename = Self::generate_preview_filename(id, subdir);
         let preview_path = self.preview_dir.join(&preview_filename);
-        fs::write(&preview_path, preview_bytes).context("write snippet preview")?;
-        snippet.preview_path = Some(preview_filename);
-        snippet.updated_at = Utc::now();
-
-        let serialized = serde_json::to_string(&snippet)?;
-        let write_txn = self.db.begin_write()?;
-        {
-            let mut table = write_txn.open_table(TABLE_SNIPPETS)?;
-            table.insert(snippet.id, serialized)?;
+        if imaging::can_decode(bytes) {
+            let scaled = imaging::downscale_image_with(
+                bytes.to_vec(),
+                self.preview_max_dimension,
+                self.png_compression,
+            )?;
+            fs::write(&preview_path, scaled.bytes)?;
+        } else {
+            fs::write(&preview_path, bytes)?;
         }
-        write_txn.commit()?;
-        info!(id=%snippet.id, "snippet preview updated");
-        Ok(snippet)
+        Ok(preview_filename)
     }
 
-    /// 删除 snippet 的预览图
-    pub fn delete_snippet_preview(&self, id: Uuid) -> CoreResult<Snippet> {
-        let mut snippet = self
-            .get_snippet(id)?
-            .ok_or_else(|| anyhow!("snippet not found"))?;
-
-        if let Some(path) = &snippet.preview_path {
+    /// 删除旧的预览图文件（如果存在）
+    fn remove_old_preview(&self, old_path: Option<&str>) {
+        if let Some(path) = old_path {
             let full_path = self.preview_dir.join(path);
             let _ = fs::remove_file(full_path);
         }
-        snippet.preview_path = None;
-        snippet.updated_at = Utc::now();
-
-        let serialized = serde_json::to_string(&snippet)?;
-        let write_txn = self.db.begin_write()?;
-        {
-            let mut table = write_txn.open_table(TABLE_SNIPPETS)?;
-            table.insert(snippet.id, serialized)?;
-        }
-        write_txn.commit()?;
-        info!(id=%snippet.id, "snippet preview deleted");
-        Ok(snippet)
     }
 
     /// 获取 preview 目录路径
     pub fn preview_dir(&self) -> &PathBuf {
         &self.preview_dir
     }
-
-    pub fn append_record(&self, record: &GenerationRecord) -> CoreResult<()> {
-        let serialized = serde_json::to_string(record)?;
-        let write_txn = self.db.begin_write()?;
-        {
-            let mut table = write_txn.open_table(TABLE_RECORDS)?;
-            table.insert(record.id, serialized)?;
-        }
-        write_txn.commit()?;
-        info!(id=%record.id, task_id=%record.task_id, images=%record.images.len(), "record appended");
-        Ok(())
-    }
-
-    /// 获取单条记录
-    pub fn get_record(&self, id: Uuid) -> CoreResult<Option<GenerationRecord>> {
-        let read_txn = self.db.begin_read()?;
-        let table = read_txn.open_table(TABLE_RECORDS)?;
-        if let Some(value) = table.get(id)? {
-            let record: GenerationRecord = serde_json::from_str(&value.value())?;
-            return Ok(Some(record));
-        }
-        Ok(None)
-    }
-
-    /// 删除记录（同时删除关联的图片文件）
-    pub fn delete_record(&self, id: Uuid) -> CoreResult<Option<GenerationRecord>> {
-        // 先获取记录以便后续删除文件
-        let record = self.get_record(id)?;
-        if record.is_none() {
-            return Ok(None);
-        }
-        let record = record.unwrap();
-
-        // 删除关联的图片文件
-        for img in &record.images {
-            if img.path.exists() {
-                if let Err(e) = fs::remove_file(&img.path) {
-                    info!(path=?img.path, error=%e, "failed to delete gallery image file");
-                } else {
-                    info!(path=?img.path, "deleted gallery image file");
-                }
-            }
-        }
-
-        // 从数据库删除记录
-        let write_txn = self.db.begin_write()?;
-        {
-            let mut table = write_txn.open_table(TABLE_RECORDS)?;
-            table.remove(id)?;
-        }
-        write_txn.commit()?;
-        info!(id=%id, images=%record.images.len(), "record deleted");
-        Ok(Some(record))
-    }
-
-    /// 删除记录（仅删除数据库记录，不删除图片文件）
-    /// 用于归档场景，图片文件已被压缩到归档中
-    pub fn delete_record_without_files(&self, id: Uuid) -> CoreResult<bool> {
-        let write_txn = self.db.begin_write()?;
-        let removed = {
-            let mut table = write_txn.open_table(TABLE_RECORDS)?;
-            table.remove(id)?.is_some()
-        };
-        write_txn.commit()?;
-        if removed {
-            info!(id=%id, "record deleted (files preserved for archive)");
-        }
-        Ok(removed)
-    }
-
-    /// 批量删除记录
-    pub fn delete_records(&self, ids: &[Uuid]) -> CoreResult<usize> {
-        let mut deleted = 0;
-        for id in ids {
-            if self.delete_record(*id)?.is_some() {
-                deleted += 1;
-            }
-        }
-        Ok(deleted)
-    }
-
-    pub fn list_snippets(
-        &self,
-        query: Option<&str>,
-        category: Option<&str>,
-        offset: usize,
-        limit: usize,
-    ) -> CoreResult<Page<Snippet>> {
-        let read_txn = self.db.begin_read()?;
-        let table = read_txn.open_table(TABLE_SNIPPETS)?;
-        let mut out = Vec::new();
-        for entry in table.iter()? {
-            let (_, value) = entry?;
-            let snippet: Snippet = serde_json::from_str(&value.value())?;
-            if let Some(cat) = category {
-                if snippet.category != cat {
-                    continue;
-                }
-            }
-            if let Some(q) = query {
-                let ql = q.to_lowercase();
-                let hay = format!(
-                    "{} {} {:?}",
-                    snippet.name,
-                    snippet.description.clone().unwrap_or_default(),
-                    snippet.tags.join(" ")
-                )
-                .to_lowercase();
-                if !hay.contains(&ql) {
-                    continue;
-                }
-            }
-            out.push(snippet);
-        }
-        let total = out.len();
-        let items = out.into_iter().skip(offset).take(limit).collect();
-        Ok(Page { items, total })
-    }
-
-    pub fn list_recent_records(&self, limit: usize) -> CoreResult<Vec<GenerationRecord>> {
-        let read_txn = self.db.begin_read()?;
-        let table = read_txn.open_table(TABLE_RECORDS)?;
-        let mut records = Vec::new();
-        for entry in table.iter()? {
-            let (_, value) = entry?;
-            let rec: GenerationRecord = serde_json::from_str(&value.value())?;
-            records.push(rec);
-        }
-        records.sort_by_key(|r| r.created_at);
-        records.reverse();
-        records.truncate(limit);
-        Ok(records)
-    }
-
-    pub fn list_record_ids_by_dates(&self, dates: &HashSet<String>) -> CoreResult<Vec<Uuid>> {
-        if dates.is_empty() {
-            return Ok(Vec::new());
-        }
-
-        let read_txn = self.db.begin_read()?;
-        let table = read_txn.open_table(TABLE_RECORDS)?;
-        let mut ids = Vec::new();
-
-        for entry in table.iter()? {
-            let (_, value) = entry?;
-            let rec: GenerationRecord = serde_json::from_str(&value.value())?;
-            let record_date = rec
-                .created_at
-                .with_timezone(&Local)
-                .format("%Y-%m-%d")
-                .to_string();
-            if dates.contains(&record_date) {
-                ids.push(rec.id);
-            }
-        }
-
-        Ok(ids)
-    }
-
-    pub fn list_presets(&self, offset: usize, limit: usize) -> CoreResult<Page<CharacterPreset>> {
-        let read_txn = self.db.begin_read()?;
-        let table = read_txn.open_table(TABLE_PRESETS)?;
-        let mut presets = Vec::new();
-        for entry in table.iter()? {
-            let (_, value) = entry?;
-            let preset: CharacterPreset = serde_json::from_str(&value.value())?;
-            presets.push(preset);
-        }
-        presets.sort_by(|a, b| a.name.cmp(&b.name));
-        let total = presets.len();
-        let items = presets.into_iter().skip(offset).take(limit).collect();
-        Ok(Page { items, total })
-    }
-
-    // ==================== 主预设 CRUD ====================
-
-    /// 创建或更新主预设
-    pub fn upsert_main_preset(&self, preset: MainPreset) -> CoreResult<MainPreset> {
-        let serialized = serde_json::to_string(&preset)?;
-        let write_txn = self.db.begin_write()?;
-        {
-            let mut table = write_txn.open_table(TABLE_MAIN_PRESETS)?;
-            table.insert(preset.id, serialized)?;
-        }
-        write_txn.commit()?;
-        info!(id=%preset.id, name=%preset.name, "main preset upserted");
-        Ok(preset)
-    }
-
-    /// 获取主预设
-    pub fn get_main_preset(&self, id: Uuid) -> CoreResult<Option<MainPreset>> {
-        let read_txn = self.db.begin_read()?;
-        let table = read_txn.open_table(TABLE_MAIN_PRESETS)?;
-        if let Some(value) = table.get(id)? {
-            let preset: MainPreset = serde_json::from_str(&value.value())?;
-            return Ok(Some(preset));
-        }
-        Ok(None)
-    }
-
-    /// 删除主预设
-    pub fn delete_main_preset(&self, id: Uuid) -> CoreResult<bool> {
-        let write_txn = self.db.begin_write()?;
-        let removed = {
-            let mut table = write_txn.open_table(TABLE_MAIN_PRESETS)?;
-            table.remove(id)?.is_some()
-        };
-        write_txn.commit()?;
-        if removed {
-            info!(id=%id, "main preset deleted");
-        }
-        Ok(removed)
-    }
-
-    /// 列出所有主预设
-    pub fn list_main_presets(&self, offset: usize, limit: usize) -> CoreResult<Page<MainPreset>> {
-        let read_txn = self.db.begin_read()?;
-        let table = read_txn.open_table(TABLE_MAIN_PRESETS)?;
-        let mut presets = Vec::new();
-        for entry in table.iter()? {
-            let (_, value) = entry?;
-            let preset: MainPreset = serde_json::from_str(&value.value())?;
-            presets.push(preset);
-        }
-        presets.sort_by(|a, b| a.name.cmp(&b.name));
-        let total = presets.len();
-        let items = presets.into_iter().skip(offset).take(limit).collect();
-        Ok(Page { items, total })
-    }
-
-    /// 保存上次生成设置
-    pub fn save_last_generation_settings(
-        &self,
-        settings: &LastGenerationSettings,
-    ) -> CoreResult<()> {
-        let serialized = serde_json::to_string(settings)?;
-        let write_txn = self.db.begin_write()?;
-        {
-            let mut table = write_txn.open_table(TABLE_SETTINGS)?;
-            table.insert(SETTINGS_KEY_LAST_GENERATION, serialized)?;
-        }
-        write_txn.commit()?;
-        info!("last generation settings saved");
-        Ok(())
-    }
-
-    /// 加载上次生成设置
-    pub fn load_last_generation_settings(&self) -> CoreResult<Option<LastGenerationSettings>> {
-        let read_txn = self.db.begin_read()?;
-        let table = read_txn.open_table(TABLE_SETTINGS)?;
-        if let Some(value) = table.get(SETTINGS_KEY_LAST_GENERATION)? {
-            let settings: LastGenerationSettings = serde_json::from_str(&value.value())?;
-            return Ok(Some(settings));
-        }
-        Ok(None)
-    }
-}
-
-#[derive(Debug, Clone)]
-pub struct SnippetResolver {
-    storage: Arc<CoreStorage>,
-}
-
-impl SnippetResolver {
-    pub fn new(storage: Arc<CoreStorage>) -> Self {
-        Self { storage }
-    }
-
-    pub fn expand(&self, prompt: &str) -> CoreResult<String> {
-        let mut result = String::with_capacity(prompt.len());
-        let mut chars = prompt.chars().peekable();
-
-        while let Some(ch) = chars.next() {
-            if ch == '<' {
-                let mut token = String::new();
-                while let Some(&next) = chars.peek() {
-                    chars.next();
-                    if next == '>' {
-                        break;
-                    }
-                    token.push(next);
-                }
-                if let Some(rest) = token.strip_prefix("snippet:") {
-                    validate_snippet_name(rest)?;
-                    let snippet = self
-                        .storage
-                        .get_snippet_by_name(rest)?
-                        .ok_or_else(|| anyhow!("snippet not found: {rest}"))?;
-                    result.push_str(&snippet.content);
-                } else {
-                    // Unknown token, keep literal
-                    result.push('<');
-                    result.push_str(&token);
-                    result.push('>');
-                }
-            } else {
-                result.push(ch);
-            }
-        }
-
-        Ok(result)
-    }
-}
-
-/// 角色提示词处理结果
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ProcessedCharacterPrompt {
-    /// 预设应用后的正面提示词
-    pub after_preset: String,
-    /// snippet 展开后的最终正面提示词
-    pub final_prompt: String,
-    /// 预设应用后的负面提示词
-    pub uc_after_preset: String,
-    /// snippet 展开后的最终负面提示词
-    pub final_uc: String,
-    pub enabled: bool,
 }
 
-/// Dry-run 结果，展示提示词处理链的各个阶段
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DryRunResult {
-    /// 原始正面提示词
-    pub raw_positive: String,
-    /// 主预设应用后的正面提示词
-    pub positive_after_preset: String,
-    /// snippet 展开后的最终正面提示词
-    pub final_positive: String,
-    /// 原始负面提示词
-    pub raw_negative: String,
-    /// 主预设应用后的负面提示词
-    pub negative_after_preset: String,
-    /// snippet 展开后的最终负面提示词
-    pub final_negative: String,
-    /// 角色提示词处理结果
-    pub character_prompts: Vec<ProcessedCharacterPrompt>,
-}
-
-/// 提示词处理器 - 统一处理提示词预设注入和 snippet 展开
-///
-/// 处理链：
-/// 1. 应用主预设（before/after/replace）到主提示词
-/// 2. 应用角色预设到角色提示词
-/// 3. 展开所有 snippet 引用
-#[derive(Debug, Clone)]
-pub struct PromptProcessor {
-    storage: Arc<CoreStorage>,
-}
-
-impl PromptProcessor {
-    pub fn new(storage: Arc<CoreStorage>) -> Self {
-        Self { storage }
-    }
-
-    /// 执行 dry-run，返回处理链各阶段的结果
-    pub fn dry_run(
-        &self,
-        raw_positive: &str,
-        raw_negative: &str,
-        main_preset: &MainPresetSettings,
-        character_slots: &[CharacterSlotSettings],
-    ) -> CoreResult<DryRunResult> {
-        let resolver = SnippetResolver::new(Arc::clone(&self.storage));
-
-        // 步骤 1: 剥离注释
-        let positive_no_comment = PromptParser::strip_comments(raw_positive)
-            .map_err(|e| anyhow!("strip comments error: {}", e))?;
-        let negative_no_comment = PromptParser::strip_comments(raw_negative)
-            .map_err(|e| anyhow!("strip comments error: {}", e))?;
-
-        // 步骤 2: 应用主预设
-        let positive_after_preset = main_preset.apply_positive(&positive_no_comment);
-        let negative_after_preset = main_preset.apply_negative(&negative_no_comment);
-
-        // 步骤 3: 展开 snippet
-        let final_positive = resolver.expand(&positive_after_preset)?;
-        let final_negative = resolver.expand(&negative_after_preset)?;
-
-        // 步骤 4: 处理角色提示词
-        let mut processed_chars = Vec::new();
-        for slot in character_slots {
-            if !slot.enabled {
-                continue;
-            }
-            if slot.prompt.trim().is_empty() && slot.preset_id.is_none() {
-                continue;
-            }
-
-            // 先剥离注释
-            let char_positive_no_comment = PromptParser::strip_comments(&slot.prompt)
-                .map_err(|e| anyhow!("strip comments error: {}", e))?;
-            let char_negative_no_comment = PromptParser::strip_comments(&slot.uc)
-                .map_err(|e| anyhow!("strip comments error: {}", e))?;
-
-            let mut char_positive = char_positive_no_comment;
-            let mut char_negative = char_negative_no_comment;
-
-            // 应用角色预设
-            if let Some(preset_id) = slot.preset_id {
-                if let Some(preset) = self.storage.get_preset(preset_id)? {
-                    char_positive = preset.apply(&char_positive);
-                    char_negative = preset.apply_uc(&char_negative);
-                }
-            }
-
-            let after_preset = char_positive.clone();
-            let uc_after_preset = char_negative.clone();
-
-            // 展开 snippet
-            let final_char_prompt = resolver.expand(&char_positive)?;
-            let final_char_uc = resolver.expand(&char_negative)?;
-
-            processed_chars.push(ProcessedCharacterPrompt {
-                after_preset,
-                final_prompt: final_char_prompt,
-                uc_after_preset,
-                final_uc: final_char_uc,
-                enabled: true,
-            });
-        }
-
-        Ok(DryRunResult {
-            raw_positive: raw_positive.to_string(),
-            positive_after_preset,
-            final_positive,
-            raw_negative: raw_negative.to_string(),
-            negative_after_preset,
-            final_negative,
-            character_prompts: processed_chars,
-        })
-    }
-
-    /// 处理任务请求中的提示词，返回处理后的结果
-    pub fn process_task(&self, task: &mut GenerateTaskRequest) -> CoreResult<(String, String)> {
-        let resolver = SnippetResolver::new(Arc::clone(&self.storage));
-
-        // 步骤 1: 应用主预设
-        let positive_after_preset = task.main_preset.apply_positive(&task.raw_prompt);
-        let negative_after_preset = task.main_preset.apply_negative(&task.negative_prompt);
-
-        // 步骤 2: 展开主提示词中的 snippet
-        let final_positive = resolver.expand(&positive_after_preset)?;
-        let final_negative = resolver.expand(&negative_after_preset)?;
-
-        // 步骤 3: 处理角色提示词
-        if let Some(ref mut chars) = task.params.character_prompts {
-            for char_prompt in chars.iter_mut() {
-                char_prompt.prompt = resolver.expand(&char_prompt.prompt)?;
-                char_prompt.uc = resolver.expand(&char_prompt.uc)?;
-            }
-        }
-
-        Ok((final_positive, final_negative))
-    }
-}
-
-#[derive(Debug, Clone)]
-pub struct TaskExecutor {
-    client: Arc<NaiClient>,
-    storage: Arc<CoreStorage>,
-    gallery: GalleryPaths,
-}
-
-impl TaskExecutor {
-    pub fn new(client: Arc<NaiClient>, storage: Arc<CoreStorage>, gallery: GalleryPaths) -> Self {
-        Self {
-            client,
-            storage,
-            gallery,
-        }
-    }
-
-    pub async fn execute(&self, mut task: GenerateTaskRequest) -> CoreResult<GenerationRecord> {
-        info!(task_id=%task.id, count=task.count, "task started");
-
-        let storage_for_process = Arc::clone(&self.storage);
-        let main_preset = task.main_preset.clone();
-        let raw_prompt = task.raw_prompt.clone();
-        let raw_negative = task.negative_prompt.clone();
-        let character_prompts = task.params.character_prompts.clone();
-
-        // 使用 PromptProcessor 处理提示词
-        // 处理链：剥离注释 -> 注入主预设 -> 展开 snippet
-        let (expanded_prompt, expanded_negative, expanded_character_prompts) =
-            tokio::task::spawn_blocking(move || {
-                let processor = PromptProcessor::new(storage_for_process);
-                let resolver = SnippetResolver::new(Arc::clone(&processor.storage));
-
-                // 步骤 1: 剥离注释
-                let positive_no_comment = PromptParser::strip_comments(&raw_prompt)
-                    .map_err(|e| anyhow!("strip comments error: {}", e))?;
-                let negative_no_comment = PromptParser::strip_comments(&raw_negative)
-                    .map_err(|e| anyhow!("strip comments error: {}", e))?;
-
-                // 步骤 2: 应用主预设
-                let positive_after_preset = main_preset.apply_positive(&positive_no_comment);
-                let negative_after_preset = main_preset.apply_negative(&negative_no_comment);
-
-                // 步骤 3: 展开 snippet
-                let final_positive = resolver.expand(&positive_after_preset)?;
-                let final_negative = resolver.expand(&negative_after_preset)?;
-
-                // 步骤 4: 处理角色提示词
-                let expanded_chars = if let Some(chars) = character_prompts {
-                    let mut result = Vec::with_capacity(chars.len());
-                    for mut char_prompt in chars {
-                        // 先剥离注释
-                        let prompt_no_comment =
-                            PromptParser::strip_comments(&char_prompt.prompt)
-                                .map_err(|e| anyhow!("strip comments error: {}", e))?;
-                        let uc_no_comment = PromptParser::strip_comments(&char_prompt.uc)
-                            .map_err(|e| anyhow!("strip comments error: {}", e))?;
-                        // 再展开 snippet
-                        char_prompt.prompt = resolver.expand(&prompt_no_comment)?;
-                        char_prompt.uc = resolver.expand(&uc_no_comment)?;
-                        result.push(char_prompt);
-                    }
-                    Some(result)
-                } else {
-                    None
-                };
-
-                Ok::<_, anyhow::Error>((final_positive, final_negative, expanded_chars))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{TestDir, TestStorage};
+
+    #[test]
+    fn test_storage_errors_are_typed() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        storage
+            .upsert_snippet(
+                Snippet::new("hair".into(), "char".into(), "red".into()).unwrap(),
+                None,
+            )
+            .unwrap();
+        let other = storage
+            .upsert_snippet(
+                Snippet::new("eyes".into(), "char".into(), "blue".into()).unwrap(),
+                None,
+            )
+            .unwrap();
+
+        let err = storage.rename_snippet(other.id, "hair".into()).unwrap_err();
+        assert!(matches!(err, CoreError::NameTaken { ref name } if name == "hair"));
+        let err = storage.delete_preset_preview(Uuid::new_v4()).unwrap_err();
+        assert!(matches!(err, CoreError::NotFound { .. }));
+        assert_eq!(err.to_string(), "preset not found");
+
+        let err = storage.add_blocked_tag("a, b").unwrap_err();
+        assert_eq!(
+            serde_json::to_value(match &err {
+                CoreError::Validation(v) => v,
+                other => panic!("unexpected error: {other}"),
             })
-            .await
-            .map_err(|e| anyhow!("join error: {e}"))??;
-
-        // 更新 task 中的 character_prompts 为展开后的版本
-        task.params.character_prompts = expanded_character_prompts;
-
-        let mut images = Vec::with_capacity(task.count as usize);
-
-        // Use fixed seed if provided, otherwise random
-        let base_seed = task.params.seed.filter(|&s| s > 0).map(|s| s as u64);
-
-        for idx in 0..task.count {
-            // 图片之间添加随机延迟（首张图片除外）
-            if idx > 0 {
-                let delay = random_delay();
-                info!(task_id=%task.id, idx, "waiting {:?} before next image", delay);
-                tokio::time::sleep(delay).await;
-            }
+            .unwrap(),
+            serde_json::json!({ "reason": "comma" })
+        );
+        let err: anyhow::Error = err.into();
+        assert!(err.downcast_ref::<CoreError>().is_some());
+    }
+
+    #[test]
+    fn test_preview_uploads_are_downscaled() {
+        fn png(width: u32, height: u32) -> Vec<u8> {
+            let img = image::DynamicImage::ImageRgb8(image::RgbImage::new(width, height));
+            let mut out = std::io::Cursor::new(Vec::new());
+            img.write_to(&mut out, image::ImageFormat::Png).unwrap();
+            out.into_inner()
+        }
+        let TestStorage { dir, storage } = TestStorage::new();
+        let stored_size = |path: Option<String>| {
+            let img = image::open(dir.join("previews").join(path.unwrap())).unwrap();
+            (img.width(), img.height())
+        };
 
-            let seed = base_seed.unwrap_or_else(random_seed);
-            info!(task_id=%task.id, idx, seed, "generating image");
-            let req = to_nai_request(&task, &expanded_prompt, &expanded_negative, seed);
-            let bytes = self.client.generate_image(&req).await?;
-            let path = self.gallery.image_path(idx, seed);
-
-            let path_clone = path.clone();
-            tokio::task::spawn_blocking(move || -> CoreResult<()> {
-                if let Some(parent) = path_clone.parent() {
-                    fs::create_dir_all(parent).context("create gallery dir")?;
-                }
-                fs::write(&path_clone, &bytes).context("write generated image")?;
-                Ok(())
+        let snippet = storage
+            .upsert_snippet(
+                Snippet::new("hair".into(), "char".into(), "red".into()).unwrap(),
+                Some(&png(2000, 1000)),
+            )
+            .unwrap();
+        assert_eq!(stored_size(snippet.preview_path), (512, 256));
+
+        // 已经足够小的图片原样保存
+        let small = png(100, 300);
+        let snippet = storage.update_snippet_preview(snippet.id, &small).unwrap();
+        let path = dir.join("previews").join(snippet.preview_path.unwrap());
+        assert_eq!(std::fs::read(path).unwrap(), small);
+
+        let preset = storage
+            .upsert_preset(CharacterPreset::new("girl".into()))
+            .unwrap();
+        let preset = storage
+            .update_preset_preview(preset.id, &png(1000, 2000))
+            .unwrap();
+        assert_eq!(stored_size(preset.preview_path), (256, 512));
+
+        // JPEG 同样缩小
+        let mut jpeg = std::io::Cursor::new(Vec::new());
+        image::DynamicImage::ImageRgb8(image::RgbImage::new(1024, 768))
+            .write_to(&mut jpeg, image::ImageFormat::Jpeg)
+            .unwrap();
+        let preset = storage
+            .update_preset_preview(preset.id, &jpeg.into_inner())
+            .unwrap();
+        assert_eq!(stored_size(preset.preview_path), (512, 384));
+    }
+
+    #[test]
+    fn test_page_metadata() {
+        let page = Page::new(vec![1, 2], 5, 0);
+        assert!(page.has_more);
+        assert_eq!(page.next_offset, Some(2));
+        let page = Page::new(vec![5], 5, 4);
+        assert!(!page.has_more);
+        assert_eq!(page.next_offset, None);
+        let page: Page<i32> = Page::new(Vec::new(), 3, 10);
+        assert!(!page.has_more);
+        assert_eq!(page.next_offset, None);
+    }
+
+    #[test]
+    fn test_compact_after_mass_delete() {
+        let TestStorage { dir, storage } = TestStorage::new();
+        let ids: Vec<Uuid> = (0..500)
+            .map(|_| {
+                let record = GenerationRecord {
+                    id: Uuid::new_v4(),
+                    task_id: Uuid::new_v4(),
+                    created_at: Utc::now(),
+                    raw_prompt: "1girl, blue hair, ".repeat(100),
+                    expanded_prompt: String::new(),
+                    negative_prompt: String::new(),
+                    label: None,
+                    raw_negative_prompt: None,
+                    main_preset: None,
+                    images: Vec::new(),
+                    params: None,
+                };
+                storage.append_record(&record).unwrap();
+                record.id
             })
-            .await
-            .map_err(|e| anyhow!("join error: {e}"))??;
-
-            images.push(GalleryImage {
-                path,
-                seed,
-                width: task.params.width,
-                height: task.params.height,
-            });
-        }
-
-        let storage_for_record = Arc::clone(&self.storage);
-        let record_id = Uuid::new_v4();
-        let record_len = images.len();
+            .collect();
+        assert_eq!(storage.delete_records(&ids).unwrap().len(), ids.len());
+
+        let path = dir.join("codex.redb");
+        let before = std::fs::metadata(&path).unwrap().len();
+        let reclaimed = storage.compact().unwrap();
+        let after = std::fs::metadata(&path).unwrap().len();
+        assert!(reclaimed > 0);
+        assert_eq!(before - after, reclaimed);
+
+        // 压缩后仍可正常读写
+        assert!(storage.list_recent_records(10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_list_skips_corrupt_rows() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        storage
+            .upsert_snippet(
+                Snippet::new("hair".into(), "char".into(), "red".into()).unwrap(),
+                None,
+            )
+            .unwrap();
         let record = GenerationRecord {
-            id: record_id.clone(),
-            task_id: task.id,
+            id: Uuid::new_v4(),
+            task_id: Uuid::new_v4(),
             created_at: Utc::now(),
-            raw_prompt: task.raw_prompt,
-            expanded_prompt,
-            negative_prompt: expanded_negative,
-            images,
+            raw_prompt: String::new(),
+            expanded_prompt: String::new(),
+            negative_prompt: String::new(),
+            label: None,
+            raw_negative_prompt: None,
+            main_preset: None,
+            images: Vec::new(),
+            params: None,
         };
+        storage.append_record(&record).unwrap();
 
-        let append = record.clone();
-        tokio::task::spawn_blocking(move || storage_for_record.append_record(&append))
-            .await
-            .map_err(|e| anyhow!("join error: {e}"))??;
-
-        info!(task_id=%task.id, record_id=%record_id, images=%record_len, "task completed");
-        Ok(record)
-    }
-}
-
-fn to_nai_request(
-    task: &GenerateTaskRequest,
-    prompt: &str,
-    negative: &str,
-    seed: u64,
-) -> ImageGenerationRequest {
-    ImageGenerationRequest {
-        model: task.params.model,
-        prompt_positive: prompt.to_string(),
-        prompt_negative: negative.to_string(),
-        quantity: None,
-        width: task.params.width,
-        height: task.params.height,
-        steps: task.params.steps,
-        scale: task.params.scale,
-        sampler: task.params.sampler,
-        noise: task.params.noise,
-        cfg_rescale: task.params.cfg_rescale,
-        seed: Some(seed as i64),
-        character_prompts: task.params.character_prompts.clone(),
-        add_quality_tags: task.params.add_quality_tags,
-        undesired_content_preset: task.params.undesired_content_preset,
-        legacy_uc: false,
-        variety_plus: task.params.variety_plus,
-    }
-}
-
-fn random_seed() -> u64 {
-    let mut rng = rng();
-    rng.random_range(1_000_000_000u64..=9_999_999_999u64)
-}
-
-/// 生成随机延迟时间，基准3秒，有0.5秒的波动范围
-fn random_delay() -> Duration {
-    let mut rng = rng();
-    let base_ms = 3000i32;
-    let bounce_ms = rng.random_range(-500..=500);
-    Duration::from_millis((base_ms + bounce_ms) as u64)
-}
-
-fn validate_snippet_name(name: &str) -> CoreResult<()> {
-    if name.contains(['<', '>', ',', ' ', '{', '}', '(', ')', '[', ']']) || name.is_empty() {
-        return Err(anyhow!("invalid snippet name"));
+        let write_txn = storage.db().unwrap().begin_write().unwrap();
+        {
+            let mut table = write_txn.open_table(TABLE_SNIPPETS).unwrap();
+            table
+                .insert(Uuid::new_v4(), "{\"name\":".to_string())
+                .unwrap();
+            let mut table = write_txn.open_table(TABLE_RECORDS).unwrap();
+            table
+                .insert(Uuid::new_v4(), "not json".to_string())
+                .unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        let page = storage.list_snippets(None, None, 0, 10).unwrap();
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.total, 1);
+        assert_eq!(page.skipped, 1);
+        let records = storage.list_recent_records(10).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].id, record.id);
+    }
+
+    #[test]
+    fn test_migrate_v1_with_corrupt_snippet_row() {
+        let dir = TestDir::new();
+        let record = GenerationRecord {
+            id: Uuid::new_v4(),
+            task_id: Uuid::new_v4(),
+            created_at: Utc::now(),
+            raw_prompt: String::new(),
+            expanded_prompt: String::new(),
+            negative_prompt: String::new(),
+            label: None,
+            raw_negative_prompt: None,
+            main_preset: None,
+            images: Vec::new(),
+            params: None,
+        };
+        let hair = Snippet::new("hair".into(), "char".into(), "red".into()).unwrap();
+        {
+            // 构造 v1 数据库：没有时间索引，且有一行损坏的 snippet
+            let storage = CoreStorage::open(dir.join("codex.redb"), dir.join("previews")).unwrap();
+            storage.upsert_snippet(hair.clone(), None).unwrap();
+            storage.append_record(&record).unwrap();
+            let write_txn = storage.db().unwrap().begin_write().unwrap();
+            {
+                write_txn.delete_table(TABLE_RECORDS_BY_TIME).unwrap();
+                write_txn.delete_table(TABLE_SNIPPET_NAME_INDEX).unwrap();
+                let mut snippets = write_txn.open_table(TABLE_SNIPPETS).unwrap();
+                snippets
+                    .insert(Uuid::new_v4(), "{not json".to_string())
+                    .unwrap();
+                let mut settings = write_txn.open_table(TABLE_SETTINGS).unwrap();
+                settings
+                    .insert(SETTINGS_KEY_SCHEMA_VERSION, "1".to_string())
+                    .unwrap();
+            }
+            write_txn.commit().unwrap();
+        }
+
+        let storage = CoreStorage::open(dir.join("codex.redb"), dir.join("previews")).unwrap();
+        assert_eq!(
+            storage.get_snippet_by_name("hair").unwrap().unwrap().id,
+            hair.id
+        );
+        let recent = storage.list_recent_records(10).unwrap();
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].id, record.id);
+        let read_txn = storage.db().unwrap().begin_read().unwrap();
+        let settings = read_txn.open_table(TABLE_SETTINGS).unwrap();
+        assert_eq!(
+            settings
+                .get(SETTINGS_KEY_SCHEMA_VERSION)
+                .unwrap()
+                .unwrap()
+                .value(),
+            SCHEMA_VERSION.to_string()
+        );
     }
-    Ok(())
 }
@@ -0,0 +1,330 @@
+//! 生成参数 - 完整参数、部分覆盖及转换为 NovelAI 请求
+
+use codex_api::{CharacterPrompt, ImageGenerationRequest, Model, Noise, Sampler};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use serde::{Deserialize, Serialize};
+
+/// 反序列化时缺省的字段取默认值；steps / scale 缺省时使用所选模型的推荐值
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "PartialGenerationParams")]
+pub struct GenerationParams {
+    pub model: Model,
+    pub width: u32,
+    pub height: u32,
+    pub steps: u32,
+    pub scale: f32,
+    pub sampler: Sampler,
+    pub noise: Noise,
+    pub cfg_rescale: f32,
+    pub undesired_content_preset: Option<u8>,
+    pub add_quality_tags: bool,
+    pub character_prompts: Option<Vec<CharacterPrompt>>,
+    /// Fixed seed for reproducibility. None or negative means random; 0 is a valid fixed seed.
+    pub seed: Option<i64>,
+    /// Variety+ mode for dynamic variation
+    pub variety_plus: bool,
+    /// 主种子：设置后任务中的所有随机选择（每张图片的种子）都由它派生，整批可复现
+    ///
+    /// 固定种子 `seed` 优先于主种子
+    pub master_seed: Option<u64>,
+}
+
+impl Default for GenerationParams {
+    fn default() -> Self {
+        Self::for_model(Model::default())
+    }
+}
+
+impl From<PartialGenerationParams> for GenerationParams {
+    fn from(partial: PartialGenerationParams) -> Self {
+        Self::for_model(partial.model.unwrap_or_default()).merge(partial)
+    }
+}
+
+impl GenerationParams {
+    /// 使用模型推荐的 steps / scale 构造默认参数
+    pub fn for_model(model: Model) -> Self {
+        Self {
+            model,
+            width: 1024,
+            height: 1024,
+            steps: model.recommended_steps(),
+            scale: model.recommended_scale(),
+            sampler: Sampler::default(),
+            noise: Noise::default(),
+            cfg_rescale: 0.0,
+            undesired_content_preset: None,
+            add_quality_tags: true,
+            character_prompts: None,
+            seed: None,
+            variety_plus: false,
+            master_seed: None,
+        }
+    }
+
+    /// 未指定 UC 预设时使用实例默认值，按模型允许的范围截断；请求中指定的值不受影响
+    pub fn apply_default_uc_preset(&mut self, default: Option<u8>) {
+        if self.undesired_content_preset.is_none() {
+            self.undesired_content_preset =
+                default.map(|preset| preset.min(self.model.max_uc_preset()));
+        }
+    }
+
+    /// 固定种子；`None` 或负数表示每张图片随机（0 是合法的固定种子）
+    pub fn fixed_seed(&self) -> Option<u64> {
+        codex_api::fixed_seed(self.seed)
+    }
+
+    /// 第 `start` 张起连续 `count` 张图片使用的种子
+    ///
+    /// - 固定种子：每张相同
+    /// - 主种子：由主种子初始化的 `StdRng` 依次生成，同一下标的图片种子总是相同（重试时也一致）
+    /// - 否则每张随机
+    pub fn image_seeds(&self, start: u32, count: u32) -> Vec<u64> {
+        if let Some(seed) = self.fixed_seed() {
+            return vec![seed; count as usize];
+        }
+        match self.master_seed {
+            Some(master) => {
+                let mut rng = StdRng::seed_from_u64(master);
+                (0..start + count)
+                    .map(|_| rng.random_range(1_000_000_000u64..=9_999_999_999u64))
+                    .skip(start as usize)
+                    .collect()
+            }
+            None => (0..count).map(|_| codex_api::random_seed()).collect(),
+        }
+    }
+
+    /// 按当前参数估算单张图片消耗的 Anlas（仅供参考）
+    pub fn estimated_anlas(&self, opus: bool) -> u32 {
+        to_nai_request(self, "", "", 0).estimated_anlas(opus)
+    }
+
+    /// 字段级合并：仅覆盖 `overrides` 中提供的字段，其余保持不变
+    pub fn merge(mut self, overrides: PartialGenerationParams) -> Self {
+        if let Some(model) = overrides.model {
+            self.model = model;
+        }
+        if let Some(width) = overrides.width {
+            self.width = width;
+        }
+        if let Some(height) = overrides.height {
+            self.height = height;
+        }
+        if let Some(steps) = overrides.steps {
+            self.steps = steps;
+        }
+        if let Some(scale) = overrides.scale {
+            self.scale = scale;
+        }
+        if let Some(sampler) = overrides.sampler {
+            self.sampler = sampler;
+        }
+        if let Some(noise) = overrides.noise {
+            self.noise = noise;
+        }
+        if let Some(cfg_rescale) = overrides.cfg_rescale {
+            self.cfg_rescale = cfg_rescale;
+        }
+        if let Some(preset) = overrides.undesired_content_preset {
+            self.undesired_content_preset = preset;
+        }
+        if let Some(add_quality_tags) = overrides.add_quality_tags {
+            self.add_quality_tags = add_quality_tags;
+        }
+        if let Some(character_prompts) = overrides.character_prompts {
+            self.character_prompts = character_prompts;
+        }
+        if let Some(seed) = overrides.seed {
+            self.seed = seed;
+        }
+        if let Some(variety_plus) = overrides.variety_plus {
+            self.variety_plus = variety_plus;
+        }
+        if let Some(master_seed) = overrides.master_seed {
+            self.master_seed = master_seed;
+        }
+        self
+    }
+}
+
+/// 部分生成参数，用于字段级覆盖
+///
+/// 可空字段（如 seed）区分“未提供”与显式 `null`：未提供时保持原值，`null` 时清空
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct PartialGenerationParams {
+    pub model: Option<Model>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub steps: Option<u32>,
+    pub scale: Option<f32>,
+    pub sampler: Option<Sampler>,
+    pub noise: Option<Noise>,
+    pub cfg_rescale: Option<f32>,
+    #[serde(deserialize_with = "deserialize_present")]
+    pub undesired_content_preset: Option<Option<u8>>,
+    pub add_quality_tags: Option<bool>,
+    #[serde(deserialize_with = "deserialize_present")]
+    pub character_prompts: Option<Option<Vec<CharacterPrompt>>>,
+    #[serde(deserialize_with = "deserialize_present")]
+    pub seed: Option<Option<i64>>,
+    pub variety_plus: Option<bool>,
+    #[serde(deserialize_with = "deserialize_present")]
+    pub master_seed: Option<Option<u64>>,
+}
+
+/// 字段出现即为 `Some`（包括显式 `null`），缺省时由 `#[serde(default)]` 得到 `None`
+fn deserialize_present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+pub(crate) fn to_nai_request(
+    params: &GenerationParams,
+    prompt: &str,
+    negative: &str,
+    seed: u64,
+) -> ImageGenerationRequest {
+    ImageGenerationRequest {
+        model: params.model,
+        prompt_positive: prompt.to_string(),
+        prompt_negative: negative.to_string(),
+        quantity: None,
+        width: params.width,
+        height: params.height,
+        steps: params.steps,
+        scale: params.scale,
+        sampler: params.sampler,
+        noise: params.noise,
+        cfg_rescale: params.cfg_rescale,
+        seed: Some(seed as i64),
+        character_prompts: params.character_prompts.clone(),
+        add_quality_tags: params.add_quality_tags,
+        undesired_content_preset: params.undesired_content_preset,
+        legacy_uc: false,
+        variety_plus: params.variety_plus,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_fixed_seed_zero_is_fixed() {
+        let mut params = GenerationParams::default();
+        assert_eq!(params.fixed_seed(), None);
+        params.seed = Some(0);
+        assert_eq!(params.fixed_seed(), Some(0));
+        params.seed = Some(42);
+        assert_eq!(params.fixed_seed(), Some(42));
+        params.seed = Some(-1);
+        assert_eq!(params.fixed_seed(), None);
+        params.seed = Some(-5);
+        assert_eq!(params.fixed_seed(), None);
+    }
+
+    #[test]
+    fn test_default_uc_preset_clamped_to_model() {
+        let mut params = GenerationParams::for_model(Model::V45Curated);
+        params.apply_default_uc_preset(Some(4));
+        // Curated 只有 0-3
+        assert_eq!(params.undesired_content_preset, Some(3));
+
+        let mut params = GenerationParams::for_model(Model::V45Full);
+        params.apply_default_uc_preset(Some(1));
+        assert_eq!(params.undesired_content_preset, Some(1));
+        assert_eq!(to_nai_request(&params, "", "", 0).uc_preset_id(), 1);
+
+        // 请求中指定的值优先
+        params.undesired_content_preset = Some(0);
+        params.apply_default_uc_preset(Some(2));
+        assert_eq!(params.undesired_content_preset, Some(0));
+
+        let mut params = GenerationParams::default();
+        params.apply_default_uc_preset(None);
+        assert_eq!(params.undesired_content_preset, None);
+    }
+
+    #[test]
+    fn test_params_default_to_model_recommendations() {
+        let params: GenerationParams =
+            serde_json::from_str(r#"{"model": "nai-diffusion-4-5-curated"}"#).unwrap();
+        assert_eq!(params.steps, Model::V45Curated.recommended_steps());
+        assert_eq!(params.scale, Model::V45Curated.recommended_scale());
+
+        let params: GenerationParams =
+            serde_json::from_str(r#"{"model": "nai-diffusion-4-5-curated", "scale": 4.0}"#)
+                .unwrap();
+        assert_eq!(params.scale, 4.0);
+        assert_eq!(params.width, 1024);
+    }
+
+    #[test]
+    fn test_merge_params_only_seed() {
+        let base = GenerationParams {
+            width: 832,
+            height: 1216,
+            steps: 23,
+            sampler: Sampler::DpmSde,
+            seed: Some(42),
+            variety_plus: true,
+            ..GenerationParams::default()
+        };
+        let overrides: PartialGenerationParams = serde_json::from_str(r#"{"seed": 7}"#).unwrap();
+
+        let merged = base.clone().merge(overrides);
+        assert_eq!(merged.seed, Some(7));
+        assert_eq!(merged.width, 832);
+        assert_eq!(merged.height, 1216);
+        assert_eq!(merged.steps, 23);
+        assert_eq!(merged.sampler, Sampler::DpmSde);
+        assert!(merged.variety_plus);
+    }
+
+    #[test]
+    fn test_master_seed_reproduces_image_seeds() {
+        let params = GenerationParams {
+            master_seed: Some(12345),
+            ..GenerationParams::default()
+        };
+        let first = params.image_seeds(0, 4);
+        assert_eq!(first, params.clone().image_seeds(0, 4));
+        assert_eq!(first.iter().collect::<HashSet<_>>().len(), 4);
+        // 重试时同一下标得到相同种子
+        assert_eq!(params.image_seeds(2, 2), first[2..]);
+
+        let other = GenerationParams {
+            master_seed: Some(54321),
+            ..GenerationParams::default()
+        };
+        assert_ne!(other.image_seeds(0, 4), first);
+
+        let fixed = GenerationParams {
+            seed: Some(7),
+            ..params
+        };
+        assert_eq!(fixed.image_seeds(0, 3), vec![7, 7, 7]);
+    }
+
+    #[test]
+    fn test_merge_params_null_clears_optional() {
+        let base = GenerationParams {
+            seed: Some(42),
+            ..GenerationParams::default()
+        };
+
+        let missing: PartialGenerationParams = serde_json::from_str("{}").unwrap();
+        assert_eq!(base.clone().merge(missing).seed, Some(42));
+
+        let null: PartialGenerationParams = serde_json::from_str(r#"{"seed": null}"#).unwrap();
+        assert_eq!(base.merge(null).seed, None);
+    }
+}
@@ -5,10 +5,20 @@
 //! 2. 如果设置了 replace，则 before 和 after 失效
 //! 3. before/after 会在原提示词前后添加内容
 
+use std::fs;
+
 use chrono::Utc;
+use redb::{ReadableDatabase, ReadableTable, TableHandle};
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::info;
 use uuid::Uuid;
 
+use crate::{
+    CoreError, CoreResult, CoreStorage, Page, SETTINGS_KEY_DEFAULT_MAIN_PRESET, TABLE_MAIN_PRESETS,
+    TABLE_PRESETS, TABLE_SETTINGS, decode_row,
+};
+
 /// 判断字符串是否为空或仅包含空白字符
 fn is_blank(s: &Option<String>) -> bool {
     match s {
@@ -17,6 +27,111 @@ fn is_blank(s: &Option<String>) -> bool {
     }
 }
 
+/// 拼接两段非空白内容，以 `, ` 分隔
+fn join_parts(first: Option<&str>, second: Option<&str>) -> Option<String> {
+    let parts: Vec<&str> = [first, second]
+        .into_iter()
+        .flatten()
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .collect();
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(", "))
+    }
+}
+
+/// 合并时两段内容的先后顺序
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeOrder {
+    /// 目标预设在前，来源预设在后
+    #[default]
+    IntoFirst,
+    /// 来源预设在前，目标预设在后
+    FromFirst,
+}
+
+/// 两个预设都设置了 replace 时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplaceConflict {
+    /// 报错，不进行合并
+    #[default]
+    Error,
+    /// 保留目标预设的 replace
+    KeepInto,
+    /// 保留来源预设的 replace
+    KeepFrom,
+}
+
+/// 预设合并策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PresetMergeStrategy {
+    pub order: MergeOrder,
+    pub on_replace_conflict: ReplaceConflict,
+}
+
+/// 预设合并错误
+#[derive(Debug, Clone, PartialEq, Eq, Error, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum PresetMergeError {
+    #[error("两个预设都设置了 {field}，无法合并")]
+    ReplaceConflict { field: &'static str },
+}
+
+/// 合并一组 before/after/replace
+///
+/// 设置了 replace 的一方其 before/after 本就不生效，不参与拼接
+fn merge_side(
+    into: [&Option<String>; 3],
+    from: [&Option<String>; 3],
+    strategy: PresetMergeStrategy,
+    field: &'static str,
+) -> Result<[Option<String>; 3], PresetMergeError> {
+    let [into_before, into_after, into_replace] = into;
+    let [from_before, from_after, from_replace] = from;
+
+    let replace = match (!is_blank(into_replace), !is_blank(from_replace)) {
+        (true, true) => match strategy.on_replace_conflict {
+            ReplaceConflict::Error => return Err(PresetMergeError::ReplaceConflict { field }),
+            ReplaceConflict::KeepInto => into_replace.clone(),
+            ReplaceConflict::KeepFrom => from_replace.clone(),
+        },
+        (true, false) => into_replace.clone(),
+        (false, true) => from_replace.clone(),
+        (false, false) => None,
+    };
+
+    let effective = |value: &Option<String>, replace: &Option<String>| {
+        if is_blank(replace) {
+            value.clone()
+        } else {
+            None
+        }
+    };
+    let into_parts = (
+        effective(into_before, into_replace),
+        effective(into_after, into_replace),
+    );
+    let from_parts = (
+        effective(from_before, from_replace),
+        effective(from_after, from_replace),
+    );
+    let (first, second) = match strategy.order {
+        MergeOrder::IntoFirst => (into_parts, from_parts),
+        MergeOrder::FromFirst => (from_parts, into_parts),
+    };
+
+    Ok([
+        join_parts(first.0.as_deref(), second.0.as_deref()),
+        join_parts(first.1.as_deref(), second.1.as_deref()),
+        replace,
+    ])
+}
+
 /// 角色预设
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CharacterPreset {
@@ -64,6 +179,37 @@ impl CharacterPreset {
         }
     }
 
+    /// 将 `from` 合并到当前预设，返回合并结果（保留当前预设的 id、名称与预览图）
+    pub fn merged(
+        &self,
+        from: &CharacterPreset,
+        strategy: PresetMergeStrategy,
+    ) -> Result<CharacterPreset, PresetMergeError> {
+        let [before, after, replace] = merge_side(
+            [&self.before, &self.after, &self.replace],
+            [&from.before, &from.after, &from.replace],
+            strategy,
+            "replace",
+        )?;
+        let [uc_before, uc_after, uc_replace] = merge_side(
+            [&self.uc_before, &self.uc_after, &self.uc_replace],
+            [&from.uc_before, &from.uc_after, &from.uc_replace],
+            strategy,
+            "uc_replace",
+        )?;
+
+        Ok(CharacterPreset {
+            before,
+            after,
+            replace,
+            uc_before,
+            uc_after,
+            uc_replace,
+            updated_at: Utc::now(),
+            ..self.clone()
+        })
+    }
+
     /// Apply preset to negative prompt (UC).
     /// 规则: replace 非空白则直接替换；否则应用 before/after（非空白时）
     pub fn apply_uc(&self, raw_uc: &str) -> String {
@@ -266,9 +412,413 @@ impl MainPresetSettings {
     }
 }
 
+/// 主预设设置中一个字段的差异；空白与未设置视为相同，统一为 None
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PresetFieldDiff {
+    pub field: &'static str,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+impl MainPresetSettings {
+    fn fields(&self) -> [(&'static str, &Option<String>); 6] {
+        [
+            ("before", &self.before),
+            ("after", &self.after),
+            ("replace", &self.replace),
+            ("uc_before", &self.uc_before),
+            ("uc_after", &self.uc_after),
+            ("uc_replace", &self.uc_replace),
+        ]
+    }
+
+    /// 逐字段比较，返回 `current` 与 `self`（已保存的预设）不同的字段
+    pub fn diff(&self, current: &MainPresetSettings) -> Vec<PresetFieldDiff> {
+        let normalize =
+            |value: &Option<String>| (!is_blank(value)).then(|| value.clone()).flatten();
+        self.fields()
+            .into_iter()
+            .zip(current.fields())
+            .filter_map(|((field, old), (_, new))| {
+                let (old, new) = (normalize(old), normalize(new));
+                (old != new).then_some(PresetFieldDiff { field, old, new })
+            })
+            .collect()
+    }
+}
+
+/// 服务端配置的全局前缀 / 后缀，在主预设之前加到每个正面提示词上
+///
+/// 与预设不同，它对所有请求生效且不能按请求修改；主预设使用 replace 时加在替换后的内容上
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GlobalAffix {
+    pub prefix: Option<String>,
+    pub suffix: Option<String>,
+}
+
+impl GlobalAffix {
+    pub fn is_empty(&self) -> bool {
+        is_blank(&self.prefix) && is_blank(&self.suffix)
+    }
+
+    /// 按主预设 before/after 的拼接规则加上前缀与后缀
+    pub fn apply(&self, prompt: &str) -> String {
+        MainPresetSettings {
+            before: self.prefix.clone(),
+            after: self.suffix.clone(),
+            ..Default::default()
+        }
+        .apply_positive(prompt)
+    }
+
+    /// 加上前缀 / 后缀后再应用主预设
+    ///
+    /// 主预设的 replace 会整体替换提示词，此时前缀 / 后缀改为加在替换后的内容上，不会随之丢失
+    pub fn apply_with_preset(&self, preset: &MainPresetSettings, prompt: &str) -> String {
+        if is_blank(&preset.replace) {
+            preset.apply_positive(&self.apply(prompt))
+        } else {
+            self.apply(&preset.apply_positive(prompt))
+        }
+    }
+}
+
+impl CoreStorage {
+    pub fn upsert_preset(&self, preset: CharacterPreset) -> CoreResult<CharacterPreset> {
+        let serialized = serde_json::to_string(&preset)?;
+        let write_txn = self.begin_write_with_retry()?;
+        {
+            let mut table = write_txn.open_table(TABLE_PRESETS)?;
+            table.insert(preset.id, serialized)?;
+        }
+        write_txn.commit()?;
+        info!(id=%preset.id, name=%preset.name, "preset upserted");
+        Ok(preset)
+    }
+
+    /// 创建或更新 preset 并可选保存预览图
+    pub fn upsert_preset_with_preview(
+        &self,
+        mut preset: CharacterPreset,
+        preview_bytes: Option<&[u8]>,
+    ) -> CoreResult<CharacterPreset> {
+        // 处理预览图
+        if let Some(bytes) = preview_bytes {
+            // 获取旧的预览图路径以便删除
+            if let Some(old_preset) = self.get_preset(preset.id)? {
+                self.remove_old_preview(old_preset.preview_path.as_deref());
+            }
+            // 保存新的预览图（带时间戳，过大时缩小）
+            preset.preview_path = Some(self.write_preview(preset.id, "presets", bytes)?);
+        }
+
+        let serialized = serde_json::to_string(&preset)?;
+        let write_txn = self.begin_write_with_retry()?;
+        {
+            let mut table = write_txn.open_table(TABLE_PRESETS)?;
+            table.insert(preset.id, serialized)?;
+        }
+        write_txn.commit()?;
+        info!(id=%preset.id, name=%preset.name, "preset upserted");
+        Ok(preset)
+    }
+
+    /// 重命名 preset
+    pub fn rename_preset(&self, id: Uuid, new_name: String) -> CoreResult<CharacterPreset> {
+        let mut preset = self
+            .get_preset(id)?
+            .ok_or_else(|| CoreError::not_found("preset"))?;
+
+        let old_name = preset.name.clone();
+        preset.name = new_name.clone();
+        preset.updated_at = Utc::now();
+
+        let serialized = serde_json::to_string(&preset)?;
+        let write_txn = self.begin_write_with_retry()?;
+        {
+            let mut table = write_txn.open_table(TABLE_PRESETS)?;
+            table.insert(preset.id, serialized)?;
+        }
+        write_txn.commit()?;
+        info!(id=%preset.id, old_name=%old_name, new_name=%new_name, "preset renamed");
+        Ok(preset)
+    }
+
+    /// 将 `from` 合并到 `into` 并保存，可选删除来源预设
+    pub fn merge_presets(
+        &self,
+        into: Uuid,
+        from: Uuid,
+        strategy: PresetMergeStrategy,
+        delete_source: bool,
+    ) -> CoreResult<CharacterPreset> {
+        if into == from {
+            return Err(CoreError::invalid("cannot merge a preset into itself"));
+        }
+        // 读取、写入合并结果与删除来源在同一事务中完成，中途失败时两者都不变
+        let write_txn = self.begin_write_with_retry()?;
+        let (merged, source) = {
+            let mut table = write_txn.open_table(TABLE_PRESETS)?;
+            let get = |id: Uuid| -> CoreResult<CharacterPreset> {
+                let value = table
+                    .get(id)?
+                    .ok_or_else(|| CoreError::not_found(format!("preset {id}")))?;
+                Ok(serde_json::from_str(&value.value())?)
+            };
+            let target = get(into)?;
+            let source = get(from)?;
+
+            let merged = target.merged(&source, strategy)?;
+            table.insert(merged.id, serde_json::to_string(&merged)?)?;
+            if delete_source {
+                table.remove(from)?;
+            }
+            (merged, source)
+        };
+        write_txn.commit()?;
+
+        if delete_source && let Some(path) = source.preview_path {
+            let _ = fs::remove_file(self.preview_dir.join(path));
+        }
+        info!(into=%into, from=%from, delete_source, "presets merged");
+        Ok(merged)
+    }
+
+    pub fn get_preset(&self, id: Uuid) -> CoreResult<Option<CharacterPreset>> {
+        let read_txn = self.db()?.begin_read()?;
+        let table = read_txn.open_table(TABLE_PRESETS)?;
+        if let Some(value) = table.get(id)? {
+            let preset: CharacterPreset = serde_json::from_str(&value.value())?;
+            return Ok(Some(preset));
+        }
+        Ok(None)
+    }
+
+    pub fn delete_preset(&self, id: Uuid) -> CoreResult<bool> {
+        // First read the preset to get its preview path
+        let preview_path = {
+            let read_txn = self.db()?.begin_read()?;
+            let table = read_txn.open_table(TABLE_PRESETS)?;
+            if let Some(value) = table.get(id)? {
+                let preset: CharacterPreset = serde_json::from_str(&value.value())?;
+                preset.preview_path
+            } else {
+                return Ok(false);
+            }
+        };
+
+        let write_txn = self.begin_write_with_retry()?;
+        let removed = {
+            let mut table = write_txn.open_table(TABLE_PRESETS)?;
+            table.remove(id)?.is_some()
+        };
+        write_txn.commit()?;
+
+        // Remove preview file if exists
+        if let Some(path) = preview_path {
+            let full_path = self.preview_dir.join(path);
+            let _ = fs::remove_file(full_path);
+        }
+
+        if removed {
+            info!(id=%id, "preset deleted");
+        }
+        Ok(removed)
+    }
+
+    /// 更新 preset 的预览图
+    pub fn update_preset_preview(
+        &self,
+        id: Uuid,
+        preview_bytes: &[u8],
+    ) -> CoreResult<CharacterPreset> {
+        let mut preset = self
+            .get_preset(id)?
+            .ok_or_else(|| CoreError::not_found("preset"))?;
+
+        // 删除旧的预览图
+        self.remove_old_preview(preset.preview_path.as_deref());
+
+        // 保存新的预览图（带时间戳，过大时缩小）
+        preset.preview_path = Some(self.write_preview(preset.id, "presets", preview_bytes)?);
+        preset.updated_at = Utc::now();
+
+        let serialized = serde_json::to_string(&preset)?;
+        let write_txn = self.begin_write_with_retry()?;
+        {
+            let mut table = write_txn.open_table(TABLE_PRESETS)?;
+            table.insert(preset.id, serialized)?;
+        }
+        write_txn.commit()?;
+        info!(id=%preset.id, "preset preview updated");
+        Ok(preset)
+    }
+
+    /// 删除 preset 的预览图
+    pub fn delete_preset_preview(&self, id: Uuid) -> CoreResult<CharacterPreset> {
+        let mut preset = self
+            .get_preset(id)?
+            .ok_or_else(|| CoreError::not_found("preset"))?;
+
+        if let Some(path) = &preset.preview_path {
+            let full_path = self.preview_dir.join(path);
+            let _ = fs::remove_file(full_path);
+        }
+        preset.preview_path = None;
+        preset.updated_at = Utc::now();
+
+        let serialized = serde_json::to_string(&preset)?;
+        let write_txn = self.begin_write_with_retry()?;
+        {
+            let mut table = write_txn.open_table(TABLE_PRESETS)?;
+            table.insert(preset.id, serialized)?;
+        }
+        write_txn.commit()?;
+        info!(id=%preset.id, "preset preview deleted");
+        Ok(preset)
+    }
+
+    pub fn list_presets(&self, offset: usize, limit: usize) -> CoreResult<Page<CharacterPreset>> {
+        let read_txn = self.db()?.begin_read()?;
+        let table = read_txn.open_table(TABLE_PRESETS)?;
+        let mut presets = Vec::new();
+        let mut skipped = 0;
+        for entry in table.iter()? {
+            let (key, value) = entry?;
+            match decode_row::<CharacterPreset>(TABLE_PRESETS.name(), key.value(), &value.value()) {
+                Some(preset) => presets.push(preset),
+                None => skipped += 1,
+            }
+        }
+        presets.sort_by(|a, b| a.name.cmp(&b.name));
+        let total = presets.len();
+        let items = presets.into_iter().skip(offset).take(limit).collect();
+        Ok(Page {
+            skipped,
+            ..Page::new(items, total, offset)
+        })
+    }
+
+    /// 创建或更新主预设
+    pub fn upsert_main_preset(&self, preset: MainPreset) -> CoreResult<MainPreset> {
+        let serialized = serde_json::to_string(&preset)?;
+        let write_txn = self.begin_write_with_retry()?;
+        {
+            let mut table = write_txn.open_table(TABLE_MAIN_PRESETS)?;
+            table.insert(preset.id, serialized)?;
+        }
+        write_txn.commit()?;
+        info!(id=%preset.id, name=%preset.name, "main preset upserted");
+        Ok(preset)
+    }
+
+    /// 获取主预设
+    pub fn get_main_preset(&self, id: Uuid) -> CoreResult<Option<MainPreset>> {
+        let read_txn = self.db()?.begin_read()?;
+        let table = read_txn.open_table(TABLE_MAIN_PRESETS)?;
+        if let Some(value) = table.get(id)? {
+            let preset: MainPreset = serde_json::from_str(&value.value())?;
+            return Ok(Some(preset));
+        }
+        Ok(None)
+    }
+
+    /// 删除主预设；若它是默认主预设，一并清除默认设置
+    pub fn delete_main_preset(&self, id: Uuid) -> CoreResult<bool> {
+        let write_txn = self.begin_write_with_retry()?;
+        let removed = {
+            let mut table = write_txn.open_table(TABLE_MAIN_PRESETS)?;
+            table.remove(id)?.is_some()
+        };
+        if removed {
+            let mut settings = write_txn.open_table(TABLE_SETTINGS)?;
+            let is_default = settings
+                .get(SETTINGS_KEY_DEFAULT_MAIN_PRESET)?
+                .is_some_and(|v| v.value() == id.to_string());
+            if is_default {
+                settings.remove(SETTINGS_KEY_DEFAULT_MAIN_PRESET)?;
+            }
+        }
+        write_txn.commit()?;
+        if removed {
+            info!(id=%id, "main preset deleted");
+        }
+        Ok(removed)
+    }
+
+    /// 列出所有主预设
+    pub fn list_main_presets(&self, offset: usize, limit: usize) -> CoreResult<Page<MainPreset>> {
+        let read_txn = self.db()?.begin_read()?;
+        let table = read_txn.open_table(TABLE_MAIN_PRESETS)?;
+        let mut presets = Vec::new();
+        let mut skipped = 0;
+        for entry in table.iter()? {
+            let (key, value) = entry?;
+            match decode_row::<MainPreset>(TABLE_MAIN_PRESETS.name(), key.value(), &value.value()) {
+                Some(preset) => presets.push(preset),
+                None => skipped += 1,
+            }
+        }
+        presets.sort_by(|a, b| a.name.cmp(&b.name));
+        let total = presets.len();
+        let items = presets.into_iter().skip(offset).take(limit).collect();
+        Ok(Page {
+            skipped,
+            ..Page::new(items, total, offset)
+        })
+    }
+
+    /// 将主预设设为默认，替换之前的默认主预设
+    pub fn set_default_main_preset(&self, id: Uuid) -> CoreResult<MainPreset> {
+        let write_txn = self.begin_write_with_retry()?;
+        let preset = {
+            let presets = write_txn.open_table(TABLE_MAIN_PRESETS)?;
+            let Some(value) = presets.get(id)? else {
+                return Err(CoreError::not_found(format!("main preset {id}")));
+            };
+            serde_json::from_str::<MainPreset>(&value.value())?
+        };
+        {
+            let mut settings = write_txn.open_table(TABLE_SETTINGS)?;
+            settings.insert(SETTINGS_KEY_DEFAULT_MAIN_PRESET, id.to_string())?;
+        }
+        write_txn.commit()?;
+        info!(id=%id, "default main preset set");
+        Ok(preset)
+    }
+
+    /// 清除默认主预设，返回之前是否设置过
+    pub fn clear_default_main_preset(&self) -> CoreResult<bool> {
+        let write_txn = self.begin_write_with_retry()?;
+        let removed = {
+            let mut settings = write_txn.open_table(TABLE_SETTINGS)?;
+            settings.remove(SETTINGS_KEY_DEFAULT_MAIN_PRESET)?.is_some()
+        };
+        write_txn.commit()?;
+        Ok(removed)
+    }
+
+    /// 读取默认主预设；未设置或指向的预设已不存在时返回 `None`
+    pub fn default_main_preset(&self) -> CoreResult<Option<MainPreset>> {
+        let id = {
+            let read_txn = self.db()?.begin_read()?;
+            let table = read_txn.open_table(TABLE_SETTINGS)?;
+            let Some(value) = table.get(SETTINGS_KEY_DEFAULT_MAIN_PRESET)? else {
+                return Ok(None);
+            };
+            match Uuid::parse_str(&value.value()) {
+                Ok(id) => id,
+                Err(_) => return Ok(None),
+            }
+        };
+        self.get_main_preset(id)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{ValidationError, test_support::TestStorage};
 
     #[test]
     fn test_main_preset_settings_blank_skip() {
@@ -335,4 +885,233 @@ mod tests {
         let result = preset.apply("original");
         assert_eq!(result, "complete replacement");
     }
+
+    fn preset(before: &str, after: &str, replace: &str) -> CharacterPreset {
+        let opt = |s: &str| (!s.is_empty()).then(|| s.to_string());
+        let mut preset = CharacterPreset::new("test".to_string());
+        preset.before = opt(before);
+        preset.after = opt(after);
+        preset.replace = opt(replace);
+        preset
+    }
+
+    #[test]
+    fn test_merge_presets_order() {
+        let into = preset("1girl", "solo", "");
+        let from = preset("blue hair", "smile", "");
+
+        let merged = into.merged(&from, PresetMergeStrategy::default()).unwrap();
+        assert_eq!(merged.id, into.id);
+        assert_eq!(merged.before.as_deref(), Some("1girl, blue hair"));
+        assert_eq!(merged.after.as_deref(), Some("solo, smile"));
+        assert_eq!(merged.replace, None);
+
+        let strategy = PresetMergeStrategy {
+            order: MergeOrder::FromFirst,
+            ..Default::default()
+        };
+        let merged = into.merged(&from, strategy).unwrap();
+        assert_eq!(merged.before.as_deref(), Some("blue hair, 1girl"));
+    }
+
+    #[test]
+    fn test_merge_presets_skips_blank_and_shadowed_parts() {
+        // from 设置了 replace，其 before/after 不生效，不参与拼接
+        let into = preset("1girl", "   ", "");
+        let from = preset("ignored", "ignored", "cat");
+
+        let merged = into.merged(&from, PresetMergeStrategy::default()).unwrap();
+        assert_eq!(merged.before.as_deref(), Some("1girl"));
+        assert_eq!(merged.after, None);
+        assert_eq!(merged.replace.as_deref(), Some("cat"));
+        assert_eq!(merged.apply("x"), "cat");
+    }
+
+    #[test]
+    fn test_merge_presets_replace_conflict() {
+        let into = preset("", "", "dog");
+        let from = preset("", "", "cat");
+
+        assert_eq!(
+            into.merged(&from, PresetMergeStrategy::default())
+                .unwrap_err(),
+            PresetMergeError::ReplaceConflict { field: "replace" }
+        );
+
+        let keep_from = PresetMergeStrategy {
+            on_replace_conflict: ReplaceConflict::KeepFrom,
+            ..Default::default()
+        };
+        assert_eq!(
+            into.merged(&from, keep_from).unwrap().replace.as_deref(),
+            Some("cat")
+        );
+
+        let keep_into = PresetMergeStrategy {
+            on_replace_conflict: ReplaceConflict::KeepInto,
+            ..Default::default()
+        };
+        assert_eq!(
+            into.merged(&from, keep_into).unwrap().replace.as_deref(),
+            Some("dog")
+        );
+    }
+
+    #[test]
+    fn test_merge_presets_uc_conflict() {
+        let mut into = preset("", "", "");
+        into.uc_replace = Some("lowres".to_string());
+        let mut from = preset("", "", "");
+        from.uc_replace = Some("bad hands".to_string());
+
+        assert_eq!(
+            into.merged(&from, PresetMergeStrategy::default())
+                .unwrap_err(),
+            PresetMergeError::ReplaceConflict {
+                field: "uc_replace"
+            }
+        );
+    }
+
+    #[test]
+    fn test_main_preset_settings_diff() {
+        let stored = MainPresetSettings {
+            before: Some("masterpiece".into()),
+            after: Some("   ".into()),
+            ..Default::default()
+        };
+        // 空白与 None 视为相同
+        let same = MainPresetSettings {
+            before: Some("masterpiece".into()),
+            uc_after: Some("".into()),
+            ..Default::default()
+        };
+        assert!(stored.diff(&same).is_empty());
+
+        let fields = [
+            "before",
+            "after",
+            "replace",
+            "uc_before",
+            "uc_after",
+            "uc_replace",
+        ];
+        for field in fields {
+            let mut current = stored.clone();
+            let slot = match field {
+                "before" => &mut current.before,
+                "after" => &mut current.after,
+                "replace" => &mut current.replace,
+                "uc_before" => &mut current.uc_before,
+                "uc_after" => &mut current.uc_after,
+                _ => &mut current.uc_replace,
+            };
+            let old = (field == "before").then(|| "masterpiece".to_string());
+            *slot = Some("changed".into());
+            assert_eq!(
+                stored.diff(&current),
+                vec![PresetFieldDiff {
+                    field,
+                    old,
+                    new: Some("changed".into()),
+                }]
+            );
+        }
+
+        let cleared = MainPresetSettings::default();
+        assert_eq!(
+            stored.diff(&cleared),
+            vec![PresetFieldDiff {
+                field: "before",
+                old: Some("masterpiece".into()),
+                new: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_merge_presets_is_atomic() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        let mut into = CharacterPreset::new("into".into());
+        into.replace = Some("dog".into());
+        let into = storage.upsert_preset(into).unwrap();
+        let mut from = CharacterPreset::new("from".into());
+        from.replace = Some("cat".into());
+        let from = storage.upsert_preset(from).unwrap();
+
+        // 冲突时两个 preset 都不变
+        assert!(matches!(
+            storage.merge_presets(into.id, from.id, PresetMergeStrategy::default(), true),
+            Err(CoreError::Validation(ValidationError::PresetMerge(_)))
+        ));
+        assert!(storage.get_preset(from.id).unwrap().is_some());
+        assert!(matches!(
+            storage.merge_presets(
+                into.id,
+                Uuid::new_v4(),
+                PresetMergeStrategy::default(),
+                true
+            ),
+            Err(CoreError::NotFound { .. })
+        ));
+
+        let strategy = PresetMergeStrategy {
+            on_replace_conflict: ReplaceConflict::KeepFrom,
+            ..Default::default()
+        };
+        let merged = storage
+            .merge_presets(into.id, from.id, strategy, true)
+            .unwrap();
+        assert_eq!(merged.replace.as_deref(), Some("cat"));
+        assert_eq!(
+            storage
+                .get_preset(into.id)
+                .unwrap()
+                .unwrap()
+                .replace
+                .as_deref(),
+            Some("cat")
+        );
+        assert!(storage.get_preset(from.id).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_default_main_preset() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+
+        let first = storage
+            .upsert_main_preset(MainPreset::new("first".to_string()))
+            .unwrap();
+        let second = storage
+            .upsert_main_preset(MainPreset::new("second".to_string()))
+            .unwrap();
+        assert!(storage.default_main_preset().unwrap().is_none());
+        assert!(matches!(
+            storage.set_default_main_preset(Uuid::new_v4()),
+            Err(CoreError::NotFound { .. })
+        ));
+
+        storage.set_default_main_preset(first.id).unwrap();
+        storage.set_default_main_preset(second.id).unwrap();
+        assert_eq!(
+            storage.default_main_preset().unwrap().unwrap().id,
+            second.id
+        );
+
+        // 新会话预选默认主预设
+        let resolved = storage.load_resolved_generation_settings().unwrap();
+        assert_eq!(resolved.main_preset_id, Some(second.id));
+
+        // 删除其他预设不影响默认；删除默认预设时清除
+        storage.delete_main_preset(first.id).unwrap();
+        assert_eq!(
+            storage.default_main_preset().unwrap().unwrap().id,
+            second.id
+        );
+        storage.delete_main_preset(second.id).unwrap();
+        assert!(storage.default_main_preset().unwrap().is_none());
+        assert!(!storage.clear_default_main_preset().unwrap());
+        let resolved = storage.load_resolved_generation_settings().unwrap();
+        assert_eq!(resolved.main_preset_id, None);
+    }
 }
@@ -0,0 +1,442 @@
+//! 提示词处理 - 叠加全局前缀 / 后缀与预设、展开 snippet、移除屏蔽标签，得到最终发送的提示词
+
+use std::sync::Arc;
+
+use codex_api::{Model, WeightRange};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AnlasEstimate, CharacterSlotSettings, CoreError, CoreResult, CoreStorage, GenerateTaskRequest,
+    GlobalAffix, MainPresetSettings, PromptParser, SnippetResolver, TaskPreview, lexicon,
+    params::to_nai_request,
+};
+
+/// 角色提示词处理结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessedCharacterPrompt {
+    /// 预设应用后的正面提示词
+    pub after_preset: String,
+    /// snippet 展开后的最终正面提示词
+    pub final_prompt: String,
+    /// 预设应用后的负面提示词
+    pub uc_after_preset: String,
+    /// snippet 展开后的最终负面提示词
+    pub final_uc: String,
+    pub enabled: bool,
+}
+
+/// Dry-run 结果，展示提示词处理链的各个阶段
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DryRunResult {
+    /// 原始正面提示词
+    pub raw_positive: String,
+    /// 加上全局前缀 / 后缀后的正面提示词（未配置时为空）
+    #[serde(default)]
+    pub positive_after_global: Option<String>,
+    /// 主预设应用后的正面提示词
+    pub positive_after_preset: String,
+    /// snippet 展开后的最终正面提示词
+    pub final_positive: String,
+    /// 原始负面提示词
+    pub raw_negative: String,
+    /// 主预设应用后的负面提示词
+    pub negative_after_preset: String,
+    /// snippet 展开后的最终负面提示词
+    pub final_negative: String,
+    /// 角色提示词处理结果
+    pub character_prompts: Vec<ProcessedCharacterPrompt>,
+    /// 因全局屏蔽从最终正面提示词（含角色提示词）中删除的标签；
+    /// 负面提示词中的屏蔽标签保留，它们正起到排除作用
+    #[serde(default)]
+    pub blocked_tags: Vec<String>,
+    /// 追加到最终正面提示词末尾的质量标签（仅在请求预览时填充）
+    #[serde(default)]
+    pub quality_tags: Option<String>,
+}
+
+impl DryRunResult {
+    /// 按客户端的规则将质量标签追加到最终正面提示词，使预览与实际请求一致
+    pub fn apply_quality_tags(&mut self, model: Model, add_quality_tags: bool) {
+        if add_quality_tags {
+            self.final_positive = model.append_quality_tags(&self.final_positive, true);
+            self.quality_tags = Some(model.quality_tags().to_string());
+        }
+    }
+}
+
+/// 提示词处理器 - 统一处理提示词预设注入和 snippet 展开
+///
+/// 处理链：
+/// 1. 加上全局前缀 / 后缀，再应用主预设（before/after/replace）到主提示词
+/// 2. 应用角色预设到角色提示词
+/// 3. 展开所有 snippet 引用
+/// 4. 从主正面提示词中删除全局屏蔽的标签
+#[derive(Debug, Clone)]
+pub struct PromptProcessor {
+    storage: Arc<CoreStorage>,
+    global_affix: GlobalAffix,
+    trim_separators: bool,
+}
+
+impl PromptProcessor {
+    pub fn new(storage: Arc<CoreStorage>) -> Self {
+        Self {
+            storage,
+            global_affix: GlobalAffix::default(),
+            trim_separators: true,
+        }
+    }
+
+    /// 设置在主预设之前加到正面提示词上的全局前缀 / 后缀
+    pub fn with_global_affix(mut self, affix: GlobalAffix) -> Self {
+        self.global_affix = affix;
+        self
+    }
+
+    /// 是否去掉最终正负面提示词首尾多余的逗号与空白（默认开启）
+    pub fn with_trim_separators(mut self, enabled: bool) -> Self {
+        self.trim_separators = enabled;
+        self
+    }
+
+    /// 最终清理：预设拼接或 snippet 展开为空时可能留下首尾的 `, `
+    fn finalize(&self, prompt: String) -> String {
+        if !self.trim_separators {
+            return prompt;
+        }
+        let trimmed = prompt.trim_matches(|c: char| c == ',' || c.is_whitespace());
+        if trimmed.len() == prompt.len() {
+            prompt
+        } else {
+            trimmed.to_string()
+        }
+    }
+
+    /// 执行 dry-run，返回处理链各阶段的结果
+    pub fn dry_run(
+        &self,
+        raw_positive: &str,
+        raw_negative: &str,
+        main_preset: &MainPresetSettings,
+        character_slots: &[CharacterSlotSettings],
+    ) -> CoreResult<DryRunResult> {
+        let resolver = SnippetResolver::new(Arc::clone(&self.storage));
+
+        // 步骤 1: 剥离注释
+        let positive_no_comment = PromptParser::strip_comments(raw_positive)
+            .map_err(|e| CoreError::invalid(format!("strip comments error: {}", e)))?;
+        let negative_no_comment = PromptParser::strip_comments(raw_negative)
+            .map_err(|e| CoreError::invalid(format!("strip comments error: {}", e)))?;
+
+        // 步骤 2: 加上全局前缀 / 后缀，再应用主预设
+        let positive_after_global =
+            (!self.global_affix.is_empty()).then(|| self.global_affix.apply(&positive_no_comment));
+        let positive_after_preset = self
+            .global_affix
+            .apply_with_preset(main_preset, &positive_no_comment);
+        let negative_after_preset = main_preset.apply_negative(&negative_no_comment);
+
+        // 步骤 3: 展开 snippet，并删除屏蔽标签
+        let (final_positive, mut blocked_tags) =
+            self.strip_blocked_tags(&resolver.expand(&positive_after_preset)?)?;
+        let final_positive = self.finalize(final_positive);
+        let final_negative = self.finalize(resolver.expand(&negative_after_preset)?);
+
+        // 步骤 4: 处理角色提示词
+        let mut processed_chars = Vec::new();
+        for slot in character_slots {
+            if !slot.enabled {
+                continue;
+            }
+            if slot.prompt.trim().is_empty() && slot.preset_id.is_none() {
+                continue;
+            }
+
+            // 先剥离注释
+            let char_positive_no_comment = PromptParser::strip_comments(&slot.prompt)
+                .map_err(|e| CoreError::invalid(format!("strip comments error: {}", e)))?;
+            let char_negative_no_comment = PromptParser::strip_comments(&slot.uc)
+                .map_err(|e| CoreError::invalid(format!("strip comments error: {}", e)))?;
+
+            let mut char_positive = char_positive_no_comment;
+            let mut char_negative = char_negative_no_comment;
+
+            // 应用角色预设
+            if let Some(preset_id) = slot.preset_id
+                && let Some(preset) = self.storage.get_preset(preset_id)?
+            {
+                char_positive = preset.apply(&char_positive);
+                char_negative = preset.apply_uc(&char_negative);
+            }
+
+            let after_preset = char_positive.clone();
+            let uc_after_preset = char_negative.clone();
+
+            // 展开 snippet，正面提示词同样删除屏蔽标签
+            let (final_char_prompt, char_blocked) =
+                self.strip_blocked_tags(&resolver.expand(&char_positive)?)?;
+            for tag in char_blocked {
+                if !blocked_tags.contains(&tag) {
+                    blocked_tags.push(tag);
+                }
+            }
+            let final_char_uc = resolver.expand(&char_negative)?;
+
+            processed_chars.push(ProcessedCharacterPrompt {
+                after_preset,
+                final_prompt: final_char_prompt,
+                uc_after_preset,
+                final_uc: final_char_uc,
+                enabled: true,
+            });
+        }
+
+        Ok(DryRunResult {
+            raw_positive: raw_positive.to_string(),
+            positive_after_global,
+            positive_after_preset,
+            final_positive,
+            raw_negative: raw_negative.to_string(),
+            negative_after_preset,
+            final_negative,
+            character_prompts: processed_chars,
+            blocked_tags,
+            quality_tags: None,
+        })
+    }
+
+    /// 删除提示词中被全局屏蔽的标签，返回结果与被删除的标签
+    fn strip_blocked_tags(&self, prompt: &str) -> CoreResult<(String, Vec<String>)> {
+        let blocked = self.storage.blocked_tag_set()?;
+        if blocked.is_empty() {
+            return Ok((prompt.to_string(), Vec::new()));
+        }
+        Ok(PromptParser::remove_tags(prompt, |tag| {
+            blocked.contains(&lexicon::normalize_tag(tag))
+        }))
+    }
+
+    /// 处理任务请求中的提示词，返回处理后的正面/负面提示词
+    ///
+    /// 处理链：剥离注释 -> 全局前缀 / 后缀 -> 注入主预设 -> 展开 snippet -> 删除屏蔽标签；角色提示词原地替换为展开并删除屏蔽标签后的版本（负面提示词不做屏蔽）
+    ///
+    /// `pre_expanded` 的任务只删除屏蔽标签，其余步骤在首次生成时已完成
+    pub fn process_task(&self, task: &mut GenerateTaskRequest) -> CoreResult<(String, String)> {
+        if task.pre_expanded {
+            // 已展开的提示词（如记录中保存的）只删除屏蔽标签，避免再次叠加前缀 / 后缀与预设
+            let (positive, _) = self.strip_blocked_tags(&task.raw_prompt)?;
+            return Ok((
+                self.finalize(positive),
+                self.finalize(task.negative_prompt.clone()),
+            ));
+        }
+        let resolver = SnippetResolver::new(Arc::clone(&self.storage));
+
+        // 步骤 1: 剥离注释
+        let positive_no_comment = PromptParser::strip_comments(&task.raw_prompt)
+            .map_err(|e| CoreError::invalid(format!("strip comments error: {}", e)))?;
+        let negative_no_comment = PromptParser::strip_comments(&task.negative_prompt)
+            .map_err(|e| CoreError::invalid(format!("strip comments error: {}", e)))?;
+
+        // 步骤 2: 加上全局前缀 / 后缀，再应用主预设
+        let positive_after_preset = self
+            .global_affix
+            .apply_with_preset(&task.main_preset, &positive_no_comment);
+        let negative_after_preset = task.main_preset.apply_negative(&negative_no_comment);
+
+        // 步骤 3: 展开主提示词中的 snippet，并删除屏蔽标签
+        let (final_positive, _) =
+            self.strip_blocked_tags(&resolver.expand(&positive_after_preset)?)?;
+        let final_positive = self.finalize(final_positive);
+        let final_negative = self.finalize(resolver.expand(&negative_after_preset)?);
+
+        // 步骤 4: 处理角色提示词（先剥离注释，再展开 snippet）
+        if let Some(ref mut chars) = task.params.character_prompts {
+            for char_prompt in chars.iter_mut() {
+                let prompt_no_comment = PromptParser::strip_comments(&char_prompt.prompt)
+                    .map_err(|e| CoreError::invalid(format!("strip comments error: {}", e)))?;
+                let uc_no_comment = PromptParser::strip_comments(&char_prompt.uc)
+                    .map_err(|e| CoreError::invalid(format!("strip comments error: {}", e)))?;
+                char_prompt.prompt = self
+                    .strip_blocked_tags(&resolver.expand(&prompt_no_comment)?)?
+                    .0;
+                char_prompt.uc = resolver.expand(&uc_no_comment)?;
+            }
+        }
+
+        Ok((final_positive, final_negative))
+    }
+
+    /// 提交前预览任务：处理提示词、校验参数并估算 Anlas 消耗，不发送请求
+    ///
+    /// `opus` 为 true 时按 Opus 订阅的免费额度计算
+    pub fn preview_task(
+        &self,
+        task: &GenerateTaskRequest,
+        weight_range: WeightRange,
+        opus: bool,
+    ) -> CoreResult<TaskPreview> {
+        let snippet_exists =
+            |name: &str| !matches!(self.storage.get_snippet_by_name(name), Ok(None));
+        let positive_diagnostics =
+            PromptParser::lint(&task.raw_prompt, weight_range, snippet_exists);
+        let negative_diagnostics =
+            PromptParser::lint(&task.negative_prompt, weight_range, snippet_exists);
+
+        let mut task = task.clone();
+        let (positive, negative) = self.process_task(&mut task)?;
+        let seed = task.params.fixed_seed().unwrap_or(0);
+        let mut req = to_nai_request(&task.params, &positive, &negative, seed);
+        let warnings = req.validate(weight_range);
+        let per_image = req.estimated_anlas(opus);
+
+        Ok(TaskPreview {
+            final_positive: req.prompt_positive,
+            final_negative: req.prompt_negative,
+            character_prompts: req.character_prompts,
+            warnings,
+            positive_diagnostics,
+            negative_diagnostics,
+            estimated_anlas: AnlasEstimate {
+                per_image,
+                total: per_image * task.count.max(1),
+            },
+        })
+    }
+
+    /// 按完整处理流程构建第一张图片将发送给 NovelAI 的请求体（不含鉴权），不发送请求
+    pub fn debug_payload(
+        &self,
+        task: &GenerateTaskRequest,
+        weight_range: WeightRange,
+    ) -> CoreResult<serde_json::Value> {
+        let mut task = task.clone();
+        let (positive, negative) = self.process_task(&mut task)?;
+        let seed = task.params.image_seeds(0, 1)[0];
+        let mut req = to_nai_request(&task.params, &positive, &negative, seed);
+        req.validate(weight_range);
+        Ok(codex_api::build_payload(&req, seed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GenerationParams, test_support::TestStorage};
+
+    #[test]
+    fn test_final_prompts_trim_stray_separators() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        let processor = PromptProcessor::new(Arc::clone(&storage));
+
+        // before 为空白、原提示词以逗号开头
+        let mut task = GenerateTaskRequest::new(" , middle, end".into(), "blurry, ".into());
+        task.main_preset = MainPresetSettings {
+            before: Some("   ".into()),
+            uc_after: Some(" ".into()),
+            ..Default::default()
+        };
+        let (positive, negative) = processor.process_task(&mut task).unwrap();
+        assert_eq!(positive, "middle, end");
+        assert_eq!(negative, "blurry");
+        assert_eq!(task.raw_prompt, " , middle, end");
+
+        // 原提示词为空时 before 之后留下的分隔符
+        let mut task = GenerateTaskRequest::new(String::new(), String::new());
+        task.main_preset = MainPresetSettings {
+            before: Some("masterpiece".into()),
+            after: Some("  ".into()),
+            uc_before: Some("lowres".into()),
+            ..Default::default()
+        };
+        let (positive, negative) = processor.process_task(&mut task).unwrap();
+        assert_eq!(positive, "masterpiece");
+        assert_eq!(negative, "lowres");
+
+        let (positive, _) = PromptProcessor::new(Arc::clone(&storage))
+            .with_trim_separators(false)
+            .process_task(&mut task)
+            .unwrap();
+        assert_eq!(positive, "masterpiece, ");
+    }
+
+    #[test]
+    fn test_global_affix_applied_inside_main_preset() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        let processor = PromptProcessor::new(Arc::clone(&storage)).with_global_affix(GlobalAffix {
+            prefix: Some("house style".into()),
+            suffix: Some("signature".into()),
+        });
+        let main_preset = MainPresetSettings {
+            before: Some("best quality".into()),
+            after: Some("outdoors".into()),
+            ..Default::default()
+        };
+
+        let result = processor
+            .dry_run("1girl // note //", "", &main_preset, &[])
+            .unwrap();
+        assert_eq!(
+            result.positive_after_global.as_deref(),
+            Some("house style, 1girl , signature")
+        );
+        assert_eq!(
+            result.positive_after_preset,
+            "best quality, house style, 1girl , signature, outdoors"
+        );
+
+        let mut task = GenerateTaskRequest::new("1girl".into(), String::new());
+        task.main_preset = main_preset;
+        let (positive, _) = processor.process_task(&mut task).unwrap();
+        assert_eq!(
+            positive,
+            "best quality, house style, 1girl, signature, outdoors"
+        );
+
+        // replace 覆盖原提示词时前缀 / 后缀加在替换内容上
+        let replace = MainPresetSettings {
+            replace: Some("2boys".into()),
+            ..Default::default()
+        };
+        let result = processor.dry_run("1girl", "", &replace, &[]).unwrap();
+        assert_eq!(
+            result.positive_after_preset,
+            "house style, 2boys, signature"
+        );
+        let mut task = GenerateTaskRequest::new("1girl".into(), String::new());
+        task.main_preset = replace;
+        let (positive, _) = processor.process_task(&mut task).unwrap();
+        assert_eq!(positive, "house style, 2boys, signature");
+
+        // 未配置时不出现该阶段
+        let result = PromptProcessor::new(Arc::clone(&storage))
+            .dry_run("1girl", "", &MainPresetSettings::default(), &[])
+            .unwrap();
+        assert_eq!(result.positive_after_global, None);
+    }
+
+    #[test]
+    fn test_dry_run_quality_tags_match_payload() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+
+        let mut result = PromptProcessor::new(Arc::clone(&storage))
+            .dry_run("1girl", "", &MainPresetSettings::default(), &[])
+            .unwrap();
+        result.apply_quality_tags(Model::V45Curated, false);
+        assert_eq!(result.final_positive, "1girl");
+        assert_eq!(result.quality_tags, None);
+
+        result.apply_quality_tags(Model::V45Curated, true);
+        let params = GenerationParams {
+            model: Model::V45Curated,
+            add_quality_tags: true,
+            ..Default::default()
+        };
+        let payload = codex_api::build_payload(&to_nai_request(&params, "1girl", "", 1), 1);
+        assert_eq!(payload["input"], result.final_positive);
+        assert_eq!(
+            result.quality_tags.as_deref(),
+            Some(Model::V45Curated.quality_tags())
+        );
+    }
+}
@@ -13,6 +13,7 @@
 //! - 底层: 逗号分隔的提示词序列 (tags)
 //! - 上层: 权重修饰层 (weight layer)
 
+use codex_api::{Model, WeightRange};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -171,6 +172,153 @@ pub struct HighlightSpan {
     pub span_type: String,
 }
 
+/// 标签的有效权重
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagWeight {
+    /// 标签文本（snippet 引用保持 `<snippet:name>` 形式）
+    pub name: String,
+    /// 有效权重；同一标签多次出现时取平均值
+    pub weight: f64,
+    /// 出现次数
+    pub occurrences: usize,
+}
+
+/// 估算的 CLIP 分块中的一个标签
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkedTag {
+    /// 标签文本（snippet 引用保持 `<snippet:name>` 形式）
+    pub tag: String,
+    /// 标签起始 token 所在的分块（从 0 开始）
+    pub chunk: usize,
+    /// 标签起始 token 的序号
+    pub start_token: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// 提示词的分块估算结果，只是粗略估计，并非真实分词
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkEstimate {
+    /// 估算的 token 数
+    pub tokens: usize,
+    /// 占用的分块数，每块 [`CHUNK_TOKENS`] 个 token
+    pub chunks: usize,
+    /// 按出现顺序列出的标签
+    pub tags: Vec<ChunkedTag>,
+}
+
+/// CLIP 每个分块可容纳的 token 数（不含首尾标记）
+pub const CHUNK_TOKENS: usize = 75;
+
+/// snippet 引用在上下文中的有效权重
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnippetWeight {
+    /// snippet 名称
+    pub name: String,
+    /// 由外层括号与冒号权重算出的权重，展开后会丢失
+    pub weight: f64,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// 诊断严重程度
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// 会导致生成失败
+    Error,
+    /// 可以生成，但结果可能不符合预期
+    Warning,
+}
+
+/// 单个 token 的可读说明
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenExplanation {
+    /// 字节偏移范围
+    pub start: usize,
+    pub end: usize,
+    /// token 类型，与 `Token` 序列化的 `type` 一致
+    pub kind: String,
+    pub text: String,
+    pub description: String,
+}
+
+/// 提示词检查结果
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    /// 字节偏移范围
+    pub start: usize,
+    pub end: usize,
+    pub severity: Severity,
+    /// 机器可读的诊断代码
+    pub code: String,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn new(start: usize, end: usize, severity: Severity, code: &str, message: String) -> Self {
+        Self {
+            start,
+            end,
+            severity,
+            code: code.to_string(),
+            message,
+        }
+    }
+}
+
+/// 格式化选项，默认值即 `PromptParser::format` 的行为
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FormatOptions {
+    /// 将连续空白压缩为单个空格（换行后的缩进除外）
+    pub collapse_whitespace: bool,
+    /// 逗号后缺少空格时补上
+    pub comma_space: bool,
+    /// 最多保留的连续空行数
+    pub max_blank_lines: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            collapse_whitespace: true,
+            comma_space: true,
+            max_blank_lines: 1,
+        }
+    }
+}
+
+/// 将一段提示词拆分为多个角色提示词的方式，默认按 `|` 拆分
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CharacterSplit {
+    /// 按分隔符拆分
+    Delimiter { delimiter: String },
+    /// 遇到 `girl:` 这类标记时开始新角色，标记词保留为该角色的第一个标签
+    Markers { markers: Vec<String> },
+}
+
+impl Default for CharacterSplit {
+    fn default() -> Self {
+        Self::Delimiter {
+            delimiter: "|".to_string(),
+        }
+    }
+}
+
+/// 逗号分隔的标签片段（字节偏移）
+struct TagSegment {
+    /// 片段去除首尾空白后的范围
+    start: usize,
+    end: usize,
+    /// 标签及其成对包裹的权重语法
+    core_start: usize,
+    core_end: usize,
+    /// 标签的有效权重
+    weight: f64,
+}
+
 /// 注释信息
 #[derive(Debug, Clone)]
 pub struct CommentSpan {
@@ -343,19 +491,18 @@ impl PromptParser {
             }
 
             // 检查冒号权重语法: `number::`
-            if ch.is_ascii_digit() || ch == '-' || ch == '.' {
-                if let Some((weight_val, consumed, end_byte)) =
+            if (ch.is_ascii_digit() || ch == '-' || ch == '.')
+                && let Some((weight_val, consumed, end_byte)) =
                     Self::try_parse_weight_start(&chars, pos, input)
-                {
-                    tokens.push(Token::WeightStart {
-                        value: weight_val,
-                        start: byte_pos,
-                        end: end_byte,
-                    });
-                    colon_weight = Some(weight_val);
-                    pos += consumed;
-                    continue;
-                }
+            {
+                tokens.push(Token::WeightStart {
+                    value: weight_val,
+                    start: byte_pos,
+                    end: end_byte,
+                });
+                colon_weight = Some(weight_val);
+                pos += consumed;
+                continue;
             }
 
             // 检查权重结束 `::`
@@ -464,20 +611,19 @@ impl PromptParser {
             }
 
             // 检查 snippet 引用: `<snippet:name>`
-            if ch == '<' {
-                if let Some((name, consumed, end_byte)) =
+            if ch == '<'
+                && let Some((name, consumed, end_byte)) =
                     Self::try_parse_snippet_ref(&chars, pos, input)
-                {
-                    let weight = Self::calculate_weight(brace_depth, bracket_depth, colon_weight);
-                    tokens.push(Token::SnippetRef {
-                        name,
-                        start: byte_pos,
-                        end: end_byte,
-                        weight,
-                    });
-                    pos += consumed;
-                    continue;
-                }
+            {
+                let weight = Self::calculate_weight(brace_depth, bracket_depth, colon_weight);
+                tokens.push(Token::SnippetRef {
+                    name,
+                    start: byte_pos,
+                    end: end_byte,
+                    weight,
+                });
+                pos += consumed;
+                continue;
             }
 
             // 普通文本 - 收集直到遇到特殊字符
@@ -494,17 +640,25 @@ impl PromptParser {
                     || c == ','
                     || c == '\n'
                     || c == '\r'
-                    || c == '<'
-                    || (c == ':' && pos + 1 < chars.len() && chars[pos + 1].1 == ':')
-                    || (c == '/' && pos + 1 < chars.len() && chars[pos + 1].1 == '/')
+                    // 位于开头的 `<` 不是 snippet 引用、`::` 不是权重结束，按普通文本处理，避免死循环
+                    || (c == '<' && !text.is_empty())
+                    || (c == ':'
+                        && pos + 1 < chars.len()
+                        && chars[pos + 1].1 == ':'
+                        && !text.is_empty())
+                    // 位于开头的 `//` 是未闭合的注释，按普通文本处理，避免死循环
+                    || (c == '/'
+                        && pos + 1 < chars.len()
+                        && chars[pos + 1].1 == '/'
+                        && !text.is_empty())
                 {
                     break;
                 }
                 // 检查是否是权重开始
-                if c.is_ascii_digit() || c == '-' || c == '.' {
-                    if Self::try_parse_weight_start(&chars, pos, input).is_some() {
-                        break;
-                    }
+                if (c.is_ascii_digit() || c == '-' || c == '.')
+                    && Self::try_parse_weight_start(&chars, pos, input).is_some()
+                {
+                    break;
                 }
                 text.push(c);
                 text_end = b + c.len_utf8();
@@ -754,11 +908,675 @@ impl PromptParser {
         spans
     }
 
-    /// 格式化提示词
+    /// 列出每个 snippet 引用及其上下文权重，按出现顺序，不合并重复引用
+    pub fn snippet_weights(input: &str) -> Vec<SnippetWeight> {
+        Self::parse(input)
+            .tokens
+            .into_iter()
+            .filter_map(|token| match token {
+                Token::SnippetRef {
+                    name,
+                    start,
+                    end,
+                    weight,
+                } => Some(SnippetWeight {
+                    name,
+                    weight,
+                    start,
+                    end,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// 计算每个标签的有效权重（已考虑括号与冒号权重）
+    /// 按首次出现顺序返回，注释中的内容不计入
+    pub fn weight_map(input: &str) -> Vec<TagWeight> {
+        let result = Self::parse(input);
+        let mut tags: Vec<TagWeight> = Vec::new();
+
+        for token in &result.tokens {
+            let (name, weight) = match token {
+                Token::Text { value, weight, .. } => (value.trim().to_string(), *weight),
+                Token::SnippetRef { name, weight, .. } => (format!("<snippet:{}>", name), *weight),
+                _ => continue,
+            };
+            if name.is_empty() {
+                continue;
+            }
+
+            match tags.iter_mut().find(|t| t.name == name) {
+                Some(tag) => {
+                    // 增量更新平均值
+                    tag.occurrences += 1;
+                    tag.weight += (weight - tag.weight) / tag.occurrences as f64;
+                }
+                None => tags.push(TagWeight {
+                    name,
+                    weight,
+                    occurrences: 1,
+                }),
+            }
+        }
+
+        tags
+    }
+
+    /// 在字节偏移处插入标签，自动维护 `, ` 分隔
+    /// - 偏移落在标签内部时移动到该标签末尾，避免拆开标签
+    /// - 开头不加前导逗号，已有逗号时不重复添加
+    /// - 紧贴括号、冒号权重或换行时不添加逗号
+    ///
+    /// 返回 (新提示词, 插入内容之后的光标字节偏移)
+    pub fn insert_tag(input: &str, offset: usize, tag: &str) -> (String, usize) {
+        let tag = tag.trim();
+        let mut offset = offset.min(input.len());
+        while !input.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        if tag.is_empty() {
+            return (input.to_string(), offset);
+        }
+
+        let result = Self::parse(input);
+        // 偏移在 token 内部时移动到 token 末尾
+        if let Some(token) = result.tokens.iter().find(|t| {
+            t.start() < offset && offset < t.end() && !matches!(t, Token::Whitespace { .. })
+        }) {
+            offset = token.end();
+        }
+
+        let prev = result
+            .tokens
+            .iter()
+            .rev()
+            .find(|t| t.end() <= offset && !matches!(t, Token::Whitespace { .. }));
+        let next = result
+            .tokens
+            .iter()
+            .find(|t| t.start() >= offset && !matches!(t, Token::Whitespace { .. }));
+
+        let (before, after) = input.split_at(offset);
+        let mut output = String::with_capacity(input.len() + tag.len() + 4);
+
+        match prev {
+            None
+            | Some(
+                Token::BraceOpen { .. }
+                | Token::BracketOpen { .. }
+                | Token::WeightStart { .. }
+                | Token::Newline { .. },
+            ) => output.push_str(before),
+            Some(Token::Comma { .. }) => {
+                output.push_str(before.trim_end());
+                output.push(' ');
+            }
+            Some(_) => {
+                output.push_str(before.trim_end());
+                output.push_str(", ");
+            }
+        }
+        output.push_str(tag);
+
+        let cursor;
+        match next {
+            None
+            | Some(
+                Token::BraceClose { .. }
+                | Token::BracketClose { .. }
+                | Token::WeightEnd { .. }
+                | Token::Newline { .. },
+            ) => {
+                cursor = output.len();
+                output.push_str(after);
+            }
+            Some(Token::Comma { .. }) => {
+                cursor = output.len();
+                output.push_str(after.trim_start());
+            }
+            Some(_) => {
+                output.push_str(", ");
+                cursor = output.len();
+                output.push_str(after.trim_start());
+            }
+        }
+
+        (output, cursor)
+    }
+
+    /// 为每个 token 生成可读说明（跳过空白与换行）
+    ///
+    /// `tag_meaning` 返回标签的释义（如词库中的中文名），`snippet_len` 返回 snippet 内容的字符数
+    pub fn explain(
+        input: &str,
+        tag_meaning: impl Fn(&str) -> Option<String>,
+        snippet_len: impl Fn(&str) -> Option<usize>,
+    ) -> Vec<TokenExplanation> {
+        let result = Self::parse(input);
+        let mut explanations = Vec::new();
+
+        for token in &result.tokens {
+            let (kind, description) = match token {
+                Token::Whitespace { .. } | Token::Newline { .. } => continue,
+                Token::Text { value, weight, .. } => {
+                    let mut description = match tag_meaning(value.trim()) {
+                        Some(meaning) => format!("标签「{meaning}」"),
+                        None => "标签".to_string(),
+                    };
+                    if (weight - 1.0).abs() > 1e-9 {
+                        description.push_str(&format!("，权重 ×{}", Self::format_weight(*weight)));
+                    }
+                    ("text", description)
+                }
+                Token::Comma { .. } => ("comma", "分隔符".to_string()),
+                Token::BraceOpen { depth, .. } => (
+                    "brace_open",
+                    format!(
+                        "增强第 {depth} 层，权重 ×{}",
+                        Self::format_weight(WEIGHT_MULTIPLIER.powi(*depth))
+                    ),
+                ),
+                Token::BraceClose { depth, .. } => {
+                    ("brace_close", format!("增强结束，回到第 {depth} 层"))
+                }
+                Token::BracketOpen { depth, .. } => (
+                    "bracket_open",
+                    format!(
+                        "减弱第 {depth} 层，权重 ×{}",
+                        Self::format_weight(1.0 / WEIGHT_MULTIPLIER.powi(*depth))
+                    ),
+                ),
+                Token::BracketClose { depth, .. } => {
+                    ("bracket_close", format!("减弱结束，回到第 {depth} 层"))
+                }
+                Token::WeightStart { value, .. } => (
+                    "weight_start",
+                    format!("权重 {} 开始，直到 '::'", Self::format_weight(*value)),
+                ),
+                Token::WeightEnd { .. } => ("weight_end", "冒号权重结束".to_string()),
+                Token::SnippetRef { name, .. } => {
+                    let description = match snippet_len(name) {
+                        Some(len) => format!("插入 snippet '{name}'（{len} 个字符）"),
+                        None => format!("snippet '{name}' 不存在"),
+                    };
+                    ("snippet_ref", description)
+                }
+                Token::Comment { .. } => ("comment", "注释，发送前会被移除".to_string()),
+            };
+            explanations.push(TokenExplanation {
+                start: token.start(),
+                end: token.end(),
+                kind: kind.to_string(),
+                text: input[token.start()..token.end()].to_string(),
+                description,
+            });
+        }
+        explanations
+    }
+
+    /// 检查提示词中的常见问题，按起始位置排序
+    /// - 引用不存在的 snippet、未闭合的注释（error）
+    /// - 未闭合或多余的括号、未结束的冒号权重、空的冒号权重区域（warning）
+    /// - 超出 `weight_range` 的冒号权重，范围为该 `weight_num` span（warning）
+    ///
+    /// `snippet_exists` 用于判断 snippet 名称是否存在
+    pub fn lint(
+        input: &str,
+        weight_range: WeightRange,
+        snippet_exists: impl Fn(&str) -> bool,
+    ) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+
+        if let Err(ParseError::UnclosedComment(start)) = Self::strip_comments(input) {
+            diagnostics.push(Diagnostic::new(
+                start,
+                input.len(),
+                Severity::Error,
+                "unclosed_comment",
+                "注释没有结束符 '//'".to_string(),
+            ));
+        }
+
+        let result = Self::parse(input);
+        let mut braces: Vec<&Token> = Vec::new();
+        let mut brackets: Vec<&Token> = Vec::new();
+        // 当前冒号权重区域的开始 token，以及区域内是否有内容
+        let mut weight: Option<(&Token, bool)> = None;
+
+        for token in &result.tokens {
+            match token {
+                Token::BraceOpen { .. } => braces.push(token),
+                Token::BracketOpen { .. } => brackets.push(token),
+                Token::BraceClose { start, end, .. } if braces.pop().is_none() => {
+                    diagnostics.push(Diagnostic::new(
+                        *start,
+                        *end,
+                        Severity::Warning,
+                        "unmatched_brace",
+                        "多余的 '}'".to_string(),
+                    ));
+                }
+                Token::BracketClose { start, end, .. } if brackets.pop().is_none() => {
+                    diagnostics.push(Diagnostic::new(
+                        *start,
+                        *end,
+                        Severity::Warning,
+                        "unmatched_bracket",
+                        "多余的 ']'".to_string(),
+                    ));
+                }
+                Token::WeightStart { value, start, end } => {
+                    if !weight_range.contains(*value) {
+                        diagnostics.push(Diagnostic::new(
+                            *start,
+                            *end,
+                            Severity::Warning,
+                            "weight_out_of_range",
+                            format!(
+                                "权重 {} 超出范围 {}..={}，生成时会被修正",
+                                value, weight_range.min, weight_range.max
+                            ),
+                        ));
+                    }
+                    weight = Some((token, false));
+                }
+                Token::WeightEnd { end, .. } => {
+                    if let Some((open, false)) = weight {
+                        diagnostics.push(Diagnostic::new(
+                            open.start(),
+                            *end,
+                            Severity::Warning,
+                            "empty_weight",
+                            "冒号权重区域内没有内容".to_string(),
+                        ));
+                    }
+                    weight = None;
+                }
+                Token::Text { .. } => {
+                    if let Some((_, has_content)) = weight.as_mut() {
+                        *has_content = true;
+                    }
+                }
+                Token::SnippetRef {
+                    name, start, end, ..
+                } => {
+                    if let Some((_, has_content)) = weight.as_mut() {
+                        *has_content = true;
+                    }
+                    if !snippet_exists(name) {
+                        diagnostics.push(Diagnostic::new(
+                            *start,
+                            *end,
+                            Severity::Error,
+                            "unknown_snippet",
+                            format!("snippet 不存在：{}", name),
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for open in braces {
+            diagnostics.push(Diagnostic::new(
+                open.start(),
+                open.end(),
+                Severity::Warning,
+                "unclosed_brace",
+                "未闭合的 '{'，会影响后续所有提示词".to_string(),
+            ));
+        }
+        for open in brackets {
+            diagnostics.push(Diagnostic::new(
+                open.start(),
+                open.end(),
+                Severity::Warning,
+                "unclosed_bracket",
+                "未闭合的 '['，会影响后续所有提示词".to_string(),
+            ));
+        }
+        if let Some((open, _)) = weight {
+            diagnostics.push(Diagnostic::new(
+                open.start(),
+                open.end(),
+                Severity::Warning,
+                "unclosed_weight",
+                "冒号权重没有结束符 '::'".to_string(),
+            ));
+        }
+
+        diagnostics.sort_by_key(|d| d.start);
+        diagnostics
+    }
+
+    /// 将第 `tag_index` 个标签移动到第 `new_index` 个位置（按逗号分隔的标签计数）
+    /// - 完整包裹标签的括号或冒号权重随标签一起移动
+    /// - 跨标签的权重区域留在原处；移动后有效权重变化时自动补偿
+    ///   （1.05 的整数次幂用 `{}`/`[]`，否则用冒号权重，必要时重新开启所在区域）
+    ///
+    /// `tag_index` 越界时原样返回，`new_index` 越界时移动到末尾
+    pub fn move_tag(input: &str, tag_index: usize, new_index: usize) -> String {
+        let segments = Self::tag_segments(input);
+        let Some(seg) = segments.get(tag_index) else {
+            return input.to_string();
+        };
+        let new_index = new_index.min(segments.len() - 1);
+        if new_index == tag_index {
+            return input.to_string();
+        }
+
+        let core = &input[seg.core_start..seg.core_end];
+        let (remove_start, remove_end) = Self::removal_range(input, seg);
+        let rest = format!("{}{}", &input[..remove_start], &input[remove_end..]);
+
+        // 插入到目标标签片段之前，或最后一个标签之后
+        let rest_segments = Self::tag_segments(&rest);
+        let (offset, prefix, suffix) = match rest_segments.get(new_index) {
+            Some(target) => (target.start, "", ", "),
+            None => match rest_segments.last() {
+                Some(last) => (last.end, ", ", ""),
+                None => (rest.len(), "", ""),
+            },
+        };
+        let place = |tag: &str| {
+            format!(
+                "{}{}{}{}{}",
+                &rest[..offset],
+                prefix,
+                tag,
+                suffix,
+                &rest[offset..]
+            )
+        };
+
+        let moved = place(core);
+        let core_offset = offset + prefix.len();
+        let result = Self::parse(&moved);
+        let got = result
+            .tokens
+            .iter()
+            .find(|t| t.start() >= core_offset && t.weight().is_some())
+            .and_then(Token::weight)
+            .unwrap_or(seg.weight);
+        if (got - seg.weight).abs() < 1e-9 {
+            return moved;
+        }
+
+        // 有效权重改变：按比例补偿
+        let ratio = seg.weight / got;
+        let steps = ratio.ln() / WEIGHT_MULTIPLIER.ln();
+        if (steps - steps.round()).abs() < 1e-6 {
+            let n = steps.round() as i32;
+            let (open, close) = if n > 0 { ("{", "}") } else { ("[", "]") };
+            let n = n.unsigned_abs() as usize;
+            return place(&format!("{}{}{}", open.repeat(n), core, close.repeat(n)));
+        }
+
+        // 目标位置所在的冒号权重区域；插入的 `::` 会提前结束该区域，需要重新开启
+        let region = result
+            .tokens
+            .iter()
+            .rev()
+            .filter(|t| t.end() <= offset)
+            .find_map(|t| match t {
+                Token::WeightStart { value, .. } => Some(Some(*value)),
+                Token::WeightEnd { .. } => Some(None),
+                _ => None,
+            })
+            .flatten();
+        let weight = region.unwrap_or(1.0) * ratio;
+        let mut wrapped = format!("{}::{} ::", Self::format_weight(weight), core);
+        if let Some(region) = region {
+            wrapped.push_str(&format!("{}::", Self::format_weight(region)));
+        }
+        place(&wrapped)
+    }
+
+    /// 删除整段匹配 `is_blocked` 的标签（连同其包裹的权重语法与一个相邻逗号），返回结果与被删除的标签
+    ///
+    /// 标签文本为片段内文本 token 拼接后去除首尾空白，不含 snippet 引用；按整段匹配，不匹配子串
+    pub fn remove_tags(input: &str, is_blocked: impl Fn(&str) -> bool) -> (String, Vec<String>) {
+        let mut output = input.to_string();
+        let mut removed = Vec::new();
+        loop {
+            let result = Self::parse(&output);
+            let found = Self::tag_segments(&output).into_iter().find_map(|seg| {
+                let text: String = result
+                    .tokens
+                    .iter()
+                    .filter_map(|t| match t {
+                        Token::Text { value, start, .. }
+                            if (seg.core_start..seg.core_end).contains(start) =>
+                        {
+                            Some(value.as_str())
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                let text = text.trim();
+                (!text.is_empty() && is_blocked(text)).then(|| (seg, text.to_string()))
+            });
+            let Some((seg, tag)) = found else {
+                return (output, removed);
+            };
+            let (start, end) = Self::removal_range(&output, &seg);
+            output.replace_range(start..end, "");
+            removed.push(tag);
+        }
+    }
+
+    /// 按逗号切分出包含标签的片段
+    fn tag_segments(input: &str) -> Vec<TagSegment> {
+        let result = Self::parse(input);
+        result
+            .tokens
+            .split(|t| matches!(t, Token::Comma { .. }))
+            .filter_map(Self::tag_segment)
+            .collect()
+    }
+
+    fn tag_segment(tokens: &[Token]) -> Option<TagSegment> {
+        let is_blank = |t: &Token| {
+            matches!(
+                t,
+                Token::Whitespace { .. } | Token::Newline { .. } | Token::Comment { .. }
+            )
+        };
+        let mut first = tokens.iter().position(|t| t.weight().is_some())?;
+        let mut last = tokens.iter().rposition(|t| t.weight().is_some())?;
+        let weight = tokens[first].weight()?;
+
+        // 向外吸收成对包裹标签的括号或冒号权重
+        loop {
+            let prev = tokens[..first].iter().rposition(|t| !is_blank(t));
+            let next = tokens[last + 1..]
+                .iter()
+                .position(|t| !is_blank(t))
+                .map(|i| last + 1 + i);
+            match (prev, next) {
+                (Some(p), Some(n))
+                    if matches!(
+                        (&tokens[p], &tokens[n]),
+                        (Token::BraceOpen { .. }, Token::BraceClose { .. })
+                            | (Token::BracketOpen { .. }, Token::BracketClose { .. })
+                            | (Token::WeightStart { .. }, Token::WeightEnd { .. })
+                    ) =>
+                {
+                    first = p;
+                    last = n;
+                }
+                _ => break,
+            }
+        }
+
+        // 文本 token 可能带有尾随空白（例如 `tag ::`）
+        let core_end = match &tokens[last] {
+            Token::Text { value, start, .. } => start + value.trim_end().len(),
+            t => t.end(),
+        };
+        Some(TagSegment {
+            start: tokens.iter().find(|t| !is_blank(t))?.start(),
+            end: tokens.iter().rev().find(|t| !is_blank(t))?.end(),
+            core_start: tokens[first].start(),
+            core_end,
+            weight,
+        })
+    }
+
+    /// 移除标签时连带删除的范围：标签本身、一个相邻逗号及其间的空白
+    fn removal_range(input: &str, seg: &TagSegment) -> (usize, usize) {
+        let result = Self::parse(input);
+        let is_space = |t: &&Token| matches!(t, Token::Whitespace { .. } | Token::Newline { .. });
+
+        // 优先删除后面的逗号，直到下一个非空白 token
+        let mut after = result
+            .tokens
+            .iter()
+            .skip_while(|t| t.start() < seg.core_end);
+        let mut after_ws = after.by_ref().skip_while(is_space);
+        if let Some(Token::Comma { end, .. }) = after_ws.next() {
+            let next = after_ws
+                .find(|t| !is_space(t))
+                .map_or(input.len(), Token::start);
+            // 文本 token 的尾随空白已计入 core_end 之后
+            return (seg.core_start, next.max(*end));
+        }
+
+        // 否则删除前面的逗号，从上一个非空白 token 末尾开始
+        let mut before = result
+            .tokens
+            .iter()
+            .rev()
+            .skip_while(|t| t.end() > seg.core_start);
+        let mut before_ws = before.by_ref().skip_while(is_space);
+        if let Some(Token::Comma { start, .. }) = before_ws.next() {
+            let prev = before_ws
+                .find(|t| !is_space(t))
+                .map_or(0, |t| t.end().min(*start));
+            return (prev, seg.core_end);
+        }
+
+        (seg.core_start, seg.core_end)
+    }
+
+    /// 格式化冒号权重数值，去除多余的小数位
+    fn format_weight(value: f64) -> String {
+        let text = format!("{:.4}", value);
+        text.trim_end_matches('0').trim_end_matches('.').to_string()
+    }
+
+    /// 按默认方式（`|` 分隔）拆分角色提示词
+    pub fn split_characters(input: &str) -> Vec<String> {
+        Self::split_characters_with(input, &CharacterSplit::default())
+    }
+
+    /// 将描述多个角色的提示词拆分为各角色的片段
+    /// - 注释内的分隔符或标记不参与拆分
+    /// - 标记不区分大小写，须位于开头或逗号、空白之后，且不能是 `::` 权重
+    /// - 片段去除首尾的空白与逗号，空片段被丢弃
+    pub fn split_characters_with(input: &str, split: &CharacterSplit) -> Vec<String> {
+        let comments = Self::find_comments(input);
+        let in_comment = |pos: usize| comments.iter().any(|c| pos >= c.start && pos < c.end);
+
+        // (切分位置, 下一段正文起点, 下一段的标记词)
+        let mut cuts: Vec<(usize, usize, Option<&str>)> = Vec::new();
+        match split {
+            CharacterSplit::Delimiter { delimiter } if !delimiter.is_empty() => {
+                for (pos, _) in input.match_indices(delimiter.as_str()) {
+                    if !in_comment(pos) {
+                        cuts.push((pos, pos + delimiter.len(), None));
+                    }
+                }
+            }
+            CharacterSplit::Delimiter { .. } => {}
+            CharacterSplit::Markers { markers } => {
+                for (pos, _) in input.char_indices() {
+                    let at_boundary = input[..pos]
+                        .chars()
+                        .next_back()
+                        .is_none_or(|c| c == ',' || c.is_whitespace());
+                    if !at_boundary || in_comment(pos) {
+                        continue;
+                    }
+                    let rest = &input[pos..];
+                    let found = markers.iter().find_map(|marker| {
+                        let head = rest.get(..marker.len())?;
+                        let after = &rest[marker.len()..];
+                        let matched = !marker.is_empty()
+                            && head.eq_ignore_ascii_case(marker)
+                            && after.starts_with(':')
+                            && !after.starts_with("::");
+                        matched.then_some(head)
+                    });
+                    if let Some(head) = found {
+                        cuts.push((pos, pos + head.len() + 1, Some(head)));
+                    }
+                }
+            }
+        }
+
+        let mut segments = Vec::new();
+        let mut start = 0;
+        let mut marker: Option<&str> = None;
+        for (cut, next, next_marker) in
+            cuts.into_iter()
+                .chain(std::iter::once((input.len(), input.len(), None)))
+        {
+            let body = input[start..cut].trim_matches(|c: char| c == ',' || c.is_whitespace());
+            let segment = match marker {
+                Some(marker) if body.is_empty() => marker.to_string(),
+                Some(marker) => format!("{marker}, {body}"),
+                None => body.to_string(),
+            };
+            if !segment.is_empty() {
+                segments.push(segment);
+            }
+            start = next;
+            marker = next_marker;
+        }
+        segments
+    }
+
+    /// 将从 NovelAI 官网复制的提示词规范化为本工具的格式
+    /// - 反斜杠转义的括号 `\(` `\)` 还原为普通括号（NAI 中括号本就是字面字符）
+    /// - 全角逗号转换为半角逗号
+    /// - 去除末尾由“质量标签”开关自动追加的内容（本工具生成时会自行追加）
+    /// - 最后按 [`PromptParser::format`] 统一空格
+    pub fn from_novelai(input: &str) -> String {
+        let mut normalized = String::with_capacity(input.len());
+        let mut chars = input.chars().peekable();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '\\' if matches!(chars.peek(), Some('(' | ')' | '\\')) => {
+                    normalized.extend(chars.next());
+                }
+                '，' => normalized.push(','),
+                _ => normalized.push(ch),
+            }
+        }
+
+        let mut trimmed = normalized.trim_end();
+        for model in Model::ALL {
+            if let Some(rest) = trimmed.strip_suffix(model.quality_tags()) {
+                trimmed = rest.trim_end();
+                break;
+            }
+        }
+
+        Self::format(trimmed)
+    }
+
+    /// 按默认选项格式化提示词
     /// - 逗号后添加空格
     /// - 权重结束 `::` 前添加空格
-    /// - 限制连续空行最多 2 行
+    /// - 压缩连续空白，最多保留 1 个空行
     pub fn format(input: &str) -> String {
+        Self::format_with(input, FormatOptions::default())
+    }
+
+    /// 按指定选项格式化提示词，权重结束 `::` 前总是添加空格
+    pub fn format_with(input: &str, options: FormatOptions) -> String {
         let result = Self::parse(input);
         let mut output = String::with_capacity(input.len());
         let mut consecutive_newlines = 0;
@@ -768,7 +1586,7 @@ impl PromptParser {
             match token {
                 Token::Newline { .. } => {
                     consecutive_newlines += 1;
-                    if consecutive_newlines <= 2 {
+                    if consecutive_newlines <= options.max_blank_lines + 1 {
                         output.push('\n');
                     }
                 }
@@ -779,13 +1597,14 @@ impl PromptParser {
                 }
                 Token::Whitespace { value, .. } => {
                     // 如果前一个是逗号，确保有空格
-                    if let Some(Token::Comma { .. }) = prev_token {
-                        if !value.starts_with(' ') {
-                            output.push(' ');
-                        }
+                    if options.comma_space
+                        && let Some(Token::Comma { .. }) = prev_token
+                        && !value.starts_with(' ')
+                    {
+                        output.push(' ');
                     }
                     // 只保留单个空格，除非是换行后的缩进
-                    if consecutive_newlines > 0 {
+                    if !options.collapse_whitespace || consecutive_newlines > 0 {
                         output.push_str(value);
                     } else {
                         output.push(' ');
@@ -803,15 +1622,22 @@ impl PromptParser {
                 Token::Text { value, .. } => {
                     consecutive_newlines = 0;
                     // 如果前一个是逗号且没有空格，添加空格
-                    if let Some(Token::Comma { .. }) = prev_token {
-                        if !output.ends_with(' ') {
-                            output.push(' ');
-                        }
+                    if options.comma_space
+                        && let Some(Token::Comma { .. }) = prev_token
+                        && !output.ends_with(' ')
+                    {
+                        output.push(' ');
                     }
                     output.push_str(value);
                 }
                 Token::BraceOpen { .. } => {
                     consecutive_newlines = 0;
+                    if options.comma_space
+                        && let Some(Token::Comma { .. }) = prev_token
+                        && !output.ends_with(' ')
+                    {
+                        output.push(' ');
+                    }
                     output.push('{');
                 }
                 Token::BraceClose { .. } => {
@@ -820,6 +1646,12 @@ impl PromptParser {
                 }
                 Token::BracketOpen { .. } => {
                     consecutive_newlines = 0;
+                    if options.comma_space
+                        && let Some(Token::Comma { .. }) = prev_token
+                        && !output.ends_with(' ')
+                    {
+                        output.push(' ');
+                    }
                     output.push('[');
                 }
                 Token::BracketClose { .. } => {
@@ -828,6 +1660,12 @@ impl PromptParser {
                 }
                 Token::WeightStart { value, .. } => {
                     consecutive_newlines = 0;
+                    if options.comma_space
+                        && let Some(Token::Comma { .. }) = prev_token
+                        && !output.ends_with(' ')
+                    {
+                        output.push(' ');
+                    }
                     // 格式化数字
                     if *value == value.floor() {
                         output.push_str(&format!("{}::", *value as i64));
@@ -837,10 +1675,11 @@ impl PromptParser {
                 }
                 Token::SnippetRef { name, .. } => {
                     consecutive_newlines = 0;
-                    if let Some(Token::Comma { .. }) = prev_token {
-                        if !output.ends_with(' ') {
-                            output.push(' ');
-                        }
+                    if options.comma_space
+                        && let Some(Token::Comma { .. }) = prev_token
+                        && !output.ends_with(' ')
+                    {
+                        output.push(' ');
                     }
                     output.push_str(&format!("<snippet:{}>", name));
                 }
@@ -855,6 +1694,155 @@ impl PromptParser {
 
         output
     }
+
+    /// 压缩提示词到最短的等价形式（与美化的 [`format`](Self::format) 相反）
+    /// - 删除注释，空白压缩为单个空格
+    /// - 去掉逗号、括号与冒号权重两侧的空格，以及空的标签段（连续或首尾的逗号）
+    /// - 词与词之间的空格保留，不会把相邻标签合并
+    pub fn compact(input: &str) -> String {
+        const SEPARATORS: [char; 5] = [',', '{', '}', '[', ']'];
+        let result = Self::parse(input);
+        let mut output = String::with_capacity(input.len());
+        let mut pending_space = false;
+        let mut pending_comma = false;
+        // 上一个输出的是否为开启符号（或输出为空）
+        let mut after_open = true;
+
+        for token in &result.tokens {
+            let mut trailing_space = false;
+            // (要输出的文本, 是否为闭合符号)
+            let (piece, closes) = match token {
+                Token::Whitespace { .. } | Token::Newline { .. } | Token::Comment { .. } => {
+                    pending_space = true;
+                    continue;
+                }
+                Token::Comma { .. } => {
+                    pending_comma = true;
+                    pending_space = false;
+                    continue;
+                }
+                Token::Text { value, .. } => {
+                    let words = value.split_whitespace().collect::<Vec<_>>().join(" ");
+                    if value.starts_with(char::is_whitespace) {
+                        pending_space = true;
+                    }
+                    if words.is_empty() {
+                        continue;
+                    }
+                    trailing_space = value.ends_with(char::is_whitespace);
+                    (words, false)
+                }
+                Token::SnippetRef { name, .. } => (format!("<snippet:{}>", name), false),
+                Token::BraceOpen { .. } => ("{".to_string(), false),
+                Token::BracketOpen { .. } => ("[".to_string(), false),
+                Token::WeightStart { value, .. } => (format!("{}::", value), false),
+                Token::BraceClose { .. } => ("}".to_string(), true),
+                Token::BracketClose { .. } => ("]".to_string(), true),
+                Token::WeightEnd { .. } => ("::".to_string(), true),
+            };
+            let prev = output.chars().last();
+            if pending_comma && !closes && !after_open {
+                output.push(',');
+            } else if pending_space
+                && let Some(prev) = prev
+                && !after_open
+                && !SEPARATORS.contains(&prev)
+            {
+                let first = piece.chars().next().unwrap_or(' ');
+                let needed = match token {
+                    // `v2::` 会被解析为权重开始，数字后的空格需要保留
+                    Token::WeightEnd { .. } => prev.is_ascii_digit() || prev == '.' || prev == '-',
+                    _ => !SEPARATORS.contains(&first),
+                };
+                if needed {
+                    output.push(' ');
+                }
+            }
+            output.push_str(&piece);
+            // 文本末尾的空白留到下一个 token 再决定
+            pending_space = trailing_space;
+            pending_comma = false;
+            after_open = matches!(
+                token,
+                Token::BraceOpen { .. } | Token::BracketOpen { .. } | Token::WeightStart { .. }
+            );
+        }
+
+        output
+    }
+
+    /// 粗略估算提示词的 CLIP token 数与分块
+    /// - 每个连续的字母数字串算一个 token，其余非空白字符各算一个（含逗号）
+    /// - 括号与冒号权重语法不计入，注释忽略；snippet 引用按未展开的名称估算
+    /// - 真实的 BPE 分词会把长词或生僻词拆成多个 token，结果只能作为参考
+    pub fn estimate_chunks(result: &ParseResult) -> ChunkEstimate {
+        fn count(text: &str) -> usize {
+            let mut tokens = 0;
+            let mut in_word = false;
+            for c in text.chars() {
+                if c.is_alphanumeric() {
+                    if !in_word {
+                        tokens += 1;
+                    }
+                    in_word = true;
+                } else {
+                    in_word = false;
+                    if !c.is_whitespace() {
+                        tokens += 1;
+                    }
+                }
+            }
+            tokens
+        }
+
+        let mut tokens = 0;
+        let mut tags = Vec::new();
+        let mut current: Option<ChunkedTag> = None;
+        for token in &result.tokens {
+            let (text, start, end) = match token {
+                Token::Comma { .. } => {
+                    tags.extend(current.take());
+                    tokens += 1;
+                    continue;
+                }
+                Token::Text {
+                    value, start, end, ..
+                } => (value.trim().to_string(), *start, *end),
+                Token::SnippetRef {
+                    name, start, end, ..
+                } => (format!("<snippet:{}>", name), *start, *end),
+                _ => continue,
+            };
+            let n = count(&text);
+            if n == 0 {
+                continue;
+            }
+            match current.as_mut() {
+                Some(tag) => {
+                    tag.tag.push(' ');
+                    tag.tag.push_str(&text);
+                    tag.end = end;
+                }
+                None => {
+                    current = Some(ChunkedTag {
+                        tag: text,
+                        chunk: tokens / CHUNK_TOKENS,
+                        start_token: tokens,
+                        start,
+                        end,
+                    })
+                }
+            }
+            tokens += n;
+        }
+        tags.extend(current);
+
+        ChunkEstimate {
+            tokens,
+            chunks: tokens.div_ceil(CHUNK_TOKENS),
+            tags,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -941,6 +1929,112 @@ mod tests {
         assert!(formatted.contains(", "));
     }
 
+    #[test]
+    fn test_estimate_chunks() {
+        let empty = PromptParser::estimate_chunks(&PromptParser::parse(""));
+        assert_eq!((empty.tokens, empty.chunks), (0, 0));
+        assert!(empty.tags.is_empty());
+
+        // 权重语法与注释不计入：blue hair(2) , {smile}(1) , 1.5::cat-ears::(3)
+        let result = PromptParser::parse("blue hair, {smile}, //note// 1.5::cat-ears::");
+        let estimate = PromptParser::estimate_chunks(&result);
+        assert_eq!(estimate.tokens, 8);
+        assert_eq!(estimate.chunks, 1);
+        let tags: Vec<_> = estimate.tags.iter().map(|t| t.tag.as_str()).collect();
+        assert_eq!(tags, ["blue hair", "smile", "cat-ears"]);
+        assert_eq!(estimate.tags[2].start_token, 5);
+
+        // 每个标签 1 个 token + 1 个逗号，第 38 个标签起始于第 74 个 token
+        let prompt = (0..40)
+            .map(|i| format!("t{}", i))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let estimate = PromptParser::estimate_chunks(&PromptParser::parse(&prompt));
+        assert_eq!(estimate.tokens, 79);
+        assert_eq!(estimate.chunks, 2);
+        let late: Vec<_> = estimate
+            .tags
+            .iter()
+            .filter(|t| t.chunk > 0)
+            .map(|t| t.tag.as_str())
+            .collect();
+        assert_eq!(late, ["t38", "t39"]);
+        let first = &estimate.tags[38];
+        assert_eq!(&prompt[first.start..first.end], "t38");
+    }
+
+    #[test]
+    fn test_compact() {
+        let cases = [
+            (
+                "1girl,  blue   hair , //note// , , smile\n\n{ red eyes , }",
+                "1girl,blue hair,smile{red eyes}",
+            ),
+            (
+                "[[ cat ]] , 1.50::  smile  :: , tag ,",
+                "[[cat]],1.5::smile::,tag",
+            ),
+            (
+                ", , <snippet:style> ,\n school uniform",
+                "<snippet:style>,school uniform",
+            ),
+            // 数字后的空格不能删，否则 `v2::` 会变成权重开始
+            ("1.5:: v2 ::", "1.5::v2 ::"),
+        ];
+        for (input, expected) in cases {
+            let compacted = PromptParser::compact(input);
+            assert_eq!(compacted, expected);
+            assert!(compacted.len() < input.len());
+        }
+
+        // 权重不变
+        let input = "{blue hair , smile}, [ 1.5::  cat ears  :: ], //x// v2 ::";
+        let weights = |s: &str| {
+            PromptParser::weight_map(s)
+                .into_iter()
+                .map(|t| (t.name, (t.weight * 1000.0).round() as i64))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(weights(&PromptParser::compact(input)), weights(input));
+    }
+
+    #[test]
+    fn test_format_options_collapse_whitespace() {
+        let input = "a,  b,c";
+        let options = FormatOptions {
+            collapse_whitespace: false,
+            ..FormatOptions::default()
+        };
+        assert_eq!(PromptParser::format(input), "a, b, c");
+        assert_eq!(PromptParser::format_with(input, options), "a,  b, c");
+    }
+
+    #[test]
+    fn test_format_options_comma_space() {
+        let input = "a,b,  {c}";
+        let options = FormatOptions {
+            comma_space: false,
+            ..FormatOptions::default()
+        };
+        assert_eq!(PromptParser::format_with(input, options), "a,b, {c}");
+    }
+
+    #[test]
+    fn test_format_options_max_blank_lines() {
+        let input = "a,\n\n\n\nb";
+        assert_eq!(PromptParser::format(input), "a,\n\nb");
+        let keep = FormatOptions {
+            max_blank_lines: 2,
+            ..FormatOptions::default()
+        };
+        assert_eq!(PromptParser::format_with(input, keep), "a,\n\n\nb");
+        let none = FormatOptions {
+            max_blank_lines: 0,
+            ..FormatOptions::default()
+        };
+        assert_eq!(PromptParser::format_with(input, none), "a,\nb");
+    }
+
     #[test]
     fn test_snippet_ref() {
         let input = "1girl, <snippet:my_style>";
@@ -1103,4 +2197,302 @@ mod tests {
             assert_eq!(value, "/content");
         }
     }
+
+    #[test]
+    fn test_weight_map() {
+        let input = "{blue hair}, 1.5::smile ::, //ignored//, blue hair, <snippet:style>";
+        let tags = PromptParser::weight_map(input);
+
+        let names: Vec<_> = tags.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["blue hair", "smile", "<snippet:style>"]);
+
+        // blue hair 出现两次：1.05 与 1.0 取平均
+        assert_eq!(tags[0].occurrences, 2);
+        assert!((tags[0].weight - 1.025).abs() < 0.001);
+        assert!((tags[1].weight - 1.5).abs() < 0.001);
+        assert!((tags[2].weight - 1.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_snippet_weights() {
+        let input = "{<snippet:hair>}, [[<snippet:bg>]], 1.5::<snippet:hair>::, //<snippet:x>//";
+        let refs = PromptParser::snippet_weights(input);
+
+        let names: Vec<_> = refs.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["hair", "bg", "hair"]);
+        assert!((refs[0].weight - 1.05).abs() < 0.001);
+        assert!((refs[1].weight - 1.0 / (1.05 * 1.05)).abs() < 0.001);
+        assert!((refs[2].weight - 1.5).abs() < 0.001);
+        assert_eq!(&input[refs[0].start..refs[0].end], "<snippet:hair>");
+    }
+
+    #[test]
+    fn test_insert_tag_start() {
+        let (out, cursor) = PromptParser::insert_tag("blue hair, smile", 0, "1girl");
+        assert_eq!(out, "1girl, blue hair, smile");
+        assert_eq!(&out[..cursor], "1girl, ");
+
+        let (out, cursor) = PromptParser::insert_tag("", 0, "1girl");
+        assert_eq!(out, "1girl");
+        assert_eq!(cursor, 5);
+    }
+
+    #[test]
+    fn test_insert_tag_middle() {
+        // 逗号之后插入
+        let (out, cursor) = PromptParser::insert_tag("blue hair, smile", 10, "1girl");
+        assert_eq!(out, "blue hair, 1girl, smile");
+        assert_eq!(&out[..cursor], "blue hair, 1girl, ");
+
+        // 标签内部插入：移动到标签末尾
+        let (out, _) = PromptParser::insert_tag("blue hair, smile", 3, "1girl");
+        assert_eq!(out, "blue hair, 1girl, smile");
+
+        // 紧贴括号时不加逗号
+        let (out, _) = PromptParser::insert_tag("{}, smile", 1, "1girl");
+        assert_eq!(out, "{1girl}, smile");
+    }
+
+    #[test]
+    fn test_insert_tag_end() {
+        let (out, cursor) = PromptParser::insert_tag("blue hair, smile", 16, "1girl");
+        assert_eq!(out, "blue hair, smile, 1girl");
+        assert_eq!(cursor, out.len());
+
+        // 末尾已有逗号时不重复
+        let (out, _) = PromptParser::insert_tag("blue hair, ", 11, "1girl");
+        assert_eq!(out, "blue hair, 1girl");
+    }
+
+    #[test]
+    fn test_move_tag_first_and_last() {
+        assert_eq!(PromptParser::move_tag("a, b, c", 0, 2), "b, c, a");
+        assert_eq!(PromptParser::move_tag("a, b, c", 2, 0), "c, a, b");
+        assert_eq!(PromptParser::move_tag("a, b, c", 1, 1), "a, b, c");
+        assert_eq!(PromptParser::move_tag("a, b, c", 5, 0), "a, b, c");
+    }
+
+    #[test]
+    fn test_move_tag_keeps_own_weight() {
+        assert_eq!(PromptParser::move_tag("{{a}}, b", 0, 1), "b, {{a}}");
+        assert_eq!(PromptParser::move_tag("a, [b], c", 1, 0), "[b], a, c");
+    }
+
+    #[test]
+    fn test_move_tag_out_of_shared_region() {
+        // 跨标签的括号留在原处，移出后补偿权重
+        assert_eq!(PromptParser::move_tag("{a, b}, c", 0, 2), "{b}, c, {a}");
+
+        let out = PromptParser::move_tag("x, 1.5::a, b ::, c", 2, 0);
+        assert_eq!(out, "1.5::b ::, x, 1.5::a ::, c");
+        let weights = PromptParser::weight_map(&out);
+        let b = weights.iter().find(|t| t.name == "b").unwrap();
+        assert!((b.weight - 1.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_move_tag_into_colon_region() {
+        let out = PromptParser::move_tag("x, 1.5::a, b ::, c", 0, 1);
+        let weights = PromptParser::weight_map(&out);
+        let weight_of = |name: &str| weights.iter().find(|t| t.name == name).unwrap().weight;
+        assert!((weight_of("x") - 1.0).abs() < 1e-9);
+        assert!((weight_of("a") - 1.5).abs() < 1e-9);
+        assert!((weight_of("b") - 1.5).abs() < 1e-9);
+        assert!((weight_of("c") - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_parse_unclosed_comment_as_text() {
+        let result = PromptParser::parse("a, //note");
+        assert!(matches!(
+            result.tokens.last(),
+            Some(Token::Text { value, .. }) if value == "//note"
+        ));
+    }
+
+    #[test]
+    fn test_parse_stray_delimiters_as_text() {
+        let result = PromptParser::parse("a :: b, <c");
+        let texts: Vec<_> = result
+            .tokens
+            .iter()
+            .filter_map(|t| match t {
+                Token::Text { value, .. } => Some(value.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(texts, vec!["a ", ":: b", "<c"]);
+    }
+
+    #[test]
+    fn test_explain_tokens() {
+        let explanations = PromptParser::explain(
+            "{{red}}, 1.5::<snippet:hair>:: //x//",
+            |tag| (tag == "red").then(|| "红色".to_string()),
+            |name| (name == "hair").then_some(9),
+        );
+        let described: Vec<_> = explanations
+            .iter()
+            .map(|e| (e.kind.as_str(), e.description.as_str()))
+            .collect();
+        assert_eq!(
+            described,
+            vec![
+                ("brace_open", "增强第 1 层，权重 ×1.05"),
+                ("brace_open", "增强第 2 层，权重 ×1.1025"),
+                ("text", "标签「红色」，权重 ×1.1025"),
+                ("brace_close", "增强结束，回到第 1 层"),
+                ("brace_close", "增强结束，回到第 0 层"),
+                ("comma", "分隔符"),
+                ("weight_start", "权重 1.5 开始，直到 '::'"),
+                ("snippet_ref", "插入 snippet 'hair'（9 个字符）"),
+                ("weight_end", "冒号权重结束"),
+                ("comment", "注释，发送前会被移除"),
+            ]
+        );
+        assert_eq!(explanations[7].text, "<snippet:hair>");
+    }
+
+    #[test]
+    fn test_remove_tags_whole_segments() {
+        let blocked = |tag: &str| matches!(tag.to_lowercase().as_str(), "red hair" | "blood");
+        let (output, removed) = PromptParser::remove_tags(
+            "1girl, {Red Hair}, red hair ribbon, 1.2::blood::, smile",
+            blocked,
+        );
+        assert_eq!(output, "1girl, red hair ribbon, smile");
+        assert_eq!(removed, vec!["Red Hair", "blood"]);
+
+        let (output, removed) = PromptParser::remove_tags("smile, blood", blocked);
+        assert_eq!(output, "smile");
+        assert_eq!(removed, vec!["blood"]);
+    }
+
+    #[test]
+    fn test_lint_clean_prompt() {
+        let diagnostics = PromptParser::lint(
+            "1girl, {blue hair}, 1.2::smile ::",
+            WeightRange::default(),
+            |_| true,
+        );
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_lint_reports_problems() {
+        let input = "{a, b], <snippet:missing>, 1.5::::, //note";
+        let diagnostics =
+            PromptParser::lint(input, WeightRange::default(), |name| name != "missing");
+        let codes: Vec<(&str, usize, usize)> = diagnostics
+            .iter()
+            .map(|d| (d.code.as_str(), d.start, d.end))
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                ("unclosed_brace", 0, 1),
+                ("unmatched_bracket", 5, 6),
+                ("unknown_snippet", 8, 25),
+                ("empty_weight", 27, 34),
+                ("unclosed_comment", 36, input.len()),
+            ]
+        );
+        assert_eq!(diagnostics[2].severity, Severity::Error);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn test_lint_unclosed_weight() {
+        let diagnostics = PromptParser::lint("a, 1.5::b, c", WeightRange::default(), |_| true);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "unclosed_weight");
+        assert_eq!((diagnostics[0].start, diagnostics[0].end), (3, 8));
+    }
+
+    #[test]
+    fn test_lint_weight_out_of_range() {
+        let input = "1000::a::, -5::b::, 1.5::c::";
+        let diagnostics = PromptParser::lint(input, WeightRange::default(), |_| true);
+        let codes: Vec<(&str, usize, usize)> = diagnostics
+            .iter()
+            .map(|d| (d.code.as_str(), d.start, d.end))
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                ("weight_out_of_range", 0, 6),
+                ("weight_out_of_range", 11, 15)
+            ]
+        );
+
+        let range = WeightRange {
+            min: -5.0,
+            max: 1000.0,
+        };
+        assert!(PromptParser::lint(input, range, |_| true).is_empty());
+    }
+
+    #[test]
+    fn test_from_novelai_weights_and_quality_tags() {
+        let input = "1girl, {{blue hair}}, [[smile]], 1.3::looking at viewer::, very aesthetic, masterpiece, no text";
+        assert_eq!(
+            PromptParser::from_novelai(input),
+            "1girl, {{blue hair}}, [[smile]], 1.3::looking at viewer ::"
+        );
+
+        let curated = "2girls,-2::bad hands::, very aesthetic, masterpiece, no text, -0.8::feet::, rating:general";
+        assert_eq!(
+            PromptParser::from_novelai(curated),
+            "2girls, -2::bad hands ::"
+        );
+    }
+
+    #[test]
+    fn test_split_characters_by_delimiter() {
+        assert_eq!(
+            PromptParser::split_characters("girl, red hair, smile"),
+            vec!["girl, red hair, smile"]
+        );
+        assert_eq!(
+            PromptParser::split_characters("girl, red hair | boy, {black hair},"),
+            vec!["girl, red hair", "boy, {black hair}"]
+        );
+        assert_eq!(
+            PromptParser::split_characters("girl | | boy // a | b // | 1.2::cat::"),
+            vec!["girl", "boy // a | b //", "1.2::cat::"]
+        );
+    }
+
+    #[test]
+    fn test_split_characters_by_markers() {
+        let split = CharacterSplit::Markers {
+            markers: vec!["girl".into(), "boy".into()],
+        };
+        assert_eq!(
+            PromptParser::split_characters_with(
+                "outdoors, Girl: red hair, smile, boy: black hair girl: 1.2::1girl:: ",
+                &split
+            ),
+            vec![
+                "outdoors",
+                "Girl, red hair, smile",
+                "boy, black hair",
+                "girl, 1.2::1girl::"
+            ]
+        );
+        // 不在标签边界上的不算标记
+        assert_eq!(
+            PromptParser::split_characters_with("catgirl: ears", &split),
+            vec!["catgirl: ears"]
+        );
+    }
+
+    #[test]
+    fn test_from_novelai_escapes_and_fullwidth_comma() {
+        let input = r"hatsune miku \(vocaloid\)，twintails, C:\\path";
+        assert_eq!(
+            PromptParser::from_novelai(input),
+            r"hatsune miku (vocaloid), twintails, C:\path"
+        );
+    }
 }
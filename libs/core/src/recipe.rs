@@ -0,0 +1,544 @@
+//! 生成“配方”：可分享、可复现的一次生成设置
+//!
+//! 配方内联了原始提示词、生成参数、主预设以及提示词中（递归）引用的所有 snippet，
+//! 接收方无需访问原数据库即可重建。
+
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    CoreError, CoreResult, CoreStorage, GenerationParams, MainPresetSettings, Snippet,
+    prompt_parser::{PromptParser, Token},
+    snippet::{put_snippet, snippet_by_name_in},
+    validate_snippet_content,
+};
+
+/// 配方格式版本
+pub const RECIPE_VERSION: u32 = 1;
+
+/// 重命名冲突 snippet 时最多尝试的候选名称数（`name_2` 起）
+const MAX_RENAME_ATTEMPTS: u32 = 100;
+
+/// 可分享的生成配方
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recipe {
+    pub version: u32,
+    pub exported_at: chrono::DateTime<Utc>,
+    /// 导出来源记录
+    #[serde(default)]
+    pub source_record: Option<Uuid>,
+    /// 未展开的正面提示词（保留 `<snippet:name>` 引用）
+    pub raw_prompt: String,
+    /// 未展开的负面提示词；旧记录只有展开后的版本
+    pub negative_prompt: String,
+    #[serde(default)]
+    pub params: Option<GenerationParams>,
+    /// 生成时使用的主预设
+    #[serde(default)]
+    pub main_preset: Option<MainPresetSettings>,
+    /// 递归引用到的 snippet，按名称排序
+    #[serde(default)]
+    pub snippets: Vec<Snippet>,
+    /// 引用了但在导出时已不存在的 snippet 名称
+    #[serde(default)]
+    pub unresolved_snippets: Vec<String>,
+}
+
+impl Recipe {
+    /// 配方中所有可能引用 snippet 的文本
+    pub(crate) fn texts(&self) -> Vec<&str> {
+        let mut texts = vec![self.raw_prompt.as_str(), self.negative_prompt.as_str()];
+        if let Some(preset) = &self.main_preset {
+            texts.extend(
+                [
+                    &preset.before,
+                    &preset.after,
+                    &preset.replace,
+                    &preset.uc_before,
+                    &preset.uc_after,
+                    &preset.uc_replace,
+                ]
+                .into_iter()
+                .flatten()
+                .map(String::as_str),
+            );
+        }
+        if let Some(chars) = self
+            .params
+            .as_ref()
+            .and_then(|p| p.character_prompts.as_ref())
+        {
+            for c in chars {
+                texts.push(&c.prompt);
+                texts.push(&c.uc);
+            }
+        }
+        texts
+    }
+}
+
+/// 导入配方时同名 snippet 内容不同的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportConflict {
+    /// 保留已有的 snippet，配方改用已有内容
+    #[default]
+    Skip,
+    /// 以新名称导入，并改写配方中对它的引用
+    Rename,
+    /// 用配方中的内容覆盖已有的 snippet
+    Overwrite,
+}
+
+/// 导入后可直接提交的任务内容，字段与创建任务的请求一致
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecipeTask {
+    pub raw_prompt: String,
+    pub negative_prompt: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub params: Option<GenerationParams>,
+    pub main_preset: MainPresetSettings,
+}
+
+/// 导入时被重命名的 snippet
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RenamedSnippet {
+    pub from: String,
+    pub to: String,
+}
+
+/// 配方导入结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecipeImportResult {
+    pub task: RecipeTask,
+    /// 新建的 snippet
+    pub created: Vec<String>,
+    /// 已存在且内容相同，无需改动
+    pub unchanged: Vec<String>,
+    /// 内容不同但按 `skip` 保留了已有版本
+    pub skipped: Vec<String>,
+    pub overwritten: Vec<String>,
+    pub renamed: Vec<RenamedSnippet>,
+    /// 配方中标记为缺失的 snippet，导入后仍无法展开
+    pub unresolved: Vec<String>,
+}
+
+/// 按 `renames` 改写文本中的 snippet 引用，注释中的引用保持不变
+fn rename_refs(text: &str, renames: &HashMap<String, String>) -> String {
+    if renames.is_empty() {
+        return text.to_string();
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+    for token in PromptParser::parse(text).tokens {
+        if let Token::SnippetRef {
+            name, start, end, ..
+        } = token
+            && let Some(new_name) = renames.get(&name)
+        {
+            out.push_str(&text[last..start]);
+            out.push_str(&format!("<snippet:{new_name}>"));
+            last = end;
+        }
+    }
+    out.push_str(&text[last..]);
+    out
+}
+
+fn rename_opt(text: &mut Option<String>, renames: &HashMap<String, String>) {
+    if let Some(text) = text {
+        *text = rename_refs(text, renames);
+    }
+}
+
+/// 文本中直接引用的 snippet 名称（注释中的不计）
+pub(crate) fn referenced_snippets(text: &str) -> impl Iterator<Item = String> {
+    PromptParser::parse(text)
+        .tokens
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::SnippetRef { name, .. } => Some(name),
+            _ => None,
+        })
+}
+
+impl CoreStorage {
+    /// 将记录导出为配方，递归收集引用的 snippet；找不到的 snippet 列入 `unresolved_snippets`
+    pub fn export_recipe(&self, record_id: Uuid) -> CoreResult<Recipe> {
+        let record = self
+            .get_record(record_id)?
+            .ok_or_else(|| CoreError::not_found(format!("record {record_id}")))?;
+
+        // 没有原始负面提示词的旧记录，展开后的版本已包含主预设的负面部分
+        let (negative_prompt, main_preset) = match record.raw_negative_prompt {
+            Some(raw) => (raw, record.main_preset),
+            None => (
+                record.negative_prompt,
+                record.main_preset.map(|preset| MainPresetSettings {
+                    uc_before: None,
+                    uc_after: None,
+                    uc_replace: None,
+                    ..preset
+                }),
+            ),
+        };
+        let mut recipe = Recipe {
+            version: RECIPE_VERSION,
+            exported_at: Utc::now(),
+            source_record: Some(record.id),
+            raw_prompt: record.raw_prompt,
+            negative_prompt,
+            params: record.params,
+            main_preset,
+            snippets: Vec::new(),
+            unresolved_snippets: Vec::new(),
+        };
+
+        let mut queue: VecDeque<String> = recipe
+            .texts()
+            .into_iter()
+            .flat_map(referenced_snippets)
+            .collect();
+        let mut seen = HashSet::new();
+        let mut unresolved = BTreeSet::new();
+        while let Some(name) = queue.pop_front() {
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            match self.get_snippet_by_name(&name)? {
+                Some(snippet) => {
+                    queue.extend(referenced_snippets(&snippet.content));
+                    recipe.snippets.push(snippet);
+                }
+                None => {
+                    unresolved.insert(name);
+                }
+            }
+        }
+        recipe.snippets.sort_by(|a, b| a.name.cmp(&b.name));
+        recipe.unresolved_snippets = unresolved.into_iter().collect();
+        Ok(recipe)
+    }
+
+    /// 导入配方：创建缺少的 snippet，同名冲突按 `on_conflict` 处理，返回可直接提交的任务
+    ///
+    /// 重命名的 snippet 会同步改写配方提示词、主预设及其他导入 snippet 中的引用。
+    /// 所有 snippet 在同一个写事务中写入，任一失败时不留下部分导入的结果
+    pub fn import_recipe(
+        &self,
+        recipe: Recipe,
+        on_conflict: ImportConflict,
+    ) -> CoreResult<RecipeImportResult> {
+        if recipe.version > RECIPE_VERSION {
+            return Err(CoreError::invalid(format!(
+                "unsupported recipe version {}",
+                recipe.version
+            )));
+        }
+
+        enum Plan {
+            Create,
+            Overwrite(Snippet),
+        }
+        let incoming: HashSet<&str> = recipe.snippets.iter().map(|s| s.name.as_str()).collect();
+        let mut taken = HashSet::new();
+        let mut renames = HashMap::new();
+        let mut plans = Vec::new();
+        let mut result = RecipeImportResult {
+            task: RecipeTask {
+                raw_prompt: String::new(),
+                negative_prompt: String::new(),
+                params: None,
+                main_preset: MainPresetSettings::default(),
+            },
+            created: Vec::new(),
+            unchanged: Vec::new(),
+            skipped: Vec::new(),
+            overwritten: Vec::new(),
+            renamed: Vec::new(),
+            unresolved: recipe.unresolved_snippets.clone(),
+        };
+
+        let write_txn = self.begin_write_with_retry()?;
+        for snippet in &recipe.snippets {
+            let Some(existing) = snippet_by_name_in(&write_txn, &snippet.name)? else {
+                plans.push((snippet.name.clone(), snippet, Plan::Create));
+                continue;
+            };
+            if existing.content == snippet.content {
+                result.unchanged.push(snippet.name.clone());
+                continue;
+            }
+            match on_conflict {
+                ImportConflict::Skip => result.skipped.push(snippet.name.clone()),
+                ImportConflict::Overwrite => {
+                    plans.push((snippet.name.clone(), snippet, Plan::Overwrite(existing)));
+                }
+                ImportConflict::Rename => {
+                    let mut free = None;
+                    for n in 2..2 + MAX_RENAME_ATTEMPTS {
+                        let candidate = format!("{}_{n}", snippet.name);
+                        if !incoming.contains(candidate.as_str())
+                            && !taken.contains(&candidate)
+                            && snippet_by_name_in(&write_txn, &candidate)?.is_none()
+                        {
+                            free = Some(candidate);
+                            break;
+                        }
+                    }
+                    let new_name = free.ok_or_else(|| {
+                        CoreError::invalid(format!(
+                            "no free name for snippet {} after {MAX_RENAME_ATTEMPTS} attempts",
+                            snippet.name
+                        ))
+                    })?;
+                    taken.insert(new_name.clone());
+                    renames.insert(snippet.name.clone(), new_name.clone());
+                    result.renamed.push(RenamedSnippet {
+                        from: snippet.name.clone(),
+                        to: new_name.clone(),
+                    });
+                    plans.push((new_name, snippet, Plan::Create));
+                }
+            }
+        }
+
+        for (name, source, plan) in plans {
+            let content = rename_refs(&source.content, &renames);
+            validate_snippet_content(&content, self.max_snippet_content_bytes)?;
+            match plan {
+                Plan::Create => {
+                    let mut snippet = Snippet::new(name.clone(), source.category.clone(), content)?;
+                    snippet.tags = source.tags.clone();
+                    snippet.description = source.description.clone();
+                    put_snippet(&write_txn, &snippet, None)?;
+                    result.created.push(name);
+                }
+                Plan::Overwrite(mut existing) => {
+                    existing.content = content;
+                    existing.updated_at = Utc::now();
+                    put_snippet(&write_txn, &existing, None)?;
+                    result.overwritten.push(name);
+                }
+            }
+        }
+        write_txn.commit()?;
+
+        let mut main_preset = recipe.main_preset.unwrap_or_default();
+        for field in [
+            &mut main_preset.before,
+            &mut main_preset.after,
+            &mut main_preset.replace,
+            &mut main_preset.uc_before,
+            &mut main_preset.uc_after,
+            &mut main_preset.uc_replace,
+        ] {
+            rename_opt(field, &renames);
+        }
+        let mut params = recipe.params;
+        if let Some(chars) = params.as_mut().and_then(|p| p.character_prompts.as_mut()) {
+            for c in chars {
+                c.prompt = rename_refs(&c.prompt, &renames);
+                c.uc = rename_refs(&c.uc, &renames);
+            }
+        }
+        result.task = RecipeTask {
+            raw_prompt: rename_refs(&recipe.raw_prompt, &renames),
+            negative_prompt: rename_refs(&recipe.negative_prompt, &renames),
+            params,
+            main_preset,
+        };
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GenerationRecord;
+    use crate::test_support::{TestDir, TestStorage};
+
+    #[test]
+    fn test_export_recipe_walks_snippets() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        for (name, content) in [
+            ("outfit", "dress, <snippet:color>"),
+            ("color", "red, <snippet:color>, <snippet:gone>"),
+            ("quality", "masterpiece"),
+            ("unused", "nothing"),
+        ] {
+            let snippet = Snippet::new(name.into(), "cat".into(), content.into()).unwrap();
+            storage.upsert_snippet(snippet, None).unwrap();
+        }
+        let record = GenerationRecord {
+            id: Uuid::new_v4(),
+            task_id: Uuid::new_v4(),
+            created_at: Utc::now(),
+            raw_prompt: "1girl, <snippet:outfit> //<snippet:unused>//".into(),
+            expanded_prompt: String::new(),
+            negative_prompt: "lowres, blurry".into(),
+            label: None,
+            raw_negative_prompt: Some("<snippet:missing>".into()),
+            main_preset: Some(MainPresetSettings {
+                before: Some("<snippet:quality>".into()),
+                ..Default::default()
+            }),
+            images: Vec::new(),
+            params: None,
+        };
+        storage.append_record(&record).unwrap();
+
+        let recipe = storage.export_recipe(record.id).unwrap();
+        let names: Vec<_> = recipe.snippets.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["color", "outfit", "quality"]);
+        assert_eq!(recipe.unresolved_snippets, vec!["gone", "missing"]);
+        assert_eq!(recipe.negative_prompt, "<snippet:missing>");
+        assert!(matches!(
+            storage.export_recipe(Uuid::new_v4()),
+            Err(CoreError::NotFound { .. })
+        ));
+    }
+
+    fn recipe_with(snippets: &[(&str, &str)], raw_prompt: &str) -> Recipe {
+        Recipe {
+            version: RECIPE_VERSION,
+            exported_at: Utc::now(),
+            source_record: None,
+            raw_prompt: raw_prompt.into(),
+            negative_prompt: String::new(),
+            params: None,
+            main_preset: Some(MainPresetSettings {
+                after: Some("<snippet:hair>".into()),
+                ..Default::default()
+            }),
+            snippets: snippets
+                .iter()
+                .map(|(name, content)| {
+                    Snippet::new((*name).into(), "cat".into(), (*content).into()).unwrap()
+                })
+                .collect(),
+            unresolved_snippets: vec!["gone".into()],
+        }
+    }
+
+    #[test]
+    fn test_import_recipe_rename_remaps_references() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        for (name, content) in [
+            ("hair", "black hair"),
+            ("hair_2", "taken"),
+            ("eyes", "red eyes"),
+        ] {
+            let snippet = Snippet::new(name.into(), "cat".into(), content.into()).unwrap();
+            storage.upsert_snippet(snippet, None).unwrap();
+        }
+
+        let recipe = recipe_with(
+            &[
+                ("eyes", "red eyes"),
+                ("hair", "blue hair"),
+                ("look", "<snippet:hair>, <snippet:eyes>"),
+            ],
+            "1girl, <snippet:look>, <snippet:hair> //<snippet:hair>//",
+        );
+        let result = storage
+            .import_recipe(recipe, ImportConflict::Rename)
+            .unwrap();
+        assert_eq!(
+            result.renamed,
+            vec![RenamedSnippet {
+                from: "hair".into(),
+                to: "hair_3".into()
+            }]
+        );
+        assert_eq!(result.created, vec!["hair_3", "look"]);
+        assert_eq!(result.unchanged, vec!["eyes"]);
+        assert_eq!(result.unresolved, vec!["gone"]);
+        assert_eq!(
+            result.task.raw_prompt,
+            "1girl, <snippet:look>, <snippet:hair_3> //<snippet:hair>//"
+        );
+        assert_eq!(
+            result.task.main_preset.after.as_deref(),
+            Some("<snippet:hair_3>")
+        );
+        let look = storage.get_snippet_by_name("look").unwrap().unwrap();
+        assert_eq!(look.content, "<snippet:hair_3>, <snippet:eyes>");
+        let hair = storage.get_snippet_by_name("hair").unwrap().unwrap();
+        assert_eq!(hair.content, "black hair");
+        let renamed = storage.get_snippet_by_name("hair_3").unwrap().unwrap();
+        assert_eq!(renamed.content, "blue hair");
+    }
+
+    #[test]
+    fn test_import_recipe_skip_and_overwrite() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        let existing = Snippet::new("hair".into(), "cat".into(), "black hair".into()).unwrap();
+        let existing = storage.upsert_snippet(existing, None).unwrap();
+
+        let recipe = recipe_with(&[("hair", "blue hair")], "<snippet:hair>");
+        let result = storage
+            .import_recipe(recipe.clone(), ImportConflict::Skip)
+            .unwrap();
+        assert_eq!(result.skipped, vec!["hair"]);
+        assert_eq!(result.task.raw_prompt, "<snippet:hair>");
+        assert_eq!(
+            storage
+                .get_snippet_by_name("hair")
+                .unwrap()
+                .unwrap()
+                .content,
+            "black hair"
+        );
+
+        let result = storage
+            .import_recipe(recipe, ImportConflict::Overwrite)
+            .unwrap();
+        assert_eq!(result.overwritten, vec!["hair"]);
+        let hair = storage.get_snippet_by_name("hair").unwrap().unwrap();
+        assert_eq!(hair.id, existing.id);
+        assert_eq!(hair.content, "blue hair");
+    }
+
+    #[test]
+    fn test_import_recipe_is_all_or_nothing() {
+        let dir = TestDir::new();
+        let storage = CoreStorage::open(dir.join("codex.redb"), dir.join("previews"))
+            .unwrap()
+            .with_max_snippet_content(16);
+
+        // 第二个 snippet 超出内容上限，第一个也不应写入
+        let recipe = recipe_with(
+            &[("eyes", "blue eyes"), ("hair", "very long blue hair")],
+            "<snippet:eyes>, <snippet:hair>",
+        );
+        let err = storage
+            .import_recipe(recipe, ImportConflict::Skip)
+            .unwrap_err();
+        assert!(matches!(err, CoreError::Validation(_)));
+        assert!(storage.get_snippet_by_name("eyes").unwrap().is_none());
+        assert!(storage.get_snippet_by_name("hair").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_import_recipe_rename_attempts_are_capped() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        let write_txn = storage.db().unwrap().begin_write().unwrap();
+        for n in std::iter::once(String::new())
+            .chain((2..2 + MAX_RENAME_ATTEMPTS).map(|n| format!("_{n}")))
+        {
+            let snippet = Snippet::new(format!("hair{n}"), "cat".into(), "black".into()).unwrap();
+            put_snippet(&write_txn, &snippet, None).unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        let recipe = recipe_with(&[("hair", "blue hair")], "<snippet:hair>");
+        let err = storage
+            .import_recipe(recipe, ImportConflict::Rename)
+            .unwrap_err();
+        assert!(err.to_string().contains("no free name"));
+    }
+}
@@ -0,0 +1,1513 @@
+//! 生成记录 - 存储、按时间索引、按日期查询与删除，以及与图库文件的校验和修复
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{Local, TimeZone, Utc};
+use codex_api::WeightRange;
+use redb::{ReadableDatabase, ReadableTable, Table, TableHandle};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{
+    CoreError, CoreResult, CoreStorage, GenerationParams, MainPresetSettings,
+    SETTINGS_KEY_USAGE_STATS, TABLE_RECORDS, TABLE_RECORDS_BY_TIME, TABLE_SETTINGS, archive,
+    content_hash::remove_content_hashes_in, decode_row, gallery_hash_key, imaging,
+    params::to_nai_request,
+};
+
+/// 记录时间索引的键：创建时间（微秒，翻转符号位后大端）+ 记录 ID，字节序即时间序
+pub(crate) fn record_time_key(created_at: chrono::DateTime<Utc>, id: Uuid) -> [u8; 24] {
+    let micros = (created_at.timestamp_micros() as u64) ^ (1 << 63);
+    let mut key = [0; 24];
+    key[..8].copy_from_slice(&micros.to_be_bytes());
+    key[8..].copy_from_slice(id.as_bytes());
+    key
+}
+
+/// 写入记录并维护时间索引；覆盖已有记录且创建时间变化时移除旧索引项
+fn insert_record_row(
+    records: &mut Table<Uuid, String>,
+    by_time: &mut Table<&[u8], Uuid>,
+    record: &GenerationRecord,
+) -> CoreResult<()> {
+    let previous = records
+        .insert(record.id, serde_json::to_string(record)?)?
+        .and_then(|old| serde_json::from_str::<GenerationRecord>(&old.value()).ok());
+    if let Some(previous) = previous
+        && previous.created_at != record.created_at
+    {
+        by_time.remove(record_time_key(previous.created_at, record.id).as_slice())?;
+    }
+    by_time.insert(
+        record_time_key(record.created_at, record.id).as_slice(),
+        record.id,
+    )?;
+    Ok(())
+}
+
+/// 列出图库中 `[{label}/]YYYY-MM-DD/` 目录下的 PNG 文件，跳过隐藏目录
+fn gallery_image_files(root: &Path) -> CoreResult<Vec<PathBuf>> {
+    fn visible_dirs(dir: &Path) -> CoreResult<Vec<(String, PathBuf)>> {
+        let mut dirs = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            if path.is_dir() && !name.starts_with('.') {
+                dirs.push((name, path));
+            }
+        }
+        Ok(dirs)
+    }
+
+    if !root.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut date_dirs = Vec::new();
+    for (name, path) in visible_dirs(root)? {
+        if archive::is_valid_date(&name) {
+            date_dirs.push(path);
+        } else {
+            date_dirs.extend(
+                visible_dirs(&path)?
+                    .into_iter()
+                    .filter(|(name, _)| archive::is_valid_date(name))
+                    .map(|(_, path)| path),
+            );
+        }
+    }
+
+    let mut files = Vec::new();
+    for dir in date_dirs {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "png") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// 为孤立的图库图片构造记录；文件名不符合 `{time_index}_{index}_{seed}[_{n}].png` 时返回 `None`
+fn orphan_record(gallery: &GalleryPaths, path: &Path) -> Option<GenerationRecord> {
+    let stem = path.file_stem()?.to_str()?;
+    let parts: Vec<&str> = stem.split('_').collect();
+    if !(3..=4).contains(&parts.len()) || parts.iter().any(|p| p.parse::<u64>().is_err()) {
+        return None;
+    }
+    let seed = parts[2].parse().ok()?;
+    let (width, height) = image::image_dimensions(path).ok()?;
+
+    let date_dir = path.parent()?;
+    let date = date_dir.file_name()?.to_str()?;
+    let created_at = chrono::NaiveDateTime::parse_from_str(
+        &format!("{date} {}", parts[0]),
+        "%Y-%m-%d %H%M%S%3f",
+    )
+    .ok()
+    .and_then(|local| gallery.timezone.to_utc(local))
+    .or_else(|| Some(fs::metadata(path).ok()?.modified().ok()?.into()))
+    .unwrap_or_else(Utc::now);
+    let label = date_dir
+        .parent()
+        .filter(|dir| *dir != gallery.root)
+        .and_then(|dir| dir.file_name())
+        .map(|name| name.to_string_lossy().to_string());
+
+    Some(GenerationRecord {
+        id: Uuid::new_v4(),
+        task_id: Uuid::new_v4(),
+        created_at,
+        raw_prompt: String::new(),
+        expanded_prompt: String::new(),
+        negative_prompt: String::new(),
+        label,
+        raw_negative_prompt: None,
+        main_preset: None,
+        images: vec![GalleryImage {
+            path: path.to_path_buf(),
+            seed,
+            width,
+            height,
+            filter_retries: 0,
+        }],
+        params: None,
+    })
+}
+
+/// 删除记录及其时间索引项，返回被删除的记录（无法解析时为 `None`）
+fn remove_record_row(
+    records: &mut Table<Uuid, String>,
+    by_time: &mut Table<&[u8], Uuid>,
+    id: Uuid,
+) -> CoreResult<Option<GenerationRecord>> {
+    let removed = records
+        .remove(id)?
+        .and_then(|old| serde_json::from_str::<GenerationRecord>(&old.value()).ok());
+    if let Some(record) = &removed {
+        by_time.remove(record_time_key(record.created_at, id).as_slice())?;
+    }
+    Ok(removed)
+}
+
+/// 按日期删除记录的结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeletedByDate {
+    pub records: usize,
+    pub files: usize,
+    /// 日期目录是否已被删除
+    pub folder_removed: bool,
+}
+
+/// 记录与图库文件的一致性检查结果
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VerifyReport {
+    /// 检查的记录数
+    pub checked_records: usize,
+    /// 引用了不存在的图片文件的记录
+    pub missing: Vec<MissingImages>,
+    /// 图库中没有记录引用的图片；未扫描图库时为空
+    pub orphan_files: Vec<PathBuf>,
+}
+
+/// 记录中缺失的图片文件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MissingImages {
+    pub id: Uuid,
+    pub created_at: chrono::DateTime<Utc>,
+    pub missing: Vec<PathBuf>,
+    /// 记录中的图片总数，与 `missing` 数量相同时表示全部缺失
+    pub total_images: usize,
+}
+
+/// 修复记录与图库一致性的结果
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RepairReport {
+    /// 图片全部缺失而被删除的记录
+    pub pruned: Vec<Uuid>,
+    /// 为孤立图片新建的记录
+    pub reindexed: Vec<Uuid>,
+    /// 文件名无法解析或无法读取尺寸、未能重建记录的孤立图片
+    pub skipped_files: Vec<PathBuf>,
+}
+
+/// 本地统计的生成用量，与 NovelAI 的额度接口无关
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UsageStats {
+    /// 成功写入的图片总数
+    pub total_images: u64,
+    /// 至少写入一张图片的任务总数（重试失败图片不重复计数）
+    pub total_tasks: u64,
+    pub first_generated_at: Option<chrono::DateTime<Utc>>,
+    pub last_generated_at: Option<chrono::DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GalleryImage {
+    pub path: PathBuf,
+    pub seed: u64,
+    /// 实际保存的图片尺寸（启用 store_max_dimension 时可能小于请求尺寸）
+    pub width: u32,
+    pub height: u32,
+    /// 因内容过滤换种子重试的次数
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub filter_retries: u32,
+}
+
+/// 供 serde `skip_serializing_if` 使用：计数为 0 时省略字段
+pub fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenerationRecord {
+    pub id: Uuid,
+    pub task_id: Uuid,
+    pub created_at: chrono::DateTime<Utc>,
+    /// Raw prompt before snippet injection.
+    pub raw_prompt: String,
+    /// Prompt after preset + snippet expansion (for debug only).
+    pub expanded_prompt: String,
+    pub negative_prompt: String,
+    pub images: Vec<GalleryImage>,
+    /// 生成参数（角色提示词为展开后的版本）；旧记录没有此字段
+    #[serde(default)]
+    pub params: Option<GenerationParams>,
+    /// 任务的输出标签（已清理），图片保存在 `{label}/{date}/` 下
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// 未展开的负面提示词；旧记录没有此字段
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_negative_prompt: Option<String>,
+    /// 生成时使用的主预设（为空时不记录）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub main_preset: Option<MainPresetSettings>,
+}
+
+impl GenerationRecord {
+    /// 导出指定图片的 NovelAI 请求 JSON（`{ input, model, parameters, ... }`），不含鉴权信息
+    ///
+    /// 冒号权重按 `weight_range` 修正，应与生成时使用的范围一致
+    pub fn to_novelai_json(
+        &self,
+        image_index: usize,
+        weight_range: WeightRange,
+    ) -> CoreResult<serde_json::Value> {
+        let params = self
+            .params
+            .as_ref()
+            .ok_or_else(|| CoreError::invalid("record has no stored generation params"))?;
+        let image = self.images.get(image_index).ok_or_else(|| {
+            CoreError::invalid(format!("image index out of range: {image_index}"))
+        })?;
+        let mut req = to_nai_request(
+            params,
+            &self.expanded_prompt,
+            &self.negative_prompt,
+            image.seed,
+        );
+        req.validate(weight_range);
+        Ok(codex_api::build_payload(&req, image.seed))
+    }
+}
+
+/// 图库按日期分目录、判断"今天"时所用的时区
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GalleryTimezone {
+    /// 系统本地时区
+    #[default]
+    Local,
+    /// IANA 时区，如 `Asia/Shanghai`
+    Named(chrono_tz::Tz),
+}
+
+impl GalleryTimezone {
+    /// 解析 IANA 时区名，空串或 `local` 表示系统本地时区
+    pub fn parse(name: &str) -> CoreResult<Self> {
+        let name = name.trim();
+        if name.is_empty() || name.eq_ignore_ascii_case("local") {
+            return Ok(Self::Local);
+        }
+        name.parse::<chrono_tz::Tz>()
+            .map(Self::Named)
+            .map_err(|_| CoreError::invalid(format!("invalid timezone: {name}")))
+    }
+
+    /// 按该时区格式化时间点
+    pub fn format(&self, at: chrono::DateTime<Utc>, fmt: &str) -> String {
+        match self {
+            Self::Local => at.with_timezone(&Local).format(fmt).to_string(),
+            Self::Named(tz) => at.with_timezone(tz).format(fmt).to_string(),
+        }
+    }
+
+    /// 时间点在该时区下的日期（YYYY-MM-DD）
+    pub fn date_of(&self, at: chrono::DateTime<Utc>) -> String {
+        self.format(at, "%Y-%m-%d")
+    }
+
+    /// 该时区下的今天（YYYY-MM-DD）
+    pub fn today(&self) -> String {
+        self.date_of(Utc::now())
+    }
+
+    /// 把该时区下的本地时间换算为 UTC；夏令时重叠时取较早者，不存在的时间返回 `None`
+    pub fn to_utc(&self, local: chrono::NaiveDateTime) -> Option<chrono::DateTime<Utc>> {
+        match self {
+            Self::Local => Local
+                .from_local_datetime(&local)
+                .earliest()
+                .map(|t| t.to_utc()),
+            Self::Named(tz) => tz
+                .from_local_datetime(&local)
+                .earliest()
+                .map(|t| t.to_utc()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GalleryPaths {
+    pub root: PathBuf,
+    pub timezone: GalleryTimezone,
+}
+
+impl GalleryPaths {
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            timezone: GalleryTimezone::default(),
+        }
+    }
+
+    pub fn with_timezone(mut self, timezone: GalleryTimezone) -> Self {
+        self.timezone = timezone;
+        self
+    }
+
+    /// Build path as [{label}/]YYYY-MM-DD/{time_index}_{index}_{seed}.png
+    /// time_index format: HHMMSSmmm (hour, minute, second, millisecond)
+    /// This ensures filename sorting equals time sorting
+    ///
+    /// `label` 先经 [`sanitize_label`] 清理，清理后为空时使用仅按日期的目录
+    pub fn image_path(&self, index: u32, seed: u64, label: Option<&str>) -> PathBuf {
+        let now = Utc::now();
+        let date_dir = self.timezone.date_of(now);
+        // Time index: HHMMSSmmm format for sorting
+        let time_index = self.timezone.format(now, "%H%M%S%3f");
+        let mut dir = self.root.clone();
+        if let Some(label) = label.and_then(sanitize_label) {
+            dir.push(label);
+        }
+        dir.join(date_dir)
+            .join(format!("{}_{}_{}.png", time_index, index, seed))
+    }
+}
+
+/// 输出标签的最大长度（字符）
+pub const MAX_LABEL_LEN: usize = 64;
+
+/// 将任务标签清理为单个安全的目录名
+///
+/// 字母、数字、`-`、`_` 原样保留，其余字符（含 `/`、`\`、`.`、空白）替换为 `_`，
+/// 去除首尾的 `_` 并截断到 [`MAX_LABEL_LEN`]；结果为空时返回 `None`。
+/// 与日期同形的标签（如 `2024-03-01`）会被归档当作日期文件夹，因此加上 `label_` 前缀
+pub fn sanitize_label(label: &str) -> Option<String> {
+    let cleaned: String = label
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let cleaned: String = cleaned
+        .trim_matches('_')
+        .chars()
+        .take(MAX_LABEL_LEN)
+        .collect();
+    if archive::is_valid_date(&cleaned) {
+        return Some(format!("label_{cleaned}"));
+    }
+    (!cleaned.is_empty()).then_some(cleaned)
+}
+
+impl CoreStorage {
+    /// 清空并按记录表重建时间索引，返回索引的记录数（无法解析的记录跳过）
+    pub fn rebuild_record_time_index(&self) -> CoreResult<usize> {
+        let write_txn = self.begin_write_with_retry()?;
+        let indexed = {
+            write_txn.delete_table(TABLE_RECORDS_BY_TIME)?;
+            let records = write_txn.open_table(TABLE_RECORDS)?;
+            let mut by_time = write_txn.open_table(TABLE_RECORDS_BY_TIME)?;
+            let mut indexed = 0;
+            for entry in records.iter()? {
+                let (key, value) = entry?;
+                let Some(rec) = decode_row::<GenerationRecord>(
+                    TABLE_RECORDS.name(),
+                    key.value(),
+                    &value.value(),
+                ) else {
+                    continue;
+                };
+                by_time.insert(record_time_key(rec.created_at, rec.id).as_slice(), rec.id)?;
+                indexed += 1;
+            }
+            indexed
+        };
+        write_txn.commit()?;
+        info!(indexed, "record time index rebuilt");
+        Ok(indexed)
+    }
+
+    /// 按时间索引倒序读取 `[start, end)` 范围内的记录，最多 `limit` 条
+    fn records_in_range(
+        &self,
+        start: chrono::DateTime<Utc>,
+        end: chrono::DateTime<Utc>,
+        limit: usize,
+    ) -> CoreResult<Vec<GenerationRecord>> {
+        let read_txn = self.db()?.begin_read()?;
+        let by_time = read_txn.open_table(TABLE_RECORDS_BY_TIME)?;
+        let table = read_txn.open_table(TABLE_RECORDS)?;
+        let start = record_time_key(start, Uuid::nil());
+        let end = record_time_key(end, Uuid::nil());
+        let mut records = Vec::new();
+        for entry in by_time.range(start.as_slice()..end.as_slice())?.rev() {
+            if records.len() >= limit {
+                break;
+            }
+            let id = entry?.1.value();
+            let Some(value) = table.get(id)? else {
+                continue;
+            };
+            if let Some(rec) =
+                decode_row::<GenerationRecord>(TABLE_RECORDS.name(), id, &value.value())
+            {
+                records.push(rec);
+            }
+        }
+        Ok(records)
+    }
+
+    /// 读取生成记录中的图片并缩小为预览图尺寸，图片必须位于 `gallery_dir` 之内
+    pub fn record_image_preview(
+        &self,
+        record_id: Uuid,
+        image_index: usize,
+        gallery_dir: &Path,
+    ) -> CoreResult<Vec<u8>> {
+        let record = self
+            .get_record(record_id)?
+            .ok_or_else(|| CoreError::not_found("record"))?;
+        let image = record.images.get(image_index).ok_or_else(|| {
+            CoreError::invalid(format!("image index out of range: {image_index}"))
+        })?;
+        let path = imaging::ensure_within(gallery_dir, &image.path)?;
+        let bytes = fs::read(&path)?;
+        Ok(
+            imaging::downscale_png_with(bytes, self.preview_max_dimension, self.png_compression)?
+                .bytes,
+        )
+    }
+
+    pub fn append_record(&self, record: &GenerationRecord) -> CoreResult<()> {
+        let write_txn = self.begin_write_with_retry()?;
+        {
+            let mut table = write_txn.open_table(TABLE_RECORDS)?;
+            let mut by_time = write_txn.open_table(TABLE_RECORDS_BY_TIME)?;
+            insert_record_row(&mut table, &mut by_time, record)?;
+        }
+        write_txn.commit()?;
+        info!(id=%record.id, task_id=%record.task_id, images=%record.images.len(), "record appended");
+        Ok(())
+    }
+
+    /// 保存生成任务的记录，并在同一事务中累加用量计数
+    ///
+    /// `new_images` 为本次写入的图片数，`new_task` 表示记录是新建的（而非重试追加）
+    pub fn append_generated_record(
+        &self,
+        record: &GenerationRecord,
+        new_images: u64,
+        new_task: bool,
+    ) -> CoreResult<()> {
+        let now = Utc::now();
+        let write_txn = self.begin_write_with_retry()?;
+        {
+            let mut table = write_txn.open_table(TABLE_RECORDS)?;
+            let mut by_time = write_txn.open_table(TABLE_RECORDS_BY_TIME)?;
+            insert_record_row(&mut table, &mut by_time, record)?;
+
+            let mut settings = write_txn.open_table(TABLE_SETTINGS)?;
+            let mut stats = settings
+                .get(SETTINGS_KEY_USAGE_STATS)?
+                .and_then(|value| {
+                    decode_row::<UsageStats>(
+                        TABLE_SETTINGS.name(),
+                        SETTINGS_KEY_USAGE_STATS,
+                        &value.value(),
+                    )
+                })
+                .unwrap_or_default();
+            stats.total_images += new_images;
+            stats.total_tasks += u64::from(new_task);
+            stats.first_generated_at.get_or_insert(now);
+            stats.last_generated_at = Some(now);
+            settings.insert(SETTINGS_KEY_USAGE_STATS, serde_json::to_string(&stats)?)?;
+        }
+        write_txn.commit()?;
+        info!(id=%record.id, task_id=%record.task_id, new_images, "generated record appended");
+        Ok(())
+    }
+
+    /// 读取本地用量计数
+    pub fn usage_stats(&self) -> CoreResult<UsageStats> {
+        let read_txn = self.db()?.begin_read()?;
+        let table = read_txn.open_table(TABLE_SETTINGS)?;
+        Ok(table
+            .get(SETTINGS_KEY_USAGE_STATS)?
+            .and_then(|value| {
+                decode_row(
+                    TABLE_SETTINGS.name(),
+                    SETTINGS_KEY_USAGE_STATS,
+                    &value.value(),
+                )
+            })
+            .unwrap_or_default())
+    }
+
+    /// 清零用量计数，返回清零前的统计
+    pub fn reset_usage_stats(&self) -> CoreResult<UsageStats> {
+        let write_txn = self.begin_write_with_retry()?;
+        let previous: UsageStats = {
+            let mut table = write_txn.open_table(TABLE_SETTINGS)?;
+            table
+                .remove(SETTINGS_KEY_USAGE_STATS)?
+                .and_then(|value| {
+                    decode_row(
+                        TABLE_SETTINGS.name(),
+                        SETTINGS_KEY_USAGE_STATS,
+                        &value.value(),
+                    )
+                })
+                .unwrap_or_default()
+        };
+        write_txn.commit()?;
+        info!(
+            total_images = previous.total_images,
+            total_tasks = previous.total_tasks,
+            "usage stats reset"
+        );
+        Ok(previous)
+    }
+
+    /// 获取单条记录
+    pub fn get_record(&self, id: Uuid) -> CoreResult<Option<GenerationRecord>> {
+        let read_txn = self.db()?.begin_read()?;
+        let table = read_txn.open_table(TABLE_RECORDS)?;
+        if let Some(value) = table.get(id)? {
+            let record: GenerationRecord = serde_json::from_str(&value.value())?;
+            return Ok(Some(record));
+        }
+        Ok(None)
+    }
+
+    /// 更新记录的展开后提示词；记录不存在时返回 `false`
+    pub fn update_record_expanded_prompt(&self, id: Uuid, expanded: &str) -> CoreResult<bool> {
+        let write_txn = self.begin_write_with_retry()?;
+        let updated = {
+            let mut table = write_txn.open_table(TABLE_RECORDS)?;
+            let record = match table.get(id)? {
+                Some(value) => {
+                    let mut record: GenerationRecord = serde_json::from_str(&value.value())?;
+                    record.expanded_prompt = expanded.to_string();
+                    Some(record)
+                }
+                None => None,
+            };
+            match record {
+                Some(record) => {
+                    table.insert(id, serde_json::to_string(&record)?)?;
+                    true
+                }
+                None => false,
+            }
+        };
+        write_txn.commit()?;
+        if updated {
+            info!(id=%id, "record expanded prompt updated");
+        }
+        Ok(updated)
+    }
+
+    /// 删除记录（同时删除关联的图片文件）
+    pub fn delete_record(&self, id: Uuid) -> CoreResult<Option<GenerationRecord>> {
+        // 先获取记录以便后续删除文件
+        let record = self.get_record(id)?;
+        if record.is_none() {
+            return Ok(None);
+        }
+        let record = record.unwrap();
+
+        // 删除关联的图片文件
+        for img in &record.images {
+            if img.path.exists() {
+                if let Err(e) = fs::remove_file(&img.path) {
+                    info!(path=?img.path, error=%e, "failed to delete gallery image file");
+                } else {
+                    info!(path=?img.path, "deleted gallery image file");
+                }
+            }
+        }
+
+        // 从数据库删除记录
+        let write_txn = self.begin_write_with_retry()?;
+        {
+            let mut table = write_txn.open_table(TABLE_RECORDS)?;
+            let mut by_time = write_txn.open_table(TABLE_RECORDS_BY_TIME)?;
+            remove_record_row(&mut table, &mut by_time, id)?;
+        }
+        write_txn.commit()?;
+        info!(id=%id, images=%record.images.len(), "record deleted");
+        Ok(Some(record))
+    }
+
+    /// 删除记录（仅删除数据库记录，不删除图片文件）
+    /// 用于归档场景，图片文件已被压缩到归档中
+    pub fn delete_record_without_files(&self, id: Uuid) -> CoreResult<bool> {
+        let write_txn = self.begin_write_with_retry()?;
+        let removed = {
+            let mut table = write_txn.open_table(TABLE_RECORDS)?;
+            let mut by_time = write_txn.open_table(TABLE_RECORDS_BY_TIME)?;
+            let removed = table.get(id)?.is_some();
+            remove_record_row(&mut table, &mut by_time, id)?;
+            removed
+        };
+        write_txn.commit()?;
+        if removed {
+            info!(id=%id, "record deleted (files preserved for archive)");
+        }
+        Ok(removed)
+    }
+
+    /// 批量删除记录，返回实际删除的记录
+    pub fn delete_records(&self, ids: &[Uuid]) -> CoreResult<Vec<GenerationRecord>> {
+        let mut deleted = Vec::new();
+        for id in ids {
+            if let Some(record) = self.delete_record(*id)? {
+                deleted.push(record);
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// 最近的 `limit` 条记录，最新的在前；只读取时间索引末尾的所需范围
+    pub fn list_recent_records(&self, limit: usize) -> CoreResult<Vec<GenerationRecord>> {
+        self.records_in_range(
+            chrono::DateTime::<Utc>::MIN_UTC,
+            chrono::DateTime::<Utc>::MAX_UTC,
+            limit,
+        )
+    }
+
+    /// 列出某一天（`%Y-%m-%d`，按 `timezone` 计算）的记录，最新的在前
+    pub fn records_by_date(
+        &self,
+        date: &str,
+        timezone: GalleryTimezone,
+    ) -> CoreResult<Vec<GenerationRecord>> {
+        let Ok(day) = chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d") else {
+            return Ok(Vec::new());
+        };
+        // 时区偏移不超过一天，前后各放宽一天后再按时区日期过滤
+        let start = (day - chrono::Days::new(1)).and_time(chrono::NaiveTime::MIN);
+        let end = (day + chrono::Days::new(2)).and_time(chrono::NaiveTime::MIN);
+        let mut records = self.records_in_range(start.and_utc(), end.and_utc(), usize::MAX)?;
+        records.retain(|r| timezone.date_of(r.created_at) == date);
+        Ok(records)
+    }
+
+    /// 列出带有指定标签（子目录）的记录，最新的在前
+    pub fn records_by_label(&self, label: &str) -> CoreResult<Vec<GenerationRecord>> {
+        let read_txn = self.db()?.begin_read()?;
+        let table = read_txn.open_table(TABLE_RECORDS)?;
+        let mut records = Vec::new();
+        for entry in table.iter()? {
+            let (key, value) = entry?;
+            let Some(rec) =
+                decode_row::<GenerationRecord>(TABLE_RECORDS.name(), key.value(), &value.value())
+            else {
+                continue;
+            };
+            if rec.label.as_deref() == Some(label) {
+                records.push(rec);
+            }
+        }
+        records.sort_by_key(|r| std::cmp::Reverse(r.created_at));
+        Ok(records)
+    }
+
+    /// 统计每天（按 `timezone` 计算）的记录数量
+    pub fn record_date_counts(
+        &self,
+        timezone: GalleryTimezone,
+    ) -> CoreResult<BTreeMap<String, usize>> {
+        let read_txn = self.db()?.begin_read()?;
+        let table = read_txn.open_table(TABLE_RECORDS)?;
+        let mut counts = BTreeMap::new();
+        for entry in table.iter()? {
+            let (key, value) = entry?;
+            let Some(rec) =
+                decode_row::<GenerationRecord>(TABLE_RECORDS.name(), key.value(), &value.value())
+            else {
+                continue;
+            };
+            *counts.entry(timezone.date_of(rec.created_at)).or_insert(0) += 1;
+        }
+        Ok(counts)
+    }
+
+    /// 删除某一天的所有记录及其图片文件，并删除该日期目录
+    ///
+    /// 与归档不同，图片不会被保留
+    pub fn delete_records_by_date(
+        &self,
+        date: &str,
+        gallery: &GalleryPaths,
+    ) -> CoreResult<DeletedByDate> {
+        if !archive::is_valid_date(date) {
+            return Err(CoreError::invalid(format!(
+                "invalid date (expected YYYY-MM-DD): {}",
+                date
+            )));
+        }
+        let records = self.records_by_date(date, gallery.timezone)?;
+
+        // 先提交删除记录，再删除文件：事务失败时图片仍在，记录不会指向已删除的文件
+        let write_txn = self.begin_write_with_retry()?;
+        {
+            let mut table = write_txn.open_table(TABLE_RECORDS)?;
+            let mut by_time = write_txn.open_table(TABLE_RECORDS_BY_TIME)?;
+            for rec in &records {
+                remove_record_row(&mut table, &mut by_time, rec.id)?;
+            }
+        }
+        let hash_keys = records
+            .iter()
+            .flat_map(|r| &r.images)
+            .filter_map(|img| gallery_hash_key(&gallery.root, &img.path))
+            .chain(gallery_hash_key(&gallery.root, Path::new(date)));
+        for key in hash_keys {
+            remove_content_hashes_in(&write_txn, &key)?;
+        }
+        write_txn.commit()?;
+
+        let mut files = 0;
+        for img in records.iter().flat_map(|r| &r.images) {
+            match fs::remove_file(&img.path) {
+                Ok(()) => files += 1,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => info!(path=?img.path, error=%e, "failed to delete gallery image file"),
+            }
+            imaging::remove_cached_thumbnails(&gallery.root, &img.path);
+        }
+
+        let date_dir = gallery.root.join(date);
+        let folder_removed = date_dir.is_dir();
+        if folder_removed {
+            fs::remove_dir_all(&date_dir)?;
+        }
+        imaging::remove_cached_thumbnails(&gallery.root, Path::new(date));
+        info!(
+            date,
+            records = records.len(),
+            files,
+            folder_removed,
+            "records deleted by date"
+        );
+        Ok(DeletedByDate {
+            records: records.len(),
+            files,
+            folder_removed,
+        })
+    }
+
+    /// 检查记录引用的图片文件是否存在；传入 `gallery` 时同时列出没有记录引用的图库图片
+    ///
+    /// 图库只扫描 `[{label}/]YYYY-MM-DD/*.png`，缩略图缓存等隐藏目录会被跳过
+    pub fn verify_records(&self, gallery: Option<&GalleryPaths>) -> CoreResult<VerifyReport> {
+        let read_txn = self.db()?.begin_read()?;
+        let table = read_txn.open_table(TABLE_RECORDS)?;
+        let mut report = VerifyReport::default();
+        let mut referenced = HashSet::new();
+        for entry in table.iter()? {
+            let (key, value) = entry?;
+            let Some(rec) =
+                decode_row::<GenerationRecord>(TABLE_RECORDS.name(), key.value(), &value.value())
+            else {
+                continue;
+            };
+            report.checked_records += 1;
+            let missing: Vec<PathBuf> = rec
+                .images
+                .iter()
+                .map(|img| img.path.clone())
+                .filter(|path| !path.is_file())
+                .collect();
+            if !missing.is_empty() {
+                report.missing.push(MissingImages {
+                    id: rec.id,
+                    created_at: rec.created_at,
+                    missing,
+                    total_images: rec.images.len(),
+                });
+            }
+            referenced.extend(rec.images.into_iter().map(|img| img.path));
+        }
+        drop(read_txn);
+
+        if let Some(gallery) = gallery {
+            report.orphan_files = gallery_image_files(&gallery.root)?
+                .into_iter()
+                .filter(|path| !referenced.contains(path))
+                .collect();
+        }
+        report.missing.sort_by_key(|m| m.created_at);
+        info!(
+            checked = report.checked_records,
+            missing = report.missing.len(),
+            orphans = report.orphan_files.len(),
+            "records verified"
+        );
+        Ok(report)
+    }
+
+    /// 修复记录与图库的一致性
+    ///
+    /// 删除图片全部缺失的记录；`reindex_orphans` 时为孤立图片各建一条记录，
+    /// 种子取自文件名 `{time_index}_{index}_{seed}.png`，时间取自日期目录与文件名，提示词留空
+    pub fn repair_records(
+        &self,
+        gallery: &GalleryPaths,
+        reindex_orphans: bool,
+    ) -> CoreResult<RepairReport> {
+        let verify = self.verify_records(reindex_orphans.then_some(gallery))?;
+        let mut report = RepairReport::default();
+
+        let write_txn = self.begin_write_with_retry()?;
+        {
+            let mut table = write_txn.open_table(TABLE_RECORDS)?;
+            let mut by_time = write_txn.open_table(TABLE_RECORDS_BY_TIME)?;
+            for missing in &verify.missing {
+                if missing.missing.len() == missing.total_images {
+                    remove_record_row(&mut table, &mut by_time, missing.id)?;
+                    report.pruned.push(missing.id);
+                }
+            }
+            for path in verify.orphan_files {
+                match orphan_record(gallery, &path) {
+                    Some(record) => {
+                        insert_record_row(&mut table, &mut by_time, &record)?;
+                        report.reindexed.push(record.id);
+                    }
+                    None => report.skipped_files.push(path),
+                }
+            }
+        }
+        write_txn.commit()?;
+        info!(
+            pruned = report.pruned.len(),
+            reindexed = report.reindexed.len(),
+            skipped = report.skipped_files.len(),
+            "records repaired"
+        );
+        Ok(report)
+    }
+
+    pub fn list_record_ids_by_dates(
+        &self,
+        dates: &HashSet<String>,
+        timezone: GalleryTimezone,
+    ) -> CoreResult<Vec<Uuid>> {
+        if dates.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let read_txn = self.db()?.begin_read()?;
+        let table = read_txn.open_table(TABLE_RECORDS)?;
+        let mut ids = Vec::new();
+
+        for entry in table.iter()? {
+            let (key, value) = entry?;
+            let Some(rec) =
+                decode_row::<GenerationRecord>(TABLE_RECORDS.name(), key.value(), &value.value())
+            else {
+                continue;
+            };
+            let record_date = timezone.date_of(rec.created_at);
+            if dates.contains(&record_date) {
+                ids.push(rec.id);
+            }
+        }
+
+        Ok(ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::{
+        Snippet, SnippetResolver, TABLE_CONTENT_HASHES,
+        test_support::{TestDir, TestStorage},
+    };
+
+    #[test]
+    fn test_record_to_novelai_json() {
+        let mut record = GenerationRecord {
+            id: Uuid::new_v4(),
+            task_id: Uuid::new_v4(),
+            created_at: Utc::now(),
+            raw_prompt: "<snippet:x>".to_string(),
+            expanded_prompt: "1girl".to_string(),
+            negative_prompt: "lowres".to_string(),
+            label: None,
+            raw_negative_prompt: None,
+            main_preset: None,
+            images: vec![GalleryImage {
+                path: PathBuf::from("a.png"),
+                seed: 123,
+                width: 832,
+                height: 1216,
+                filter_retries: 0,
+            }],
+            params: Some(GenerationParams {
+                width: 832,
+                height: 1216,
+                add_quality_tags: false,
+                ..GenerationParams::default()
+            }),
+        };
+
+        let range = WeightRange::default();
+        let json = record.to_novelai_json(0, range).unwrap();
+        assert_eq!(json["input"], "1girl");
+        assert_eq!(json["parameters"]["seed"], 123);
+        assert_eq!(json["parameters"]["width"], 832);
+        assert_eq!(json["parameters"]["negative_prompt"], "lowres");
+        assert!(record.to_novelai_json(1, range).is_err());
+
+        // 冒号权重按传入的范围修正
+        record.expanded_prompt = "4::1girl::".to_string();
+        let json = record.to_novelai_json(0, range).unwrap();
+        assert_eq!(json["input"], "2::1girl::");
+        let wide = WeightRange {
+            min: -5.0,
+            max: 5.0,
+        };
+        let json = record.to_novelai_json(0, wide).unwrap();
+        assert_eq!(json["input"], "4::1girl::");
+
+        record.params = None;
+        assert!(record.to_novelai_json(0, range).is_err());
+    }
+
+    #[test]
+    fn test_concurrent_appends_all_succeed() {
+        let dir = TestDir::new();
+        let storage = CoreStorage::open(dir.join("codex.redb"), dir.join("previews")).unwrap();
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let storage = storage.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..10 {
+                        let record = GenerationRecord {
+                            id: Uuid::new_v4(),
+                            task_id: Uuid::new_v4(),
+                            created_at: Utc::now(),
+                            raw_prompt: "1girl".to_string(),
+                            expanded_prompt: "1girl".to_string(),
+                            negative_prompt: String::new(),
+                            label: None,
+                            raw_negative_prompt: None,
+                            main_preset: None,
+                            images: Vec::new(),
+                            params: None,
+                        };
+                        storage.append_record(&record).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(storage.list_recent_records(100).unwrap().len(), 80);
+    }
+
+    #[test]
+    fn test_gallery_timezone_date_of() {
+        let at = chrono::DateTime::parse_from_rfc3339("2024-03-01T20:30:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let tz = GalleryTimezone::parse("Asia/Shanghai").unwrap();
+        assert_eq!(tz.date_of(at), "2024-03-02");
+        assert_eq!(tz.format(at, "%H%M%S%3f"), "043000000");
+        assert_eq!(
+            GalleryTimezone::parse("local").unwrap(),
+            GalleryTimezone::Local
+        );
+        assert!(GalleryTimezone::parse("Mars/Olympus").is_err());
+    }
+
+    #[test]
+    fn test_sanitize_label_blocks_traversal() {
+        assert_eq!(sanitize_label("project-a").as_deref(), Some("project-a"));
+        assert_eq!(sanitize_label("../x").as_deref(), Some("x"));
+        assert_eq!(sanitize_label("a/b\\c").as_deref(), Some("a_b_c"));
+        assert_eq!(sanitize_label("/etc/passwd").as_deref(), Some("etc_passwd"));
+        assert_eq!(sanitize_label(".."), None);
+        // 与日期文件夹同名的标签加前缀
+        assert_eq!(
+            sanitize_label("2024-03-01").as_deref(),
+            Some("label_2024-03-01")
+        );
+        assert_eq!(
+            sanitize_label("/2024-03-01/").as_deref(),
+            Some("label_2024-03-01")
+        );
+        assert_eq!(sanitize_label("2024-99-99").as_deref(), Some("2024-99-99"));
+        assert_eq!(sanitize_label(" / "), None);
+        assert_eq!(
+            sanitize_label(&"x".repeat(100)).map(|l| l.len()),
+            Some(MAX_LABEL_LEN)
+        );
+
+        let gallery = GalleryPaths::new("/gallery");
+        let plain = gallery.image_path(0, 1, None);
+        assert_eq!(
+            plain.parent().unwrap().parent().unwrap(),
+            Path::new("/gallery")
+        );
+        let labelled = gallery.image_path(0, 1, Some("../../secret"));
+        assert_eq!(
+            labelled.parent().unwrap().parent().unwrap(),
+            Path::new("/gallery/secret")
+        );
+        let empty = gallery.image_path(0, 1, Some("../"));
+        assert_eq!(
+            empty.parent().unwrap().parent().unwrap(),
+            Path::new("/gallery")
+        );
+    }
+
+    #[test]
+    fn test_record_image_preview_uses_configured_dimension() {
+        let dir = TestDir::new();
+        let storage = CoreStorage::open(dir.join("codex.redb"), dir.join("previews"))
+            .unwrap()
+            .with_preview_max_dimension(64);
+        let gallery = dir.join("gallery");
+        std::fs::create_dir_all(&gallery).unwrap();
+        let path = gallery.join("a.png");
+        image::DynamicImage::ImageRgb8(image::RgbImage::new(256, 128))
+            .save(&path)
+            .unwrap();
+        let record = GenerationRecord {
+            id: Uuid::new_v4(),
+            task_id: Uuid::new_v4(),
+            created_at: Utc::now(),
+            raw_prompt: String::new(),
+            expanded_prompt: String::new(),
+            negative_prompt: String::new(),
+            label: None,
+            raw_negative_prompt: None,
+            main_preset: None,
+            images: vec![GalleryImage {
+                path,
+                seed: 1,
+                width: 256,
+                height: 128,
+                filter_retries: 0,
+            }],
+            params: None,
+        };
+        storage.append_record(&record).unwrap();
+
+        let bytes = storage
+            .record_image_preview(record.id, 0, &gallery)
+            .unwrap();
+        let img = image::load_from_memory(&bytes).unwrap();
+        assert_eq!((img.width(), img.height()), (64, 32));
+    }
+
+    #[test]
+    fn test_usage_stats_survive_reopen() {
+        let dir = TestDir::new();
+        let record = GenerationRecord {
+            id: Uuid::new_v4(),
+            task_id: Uuid::new_v4(),
+            created_at: Utc::now(),
+            raw_prompt: String::new(),
+            expanded_prompt: String::new(),
+            negative_prompt: String::new(),
+            label: None,
+            raw_negative_prompt: None,
+            main_preset: None,
+            images: Vec::new(),
+            params: None,
+        };
+        {
+            let storage = CoreStorage::open(dir.join("codex.redb"), dir.join("previews")).unwrap();
+            assert_eq!(storage.usage_stats().unwrap(), UsageStats::default());
+            storage.append_generated_record(&record, 3, true).unwrap();
+            // 重试追加不计为新任务
+            storage.append_generated_record(&record, 1, false).unwrap();
+        }
+
+        let storage = CoreStorage::open(dir.join("codex.redb"), dir.join("previews")).unwrap();
+        let stats = storage.usage_stats().unwrap();
+        assert_eq!((stats.total_images, stats.total_tasks), (4, 1));
+        assert!(stats.first_generated_at.unwrap() <= stats.last_generated_at.unwrap());
+
+        assert_eq!(storage.reset_usage_stats().unwrap(), stats);
+        assert_eq!(storage.usage_stats().unwrap(), UsageStats::default());
+    }
+
+    #[test]
+    fn test_update_record_expanded_prompt() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        storage
+            .upsert_snippet(
+                Snippet::new("hair".into(), "char".into(), "red hair".into()).unwrap(),
+                None,
+            )
+            .unwrap();
+        let record = GenerationRecord {
+            id: Uuid::new_v4(),
+            task_id: Uuid::new_v4(),
+            created_at: Utc::now(),
+            raw_prompt: "1girl, <snippet:hair>".into(),
+            expanded_prompt: "1girl, blue hair".into(),
+            negative_prompt: String::new(),
+            label: None,
+            raw_negative_prompt: None,
+            main_preset: None,
+            images: Vec::new(),
+            params: None,
+        };
+        storage.append_record(&record).unwrap();
+
+        let expanded = SnippetResolver::new(Arc::clone(&storage))
+            .expand(&record.raw_prompt)
+            .unwrap();
+        assert_eq!(expanded, "1girl, red hair");
+        assert!(
+            storage
+                .update_record_expanded_prompt(record.id, &expanded)
+                .unwrap()
+        );
+        let updated = storage.get_record(record.id).unwrap().unwrap();
+        assert_eq!(updated.expanded_prompt, "1girl, red hair");
+        assert_eq!(updated.raw_prompt, record.raw_prompt);
+        assert!(
+            !storage
+                .update_record_expanded_prompt(Uuid::new_v4(), &expanded)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_record_time_index_matches_full_scan() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        let base = chrono::DateTime::parse_from_rfc3339("2024-03-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut all: Vec<GenerationRecord> = (0..2000)
+            .map(|_| GenerationRecord {
+                id: Uuid::new_v4(),
+                task_id: Uuid::new_v4(),
+                created_at: base + chrono::Duration::minutes(rng.random_range(0..60 * 24 * 10)),
+                raw_prompt: String::new(),
+                expanded_prompt: String::new(),
+                negative_prompt: String::new(),
+                label: None,
+                raw_negative_prompt: None,
+                main_preset: None,
+                images: Vec::new(),
+                params: None,
+            })
+            .collect();
+        for record in &all {
+            storage.append_record(record).unwrap();
+        }
+        // 覆盖写入时创建时间变化，旧索引项不能残留
+        all[0].created_at = base + chrono::Duration::days(30);
+        storage.append_record(&all[0]).unwrap();
+        let deleted: Vec<Uuid> = all.drain(1..101).map(|r| r.id).collect();
+        for id in &deleted {
+            assert!(storage.delete_record_without_files(*id).unwrap());
+        }
+
+        // 不使用索引的全表扫描结果
+        let mut scanned = all.clone();
+        scanned.sort_by_key(|r| std::cmp::Reverse(r.created_at));
+        let ids = |records: &[GenerationRecord]| {
+            records
+                .iter()
+                .map(|r| (r.created_at, r.id))
+                .collect::<Vec<_>>()
+        };
+        let recent = storage.list_recent_records(50).unwrap();
+        assert_eq!(recent[0].id, all[0].id);
+        // 同一时间的记录顺序不定，只比较时间
+        let times =
+            |records: &[GenerationRecord]| records.iter().map(|r| r.created_at).collect::<Vec<_>>();
+        assert_eq!(times(&recent), times(&scanned[..50]));
+        assert_eq!(
+            storage.list_recent_records(usize::MAX).unwrap().len(),
+            all.len()
+        );
+
+        let shanghai = GalleryTimezone::parse("Asia/Shanghai").unwrap();
+        let mut by_date = storage.records_by_date("2024-03-05", shanghai).unwrap();
+        let mut expected: Vec<_> = scanned
+            .iter()
+            .filter(|r| shanghai.date_of(r.created_at) == "2024-03-05")
+            .cloned()
+            .collect();
+        assert!(!expected.is_empty());
+        by_date.sort_by_key(|r| r.id);
+        expected.sort_by_key(|r| r.id);
+        assert_eq!(ids(&by_date), ids(&expected));
+        assert!(
+            storage
+                .records_by_date("not-a-date", shanghai)
+                .unwrap()
+                .is_empty()
+        );
+
+        assert_eq!(storage.rebuild_record_time_index().unwrap(), all.len());
+        assert_eq!(
+            storage.list_recent_records(usize::MAX).unwrap().len(),
+            all.len()
+        );
+    }
+
+    #[test]
+    fn test_verify_and_repair_records() {
+        let TestStorage { dir, storage } = TestStorage::new();
+        let gallery = GalleryPaths::new(dir.join("gallery"))
+            .with_timezone(GalleryTimezone::parse("UTC").unwrap());
+        let write_png = |rel: &str| {
+            let path = gallery.root.join(rel);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            image::RgbImage::new(4, 3).save(&path).unwrap();
+            path
+        };
+        let image = |path: PathBuf| GalleryImage {
+            path,
+            seed: 1,
+            width: 4,
+            height: 3,
+            filter_retries: 0,
+        };
+        let record = |images: Vec<GalleryImage>| GenerationRecord {
+            id: Uuid::new_v4(),
+            task_id: Uuid::new_v4(),
+            created_at: Utc::now(),
+            raw_prompt: String::new(),
+            expanded_prompt: String::new(),
+            negative_prompt: String::new(),
+            label: None,
+            raw_negative_prompt: None,
+            main_preset: None,
+            images,
+            params: None,
+        };
+
+        let kept = write_png("2024-03-01/090000000_0_1.png");
+        let intact = record(vec![image(kept.clone())]);
+        let partial = record(vec![
+            image(write_png("2024-03-01/090000000_1_1.png")),
+            image(gallery.root.join("2024-03-01/gone.png")),
+        ]);
+        let lost = record(vec![image(gallery.root.join("2024-03-01/lost.png"))]);
+        for rec in [&intact, &partial, &lost] {
+            storage.append_record(rec).unwrap();
+        }
+        let orphan = write_png("2024-03-01/103000500_0_42.png");
+        let labeled = write_png("portraits/2024-03-02/120000000_1_7_1.png");
+        let unnamed = write_png("2024-03-01/export.png");
+        write_png(".thumbs/16/2024-03-01/090000000_0_1.png");
+
+        let report = storage.verify_records(None).unwrap();
+        assert_eq!(report.checked_records, 3);
+        assert!(report.orphan_files.is_empty());
+        let report = storage.verify_records(Some(&gallery)).unwrap();
+        let missing: HashMap<Uuid, usize> = report
+            .missing
+            .iter()
+            .map(|m| (m.id, m.missing.len()))
+            .collect();
+        assert_eq!(missing, HashMap::from([(partial.id, 1), (lost.id, 1)]));
+        assert_eq!(
+            report.orphan_files,
+            vec![orphan.clone(), unnamed.clone(), labeled.clone()]
+        );
+
+        // 不重建孤立图片时只删除图片全部缺失的记录
+        let repaired = storage.repair_records(&gallery, false).unwrap();
+        assert_eq!(repaired.pruned, vec![lost.id]);
+        assert!(repaired.reindexed.is_empty());
+        assert!(storage.get_record(lost.id).unwrap().is_none());
+        assert!(storage.get_record(partial.id).unwrap().is_some());
+
+        let repaired = storage.repair_records(&gallery, true).unwrap();
+        assert!(repaired.pruned.is_empty());
+        assert_eq!(repaired.skipped_files, vec![unnamed]);
+        let rebuilt: Vec<GenerationRecord> = repaired
+            .reindexed
+            .iter()
+            .map(|id| storage.get_record(*id).unwrap().unwrap())
+            .collect();
+        assert_eq!(rebuilt.len(), 2);
+        assert_eq!(rebuilt[0].images[0].path, orphan);
+        assert_eq!(rebuilt[0].images[0].seed, 42);
+        assert_eq!(
+            (rebuilt[0].images[0].width, rebuilt[0].images[0].height),
+            (4, 3)
+        );
+        assert_eq!(
+            rebuilt[0].created_at.to_rfc3339(),
+            "2024-03-01T10:30:00.500+00:00"
+        );
+        assert_eq!(rebuilt[1].label.as_deref(), Some("portraits"));
+        assert_eq!(rebuilt[1].images[0].seed, 7);
+        assert_eq!(
+            storage
+                .records_by_date("2024-03-02", gallery.timezone)
+                .unwrap()[0]
+                .id,
+            rebuilt[1].id
+        );
+
+        let report = storage.verify_records(Some(&gallery)).unwrap();
+        assert_eq!(
+            report.orphan_files,
+            vec![gallery.root.join("2024-03-01/export.png")]
+        );
+    }
+
+    #[test]
+    fn test_records_by_date_in_timezone() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        // UTC 2024-03-01 20:00 在上海已是 03-02
+        for at in [
+            "2024-03-01T10:00:00Z",
+            "2024-03-01T20:00:00Z",
+            "2024-03-02T01:00:00Z",
+        ] {
+            let record = GenerationRecord {
+                id: Uuid::new_v4(),
+                task_id: Uuid::new_v4(),
+                created_at: chrono::DateTime::parse_from_rfc3339(at)
+                    .unwrap()
+                    .with_timezone(&Utc),
+                raw_prompt: String::new(),
+                expanded_prompt: String::new(),
+                negative_prompt: String::new(),
+                label: None,
+                raw_negative_prompt: None,
+                main_preset: None,
+                images: Vec::new(),
+                params: None,
+            };
+            storage.append_record(&record).unwrap();
+        }
+
+        let shanghai = GalleryTimezone::parse("Asia/Shanghai").unwrap();
+        let utc = GalleryTimezone::parse("UTC").unwrap();
+        assert_eq!(
+            storage
+                .records_by_date("2024-03-02", shanghai)
+                .unwrap()
+                .len(),
+            2
+        );
+        let records = storage.records_by_date("2024-03-01", utc).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records[0].created_at > records[1].created_at);
+
+        let counts = storage.record_date_counts(shanghai).unwrap();
+        assert_eq!(
+            counts.into_iter().collect::<Vec<_>>(),
+            vec![("2024-03-01".to_string(), 1), ("2024-03-02".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn test_delete_records_by_date_removes_files() {
+        let TestStorage { dir, storage } = TestStorage::new();
+        let utc = GalleryTimezone::parse("UTC").unwrap();
+        let gallery = GalleryPaths::new(dir.join("gallery")).with_timezone(utc);
+
+        for (at, date) in [
+            ("2024-03-01T10:00:00Z", "2024-03-01"),
+            ("2024-03-02T10:00:00Z", "2024-03-02"),
+        ] {
+            let date_dir = gallery.root.join(date);
+            std::fs::create_dir_all(&date_dir).unwrap();
+            let path = date_dir.join("100000000_0_1.png");
+            std::fs::write(&path, b"png").unwrap();
+            // 模拟已生成的缩略图缓存
+            let thumb_dir = gallery
+                .root
+                .join(imaging::THUMBNAIL_DIR)
+                .join("256")
+                .join(date);
+            std::fs::create_dir_all(&thumb_dir).unwrap();
+            std::fs::write(thumb_dir.join("100000000_0_1.png"), b"png").unwrap();
+            let key = gallery_hash_key(&gallery.root, &path).unwrap();
+            storage.file_content_hash(&key, &path).unwrap();
+            let record = GenerationRecord {
+                id: Uuid::new_v4(),
+                task_id: Uuid::new_v4(),
+                created_at: chrono::DateTime::parse_from_rfc3339(at)
+                    .unwrap()
+                    .with_timezone(&Utc),
+                raw_prompt: String::new(),
+                expanded_prompt: String::new(),
+                negative_prompt: String::new(),
+                label: None,
+                raw_negative_prompt: None,
+                main_preset: None,
+                images: vec![GalleryImage {
+                    path,
+                    seed: 1,
+                    width: 64,
+                    height: 64,
+                    filter_retries: 0,
+                }],
+                params: None,
+            };
+            storage.append_record(&record).unwrap();
+        }
+
+        let deleted = storage
+            .delete_records_by_date("2024-03-01", &gallery)
+            .unwrap();
+        assert_eq!((deleted.records, deleted.files), (1, 1));
+        assert!(deleted.folder_removed);
+        assert!(!gallery.root.join("2024-03-01").exists());
+        assert!(gallery.root.join("2024-03-02").exists());
+        assert!(!gallery.root.join(".thumbs/256/2024-03-01").exists());
+        assert!(gallery.root.join(".thumbs/256/2024-03-02").exists());
+        // 只保留未删除日期的内容哈希
+        let read_txn = storage.db().unwrap().begin_read().unwrap();
+        let hashes = read_txn.open_table(TABLE_CONTENT_HASHES).unwrap();
+        assert!(
+            hashes
+                .get("gallery/2024-03-01/100000000_0_1.png")
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            hashes
+                .get("gallery/2024-03-02/100000000_0_1.png")
+                .unwrap()
+                .is_some()
+        );
+        assert!(
+            storage
+                .records_by_date("2024-03-01", utc)
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(storage.records_by_date("2024-03-02", utc).unwrap().len(), 1);
+        assert!(
+            storage
+                .delete_records_by_date("2024-13-01", &gallery)
+                .is_err()
+        );
+    }
+}
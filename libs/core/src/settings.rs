@@ -0,0 +1,389 @@
+//! 生成设置 - 上次使用的设置与命名配置的存储，以及载入时的预设解析
+
+use redb::{ReadableDatabase, ReadableTable, TableHandle};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{
+    CharacterPreset, CoreError, CoreResult, CoreStorage, GenerationParams, MainPreset,
+    SETTINGS_KEY_LAST_GENERATION, TABLE_GENERATION_PROFILES, TABLE_SETTINGS, decode_row,
+};
+
+/// 角色槽设置，用于保存角色提示词
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct CharacterSlotSettings {
+    pub prompt: String,
+    pub uc: String,
+    pub enabled: bool,
+    pub preset_id: Option<Uuid>,
+}
+
+/// 命名的生成设置配置，可在多套设置之间切换
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenerationProfile {
+    pub name: String,
+    pub settings: LastGenerationSettings,
+}
+
+/// 保存上次生成页面的设置，用于下次打开时恢复
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct LastGenerationSettings {
+    pub prompt: String,
+    pub negative_prompt: String,
+    pub count: u32,
+    pub params: GenerationParams,
+    #[serde(default)]
+    pub character_slots: Vec<CharacterSlotSettings>,
+    /// 主提示词预设ID（替代之前的内联设置）
+    #[serde(default)]
+    pub main_preset_id: Option<Uuid>,
+}
+
+/// 解析后的预设引用；预设已被删除时 `missing` 为 true
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolvedPreset<T> {
+    pub id: Uuid,
+    pub missing: bool,
+    pub preset: Option<T>,
+}
+
+impl<T> ResolvedPreset<T> {
+    fn new(id: Uuid, preset: Option<T>) -> Self {
+        Self {
+            id,
+            missing: preset.is_none(),
+            preset,
+        }
+    }
+}
+
+/// 内联角色预设后的角色槽设置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolvedCharacterSlot {
+    #[serde(flatten)]
+    pub slot: CharacterSlotSettings,
+    pub preset: Option<ResolvedPreset<CharacterPreset>>,
+}
+
+/// 内联所有预设引用后的生成页面设置快照
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolvedGenerationSettings {
+    pub prompt: String,
+    pub negative_prompt: String,
+    pub count: u32,
+    pub params: GenerationParams,
+    pub character_slots: Vec<ResolvedCharacterSlot>,
+    pub main_preset_id: Option<Uuid>,
+    pub main_preset: Option<ResolvedPreset<MainPreset>>,
+}
+
+/// 生成配置名称的最大字符数
+pub const MAX_PROFILE_NAME_CHARS: usize = 64;
+
+/// 生成配置名称校验错误
+#[derive(Debug, Clone, PartialEq, Eq, Error, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum ProfileNameError {
+    #[error("配置名称不能为空")]
+    Empty,
+    #[error("配置名称不能超过 {max} 个字符")]
+    TooLong { max: usize },
+    #[error("配置名称首尾不能有空白")]
+    Untrimmed,
+    #[error("配置名称不能包含字符 '{ch}'（位置 {position}）")]
+    InvalidChar { ch: char, position: usize },
+}
+
+/// 校验生成配置名称；名称会出现在 URL 路径中，不允许 `/`、`\` 和控制字符
+pub fn validate_profile_name(name: &str) -> Result<(), ProfileNameError> {
+    if name.is_empty() {
+        return Err(ProfileNameError::Empty);
+    }
+    if name.trim() != name {
+        return Err(ProfileNameError::Untrimmed);
+    }
+    if let Some((position, ch)) = name
+        .chars()
+        .enumerate()
+        .find(|(_, ch)| ch.is_control() || matches!(ch, '/' | '\\'))
+    {
+        return Err(ProfileNameError::InvalidChar { ch, position });
+    }
+    if name.chars().count() > MAX_PROFILE_NAME_CHARS {
+        return Err(ProfileNameError::TooLong {
+            max: MAX_PROFILE_NAME_CHARS,
+        });
+    }
+    Ok(())
+}
+
+impl CoreStorage {
+    /// 保存上次生成设置
+    pub fn save_last_generation_settings(
+        &self,
+        settings: &LastGenerationSettings,
+    ) -> CoreResult<()> {
+        let serialized = serde_json::to_string(settings)?;
+        let write_txn = self.begin_write_with_retry()?;
+        {
+            let mut table = write_txn.open_table(TABLE_SETTINGS)?;
+            table.insert(SETTINGS_KEY_LAST_GENERATION, serialized)?;
+        }
+        write_txn.commit()?;
+        info!("last generation settings saved");
+        Ok(())
+    }
+
+    /// 加载上次生成设置
+    pub fn load_last_generation_settings(&self) -> CoreResult<Option<LastGenerationSettings>> {
+        let read_txn = self.db()?.begin_read()?;
+        let table = read_txn.open_table(TABLE_SETTINGS)?;
+        if let Some(value) = table.get(SETTINGS_KEY_LAST_GENERATION)? {
+            let settings: LastGenerationSettings = serde_json::from_str(&value.value())?;
+            return Ok(Some(settings));
+        }
+        Ok(None)
+    }
+
+    /// 列出所有命名生成配置，按名称排序
+    pub fn list_generation_profiles(&self) -> CoreResult<Vec<GenerationProfile>> {
+        let read_txn = self.db()?.begin_read()?;
+        let table = read_txn.open_table(TABLE_GENERATION_PROFILES)?;
+        let mut profiles = Vec::new();
+        for entry in table.iter()? {
+            let (key, value) = entry?;
+            let name = key.value().to_string();
+            if let Some(settings) =
+                decode_row(TABLE_GENERATION_PROFILES.name(), &name, &value.value())
+            {
+                profiles.push(GenerationProfile { name, settings });
+            }
+        }
+        Ok(profiles)
+    }
+
+    pub fn get_generation_profile(&self, name: &str) -> CoreResult<Option<LastGenerationSettings>> {
+        let read_txn = self.db()?.begin_read()?;
+        let table = read_txn.open_table(TABLE_GENERATION_PROFILES)?;
+        match table.get(name)? {
+            Some(value) => Ok(Some(serde_json::from_str(&value.value())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// 保存命名生成配置，同名时覆盖
+    pub fn save_generation_profile(
+        &self,
+        name: &str,
+        settings: &LastGenerationSettings,
+    ) -> CoreResult<()> {
+        validate_profile_name(name)?;
+        let serialized = serde_json::to_string(settings)?;
+        let write_txn = self.begin_write_with_retry()?;
+        {
+            let mut table = write_txn.open_table(TABLE_GENERATION_PROFILES)?;
+            table.insert(name, serialized)?;
+        }
+        write_txn.commit()?;
+        info!(name, "generation profile saved");
+        Ok(())
+    }
+
+    pub fn delete_generation_profile(&self, name: &str) -> CoreResult<()> {
+        let write_txn = self.begin_write_with_retry()?;
+        {
+            let mut table = write_txn.open_table(TABLE_GENERATION_PROFILES)?;
+            if table.remove(name)?.is_none() {
+                return Err(CoreError::not_found(format!("generation profile {name}")));
+            }
+        }
+        write_txn.commit()?;
+        info!(name, "generation profile deleted");
+        Ok(())
+    }
+
+    /// 把命名生成配置载入为当前的上次生成设置
+    pub fn load_generation_profile(&self, name: &str) -> CoreResult<LastGenerationSettings> {
+        let settings = self
+            .get_generation_profile(name)?
+            .ok_or_else(|| CoreError::not_found(format!("generation profile {name}")))?;
+        self.save_last_generation_settings(&settings)?;
+        Ok(settings)
+    }
+
+    /// 读取上次生成设置并内联引用的预设；引用失效时标记为 missing 而不报错
+    pub fn load_resolved_generation_settings(&self) -> CoreResult<ResolvedGenerationSettings> {
+        let settings = match self.load_last_generation_settings()? {
+            Some(settings) => settings,
+            // 新会话：预选默认主预设
+            None => LastGenerationSettings {
+                main_preset_id: self.default_main_preset()?.map(|p| p.id),
+                ..Default::default()
+            },
+        };
+        let main_preset = match settings.main_preset_id {
+            Some(id) => Some(ResolvedPreset::new(id, self.get_main_preset(id)?)),
+            None => None,
+        };
+        let character_slots = settings
+            .character_slots
+            .into_iter()
+            .map(|slot| {
+                let preset = match slot.preset_id {
+                    Some(id) => Some(ResolvedPreset::new(id, self.get_preset(id)?)),
+                    None => None,
+                };
+                Ok(ResolvedCharacterSlot { slot, preset })
+            })
+            .collect::<CoreResult<Vec<_>>>()?;
+        Ok(ResolvedGenerationSettings {
+            prompt: settings.prompt,
+            negative_prompt: settings.negative_prompt,
+            count: settings.count,
+            params: settings.params,
+            character_slots,
+            main_preset_id: settings.main_preset_id,
+            main_preset,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Snippet, ValidationError, test_support::TestStorage};
+
+    #[test]
+    fn test_validate_profile_name() {
+        assert_eq!(validate_profile_name("人像 portraits"), Ok(()));
+        assert_eq!(validate_profile_name(""), Err(ProfileNameError::Empty));
+        assert_eq!(
+            validate_profile_name("a/b"),
+            Err(ProfileNameError::InvalidChar {
+                ch: '/',
+                position: 1
+            })
+        );
+        assert_eq!(
+            validate_profile_name(&"x".repeat(MAX_PROFILE_NAME_CHARS + 1)),
+            Err(ProfileNameError::TooLong {
+                max: MAX_PROFILE_NAME_CHARS
+            })
+        );
+    }
+
+    #[test]
+    fn test_generation_profiles_follow_snippet_rename() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        let snippet = storage
+            .upsert_snippet(
+                Snippet::new("hair".into(), "char".into(), "red".into()).unwrap(),
+                None,
+            )
+            .unwrap();
+
+        let portraits = LastGenerationSettings {
+            prompt: "<snippet:hair>, smile".into(),
+            character_slots: vec![CharacterSlotSettings {
+                uc: "<snippet:hair>".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        storage
+            .save_generation_profile("portraits", &portraits)
+            .unwrap();
+        storage
+            .save_generation_profile("landscapes", &LastGenerationSettings::default())
+            .unwrap();
+        assert!(matches!(
+            storage.save_generation_profile(" bad", &portraits),
+            Err(CoreError::Validation(ValidationError::ProfileName(
+                ProfileNameError::Untrimmed
+            )))
+        ));
+
+        let result = storage
+            .rename_snippet(snippet.id, "long_hair".into())
+            .unwrap();
+        assert_eq!(result.updated_profiles, 1);
+        let loaded = storage.load_generation_profile("portraits").unwrap();
+        assert_eq!(loaded.prompt, "<snippet:long_hair>, smile");
+        assert_eq!(loaded.character_slots[0].uc, "<snippet:long_hair>");
+        // 载入后成为当前设置
+        assert_eq!(
+            storage
+                .load_last_generation_settings()
+                .unwrap()
+                .unwrap()
+                .prompt,
+            loaded.prompt
+        );
+
+        let names: Vec<_> = storage
+            .list_generation_profiles()
+            .unwrap()
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+        assert_eq!(names, vec!["landscapes", "portraits"]);
+        storage.delete_generation_profile("landscapes").unwrap();
+        assert!(matches!(
+            storage.delete_generation_profile("landscapes"),
+            Err(CoreError::NotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_resolved_generation_settings_marks_missing() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+
+        let main = storage
+            .upsert_main_preset(MainPreset::new("main".to_string()))
+            .unwrap();
+        let character = storage
+            .upsert_preset(CharacterPreset::new("alice".to_string()))
+            .unwrap();
+        let dangling = Uuid::new_v4();
+        let settings = LastGenerationSettings {
+            main_preset_id: Some(main.id),
+            character_slots: vec![
+                CharacterSlotSettings {
+                    preset_id: Some(character.id),
+                    ..Default::default()
+                },
+                CharacterSlotSettings {
+                    preset_id: Some(dangling),
+                    ..Default::default()
+                },
+                CharacterSlotSettings::default(),
+            ],
+            ..Default::default()
+        };
+        storage.save_last_generation_settings(&settings).unwrap();
+
+        let resolved = storage.load_resolved_generation_settings().unwrap();
+        let main_ref = resolved.main_preset.unwrap();
+        assert!(!main_ref.missing);
+        assert_eq!(main_ref.preset.unwrap().name, "main");
+        let slots = &resolved.character_slots;
+        assert_eq!(slots.len(), 3);
+        assert_eq!(
+            slots[0]
+                .preset
+                .as_ref()
+                .unwrap()
+                .preset
+                .as_ref()
+                .unwrap()
+                .name,
+            "alice"
+        );
+        let missing = slots[1].preset.as_ref().unwrap();
+        assert!(missing.missing);
+        assert_eq!(missing.id, dangling);
+        assert!(slots[2].preset.is_none());
+    }
+}
@@ -0,0 +1,1228 @@
+//! Snippet - 定义、存储、名称索引与引用检查，以及提示词中 snippet 引用的递归展开
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    sync::Arc,
+};
+
+use chrono::Utc;
+use redb::{ReadableDatabase, ReadableTable, TableHandle, WriteTransaction};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{
+    CharacterPreset, CoreError, CoreResult, CoreStorage, LastGenerationSettings, MainPreset, Page,
+    ParseError, PromptParser, SETTINGS_KEY_LAST_GENERATION, TABLE_GENERATION_PROFILES,
+    TABLE_MAIN_PRESETS, TABLE_PRESETS, TABLE_SETTINGS, TABLE_SNIPPET_NAME_INDEX, TABLE_SNIPPETS,
+    Token, decode_row,
+};
+
+/// 在写事务中写入 snippet 并维护名称索引，不做内容校验
+///
+/// `old_name` 为更新前的名称，改名时移除旧索引项；名称已被其他 snippet 占用时返回
+/// [`CoreError::NameTaken`]
+pub(crate) fn put_snippet(
+    txn: &WriteTransaction,
+    snippet: &Snippet,
+    old_name: Option<&str>,
+) -> CoreResult<()> {
+    let mut index = txn.open_table(TABLE_SNIPPET_NAME_INDEX)?;
+    if let Some(existing) = index.get(snippet.name.clone())?
+        && existing.value() != snippet.id
+    {
+        return Err(CoreError::NameTaken {
+            name: snippet.name.clone(),
+        });
+    }
+    if let Some(old_name) = old_name
+        && old_name != snippet.name
+    {
+        index.remove(old_name.to_string())?;
+    }
+    index.insert(snippet.name.clone(), snippet.id)?;
+
+    let mut table = txn.open_table(TABLE_SNIPPETS)?;
+    table.insert(snippet.id, serde_json::to_string(snippet)?)?;
+    Ok(())
+}
+
+/// 在写事务中按名称读取 snippet，能看到同一事务中尚未提交的写入
+pub(crate) fn snippet_by_name_in(
+    txn: &WriteTransaction,
+    name: &str,
+) -> CoreResult<Option<Snippet>> {
+    let index = txn.open_table(TABLE_SNIPPET_NAME_INDEX)?;
+    let Some(id) = index.get(name.to_string())? else {
+        return Ok(None);
+    };
+    let table = txn.open_table(TABLE_SNIPPETS)?;
+    let Some(value) = table.get(id.value())? else {
+        return Ok(None);
+    };
+    Ok(Some(serde_json::from_str(&value.value())?))
+}
+
+/// 提示词中引用的 snippet 名称，按出现顺序去重；注释中的引用不计入
+fn snippet_ref_names(text: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for token in PromptParser::parse(text).tokens {
+        if let Token::SnippetRef { name, .. } = token
+            && !names.contains(&name)
+        {
+            names.push(name);
+        }
+    }
+    names
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snippet {
+    pub id: Uuid,
+    pub name: String,
+    pub category: String,
+    pub tags: Vec<String>,
+    pub description: Option<String>,
+    /// 预览图文件名（存储在 preview_dir 中）
+    pub preview_path: Option<String>,
+    pub content: String,
+    pub created_at: chrono::DateTime<Utc>,
+    pub updated_at: chrono::DateTime<Utc>,
+}
+
+impl Snippet {
+    /// 只校验名称；内容在 [`CoreStorage::upsert_snippet`] 中按存储配置的上限校验
+    pub fn new(name: String, category: String, content: String) -> CoreResult<Self> {
+        validate_snippet_name(&name)?;
+        let now = Utc::now();
+        Ok(Self {
+            id: Uuid::new_v4(),
+            name,
+            category,
+            tags: Vec::new(),
+            description: None,
+            preview_path: None,
+            content,
+            created_at: now,
+            updated_at: now,
+        })
+    }
+}
+
+/// Snippet 名称及分类，用于编辑器自动补全
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnippetName {
+    pub name: String,
+    pub category: String,
+}
+
+/// 重建 snippet 名称索引的结果
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RebuildIndexReport {
+    /// 写入索引的名称数量
+    pub indexed: usize,
+    pub conflicts: Vec<NameConflict>,
+    /// 无法解析而跳过的 snippet 行
+    #[serde(default)]
+    pub corrupt: Vec<Uuid>,
+}
+
+/// 多个 snippet 使用同一名称
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NameConflict {
+    pub name: String,
+    /// 索引指向的 snippet（创建最早）
+    pub kept: Uuid,
+    /// 无法通过名称访问的其余 snippet
+    pub skipped: Vec<Uuid>,
+}
+
+/// 无法解析的 snippet 引用
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DanglingRef {
+    pub location: RefLocation,
+    /// 引用所在字段，如 `uc_after`、`character_slots[0].prompt`
+    pub field: String,
+    /// 找不到的 snippet 名称
+    pub name: String,
+}
+
+/// 引用所在的对象
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RefLocation {
+    Preset { id: Uuid, name: String },
+    MainPreset { id: Uuid, name: String },
+    GenerationSettings,
+    GenerationProfile { name: String },
+}
+
+/// Snippet 重命名结果，包含更新统计
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenameSnippetResult {
+    pub snippet: Snippet,
+    pub updated_presets: usize,
+    pub updated_settings: bool,
+    /// 更新了引用的命名生成配置数量
+    pub updated_profiles: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct SnippetResolver {
+    storage: Arc<CoreStorage>,
+}
+
+impl SnippetResolver {
+    pub fn new(storage: Arc<CoreStorage>) -> Self {
+        Self { storage }
+    }
+
+    /// 递归展开提示词中的 `<snippet:name>` 引用
+    ///
+    /// 被引用的 snippet 内容中的引用同样展开；出现循环引用时返回
+    /// [`CoreError::SnippetCycle`]
+    pub fn expand(&self, prompt: &str) -> CoreResult<String> {
+        self.expand_with(prompt, &mut Vec::new(), &mut HashMap::new())
+    }
+
+    /// 展开 snippet 自身的内容，引用自身也视为循环
+    pub fn expand_snippet(&self, snippet: &Snippet) -> CoreResult<String> {
+        self.expand_with(
+            &snippet.content,
+            &mut vec![snippet.name.clone()],
+            &mut HashMap::new(),
+        )
+    }
+
+    /// `chain` 为当前正在展开的引用链，`expanded` 缓存已展开的 snippet，
+    /// 同一 snippet 在不同分支中重复引用不算循环
+    fn expand_with(
+        &self,
+        prompt: &str,
+        chain: &mut Vec<String>,
+        expanded: &mut HashMap<String, String>,
+    ) -> CoreResult<String> {
+        let mut result = String::with_capacity(prompt.len());
+        let mut chars = prompt.chars().peekable();
+
+        while let Some(ch) = chars.next() {
+            if ch == '<' {
+                let mut token = String::new();
+                while let Some(&next) = chars.peek() {
+                    chars.next();
+                    if next == '>' {
+                        break;
+                    }
+                    token.push(next);
+                }
+                if let Some(rest) = token.strip_prefix("snippet:") {
+                    validate_snippet_name(rest)?;
+                    if chain.iter().any(|name| name == rest) {
+                        let mut chain = chain.clone();
+                        chain.push(rest.to_string());
+                        return Err(CoreError::SnippetCycle { chain });
+                    }
+                    if let Some(content) = expanded.get(rest) {
+                        result.push_str(content);
+                        continue;
+                    }
+                    let snippet = self.storage.get_snippet_by_name(rest)?.ok_or_else(|| {
+                        SnippetExpandError::NotFound {
+                            name: rest.to_string(),
+                        }
+                    })?;
+                    chain.push(snippet.name);
+                    let content = self.expand_with(&snippet.content, chain, expanded)?;
+                    chain.pop();
+                    result.push_str(&content);
+                    expanded.insert(rest.to_string(), content);
+                } else {
+                    // Unknown token, keep literal
+                    result.push('<');
+                    result.push_str(&token);
+                    result.push('>');
+                }
+            } else {
+                result.push(ch);
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+/// Snippet 名称校验错误
+#[derive(Debug, Clone, PartialEq, Eq, Error, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum SnippetNameError {
+    #[error("snippet 名称不能为空")]
+    Empty,
+    #[error("snippet 名称不能包含尖括号 '{ch}'（位置 {position}）")]
+    AngleBracket { ch: char, position: usize },
+    #[error("snippet 名称不能包含逗号（位置 {position}）")]
+    Comma { position: usize },
+    #[error("snippet 名称不能包含空格（位置 {position}）")]
+    Space { position: usize },
+    #[error("snippet 名称不能包含权重括号 '{ch}'（位置 {position}）")]
+    WeightBracket { ch: char, position: usize },
+}
+
+/// Snippet 内容校验错误
+#[derive(Debug, Clone, PartialEq, Eq, Error, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum SnippetContentError {
+    #[error("snippet 内容过大：{len} 字节，上限 {max} 字节")]
+    TooLarge { len: usize, max: usize },
+    #[error("snippet 内容包含未闭合的注释（位置 {position}）")]
+    UnclosedComment { position: usize },
+}
+
+/// Snippet 展开错误
+#[derive(Debug, Clone, PartialEq, Eq, Error, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum SnippetExpandError {
+    #[error("snippet not found: {name}")]
+    NotFound { name: String },
+}
+
+/// 替换生成设置中所有提示词里的 snippet 引用，返回是否有改动
+fn replace_settings_refs(
+    settings: &mut LastGenerationSettings,
+    old_tag: &str,
+    new_tag: &str,
+) -> bool {
+    let mut changed = false;
+    let texts = [&mut settings.prompt, &mut settings.negative_prompt]
+        .into_iter()
+        .chain(
+            settings
+                .character_slots
+                .iter_mut()
+                .flat_map(|slot| [&mut slot.prompt, &mut slot.uc]),
+        );
+    for text in texts {
+        if text.contains(old_tag) {
+            *text = text.replace(old_tag, new_tag);
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// 校验 snippet 内容大小，以及是否含有会破坏展开结果的未闭合注释
+///
+/// `position` 为字符偏移（非字节偏移），便于前端定位
+pub fn validate_snippet_content(
+    content: &str,
+    max_bytes: usize,
+) -> Result<(), SnippetContentError> {
+    if content.len() > max_bytes {
+        return Err(SnippetContentError::TooLarge {
+            len: content.len(),
+            max: max_bytes,
+        });
+    }
+    if let Err(ParseError::UnclosedComment(start)) = PromptParser::strip_comments(content) {
+        return Err(SnippetContentError::UnclosedComment {
+            position: content[..start].chars().count(),
+        });
+    }
+    Ok(())
+}
+
+/// 校验 snippet 名称，返回第一个违规原因
+///
+/// `position` 为字符偏移（非字节偏移），便于前端定位
+pub fn validate_snippet_name(name: &str) -> Result<(), SnippetNameError> {
+    if name.is_empty() {
+        return Err(SnippetNameError::Empty);
+    }
+    for (position, ch) in name.chars().enumerate() {
+        match ch {
+            '<' | '>' => return Err(SnippetNameError::AngleBracket { ch, position }),
+            ',' => return Err(SnippetNameError::Comma { position }),
+            ' ' => return Err(SnippetNameError::Space { position }),
+            '{' | '}' | '(' | ')' | '[' | ']' => {
+                return Err(SnippetNameError::WeightBracket { ch, position });
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+impl CoreStorage {
+    /// 清空并按 snippet 表重建名称索引
+    ///
+    /// 同名 snippet 保留创建最早的一个，其余作为冲突报告（需手动重命名）；
+    /// 无法解析的行跳过并记入报告，不阻止其余名称建立索引
+    pub fn rebuild_name_index(&self) -> CoreResult<RebuildIndexReport> {
+        let write_txn = self.begin_write_with_retry()?;
+        let report = {
+            let snippets_table = write_txn.open_table(TABLE_SNIPPETS)?;
+            let mut by_name: BTreeMap<String, Vec<(chrono::DateTime<Utc>, Uuid)>> = BTreeMap::new();
+            let mut corrupt = Vec::new();
+            for entry in snippets_table.iter()? {
+                let (key, value) = entry?;
+                let Some(snippet) =
+                    decode_row::<Snippet>(TABLE_SNIPPETS.name(), key.value(), &value.value())
+                else {
+                    corrupt.push(key.value());
+                    continue;
+                };
+                by_name
+                    .entry(snippet.name)
+                    .or_default()
+                    .push((snippet.created_at, snippet.id));
+            }
+            drop(snippets_table);
+
+            write_txn.delete_table(TABLE_SNIPPET_NAME_INDEX)?;
+            let mut index = write_txn.open_table(TABLE_SNIPPET_NAME_INDEX)?;
+            let mut report = RebuildIndexReport {
+                corrupt,
+                ..Default::default()
+            };
+            for (name, mut owners) in by_name {
+                owners.sort();
+                index.insert(name.clone(), owners[0].1)?;
+                report.indexed += 1;
+                if owners.len() > 1 {
+                    tracing::warn!(name = %name, count = owners.len(), "duplicate snippet name");
+                    report.conflicts.push(NameConflict {
+                        name,
+                        kept: owners[0].1,
+                        skipped: owners[1..].iter().map(|(_, id)| *id).collect(),
+                    });
+                }
+            }
+            report
+        };
+        write_txn.commit()?;
+        info!(
+            indexed = report.indexed,
+            conflicts = report.conflicts.len(),
+            corrupt = report.corrupt.len(),
+            "snippet name index rebuilt"
+        );
+        Ok(report)
+    }
+
+    /// 检查预设、主预设、上次生成设置和命名生成配置中指向不存在 snippet 的引用
+    pub fn validate_references(&self) -> CoreResult<Vec<DanglingRef>> {
+        let read_txn = self.db()?.begin_read()?;
+        let index = read_txn.open_table(TABLE_SNIPPET_NAME_INDEX)?;
+        let mut dangling = Vec::new();
+        let mut check =
+            |location: &RefLocation, field: String, text: Option<&str>| -> CoreResult<()> {
+                for name in snippet_ref_names(text.unwrap_or_default()) {
+                    if index.get(name.clone())?.is_none() {
+                        dangling.push(DanglingRef {
+                            location: location.clone(),
+                            field: field.clone(),
+                            name,
+                        });
+                    }
+                }
+                Ok(())
+            };
+
+        let presets = read_txn.open_table(TABLE_PRESETS)?;
+        for entry in presets.iter()? {
+            let (key, value) = entry?;
+            let Some(preset) =
+                decode_row::<CharacterPreset>(TABLE_PRESETS.name(), key.value(), &value.value())
+            else {
+                continue;
+            };
+            let location = RefLocation::Preset {
+                id: preset.id,
+                name: preset.name.clone(),
+            };
+            for (field, text) in [
+                ("before", &preset.before),
+                ("after", &preset.after),
+                ("replace", &preset.replace),
+                ("uc_before", &preset.uc_before),
+                ("uc_after", &preset.uc_after),
+                ("uc_replace", &preset.uc_replace),
+            ] {
+                check(&location, field.to_string(), text.as_deref())?;
+            }
+        }
+
+        let main_presets = read_txn.open_table(TABLE_MAIN_PRESETS)?;
+        for entry in main_presets.iter()? {
+            let (key, value) = entry?;
+            let Some(preset) =
+                decode_row::<MainPreset>(TABLE_MAIN_PRESETS.name(), key.value(), &value.value())
+            else {
+                continue;
+            };
+            let location = RefLocation::MainPreset {
+                id: preset.id,
+                name: preset.name.clone(),
+            };
+            for (field, text) in [
+                ("before", &preset.before),
+                ("after", &preset.after),
+                ("replace", &preset.replace),
+                ("uc_before", &preset.uc_before),
+                ("uc_after", &preset.uc_after),
+                ("uc_replace", &preset.uc_replace),
+            ] {
+                check(&location, field.to_string(), text.as_deref())?;
+            }
+        }
+
+        let mut all_settings = Vec::new();
+        let settings_table = read_txn.open_table(TABLE_SETTINGS)?;
+        if let Some(value) = settings_table.get(SETTINGS_KEY_LAST_GENERATION)? {
+            let settings: LastGenerationSettings = serde_json::from_str(&value.value())?;
+            all_settings.push((RefLocation::GenerationSettings, settings));
+        }
+        let profiles = read_txn.open_table(TABLE_GENERATION_PROFILES)?;
+        for entry in profiles.iter()? {
+            let (key, value) = entry?;
+            let name = key.value().to_string();
+            let Some(settings) = decode_row::<LastGenerationSettings>(
+                TABLE_GENERATION_PROFILES.name(),
+                &name,
+                &value.value(),
+            ) else {
+                continue;
+            };
+            all_settings.push((RefLocation::GenerationProfile { name }, settings));
+        }
+        for (location, settings) in &all_settings {
+            check(location, "prompt".to_string(), Some(&settings.prompt))?;
+            check(
+                location,
+                "negative_prompt".to_string(),
+                Some(&settings.negative_prompt),
+            )?;
+            for (i, slot) in settings.character_slots.iter().enumerate() {
+                check(
+                    location,
+                    format!("character_slots[{i}].prompt"),
+                    Some(&slot.prompt),
+                )?;
+                check(location, format!("character_slots[{i}].uc"), Some(&slot.uc))?;
+            }
+        }
+
+        if !dangling.is_empty() {
+            tracing::warn!(count = dangling.len(), "dangling snippet references found");
+        }
+        Ok(dangling)
+    }
+
+    pub fn upsert_snippet(
+        &self,
+        mut snippet: Snippet,
+        preview_bytes: Option<&[u8]>,
+    ) -> CoreResult<Snippet> {
+        validate_snippet_name(&snippet.name)?;
+        validate_snippet_content(&snippet.content, self.max_snippet_content_bytes)?;
+        snippet.updated_at = Utc::now();
+
+        // 获取旧的信息以便更新索引和清理旧预览图
+        let old_data = {
+            let read_txn = self.db()?.begin_read()?;
+            let table = read_txn.open_table(TABLE_SNIPPETS)?;
+            if let Some(value) = table.get(snippet.id)? {
+                let old: Snippet = serde_json::from_str(&value.value())?;
+                Some((old.name, old.preview_path))
+            } else {
+                None
+            }
+        };
+
+        // 处理预览图
+        if let Some(bytes) = preview_bytes {
+            // 删除旧的预览图
+            if let Some((_, ref old_preview)) = old_data {
+                self.remove_old_preview(old_preview.as_deref());
+            }
+            // 保存新的预览图（带时间戳，过大时缩小）
+            snippet.preview_path = Some(self.write_preview(snippet.id, "snippets", bytes)?);
+        }
+
+        let write_txn = self.begin_write_with_retry()?;
+        put_snippet(
+            &write_txn,
+            &snippet,
+            old_data.as_ref().map(|(name, _)| name.as_str()),
+        )?;
+        write_txn.commit()?;
+        info!(id=%snippet.id, name=%snippet.name, "snippet upserted");
+        Ok(snippet)
+    }
+
+    /// 重命名 snippet，并更新所有引用该 snippet 的 preset、LastGenerationSettings 和命名生成配置
+    pub fn rename_snippet(&self, id: Uuid, new_name: String) -> CoreResult<RenameSnippetResult> {
+        validate_snippet_name(&new_name)?;
+
+        let mut snippet = self
+            .get_snippet(id)?
+            .ok_or_else(|| CoreError::not_found("snippet"))?;
+
+        let old_name = snippet.name.clone();
+
+        // 如果名称没变，直接返回
+        if old_name == new_name {
+            return Ok(RenameSnippetResult {
+                snippet,
+                updated_presets: 0,
+                updated_settings: false,
+                updated_profiles: 0,
+            });
+        }
+
+        snippet.name = new_name.clone();
+        snippet.updated_at = Utc::now();
+
+        let serialized = serde_json::to_string(&snippet)?;
+        let write_txn = self.begin_write_with_retry()?;
+        {
+            let mut table = write_txn.open_table(TABLE_SNIPPETS)?;
+            let mut index = write_txn.open_table(TABLE_SNIPPET_NAME_INDEX)?;
+
+            // 检查新名称是否已被使用
+            if let Some(existing) = index.get(new_name.clone())? {
+                let existing_id = existing.value();
+                if existing_id != snippet.id {
+                    return Err(CoreError::NameTaken { name: new_name });
+                }
+            }
+
+            // 更新数据和索引
+            table.insert(snippet.id, serialized)?;
+            index.remove(old_name.clone())?;
+            index.insert(new_name.clone(), snippet.id)?;
+        }
+        write_txn.commit()?;
+        info!(id=%snippet.id, old_name=%old_name, new_name=%new_name, "snippet renamed");
+
+        // 更新所有引用该 snippet 的 preset 和 settings
+        let (updated_presets, updated_settings, updated_profiles) =
+            self.update_snippet_references(&old_name, &new_name)?;
+
+        info!(
+            old_name=%old_name,
+            new_name=%new_name,
+            updated_presets=%updated_presets,
+            updated_settings=%updated_settings,
+            updated_profiles=%updated_profiles,
+            "snippet references updated"
+        );
+
+        Ok(RenameSnippetResult {
+            snippet,
+            updated_presets,
+            updated_settings,
+            updated_profiles,
+        })
+    }
+
+    /// 更新所有引用旧 snippet 名称的地方
+    ///
+    /// 返回 (更新的预设数, 是否更新了上次生成设置, 更新的命名配置数)
+    fn update_snippet_references(
+        &self,
+        old_name: &str,
+        new_name: &str,
+    ) -> CoreResult<(usize, bool, usize)> {
+        let old_tag = format!("<snippet:{}>", old_name);
+        let new_tag = format!("<snippet:{}>", new_name);
+
+        // 更新所有 presets
+        let mut updated_presets = 0;
+        let presets = {
+            let read_txn = self.db()?.begin_read()?;
+            let table = read_txn.open_table(TABLE_PRESETS)?;
+            let mut list = Vec::new();
+            for entry in table.iter()? {
+                let (_, value) = entry?;
+                let preset: CharacterPreset = serde_json::from_str(&value.value())?;
+                list.push(preset);
+            }
+            list
+        };
+
+        for mut preset in presets {
+            let mut changed = false;
+
+            if let Some(ref mut before) = preset.before
+                && before.contains(&old_tag)
+            {
+                *before = before.replace(&old_tag, &new_tag);
+                changed = true;
+            }
+            if let Some(ref mut after) = preset.after
+                && after.contains(&old_tag)
+            {
+                *after = after.replace(&old_tag, &new_tag);
+                changed = true;
+            }
+            if let Some(ref mut replace) = preset.replace
+                && replace.contains(&old_tag)
+            {
+                *replace = replace.replace(&old_tag, &new_tag);
+                changed = true;
+            }
+            if let Some(ref mut uc_before) = preset.uc_before
+                && uc_before.contains(&old_tag)
+            {
+                *uc_before = uc_before.replace(&old_tag, &new_tag);
+                changed = true;
+            }
+            if let Some(ref mut uc_after) = preset.uc_after
+                && uc_after.contains(&old_tag)
+            {
+                *uc_after = uc_after.replace(&old_tag, &new_tag);
+                changed = true;
+            }
+            if let Some(ref mut uc_replace) = preset.uc_replace
+                && uc_replace.contains(&old_tag)
+            {
+                *uc_replace = uc_replace.replace(&old_tag, &new_tag);
+                changed = true;
+            }
+
+            if changed {
+                preset.updated_at = Utc::now();
+                self.upsert_preset(preset)?;
+                updated_presets += 1;
+            }
+        }
+
+        // 更新 LastGenerationSettings
+        let mut updated_settings = false;
+        if let Some(mut settings) = self.load_last_generation_settings()?
+            && replace_settings_refs(&mut settings, &old_tag, &new_tag)
+        {
+            self.save_last_generation_settings(&settings)?;
+            updated_settings = true;
+        }
+
+        // 更新命名生成配置
+        let mut updated_profiles = 0;
+        for mut profile in self.list_generation_profiles()? {
+            if replace_settings_refs(&mut profile.settings, &old_tag, &new_tag) {
+                self.save_generation_profile(&profile.name, &profile.settings)?;
+                updated_profiles += 1;
+            }
+        }
+
+        Ok((updated_presets, updated_settings, updated_profiles))
+    }
+
+    pub fn get_snippet_by_name(&self, name: &str) -> CoreResult<Option<Snippet>> {
+        let read_txn = self.db()?.begin_read()?;
+        let index = read_txn.open_table(TABLE_SNIPPET_NAME_INDEX)?;
+        if let Some(id) = index.get(name.to_string())? {
+            let id = id.value();
+            let table = read_txn.open_table(TABLE_SNIPPETS)?;
+            if let Some(value) = table.get(id)? {
+                let snippet: Snippet = serde_json::from_str(&value.value())?;
+                return Ok(Some(snippet));
+            }
+        }
+        Ok(None)
+    }
+
+    /// 按名称前缀列出 snippet（按名称排序，最多 `limit` 条）
+    ///
+    /// 在名称索引上做范围查询，只读取命中的 snippet 以获取分类
+    pub fn list_snippet_names(&self, prefix: &str, limit: usize) -> CoreResult<Vec<SnippetName>> {
+        let read_txn = self.db()?.begin_read()?;
+        let index = read_txn.open_table(TABLE_SNIPPET_NAME_INDEX)?;
+        let table = read_txn.open_table(TABLE_SNIPPETS)?;
+        let mut names = Vec::new();
+        for entry in index.range(prefix.to_string()..)? {
+            if names.len() >= limit {
+                break;
+            }
+            let (name, id) = entry?;
+            let name = name.value();
+            if !name.starts_with(prefix) {
+                break;
+            }
+            let category = match table.get(id.value())? {
+                Some(value) => serde_json::from_str::<Snippet>(&value.value())?.category,
+                None => continue,
+            };
+            names.push(SnippetName { name, category });
+        }
+        Ok(names)
+    }
+
+    pub fn get_snippet(&self, id: Uuid) -> CoreResult<Option<Snippet>> {
+        let read_txn = self.db()?.begin_read()?;
+        let table = read_txn.open_table(TABLE_SNIPPETS)?;
+        if let Some(value) = table.get(id)? {
+            let snippet: Snippet = serde_json::from_str(&value.value())?;
+            return Ok(Some(snippet));
+        }
+        Ok(None)
+    }
+
+    pub fn delete_snippet(&self, id: Uuid) -> CoreResult<bool> {
+        // First read the snippet to get its name and preview path
+        let snippet_data = {
+            let read_txn = self.db()?.begin_read()?;
+            let table = read_txn.open_table(TABLE_SNIPPETS)?;
+            if let Some(value) = table.get(id)? {
+                let snippet: Snippet = serde_json::from_str(&value.value())?;
+                Some((snippet.name, snippet.preview_path))
+            } else {
+                None
+            }
+        };
+
+        let Some((name, preview_path)) = snippet_data else {
+            return Ok(false);
+        };
+
+        // Now delete from tables
+        let write_txn = self.begin_write_with_retry()?;
+        {
+            let mut table = write_txn.open_table(TABLE_SNIPPETS)?;
+            table.remove(id)?;
+            let mut index = write_txn.open_table(TABLE_SNIPPET_NAME_INDEX)?;
+            index.remove(name)?;
+        }
+        write_txn.commit()?;
+
+        // Remove preview file if exists
+        if let Some(path) = preview_path {
+            let full_path = self.preview_dir.join(path);
+            let _ = fs::remove_file(full_path);
+        }
+
+        info!(id=%id, "snippet deleted");
+        Ok(true)
+    }
+
+    /// 更新 snippet 的预览图
+    pub fn update_snippet_preview(&self, id: Uuid, preview_bytes: &[u8]) -> CoreResult<Snippet> {
+        let mut snippet = self
+            .get_snippet(id)?
+            .ok_or_else(|| CoreError::not_found("snippet"))?;
+
+        // 删除旧的预览图
+        self.remove_old_preview(snippet.preview_path.as_deref());
+
+        // 保存新的预览图（带时间戳，过大时缩小）
+        snippet.preview_path = Some(self.write_preview(snippet.id, "snippets", preview_bytes)?);
+        snippet.updated_at = Utc::now();
+
+        let serialized = serde_json::to_string(&snippet)?;
+        let write_txn = self.begin_write_with_retry()?;
+        {
+            let mut table = write_txn.open_table(TABLE_SNIPPETS)?;
+            table.insert(snippet.id, serialized)?;
+        }
+        write_txn.commit()?;
+        info!(id=%snippet.id, "snippet preview updated");
+        Ok(snippet)
+    }
+
+    /// 删除 snippet 的预览图
+    pub fn delete_snippet_preview(&self, id: Uuid) -> CoreResult<Snippet> {
+        let mut snippet = self
+            .get_snippet(id)?
+            .ok_or_else(|| CoreError::not_found("snippet"))?;
+
+        if let Some(path) = &snippet.preview_path {
+            let full_path = self.preview_dir.join(path);
+            let _ = fs::remove_file(full_path);
+        }
+        snippet.preview_path = None;
+        snippet.updated_at = Utc::now();
+
+        let serialized = serde_json::to_string(&snippet)?;
+        let write_txn = self.begin_write_with_retry()?;
+        {
+            let mut table = write_txn.open_table(TABLE_SNIPPETS)?;
+            table.insert(snippet.id, serialized)?;
+        }
+        write_txn.commit()?;
+        info!(id=%snippet.id, "snippet preview deleted");
+        Ok(snippet)
+    }
+
+    pub fn list_snippets(
+        &self,
+        query: Option<&str>,
+        category: Option<&str>,
+        offset: usize,
+        limit: usize,
+    ) -> CoreResult<Page<Snippet>> {
+        let read_txn = self.db()?.begin_read()?;
+        let table = read_txn.open_table(TABLE_SNIPPETS)?;
+        let mut out = Vec::new();
+        let mut skipped = 0;
+        for entry in table.iter()? {
+            let (key, value) = entry?;
+            let Some(snippet) =
+                decode_row::<Snippet>(TABLE_SNIPPETS.name(), key.value(), &value.value())
+            else {
+                skipped += 1;
+                continue;
+            };
+            if let Some(cat) = category
+                && snippet.category != cat
+            {
+                continue;
+            }
+            if let Some(q) = query {
+                let ql = q.to_lowercase();
+                let hay = format!(
+                    "{} {} {:?}",
+                    snippet.name,
+                    snippet.description.clone().unwrap_or_default(),
+                    snippet.tags.join(" ")
+                )
+                .to_lowercase();
+                if !hay.contains(&ql) {
+                    continue;
+                }
+            }
+            out.push(snippet);
+        }
+        let total = out.len();
+        let items = out.into_iter().skip(offset).take(limit).collect();
+        Ok(Page {
+            skipped,
+            ..Page::new(items, total, offset)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        CharacterSlotSettings, DEFAULT_MAX_SNIPPET_CONTENT_BYTES, ValidationError,
+        test_support::{TestDir, TestStorage},
+    };
+
+    #[test]
+    fn test_validate_snippet_name_ok() {
+        assert_eq!(validate_snippet_name("画风/粗糙线条"), Ok(()));
+        assert_eq!(validate_snippet_name("my_style"), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_snippet_name_empty() {
+        assert_eq!(validate_snippet_name(""), Err(SnippetNameError::Empty));
+    }
+
+    #[test]
+    fn test_validate_snippet_name_angle_bracket() {
+        assert_eq!(
+            validate_snippet_name("a<b"),
+            Err(SnippetNameError::AngleBracket {
+                ch: '<',
+                position: 1
+            })
+        );
+        assert_eq!(
+            validate_snippet_name("ab>"),
+            Err(SnippetNameError::AngleBracket {
+                ch: '>',
+                position: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_validate_snippet_name_comma() {
+        assert_eq!(
+            validate_snippet_name("a,b"),
+            Err(SnippetNameError::Comma { position: 1 })
+        );
+    }
+
+    #[test]
+    fn test_validate_snippet_name_space() {
+        // 位置按字符计算
+        assert_eq!(
+            validate_snippet_name("画风 a"),
+            Err(SnippetNameError::Space { position: 2 })
+        );
+    }
+
+    #[test]
+    fn test_validate_snippet_name_weight_bracket() {
+        for ch in ['{', '}', '(', ')', '[', ']'] {
+            let name = format!("x{ch}");
+            assert_eq!(
+                validate_snippet_name(&name),
+                Err(SnippetNameError::WeightBracket { ch, position: 1 })
+            );
+        }
+    }
+
+    #[test]
+    fn test_expand_missing_snippet_is_typed() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        let resolver = SnippetResolver::new(storage);
+
+        let err = resolver.expand("1girl, <snippet:missing>").unwrap_err();
+        assert!(matches!(
+            err,
+            CoreError::SnippetNotFound(SnippetExpandError::NotFound { ref name }) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn test_expand_is_recursive_and_detects_cycles() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        let put = |name: &str, content: &str| {
+            storage
+                .upsert_snippet(
+                    Snippet::new(name.into(), "cat".into(), content.into()).unwrap(),
+                    None,
+                )
+                .unwrap()
+        };
+        put("color", "blue");
+        put("hair", "<snippet:color> hair");
+        put("eyes", "<snippet:color> eyes");
+        let look = put("look", "<snippet:hair>, <snippet:eyes>");
+        let resolver = SnippetResolver::new(Arc::clone(&storage));
+
+        // 同一 snippet 在不同分支中引用不算循环
+        assert_eq!(
+            resolver.expand("1girl, <snippet:look>").unwrap(),
+            "1girl, blue hair, blue eyes"
+        );
+        assert_eq!(
+            resolver.expand_snippet(&look).unwrap(),
+            "blue hair, blue eyes"
+        );
+
+        put("a", "x, <snippet:b>");
+        put("b", "<snippet:c>");
+        let c = put("c", "<snippet:a>");
+        let err = resolver.expand("<snippet:a>").unwrap_err();
+        assert!(matches!(
+            err,
+            CoreError::SnippetCycle { ref chain } if chain == &["a", "b", "c", "a"]
+        ));
+
+        let own = put("own", "red, <snippet:own>");
+        let err = resolver.expand_snippet(&own).unwrap_err();
+        assert!(matches!(
+            err,
+            CoreError::SnippetCycle { ref chain } if chain == &["own", "own"]
+        ));
+        let err = resolver.expand_snippet(&c).unwrap_err();
+        assert!(matches!(
+            err,
+            CoreError::SnippetCycle { ref chain } if chain == &["c", "a", "b", "c"]
+        ));
+    }
+
+    #[test]
+    fn test_snippet_new_rejects_invalid_name() {
+        let err = Snippet::new("a,b".into(), "cat".into(), "content".into()).unwrap_err();
+        assert!(matches!(
+            err,
+            CoreError::InvalidName(SnippetNameError::Comma { position: 1 })
+        ));
+    }
+
+    #[test]
+    fn test_validate_snippet_content() {
+        assert_eq!(validate_snippet_content("a, //note// b", 64), Ok(()));
+        assert_eq!(
+            validate_snippet_content("abcdef", 4),
+            Err(SnippetContentError::TooLarge { len: 6, max: 4 })
+        );
+        assert_eq!(
+            validate_snippet_content("红发, //未闭合", 64),
+            Err(SnippetContentError::UnclosedComment { position: 4 })
+        );
+    }
+
+    #[test]
+    fn test_upsert_snippet_enforces_content_limit() {
+        let dir = TestDir::new();
+        let storage = CoreStorage::open(dir.join("codex.redb"), dir.join("previews"))
+            .unwrap()
+            .with_max_snippet_content(8);
+
+        let mut snippet = Snippet::new("hair".into(), "char".into(), "red".into()).unwrap();
+        snippet = storage.upsert_snippet(snippet, None).unwrap();
+        snippet.content = "red hair, long".into();
+        let err = storage.upsert_snippet(snippet, None).unwrap_err();
+        assert!(matches!(
+            err,
+            CoreError::Validation(ValidationError::SnippetContent(
+                SnippetContentError::TooLarge { len: 14, max: 8 }
+            ))
+        ));
+
+        let bad = Snippet::new("bad".into(), "char".into(), "x //y".into()).unwrap();
+        let err = storage.upsert_snippet(bad, None).unwrap_err();
+        assert!(matches!(
+            err,
+            CoreError::Validation(ValidationError::SnippetContent(
+                SnippetContentError::UnclosedComment { position: 2 }
+            ))
+        ));
+
+        // 上限按存储配置而非默认值校验
+        let dir = TestDir::new();
+        let storage = CoreStorage::open(dir.join("codex.redb"), dir.join("previews"))
+            .unwrap()
+            .with_max_snippet_content(DEFAULT_MAX_SNIPPET_CONTENT_BYTES * 2);
+        let large = "x".repeat(DEFAULT_MAX_SNIPPET_CONTENT_BYTES + 1);
+        let snippet = Snippet::new("large".into(), "char".into(), large).unwrap();
+        storage.upsert_snippet(snippet, None).unwrap();
+    }
+
+    #[test]
+    fn test_list_snippet_names_by_prefix() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        for (name, category) in [
+            ("画风/粗糙线条", "style"),
+            ("画风/水彩", "style"),
+            ("画面/构图", "scene"),
+            ("hair", "char"),
+        ] {
+            let snippet = Snippet::new(name.into(), category.into(), "x".into()).unwrap();
+            storage.upsert_snippet(snippet, None).unwrap();
+        }
+
+        let names: Vec<_> = storage
+            .list_snippet_names("画风/", 10)
+            .unwrap()
+            .into_iter()
+            .map(|n| (n.name, n.category))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("画风/水彩".to_string(), "style".to_string()),
+                ("画风/粗糙线条".to_string(), "style".to_string()),
+            ]
+        );
+        assert_eq!(storage.list_snippet_names("画", 10).unwrap().len(), 3);
+        assert_eq!(storage.list_snippet_names("", 2).unwrap().len(), 2);
+        assert!(storage.list_snippet_names("zzz", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_validate_references_reports_missing_snippets() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        storage
+            .upsert_snippet(
+                Snippet::new("hair".into(), "char".into(), "red".into()).unwrap(),
+                None,
+            )
+            .unwrap();
+
+        let mut preset = CharacterPreset::new("girl".into());
+        preset.before = Some("<snippet:hair>, <snippet:gone>".into());
+        preset.uc_after = Some("//<snippet:commented>//".into());
+        let preset = storage.upsert_preset(preset).unwrap();
+        let mut main = MainPreset::new("style".into());
+        main.replace = Some("<snippet:lost>, <snippet:lost>".into());
+        let main = storage.upsert_main_preset(main).unwrap();
+        storage
+            .save_last_generation_settings(&LastGenerationSettings {
+                prompt: "<snippet:hair>".into(),
+                character_slots: vec![CharacterSlotSettings {
+                    uc: "<snippet:gone>".into(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            })
+            .unwrap();
+
+        let mut dangling = storage.validate_references().unwrap();
+        dangling.sort_by(|a, b| a.field.cmp(&b.field));
+        assert_eq!(
+            dangling,
+            vec![
+                DanglingRef {
+                    location: RefLocation::Preset {
+                        id: preset.id,
+                        name: "girl".into()
+                    },
+                    field: "before".into(),
+                    name: "gone".into(),
+                },
+                DanglingRef {
+                    location: RefLocation::GenerationSettings,
+                    field: "character_slots[0].uc".into(),
+                    name: "gone".into(),
+                },
+                DanglingRef {
+                    location: RefLocation::MainPreset {
+                        id: main.id,
+                        name: "style".into()
+                    },
+                    field: "replace".into(),
+                    name: "lost".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rebuild_name_index_repairs_corruption() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        let hair = storage
+            .upsert_snippet(
+                Snippet::new("hair".into(), "char".into(), "red".into()).unwrap(),
+                None,
+            )
+            .unwrap();
+        let eyes = storage
+            .upsert_snippet(
+                Snippet::new("eyes".into(), "char".into(), "blue".into()).unwrap(),
+                None,
+            )
+            .unwrap();
+
+        // 模拟崩溃后的索引：缺失条目、残留失效条目，以及绕过索引写入的同名 snippet
+        let mut duplicate = Snippet::new("eyes".into(), "char".into(), "green".into()).unwrap();
+        duplicate.created_at = eyes.created_at + chrono::Duration::seconds(1);
+        let corrupt = Uuid::new_v4();
+        let write_txn = storage.db().unwrap().begin_write().unwrap();
+        {
+            let mut index = write_txn.open_table(TABLE_SNIPPET_NAME_INDEX).unwrap();
+            index.remove("hair".to_string()).unwrap();
+            index.insert("stale".to_string(), Uuid::new_v4()).unwrap();
+            let mut table = write_txn.open_table(TABLE_SNIPPETS).unwrap();
+            table
+                .insert(duplicate.id, serde_json::to_string(&duplicate).unwrap())
+                .unwrap();
+            // 损坏的行不影响其余名称重建
+            table.insert(corrupt, "{not json".to_string()).unwrap();
+        }
+        write_txn.commit().unwrap();
+        assert!(storage.get_snippet_by_name("hair").unwrap().is_none());
+
+        let report = storage.rebuild_name_index().unwrap();
+        assert_eq!(report.corrupt, vec![corrupt]);
+        assert_eq!(report.indexed, 2);
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(report.conflicts[0].name, "eyes");
+        assert_eq!(report.conflicts[0].kept, eyes.id);
+        assert_eq!(report.conflicts[0].skipped, vec![duplicate.id]);
+        assert_eq!(
+            storage.get_snippet_by_name("hair").unwrap().unwrap().id,
+            hair.id
+        );
+        assert!(storage.get_snippet_by_name("stale").unwrap().is_none());
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use redb::{ReadableDatabase, ReadableTable, ReadableTableMetadata, TableHandle};
+use serde::{Deserialize, Serialize};
+
+use crate::lexicon::normalize_tag;
+use crate::prompt_parser::PromptParser;
+use crate::{
+    CoreResult, CoreStorage, GenerationRecord, TABLE_RECORDS, TABLE_TAG_USAGE, decode_row,
+};
+
+/// 使用分数的半衰期（天），越久未用的标签排名越靠后
+const HALF_LIFE_DAYS: f64 = 14.0;
+/// 最多保留的标签数，超出时淘汰分数最低的
+pub const MAX_TRACKED_TAGS: usize = 500;
+
+/// 单个标签的使用统计
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagUsage {
+    pub tag: String,
+    /// 累计使用次数
+    pub count: u64,
+    /// 按半衰期衰减的使用分数，截至 `last_used`
+    pub score: f64,
+    pub last_used: DateTime<Utc>,
+}
+
+impl TagUsage {
+    pub fn new(tag: impl Into<String>, now: DateTime<Utc>) -> Self {
+        Self {
+            tag: tag.into(),
+            count: 1,
+            score: 1.0,
+            last_used: now,
+        }
+    }
+
+    /// 衰减到 `now` 时的分数
+    pub fn score_at(&self, now: DateTime<Utc>) -> f64 {
+        let days = (now - self.last_used).num_seconds().max(0) as f64 / 86_400.0;
+        self.score * 0.5f64.powf(days / HALF_LIFE_DAYS)
+    }
+
+    /// 记录一次使用：先衰减旧分数再加一
+    pub fn bump(&mut self, now: DateTime<Utc>) {
+        self.score = self.score_at(now) + 1.0;
+        self.count += 1;
+        self.last_used = self.last_used.max(now);
+    }
+}
+
+/// 提取提示词中的普通标签，按首次出现顺序去重（按规范化形式）
+///
+/// snippet 引用与注释不计入
+pub fn prompt_tags(prompt: &str) -> Vec<String> {
+    let mut seen = std::collections::HashSet::new();
+    PromptParser::weight_map(prompt)
+        .into_iter()
+        .map(|t| t.name)
+        .filter(|name| !name.starts_with("<snippet:"))
+        .filter(|name| seen.insert(normalize_tag(name)))
+        .collect()
+}
+
+/// 标签共现统计最多扫描的最近记录数
+pub const COOCCURRENCE_SCAN_LIMIT: usize = 2000;
+
+/// 全部生成记录的标签统计
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TagStats {
+    pub total_generations: usize,
+    pub total_images: usize,
+    /// 最早 / 最晚一条记录的时间，没有记录时为空
+    pub first_generation: Option<chrono::DateTime<Utc>>,
+    pub last_generation: Option<chrono::DateTime<Utc>>,
+    /// 使用次数最多的标签，按次数降序
+    pub tags: Vec<TagCount>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    /// 包含该标签的记录数
+    pub count: usize,
+}
+
+impl CoreStorage {
+    /// 记录一次提交中用到的标签；超出上限时淘汰分数最低的标签
+    pub fn record_tag_usage(&self, tags: &[String]) -> CoreResult<()> {
+        if tags.is_empty() {
+            return Ok(());
+        }
+        let now = Utc::now();
+        let write_txn = self.begin_write_with_retry()?;
+        {
+            let mut table = write_txn.open_table(TABLE_TAG_USAGE)?;
+            for tag in tags {
+                let key = normalize_tag(tag);
+                let existing = table
+                    .get(key.as_str())?
+                    .and_then(|value| serde_json::from_str::<TagUsage>(&value.value()).ok());
+                let usage = match existing {
+                    Some(mut usage) => {
+                        usage.bump(now);
+                        usage
+                    }
+                    None => TagUsage::new(tag.trim(), now),
+                };
+                table.insert(key.as_str(), serde_json::to_string(&usage)?)?;
+            }
+
+            let len = table.len()? as usize;
+            if len > MAX_TRACKED_TAGS {
+                let mut scored = Vec::with_capacity(len);
+                for entry in table.iter()? {
+                    let (key, value) = entry?;
+                    let score = serde_json::from_str::<TagUsage>(&value.value())
+                        .map_or(0.0, |u| u.score_at(now));
+                    scored.push((score, key.value().to_string()));
+                }
+                scored.sort_by(|a, b| a.0.total_cmp(&b.0));
+                for (_, key) in scored.into_iter().take(len - MAX_TRACKED_TAGS) {
+                    table.remove(key.as_str())?;
+                }
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// 按衰减后的使用分数列出最常用的标签
+    pub fn recent_tags(&self, limit: usize) -> CoreResult<Vec<TagUsage>> {
+        let now = Utc::now();
+        let read_txn = self.db()?.begin_read()?;
+        let table = read_txn.open_table(TABLE_TAG_USAGE)?;
+        let mut tags = Vec::new();
+        for entry in table.iter()? {
+            let (key, value) = entry?;
+            tags.extend(decode_row::<TagUsage>(
+                TABLE_TAG_USAGE.name(),
+                key.value(),
+                &value.value(),
+            ));
+        }
+        tags.sort_by(|a, b| {
+            b.score_at(now)
+                .total_cmp(&a.score_at(now))
+                .then(b.last_used.cmp(&a.last_used))
+        });
+        tags.truncate(limit);
+        Ok(tags)
+    }
+
+    /// 统计在最近的记录中与 `tag` 一同出现的标签及次数，按次数降序
+    ///
+    /// 标签按规范化形式比较，返回首次见到的写法；最多扫描 [`COOCCURRENCE_SCAN_LIMIT`] 条记录
+    pub fn cooccurring_tags(&self, tag: &str, limit: usize) -> CoreResult<Vec<(String, usize)>> {
+        let query = normalize_tag(tag);
+        let mut counts: HashMap<String, (String, usize)> = HashMap::new();
+        for record in self.list_recent_records(COOCCURRENCE_SCAN_LIMIT)? {
+            let tags = prompt_tags(&record.expanded_prompt);
+            if !tags.iter().any(|t| normalize_tag(t) == query) {
+                continue;
+            }
+            for other in tags {
+                let key = normalize_tag(&other);
+                if key == query {
+                    continue;
+                }
+                counts.entry(key).or_insert((other, 0)).1 += 1;
+            }
+        }
+        let mut result: Vec<(String, usize)> = counts.into_values().collect();
+        result.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        result.truncate(limit);
+        Ok(result)
+    }
+
+    /// 遍历全部记录，统计正面提示词（展开后）中最常用的 `limit` 个标签，以及记录数、图片数与时间范围
+    ///
+    /// 同一条记录中重复的标签只计一次；标签按规范化形式比较，返回首次见到的写法
+    pub fn tag_frequencies(&self, limit: usize) -> CoreResult<TagStats> {
+        let read_txn = self.db()?.begin_read()?;
+        let table = read_txn.open_table(TABLE_RECORDS)?;
+        let mut counts: HashMap<String, (String, usize)> = HashMap::new();
+        let mut stats = TagStats::default();
+        for entry in table.iter()? {
+            let (key, value) = entry?;
+            let Some(rec) =
+                decode_row::<GenerationRecord>(TABLE_RECORDS.name(), key.value(), &value.value())
+            else {
+                continue;
+            };
+            stats.total_generations += 1;
+            stats.total_images += rec.images.len();
+            stats.first_generation = Some(
+                stats
+                    .first_generation
+                    .map_or(rec.created_at, |t| t.min(rec.created_at)),
+            );
+            stats.last_generation = Some(
+                stats
+                    .last_generation
+                    .map_or(rec.created_at, |t| t.max(rec.created_at)),
+            );
+            for tag in prompt_tags(&rec.expanded_prompt) {
+                let key = normalize_tag(&tag);
+                counts.entry(key).or_insert((tag, 0)).1 += 1;
+            }
+        }
+        let mut tags: Vec<TagCount> = counts
+            .into_values()
+            .map(|(tag, count)| TagCount { tag, count })
+            .collect();
+        tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+        tags.truncate(limit);
+        stats.tags = tags;
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{GalleryImage, test_support::TestStorage};
+
+    #[test]
+    fn test_prompt_tags_dedupes_and_skips_snippets() {
+        let tags = prompt_tags("1girl, {Red_Hair}, <snippet:pose>, red hair, // note //smile");
+        assert_eq!(tags, vec!["1girl", "Red_Hair", "smile"]);
+    }
+
+    #[test]
+    fn test_tag_usage_decays() {
+        let start = Utc::now();
+        let mut usage = TagUsage::new("smile", start);
+        let later = start + chrono::Duration::days(14);
+        assert!((usage.score_at(later) - 0.5).abs() < 1e-9);
+
+        usage.bump(later);
+        assert_eq!(usage.count, 2);
+        assert!((usage.score - 1.5).abs() < 1e-9);
+        assert_eq!(usage.last_used, later);
+        // 早于 last_used 的时间不会放大分数
+        assert!((usage.score_at(start) - 1.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_cooccurring_tags_counts_records_with_tag() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        for prompt in [
+            "1girl, {red_hair}, smile",
+            "1girl, 1.2::Red Hair::, night",
+            "1girl, smile, smile",
+            "red hair, night",
+        ] {
+            storage
+                .append_record(&GenerationRecord {
+                    id: Uuid::new_v4(),
+                    task_id: Uuid::new_v4(),
+                    created_at: Utc::now(),
+                    raw_prompt: String::new(),
+                    expanded_prompt: prompt.into(),
+                    negative_prompt: String::new(),
+                    label: None,
+                    raw_negative_prompt: None,
+                    main_preset: None,
+                    images: Vec::new(),
+                    params: None,
+                })
+                .unwrap();
+        }
+
+        let tags = storage.cooccurring_tags("1GIRL", 10).unwrap();
+        assert_eq!(tags.len(), 3);
+        assert_eq!(tags[0].1, 2);
+        assert_eq!(tags[1].1, 2);
+        assert!(
+            tags[..2]
+                .iter()
+                .any(|(t, _)| normalize_tag(t) == "red hair")
+        );
+        assert_eq!(tags[2], ("night".to_string(), 1));
+        assert_eq!(storage.cooccurring_tags("1girl", 1).unwrap().len(), 1);
+        assert!(storage.cooccurring_tags("missing", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_tag_frequencies() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        let empty = storage.tag_frequencies(10).unwrap();
+        assert_eq!(empty.total_generations, 0);
+        assert_eq!(empty.first_generation, None);
+
+        let start = Utc::now();
+        for (i, (prompt, images)) in [
+            ("1girl, {red_hair}, smile", 2),
+            ("1girl, 1.2::Red Hair::, night", 1),
+            ("1girl, smile, smile, <snippet:pose>", 3),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            storage
+                .append_record(&GenerationRecord {
+                    id: Uuid::new_v4(),
+                    task_id: Uuid::new_v4(),
+                    created_at: start + chrono::Duration::hours(i as i64),
+                    raw_prompt: String::new(),
+                    expanded_prompt: prompt.into(),
+                    negative_prompt: String::new(),
+                    label: None,
+                    raw_negative_prompt: None,
+                    main_preset: None,
+                    images: (0..images)
+                        .map(|seed| GalleryImage {
+                            path: PathBuf::from(format!("{i}_{seed}.png")),
+                            seed,
+                            width: 64,
+                            height: 64,
+                            filter_retries: 0,
+                        })
+                        .collect(),
+                    params: None,
+                })
+                .unwrap();
+        }
+
+        let stats = storage.tag_frequencies(3).unwrap();
+        assert_eq!(stats.total_generations, 3);
+        assert_eq!(stats.total_images, 6);
+        assert_eq!(stats.first_generation, Some(start));
+        assert_eq!(
+            stats.last_generation,
+            Some(start + chrono::Duration::hours(2))
+        );
+        let tags: Vec<_> = stats
+            .tags
+            .iter()
+            .map(|t| (normalize_tag(&t.tag), t.count))
+            .collect();
+        assert_eq!(
+            tags,
+            vec![
+                ("1girl".to_string(), 3),
+                ("red hair".to_string(), 2),
+                ("smile".to_string(), 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recent_tags_ranked_by_usage() {
+        let TestStorage { dir: _dir, storage } = TestStorage::new();
+        storage
+            .record_tag_usage(&["1girl".into(), "smile".into()])
+            .unwrap();
+        storage
+            .record_tag_usage(&["1girl".into(), "Red_Hair".into()])
+            .unwrap();
+        storage.record_tag_usage(&["red hair".into()]).unwrap();
+
+        let tags = storage.recent_tags(10).unwrap();
+        assert_eq!(tags.len(), 3);
+        assert_eq!(tags[2].tag, "smile");
+        let red = tags.iter().find(|t| t.tag == "Red_Hair").unwrap();
+        assert_eq!(red.count, 2);
+        assert_eq!(storage.recent_tags(1).unwrap().len(), 1);
+
+        let many: Vec<String> = (0..MAX_TRACKED_TAGS + 5)
+            .map(|i| format!("tag{i}"))
+            .collect();
+        storage.record_tag_usage(&many).unwrap();
+        let tags = storage.recent_tags(usize::MAX).unwrap();
+        assert_eq!(tags.len(), MAX_TRACKED_TAGS);
+        // 使用过多次的标签分数更高，不会被淘汰
+        assert!(tags.iter().any(|t| t.tag == "1girl"));
+    }
+}
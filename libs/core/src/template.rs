@@ -0,0 +1,65 @@
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+// 编译时嵌入内置提示词模板
+const EMBEDDED_TEMPLATES: &str = include_str!("../../../assets/prompt_templates.json");
+
+/// 提示词模板：一组常用的正负面提示词与推荐尺寸
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptTemplate {
+    pub id: String,
+    pub name: String,
+    pub category: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub positive: String,
+    #[serde(default)]
+    pub negative: String,
+    /// 推荐宽度，为空时沿用当前设置
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    /// 推荐高度，为空时沿用当前设置
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+}
+
+impl PromptTemplate {
+    /// 内置的起步模板（只读，不写入存储）
+    pub fn builtins() -> &'static [PromptTemplate] {
+        static BUILTINS: OnceLock<Vec<PromptTemplate>> = OnceLock::new();
+        BUILTINS.get_or_init(|| {
+            serde_json::from_str(EMBEDDED_TEMPLATES).expect("embedded prompt templates are valid")
+        })
+    }
+
+    /// 按 id 查找内置模板
+    pub fn builtin(id: &str) -> Option<&'static PromptTemplate> {
+        Self::builtins().iter().find(|t| t.id == id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtins_are_valid() {
+        let builtins = PromptTemplate::builtins();
+        assert!(!builtins.is_empty());
+        let mut ids: Vec<_> = builtins.iter().map(|t| t.id.as_str()).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), builtins.len());
+        for template in builtins {
+            assert!(!template.positive.trim().is_empty(), "{}", template.id);
+            assert!(template.width.is_none_or(|w| w % 64 == 0));
+            assert!(template.height.is_none_or(|h| h % 64 == 0));
+        }
+        assert_eq!(
+            PromptTemplate::builtin("portrait").map(|t| t.name.as_str()),
+            Some("人物肖像")
+        );
+        assert!(PromptTemplate::builtin("missing").is_none());
+    }
+}
@@ -0,0 +1,171 @@
+//! 测试辅助 - 临时目录、存储与模拟的 NovelAI 接口
+//!
+//! 临时目录与存储通过 `test-support` feature 导出给其他 crate 的测试；
+//! 模拟接口依赖只在 dev-dependencies 中启用的 tokio 功能，仅本 crate 测试可用。
+
+#[cfg(test)]
+use std::sync::Mutex;
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+#[cfg(test)]
+use codex_api::NaiClient;
+#[cfg(test)]
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use uuid::Uuid;
+
+use crate::CoreStorage;
+
+/// 测试用临时目录，drop 时连同内容一起删除
+pub struct TestDir {
+    path: PathBuf,
+}
+
+impl TestDir {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let path = std::env::temp_dir().join(format!("codex-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TestDir {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.path).ok();
+    }
+}
+
+/// 在临时目录中打开的存储
+///
+/// 通常解构使用：`let TestStorage { dir, storage } = TestStorage::new();`，
+/// `dir` 离开作用域时删除整个目录；用不到时写成 `dir: _dir`，不能写 `dir: _`，
+/// 否则目录会立刻被删除。
+pub struct TestStorage {
+    pub dir: TestDir,
+    pub storage: Arc<CoreStorage>,
+}
+
+impl TestStorage {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let dir = TestDir::new();
+        let storage =
+            Arc::new(CoreStorage::open(dir.join("codex.redb"), dir.join("previews")).unwrap());
+        Self { dir, storage }
+    }
+}
+
+/// 本地模拟的 NovelAI 图片接口
+///
+/// 每个请求按到达顺序（从 0 开始）交给 `respond` 决定状态码与响应体，
+/// 请求体记录在 `requests` 中
+#[cfg(test)]
+pub(crate) struct MockNai {
+    pub(crate) base_url: String,
+    pub(crate) requests: Arc<Mutex<Vec<serde_json::Value>>>,
+}
+
+#[cfg(test)]
+impl MockNai {
+    pub(crate) async fn start(respond: impl Fn(usize) -> (u16, Vec<u8>) + Send + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+        tokio::spawn(async move {
+            for n in 0.. {
+                let Ok((mut stream, _)) = listener.accept().await else {
+                    break;
+                };
+                let body = read_request_body(&mut stream).await;
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push(serde_json::from_slice(&body).unwrap());
+                let (status, body) = respond(n);
+                let head = format!(
+                    "HTTP/1.1 {status} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                stream.write_all(head.as_bytes()).await.unwrap();
+                stream.write_all(&body).await.unwrap();
+            }
+        });
+        Self { base_url, requests }
+    }
+
+    /// 指向模拟接口的客户端
+    pub(crate) fn client(&self) -> Arc<NaiClient> {
+        Arc::new(
+            NaiClient::new("token".to_string())
+                .unwrap()
+                .with_image_base_url(&self.base_url),
+        )
+    }
+
+    /// 第 `n` 个请求的正面提示词
+    pub(crate) fn input(&self, n: usize) -> String {
+        self.requests.lock().unwrap()[n]["input"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    /// 成功响应：内含一张 PNG 的 zip
+    pub(crate) fn image_response() -> (u16, Vec<u8>) {
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::DynamicImage::new_rgb8(4, 4)
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        zip.start_file("image_0.png", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        std::io::Write::write_all(&mut zip, png.get_ref()).unwrap();
+        (200, zip.finish().unwrap().into_inner())
+    }
+}
+
+/// 读取一个 HTTP 请求，返回请求体
+#[cfg(test)]
+async fn read_request_body(stream: &mut TcpStream) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let header_end = loop {
+        let n = stream.read(&mut chunk).await.unwrap();
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+    };
+    let head = String::from_utf8_lossy(&buf[..header_end]).to_lowercase();
+    let len: usize = head
+        .lines()
+        .find_map(|line| line.strip_prefix("content-length:"))
+        .map_or(0, |v| v.trim().parse().unwrap());
+    while buf.len() < header_end + len {
+        let n = stream.read(&mut chunk).await.unwrap();
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    buf[header_end..header_end + len].to_vec()
+}
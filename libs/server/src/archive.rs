@@ -1,15 +1,21 @@
 use axum::{
     Json,
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     response::IntoResponse,
 };
-use codex_core::ArchiveManager;
+use codex_core::{
+    ArchiveManager,
+    archive::{ArchiveResult, is_valid_date, write_date_zip},
+};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
 use tokio::sync::Mutex;
 
-use crate::AppState;
+use crate::{AppState, core_error_response};
 
 /// 归档任务状态
 #[derive(Debug, Clone, Serialize)]
@@ -26,19 +32,42 @@ pub enum ArchiveTaskStatus {
     },
     /// 归档失败
     Failed { error: String },
+    /// 被取消；取消前已完成的日期正常归档，剩余日期保持不变
+    Cancelled {
+        archives: Vec<codex_core::ArchiveInfo>,
+        deleted_records: usize,
+        remaining_dates: Vec<String>,
+    },
 }
 
 /// 归档任务状态管理器
 #[derive(Clone)]
 pub struct ArchiveState {
     status: Arc<Mutex<ArchiveTaskStatus>>,
+    cancel: Arc<AtomicBool>,
 }
 
 impl ArchiveState {
     pub fn new() -> Self {
         Self {
             status: Arc::new(Mutex::new(ArchiveTaskStatus::Idle)),
+            cancel: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// 当前任务的取消标志，交给 [`ArchiveManager::with_cancel_flag`]
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.cancel)
+    }
+
+    /// 请求取消正在运行的任务；没有运行中的任务时返回 false
+    pub async fn request_cancel(&self) -> bool {
+        let status = self.status.lock().await;
+        if !matches!(*status, ArchiveTaskStatus::Running { .. }) {
+            return false;
         }
+        self.cancel.store(true, Ordering::SeqCst);
+        true
     }
 
     pub async fn get_status(&self) -> ArchiveTaskStatus {
@@ -50,17 +79,24 @@ impl ArchiveState {
     }
 
     pub async fn set_running(&self, message: String) {
-        *self.status.lock().await = ArchiveTaskStatus::Running { message };
+        let mut status = self.status.lock().await;
+        self.cancel.store(false, Ordering::SeqCst);
+        *status = ArchiveTaskStatus::Running { message };
     }
 
-    pub async fn set_completed(
-        &self,
-        archives: Vec<codex_core::ArchiveInfo>,
-        deleted_records: usize,
-    ) {
-        *self.status.lock().await = ArchiveTaskStatus::Completed {
-            archives,
-            deleted_records,
+    /// 按结果设置为完成或已取消
+    pub async fn set_finished(&self, result: ArchiveResult) {
+        *self.status.lock().await = if result.cancelled {
+            ArchiveTaskStatus::Cancelled {
+                archives: result.archives,
+                deleted_records: result.deleted_records,
+                remaining_dates: result.remaining_dates,
+            }
+        } else {
+            ArchiveTaskStatus::Completed {
+                archives: result.archives,
+                deleted_records: result.deleted_records,
+            }
         };
     }
 
@@ -81,19 +117,22 @@ struct ArchiveStartedResponse {
 
 /// 列出所有归档文件
 pub async fn list_archives(State(state): State<AppState>) -> impl IntoResponse {
-    let manager = ArchiveManager::new(&state.gallery_dir, &state.storage);
+    let manager =
+        ArchiveManager::new(&state.gallery_dir, &state.storage).with_timezone(state.timezone);
     match manager.list_archives().await {
         Ok(archives) => Json(archives).into_response(),
-        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        Err(err) => core_error_response(err),
     }
 }
 
 /// 列出所有可归档的日期
 pub async fn list_archivable_dates(State(state): State<AppState>) -> impl IntoResponse {
-    let manager = ArchiveManager::new(&state.gallery_dir, &state.storage);
+    let manager = ArchiveManager::new(&state.gallery_dir, &state.storage)
+        .with_timezone(state.timezone)
+        .with_options(state.archive_options);
     match manager.list_archivable_dates().await {
         Ok(dates) => Json(dates).into_response(),
-        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        Err(err) => core_error_response(err),
     }
 }
 
@@ -129,9 +168,16 @@ pub async fn create_archive(State(state): State<AppState>) -> impl IntoResponse
     let gallery_dir = state.gallery_dir.clone();
     let storage = Arc::clone(&state.storage);
     let archive_state = state.archive_state.clone();
+    let timezone = state.timezone;
+    let options = state.archive_options;
+
+    let cancel = state.archive_state.cancel_flag();
 
     tokio::spawn(async move {
-        let manager = ArchiveManager::new(&gallery_dir, &storage);
+        let manager = ArchiveManager::new(&gallery_dir, &storage)
+            .with_timezone(timezone)
+            .with_options(options)
+            .with_cancel_flag(cancel);
         let result = manager.create_archives().await;
 
         match result {
@@ -139,11 +185,10 @@ pub async fn create_archive(State(state): State<AppState>) -> impl IntoResponse
                 tracing::info!(
                     archives = res.archives.len(),
                     deleted = res.deleted_records,
-                    "archive task completed"
+                    cancelled = res.cancelled,
+                    "archive task finished"
                 );
-                archive_state
-                    .set_completed(res.archives, res.deleted_records)
-                    .await;
+                archive_state.set_finished(res).await;
             }
             Err(err) => {
                 tracing::error!(error = %err, "archive task failed");
@@ -189,6 +234,13 @@ pub async fn create_archive_selected(
     if dates.is_empty() {
         return (StatusCode::BAD_REQUEST, "no dates specified").into_response();
     }
+    if let Some(date) = dates.iter().find(|d| !is_valid_date(d)) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("invalid date (expected YYYY-MM-DD): {date}"),
+        )
+            .into_response();
+    }
 
     // 设置为运行中状态
     state
@@ -200,9 +252,16 @@ pub async fn create_archive_selected(
     let gallery_dir = state.gallery_dir.clone();
     let storage = Arc::clone(&state.storage);
     let archive_state = state.archive_state.clone();
+    let timezone = state.timezone;
+    let options = state.archive_options;
+
+    let cancel = state.archive_state.cancel_flag();
 
     tokio::spawn(async move {
-        let manager = ArchiveManager::new(&gallery_dir, &storage);
+        let manager = ArchiveManager::new(&gallery_dir, &storage)
+            .with_timezone(timezone)
+            .with_options(options)
+            .with_cancel_flag(cancel);
         let result = manager.create_archives_for_dates(&dates).await;
 
         match result {
@@ -210,11 +269,10 @@ pub async fn create_archive_selected(
                 tracing::info!(
                     archives = res.archives.len(),
                     deleted = res.deleted_records,
-                    "archive task completed"
+                    cancelled = res.cancelled,
+                    "archive task finished"
                 );
-                archive_state
-                    .set_completed(res.archives, res.deleted_records)
-                    .await;
+                archive_state.set_finished(res).await;
             }
             Err(err) => {
                 tracing::error!(error = %err, "archive task failed");
@@ -232,6 +290,21 @@ pub async fn create_archive_selected(
         .into_response()
 }
 
+/// 取消正在运行的归档任务：当前日期完成后停止，剩余日期不归档
+pub async fn cancel_archive(State(state): State<AppState>) -> impl IntoResponse {
+    if state.archive_state.request_cancel().await {
+        (
+            StatusCode::ACCEPTED,
+            Json(ArchiveStartedResponse {
+                message: "archive cancellation requested".to_string(),
+            }),
+        )
+            .into_response()
+    } else {
+        (StatusCode::CONFLICT, "no archive task is running").into_response()
+    }
+}
+
 /// 下载归档文件
 pub async fn download_archive(
     State(state): State<AppState>,
@@ -243,18 +316,11 @@ pub async fn download_archive(
 
     let gallery_dir = state.gallery_dir.clone();
     let storage = Arc::clone(&state.storage);
-    let manager = ArchiveManager::new(&gallery_dir, &storage);
+    let manager = ArchiveManager::new(&gallery_dir, &storage).with_timezone(state.timezone);
 
     let archive_path = match manager.get_archive_path(&name) {
         Ok(path) => path,
-        Err(err) => {
-            let status = if err.to_string().contains("not found") {
-                StatusCode::NOT_FOUND
-            } else {
-                StatusCode::BAD_REQUEST
-            };
-            return (status, err.to_string()).into_response();
-        }
+        Err(err) => return core_error_response(err),
     };
 
     match tokio::fs::File::open(&archive_path).await {
@@ -276,23 +342,101 @@ pub async fn download_archive(
     }
 }
 
+/// 将某天的图片即时打包为 zip 下载，不写入磁盘、不删除记录
+pub async fn download_date_zip(
+    State(state): State<AppState>,
+    Path(date): Path<String>,
+) -> impl IntoResponse {
+    use axum::body::Body;
+    use axum::http::header;
+    use tokio_util::io::{ReaderStream, SyncIoBridge};
+
+    if !is_valid_date(&date) {
+        return (
+            StatusCode::BAD_REQUEST,
+            "invalid date (expected YYYY-MM-DD)",
+        )
+            .into_response();
+    }
+    if !state.gallery_dir.join(&date).is_dir() {
+        return (StatusCode::NOT_FOUND, "date folder not found").into_response();
+    }
+
+    let (reader, writer) = tokio::io::duplex(256 * 1024);
+    let writer = SyncIoBridge::new(writer);
+    let gallery_dir = state.gallery_dir.clone();
+    let zip_date = date.clone();
+    let buffer_size = state.archive_options.buffer_size;
+    tokio::task::spawn_blocking(move || {
+        // 客户端中途断开时写入失败，只记录日志
+        if let Err(err) = write_date_zip(&gallery_dir, &zip_date, writer, buffer_size) {
+            tracing::warn!(date = %zip_date, error = %err, "date zip stream aborted");
+        }
+    });
+
+    let headers = [
+        (header::CONTENT_TYPE, "application/zip".to_string()),
+        (
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}.zip\"", date),
+        ),
+    ];
+    (headers, Body::from_stream(ReaderStream::new(reader))).into_response()
+}
+
+/// 列出归档内的文件，不解压
+pub async fn list_archive_contents(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    let manager =
+        ArchiveManager::new(&state.gallery_dir, &state.storage).with_timezone(state.timezone);
+    match manager.list_archive_contents(&name).await {
+        Ok(entries) => Json(entries).into_response(),
+        Err(err) => core_error_response(err),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ArchiveFileQuery {
+    /// zip 内路径，如 `2024-03-01/xxx.png`
+    entry: String,
+}
+
+/// 读取归档内的单个文件，按文件头返回 Content-Type
+pub async fn extract_archive_file(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<ArchiveFileQuery>,
+) -> impl IntoResponse {
+    use axum::http::header;
+
+    let manager =
+        ArchiveManager::new(&state.gallery_dir, &state.storage).with_timezone(state.timezone);
+    match manager.extract_file(&name, &query.entry).await {
+        Ok(bytes) => (
+            [(
+                header::CONTENT_TYPE,
+                codex_core::imaging::sniff_content_type(&bytes),
+            )],
+            bytes,
+        )
+            .into_response(),
+        Err(err) => core_error_response(err),
+    }
+}
+
 /// 删除归档文件
 pub async fn delete_archive(
     State(state): State<AppState>,
     Path(name): Path<String>,
 ) -> impl IntoResponse {
-    let manager = ArchiveManager::new(&state.gallery_dir, &state.storage);
+    let manager =
+        ArchiveManager::new(&state.gallery_dir, &state.storage).with_timezone(state.timezone);
 
     match manager.delete_archive(&name).await {
         Ok(true) => StatusCode::NO_CONTENT.into_response(),
         Ok(false) => StatusCode::NOT_FOUND.into_response(),
-        Err(err) => {
-            let status = if err.to_string().contains("invalid") {
-                StatusCode::BAD_REQUEST
-            } else {
-                StatusCode::INTERNAL_SERVER_ERROR
-            };
-            (status, err.to_string()).into_response()
-        }
+        Err(err) => core_error_response(err),
     }
 }
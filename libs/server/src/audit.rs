@@ -0,0 +1,204 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use codex_core::{GalleryImage, GenerateTaskRequest};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// 审计日志超过该大小时轮转为 `<path>.1`
+const MAX_AUDIT_LOG_BYTES: u64 = 10 * 1024 * 1024;
+
+/// 单个任务结束时写入的一行审计记录
+#[derive(Debug, Serialize)]
+struct AuditEntry<'a> {
+    timestamp: chrono::DateTime<Utc>,
+    task_id: Uuid,
+    /// completed / partially_completed / failed
+    status: &'a str,
+    /// 隐私模式下不记录原文
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prompt: Option<&'a str>,
+    prompt_sha256: String,
+    count: u32,
+    seeds: Vec<u64>,
+    /// 按非 Opus 计费估算的已生成图片消耗
+    estimated_anlas: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+/// 只追加的 JSON lines 审计日志，每个任务结束时写入一行
+pub struct AuditLog {
+    path: PathBuf,
+    privacy: bool,
+    /// 超过该大小时轮转
+    max_bytes: u64,
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    pub fn open(path: PathBuf, privacy: bool) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("create audit log dir")?;
+        }
+        let file = open_append(&path)?;
+        Ok(Self {
+            path,
+            privacy,
+            max_bytes: MAX_AUDIT_LOG_BYTES,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// 在阻塞线程池中记录一次作业的结果；写入失败只记录日志
+    ///
+    /// `count` 为本次作业请求的图片数，`images` 只包含本次作业生成的图片
+    /// （重试失败图片时不含原记录中已有的图片）
+    pub async fn record(
+        self: &Arc<Self>,
+        task: &GenerateTaskRequest,
+        count: u32,
+        status: &'static str,
+        images: &[GalleryImage],
+        error: Option<String>,
+    ) {
+        let seeds: Vec<u64> = images.iter().map(|img| img.seed).collect();
+        let estimated_anlas = task.params.estimated_anlas(false) * seeds.len() as u32;
+        let prompt = task.raw_prompt.clone();
+        let task_id = task.id;
+        let log = Arc::clone(self);
+
+        let result = tokio::task::spawn_blocking(move || {
+            let entry = AuditEntry {
+                timestamp: Utc::now(),
+                task_id,
+                status,
+                prompt: (!log.privacy).then_some(prompt.as_str()),
+                prompt_sha256: sha256_hex(&prompt),
+                count,
+                seeds,
+                estimated_anlas,
+                error: error.as_deref(),
+            };
+            log.append(&entry)
+        })
+        .await;
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => {
+                tracing::warn!(task_id=%task_id, error=%err, "failed to write audit log")
+            }
+            Err(err) => tracing::warn!(task_id=%task_id, error=%err, "audit log task panicked"),
+        }
+    }
+
+    /// 追加一行并 fsync，超过大小上限时先轮转
+    fn append(&self, entry: &AuditEntry<'_>) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        if file.metadata()?.len() > self.max_bytes {
+            let rotated = self.path.with_extension(match self.path.extension() {
+                Some(ext) => format!("{}.1", ext.to_string_lossy()),
+                None => "1".to_string(),
+            });
+            fs::rename(&self.path, &rotated).context("rotate audit log")?;
+            *file = open_append(&self.path)?;
+        }
+        file.write_all(&line).context("write audit log")?;
+        file.sync_data().context("sync audit log")?;
+        Ok(())
+    }
+}
+
+fn open_append(path: &PathBuf) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("open audit log {}", path.display()))
+}
+
+fn sha256_hex(text: &str) -> String {
+    Sha256::digest(text.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use codex_core::test_support::TestDir;
+
+    use super::*;
+
+    fn image(seed: u64) -> GalleryImage {
+        GalleryImage {
+            path: PathBuf::from(format!("{seed}.png")),
+            seed,
+            width: 832,
+            height: 1216,
+            filter_retries: 0,
+        }
+    }
+
+    fn lines(path: &std::path::Path) -> Vec<serde_json::Value> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_record_only_given_images_and_privacy() {
+        let dir = TestDir::new();
+        let task = GenerateTaskRequest::new("1girl, secret".to_string(), String::new());
+        let per_image = task.params.estimated_anlas(false);
+
+        let log = Arc::new(AuditLog::open(dir.join("audit.jsonl"), false).unwrap());
+        log.record(&task, 2, "completed", &[image(7), image(8)], None)
+            .await;
+        let entry = &lines(&dir.join("audit.jsonl"))[0];
+        assert_eq!(entry["prompt"], "1girl, secret");
+        assert_eq!(entry["count"], 2);
+        assert_eq!(entry["seeds"], serde_json::json!([7, 8]));
+        assert_eq!(entry["estimated_anlas"], per_image * 2);
+
+        // 隐私模式只保留哈希
+        let log = Arc::new(AuditLog::open(dir.join("private.jsonl"), true).unwrap());
+        log.record(&task, 1, "failed", &[], Some("boom".to_string()))
+            .await;
+        let entry = &lines(&dir.join("private.jsonl"))[0];
+        assert!(entry.get("prompt").is_none());
+        assert_eq!(entry["prompt_sha256"], sha256_hex("1girl, secret"));
+        assert_eq!(entry["error"], "boom");
+        assert_eq!(entry["estimated_anlas"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_log_rotates_when_too_large() {
+        let dir = TestDir::new();
+        let path = dir.join("audit.jsonl");
+        let mut log = AuditLog::open(path.clone(), false).unwrap();
+        log.max_bytes = 64;
+        let log = Arc::new(log);
+        let task = GenerateTaskRequest::new("1girl".to_string(), String::new());
+
+        log.record(&task, 1, "completed", &[image(1)], None).await;
+        assert!(!dir.join("audit.jsonl.1").exists());
+        log.record(&task, 1, "completed", &[image(2)], None).await;
+        assert_eq!(lines(&dir.join("audit.jsonl.1")).len(), 1);
+        let current = lines(&path);
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0]["seeds"], serde_json::json!([2]));
+    }
+}
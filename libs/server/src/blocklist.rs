@@ -0,0 +1,54 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use serde::Deserialize;
+
+use crate::{AppState, core_error_response};
+
+#[derive(Debug, Deserialize)]
+pub struct AddBlockedTagPayload {
+    tag: String,
+}
+
+pub async fn list_blocked_tags(State(state): State<AppState>) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state.run_db(move || storage.list_blocked_tags()).await {
+        Ok(Ok(tags)) => Json(tags).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// 添加屏蔽标签，生成时会从最终正面提示词中删除
+pub async fn add_blocked_tag(
+    State(state): State<AppState>,
+    Json(payload): Json<AddBlockedTagPayload>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state
+        .run_db(move || storage.add_blocked_tag(&payload.tag))
+        .await
+    {
+        Ok(Ok(tag)) => Json(tag).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+pub async fn remove_blocked_tag(
+    State(state): State<AppState>,
+    Path(tag): Path<String>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state.run_db(move || storage.remove_blocked_tag(&tag)).await {
+        Ok(Ok(true)) => StatusCode::NO_CONTENT.into_response(),
+        Ok(Ok(false)) => StatusCode::NOT_FOUND.into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
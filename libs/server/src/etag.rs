@@ -0,0 +1,92 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{
+        HeaderValue, Method, StatusCode,
+        header::{ETAG, IF_NONE_MATCH},
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use codex_core::CoreResult;
+
+use crate::AppState;
+
+/// 图片目录的 ETag 中间件状态
+#[derive(Clone)]
+pub struct ImageEtagState {
+    pub app: AppState,
+    pub root: Arc<PathBuf>,
+    /// 哈希缓存键的命名空间，区分 gallery 与 previews
+    pub namespace: &'static str,
+    /// 将请求中的相对路径解析为文件路径，拒绝路径遍历
+    pub resolve: fn(&Path, &str) -> CoreResult<PathBuf>,
+}
+
+/// 为图片静态文件附加基于内容哈希的强 ETag，并在 `If-None-Match` 命中时返回 304
+///
+/// 无法计算哈希（路径非法、文件不存在等）时直接交给内层服务处理
+pub async fn image_etag(
+    State(etag): State<ImageEtagState>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return next.run(req).await;
+    }
+    let rel_path = req.uri().path().trim_start_matches('/').to_string();
+    // 含百分号编码的路径交给 ServeDir 解码处理，不参与 ETag
+    if rel_path.contains('%') {
+        return next.run(req).await;
+    }
+    let Ok(path) = (etag.resolve)(&etag.root, &rel_path) else {
+        return next.run(req).await;
+    };
+
+    let storage = Arc::clone(&etag.app.storage);
+    let key = format!("{}/{}", etag.namespace, rel_path);
+    let hash = match etag
+        .app
+        .run_db(move || storage.file_content_hash(&key, &path))
+        .await
+    {
+        Ok(Ok(Some(hash))) => hash,
+        Ok(Ok(None)) => return next.run(req).await,
+        Ok(Err(err)) => {
+            tracing::warn!(error = %err, path = %rel_path, "failed to hash image");
+            return next.run(req).await;
+        }
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    let value = HeaderValue::from_str(&format!("\"{hash}\"")).expect("hex hash is a valid header");
+
+    if if_none_match(&req, &value) {
+        return (StatusCode::NOT_MODIFIED, [(ETAG, value)]).into_response();
+    }
+
+    let mut response = next.run(req).await;
+    if response.status() == StatusCode::OK {
+        response.headers_mut().insert(ETAG, value);
+    }
+    response
+}
+
+/// `If-None-Match` 是否包含当前 ETag（或 `*`）
+fn if_none_match(req: &Request<Body>, etag: &HeaderValue) -> bool {
+    let Some(header) = req
+        .headers()
+        .get(IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return false;
+    };
+    let etag = etag.to_str().unwrap_or_default();
+    header.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+    })
+}
@@ -1,12 +1,15 @@
+use std::sync::Arc;
+
 use axum::{
     Json,
     extract::{Path, Query, State},
     http::StatusCode,
     response::IntoResponse,
 };
-use serde::Deserialize;
+use codex_core::tag_usage::prompt_tags;
+use serde::{Deserialize, Serialize};
 
-use crate::AppState;
+use crate::{AppState, core_error_response};
 
 pub async fn get_lexicon_index(State(state): State<AppState>) -> impl IntoResponse {
     match &state.lexicon {
@@ -15,16 +18,45 @@ pub async fn get_lexicon_index(State(state): State<AppState>) -> impl IntoRespon
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct LexiconCategoryQuery {
+    subcategory: Option<String>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+impl LexiconCategoryQuery {
+    fn is_paged(&self) -> bool {
+        self.subcategory.is_some() || self.offset.is_some() || self.limit.is_some()
+    }
+}
+
+/// 获取分类数据
+/// 带 subcategory/offset/limit 任一参数时返回分页结果，否则返回完整分类
 pub async fn get_lexicon_category(
     State(state): State<AppState>,
     Path(name): Path<String>,
+    Query(query): Query<LexiconCategoryQuery>,
 ) -> impl IntoResponse {
-    match &state.lexicon {
-        Some(lex) => match lex.get_category(&name) {
-            Some(cat) => Json(cat.clone()).into_response(),
+    let Some(lex) = &state.lexicon else {
+        return (StatusCode::NOT_FOUND, "lexicon not loaded").into_response();
+    };
+
+    if query.is_paged() {
+        return match lex.get_category_paged(
+            &name,
+            query.subcategory.as_deref(),
+            query.offset.unwrap_or(0),
+            query.limit.unwrap_or_else(default_search_limit),
+        ) {
+            Some(page) => Json(page).into_response(),
             None => (StatusCode::NOT_FOUND, "category not found").into_response(),
-        },
-        None => (StatusCode::NOT_FOUND, "lexicon not loaded").into_response(),
+        };
+    }
+
+    match lex.get_category(&name) {
+        Some(cat) => Json(cat.clone()).into_response(),
+        None => (StatusCode::NOT_FOUND, "category not found").into_response(),
     }
 }
 
@@ -35,6 +67,12 @@ pub struct LexiconSearchQuery {
     limit: usize,
     #[serde(default)]
     offset: usize,
+    /// 优先显示的译文语言
+    lang: Option<String>,
+    /// 只在该分类内搜索
+    category: Option<String>,
+    /// 只在该子分类内搜索
+    subcategory: Option<String>,
 }
 
 fn default_search_limit() -> usize {
@@ -47,9 +85,126 @@ pub async fn search_lexicon(
 ) -> impl IntoResponse {
     match &state.lexicon {
         Some(lex) => {
-            let result = lex.search(&query.q, query.limit, query.offset);
+            let result = lex.search(
+                &query.q,
+                query.limit,
+                query.offset,
+                query.lang.as_deref(),
+                query.category.as_deref(),
+                query.subcategory.as_deref(),
+            );
             Json(result).into_response()
         }
         None => (StatusCode::NOT_FOUND, "lexicon not loaded").into_response(),
     }
 }
+
+#[derive(Debug, Deserialize)]
+pub struct RecentTagsQuery {
+    #[serde(default = "default_recent_limit")]
+    limit: usize,
+}
+
+fn default_recent_limit() -> usize {
+    20
+}
+
+/// 最近常用的标签，按衰减后的使用分数排序
+pub async fn recent_lexicon_tags(
+    State(state): State<AppState>,
+    Query(query): Query<RecentTagsQuery>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state.run_db(move || storage.recent_tags(query.limit)).await {
+        Ok(Ok(tags)) => Json(tags).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CooccurQuery {
+    tag: String,
+    #[serde(default = "default_recent_limit")]
+    limit: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct CooccurringTag {
+    tag: String,
+    count: usize,
+}
+
+/// 历史记录中常与指定标签一同使用的标签
+pub async fn suggest_cooccurring_tags(
+    State(state): State<AppState>,
+    Query(query): Query<CooccurQuery>,
+) -> impl IntoResponse {
+    if query.tag.trim().is_empty() {
+        return (StatusCode::BAD_REQUEST, "tag must not be empty").into_response();
+    }
+    let storage = Arc::clone(&state.storage);
+    match state
+        .run_db(move || storage.cooccurring_tags(&query.tag, query.limit))
+        .await
+    {
+        Ok(Ok(tags)) => {
+            let tags: Vec<CooccurringTag> = tags
+                .into_iter()
+                .map(|(tag, count)| CooccurringTag { tag, count })
+                .collect();
+            Json(tags).into_response()
+        }
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TagStatsQuery {
+    #[serde(default = "default_stats_limit")]
+    limit: usize,
+}
+
+fn default_stats_limit() -> usize {
+    50
+}
+
+/// 全部生成记录中最常用的标签及记录、图片总数与时间范围
+pub async fn get_tag_stats(
+    State(state): State<AppState>,
+    Query(query): Query<TagStatsQuery>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state
+        .run_db(move || storage.tag_frequencies(query.limit))
+        .await
+    {
+        Ok(Ok(stats)) => Json(stats).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// 在后台记录提交的提示词中用到的标签；词库已加载时只统计词库中的标签
+pub fn spawn_record_prompt_tags(state: &AppState, prompt: &str) {
+    let mut tags = prompt_tags(prompt);
+    if let Some(lex) = &state.lexicon {
+        tags = tags
+            .iter()
+            .filter_map(|tag| lex.lookup(tag).map(|entry| entry.tag.clone()))
+            .collect();
+    }
+    if tags.is_empty() {
+        return;
+    }
+    let state = state.clone();
+    tokio::spawn(async move {
+        let storage = Arc::clone(&state.storage);
+        match state.run_db(move || storage.record_tag_usage(&tags)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => tracing::warn!(error = %err, "failed to record tag usage"),
+            Err(err) => tracing::warn!(error = %err, "failed to record tag usage"),
+        }
+    });
+}
@@ -1,46 +1,91 @@
-use std::{collections::HashMap, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
 
 use anyhow::{Result, anyhow};
 use axum::{
     Json, Router,
-    body::Body,
-    extract::{DefaultBodyLimit, Path, Request, State},
-    http::{HeaderValue, StatusCode, header::CACHE_CONTROL},
+    body::{Body, Bytes, HttpBody},
+    extract::{DefaultBodyLimit, Path, Query, Request, State},
+    http::{
+        HeaderMap, HeaderValue, StatusCode,
+        header::{AUTHORIZATION, CACHE_CONTROL},
+    },
     middleware::Next,
     response::{IntoResponse, Response},
-    routing::{get, post, put},
+    routing::{MethodRouter, get, post, put},
+};
+use codex_api::{
+    Center, CenterPreset, CharacterPrompt, LimitExceeded, Model, NaiClient, Noise, Sampler,
+    default_true,
 };
-use codex_api::NaiClient;
+pub use codex_api::{GenerationLimits, PoolConfig, WeightRange};
+pub use codex_core::{ArchiveCompression, ArchiveOptions, PngCompression};
 use codex_core::{
-    CharacterSlotSettings, CoreStorage, GalleryPaths, GenerateTaskRequest, GenerationParams,
-    GenerationRecord, HighlightSpan, LastGenerationSettings, Lexicon, MainPresetSettings,
-    PromptParser, PromptProcessor, TaskExecutor,
+    CHUNK_TOKENS, CharacterSlotSettings, CharacterSplit, ChunkedTag, CoreError, CoreResult,
+    CoreStorage, Diagnostic, ExecutorConfig, FormatOptions, GalleryPaths, GalleryTimezone,
+    GenerateTaskRequest, GenerationParams, GenerationRecord, GlobalAffix, HighlightSpan,
+    ImportConflict, LastGenerationSettings, Lexicon, MainPresetSettings, PartialGenerationParams,
+    PromptParser, PromptProcessor, PromptTemplate, Recipe, SnippetWeight, TagWeight, TaskExecutor,
+    TaskOutcome, ValidationError, sanitize_label,
 };
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-use tokio::sync::{Mutex, mpsc};
+use tokio::sync::{Mutex, Notify, Semaphore, SemaphorePermit};
 use tower::ServiceBuilder;
 use tower_http::services::ServeDir;
 use uuid::Uuid;
 
 mod archive;
+mod audit;
+mod blocklist;
+mod etag;
 mod lexicon;
+mod openapi;
 mod perset;
+mod ready;
+mod seed;
 mod snippet;
+mod webhook;
+
+#[cfg(test)]
+mod test_support;
 
 use crate::archive::{
-    ArchiveState, create_archive, create_archive_selected, delete_archive, download_archive,
-    get_archive_status, list_archivable_dates, list_archives,
+    ArchiveState, cancel_archive, create_archive, create_archive_selected, delete_archive,
+    download_archive, download_date_zip, extract_archive_file, get_archive_status,
+    list_archivable_dates, list_archive_contents, list_archives,
+};
+use crate::audit::AuditLog;
+use crate::blocklist::{add_blocked_tag, list_blocked_tags, remove_blocked_tag};
+use crate::etag::{ImageEtagState, image_etag};
+use crate::lexicon::{
+    get_lexicon_category, get_lexicon_index, get_tag_stats, recent_lexicon_tags, search_lexicon,
+    spawn_record_prompt_tags, suggest_cooccurring_tags,
 };
-use crate::lexicon::{get_lexicon_category, get_lexicon_index, search_lexicon};
+use crate::openapi::get_openapi;
 use crate::perset::{
-    create_main_preset, create_preset, delete_main_preset, delete_preset, delete_preset_preview,
-    get_main_preset, get_preset, list_main_presets, list_presets, rename_preset,
-    update_main_preset, update_preset, update_preset_preview,
+    clear_default_main_preset, create_main_preset, create_preset, delete_main_preset,
+    delete_preset, delete_preset_preview, diff_main_preset, get_default_main_preset,
+    get_main_preset, get_preset, list_main_presets, list_presets, merge_presets, rename_preset,
+    set_default_main_preset, update_main_preset, update_preset, update_preset_preview,
+    update_preset_preview_from_record,
 };
+use crate::ready::{ReadinessState, ready, spawn_self_check};
+use crate::seed::{add_favorite_seed, list_favorite_seeds, remove_favorite_seed};
 use crate::snippet::{
-    create_snippet, delete_snippet, delete_snippet_preview, get_snippet, list_snippets,
-    rename_snippet, update_snippet, update_snippet_preview,
+    create_snippet, delete_snippet, delete_snippet_preview, expand_snippet, get_snippet,
+    list_snippet_names, list_snippets, reexpand_record, rename_snippet, update_snippet,
+    update_snippet_preview, update_snippet_preview_from_record, validate_snippet_name_handler,
 };
 
 #[derive(Debug, Clone)]
@@ -51,8 +96,72 @@ pub struct ServerConfig {
     pub gallery_dir: PathBuf,
     pub static_dir: Option<PathBuf>,
     pub nai_token: String,
+    /// 保存生成图片时的最长边上限（None 表示保存原图）
+    pub store_max_dimension: Option<u32>,
+    /// 缩小保存的图片、预览图与缩略图时重新编码使用的 PNG 压缩级别
+    pub png_compression: PngCompression,
+    /// API 请求体大小上限（字节）
+    pub body_limit: usize,
+    /// 新记录保存后推送记录视图的 webhook 地址
+    pub webhook_url: Option<String>,
+    /// 单个任务中等待写入磁盘的图片上限
+    pub max_pending_writes: usize,
+    /// 开启数据库写事务遇到暂时性错误时的最大重试次数；之前的 I/O 失败会先重新打开数据库再重试
+    pub db_write_retries: u32,
+    /// 图库日期目录与归档判断所用的 IANA 时区（None 表示系统本地时区）
+    pub timezone: Option<String>,
+    /// 冒号权重允许范围：提示词检查时告警，生成前修正
+    pub weight_range: WeightRange,
+    /// 单次生成允许的最大宽高与步数，超出的任务返回 400
+    pub generation_limits: GenerationLimits,
+    /// 同时进行的阻塞数据库操作上限
+    pub db_concurrency: usize,
+    /// 多图任务中图片之间额外等待的毫秒数
+    pub inter_image_delay_ms: u64,
+    /// 访问 NovelAI 使用的代理地址（HTTP 或 SOCKS5）
+    pub nai_proxy: Option<String>,
+    /// 访问 NovelAI 的连接池设置
+    pub nai_pool: PoolConfig,
+    /// 图片写入后重新读取校验
+    pub verify_writes: bool,
+    /// snippet 内容大小上限（字节）
+    pub max_snippet_content_bytes: usize,
+    /// 任务结束时追加 JSON lines 审计记录的文件（None 表示不记录）
+    pub audit_log_path: Option<PathBuf>,
+    /// 审计日志只记录提示词哈希，不记录原文
+    pub audit_privacy: bool,
+    /// 归档的压缩方式、读取图片的缓冲区大小与保留的最近天数
+    pub archive_options: ArchiveOptions,
+    /// 上传预览图的最长边上限，超出时等比缩小（0 表示不缩放）
+    pub preview_max_dimension: u32,
+    /// 在主预设之前加到每个正面提示词开头的全局前缀
+    pub global_prefix: Option<String>,
+    /// 在主预设之前加到每个正面提示词末尾的全局后缀
+    pub global_suffix: Option<String>,
+    /// 管理操作（如清零用量计数）需要的 Bearer token（None 表示禁用这些操作）
+    pub admin_token: Option<String>,
+    /// 任务未指定 UC 预设时使用的默认值，按模型范围截断（None 表示沿用模型的 "None" 预设）
+    pub default_uc_preset: Option<u8>,
 }
 
+/// 默认请求体大小上限（10MB，适应较大的图片上传）
+pub const DEFAULT_BODY_LIMIT: usize = 10 * 1024 * 1024;
+
+/// 默认数据库写事务重试次数
+pub const DEFAULT_DB_WRITE_RETRIES: u32 = codex_core::DEFAULT_WRITE_RETRIES;
+
+/// 默认 snippet 内容大小上限（16KB）
+pub const DEFAULT_MAX_SNIPPET_CONTENT_BYTES: usize = codex_core::DEFAULT_MAX_SNIPPET_CONTENT_BYTES;
+
+/// 默认上传预览图最长边（512px）
+pub const DEFAULT_PREVIEW_MAX_DIMENSION: u32 = codex_core::imaging::PREVIEW_MAX_DIMENSION;
+
+/// 默认阻塞数据库操作并发上限
+pub const DEFAULT_DB_CONCURRENCY: usize = 16;
+
+/// 默认等待写入的图片上限
+pub const DEFAULT_MAX_PENDING_WRITES: usize = 2;
+
 #[derive(Clone)]
 pub struct AppState {
     pub storage: Arc<CoreStorage>,
@@ -61,45 +170,94 @@ pub struct AppState {
     pub lexicon: Option<Arc<Lexicon>>,
     pub nai_client: Arc<NaiClient>,
     pub archive_state: ArchiveState,
+    pub timezone: GalleryTimezone,
+    pub weight_range: WeightRange,
+    pub generation_limits: GenerationLimits,
+    pub archive_options: ArchiveOptions,
+    pub global_affix: GlobalAffix,
+    /// 限制同时进行的阻塞数据库操作数量
+    pub db_permits: Arc<Semaphore>,
+    pub readiness: ReadinessState,
+    pub admin_token: Option<Arc<str>>,
+    pub default_uc_preset: Option<u8>,
 }
 
-pub async fn serve(cfg: ServerConfig) -> Result<()> {
-    let storage = Arc::new(CoreStorage::open(&cfg.db_path, &cfg.preview_dir)?);
-    let gallery = GalleryPaths::new(&cfg.gallery_dir);
-    let client = Arc::new(NaiClient::new(cfg.nai_token)?);
-    let queue = TaskQueue::new(Arc::clone(&client), Arc::clone(&storage), gallery.clone());
+impl AppState {
+    /// 在阻塞线程池中执行数据库操作，并发数受 `db_permits` 限制
+    pub async fn run_db<T, F>(&self, f: F) -> Result<T, tokio::task::JoinError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let permit = Arc::clone(&self.db_permits)
+            .acquire_owned()
+            .await
+            .expect("db semaphore is never closed");
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            f()
+        })
+        .await
+    }
+}
 
-    // 从嵌入数据加载词库
-    let lexicon = match Lexicon::load_embedded() {
-        Ok(lex) => {
-            tracing::info!("lexicon loaded from embedded data");
-            Some(Arc::new(lex))
-        }
-        Err(err) => {
-            tracing::warn!("failed to load lexicon: {}", err);
-            None
-        }
-    };
+/// 记录已注册路径的路由构建器，测试中用来与 OpenAPI 路由表对照
+#[derive(Default)]
+struct ApiRoutes {
+    router: Router<AppState>,
+    paths: Vec<&'static str>,
+}
 
-    let state = AppState {
-        storage,
-        queue,
-        gallery_dir: cfg.gallery_dir.clone(),
-        lexicon,
-        nai_client: client,
-        archive_state: ArchiveState::new(),
-    };
+impl ApiRoutes {
+    fn route(mut self, path: &'static str, method_router: MethodRouter<AppState>) -> Self {
+        self.paths.push(path);
+        self.router = self.router.route(path, method_router);
+        self
+    }
+}
 
-    // API 路由都放在 /api 前缀下
-    let api_router = Router::new()
+/// `/api` 下的所有路由（不含中间件）
+fn api_routes() -> ApiRoutes {
+    ApiRoutes::default()
         .route("/health", get(health))
+        .route("/openapi.json", get(get_openapi))
+        .route("/ready", get(ready))
+        .route("/status", get(get_server_status))
+        .route("/maintenance/rebuild-index", post(rebuild_name_index))
+        .route("/maintenance/compact", post(compact_storage))
+        .route("/maintenance/verify", get(verify_records))
+        .route("/maintenance/repair", post(repair_records))
+        .route("/maintenance/dangling-refs", get(list_dangling_refs))
         .route("/quota", get(get_quota))
+        .route("/capabilities", get(get_capabilities))
+        .route("/thumb", get(get_thumbnail))
+        .route("/previews/{*path}", get(get_preview))
         .route("/tasks", post(create_task))
+        .route("/tasks/abort-pending", post(abort_pending_tasks))
+        .route("/tasks/preview", post(preview_task))
+        .route("/tasks/debug-payload", post(debug_task_payload))
         .route("/tasks/{id}", get(get_task))
+        .route("/tasks/{id}/retry-failed", post(retry_failed_task))
         .route("/records/recent", get(list_recent_records))
-        .route("/records/{id}", axum::routing::delete(delete_record))
+        .route(
+            "/records/by-date/{date}",
+            get(list_records_by_date).delete(delete_records_by_date),
+        )
+        .route("/records/by-label/{label}", get(list_records_by_label))
+        .route("/records/date-counts", get(get_record_date_counts))
+        .route("/records/{id}", get(get_record).delete(delete_record))
         .route("/records/batch", post(delete_records_batch))
+        .route("/records/{id}/regenerate", post(regenerate_record))
+        .route("/records/{id}/reexpand", post(reexpand_record))
+        .route("/records/{id}/export-nai", get(export_record_nai))
+        .route("/records/{id}/recipe", get(export_record_recipe))
+        .route("/recipe/import", post(import_recipe))
         .route("/snippets", get(list_snippets).post(create_snippet))
+        .route("/snippets/names", get(list_snippet_names))
+        .route(
+            "/snippets/validate-name",
+            get(validate_snippet_name_handler),
+        )
         .route(
             "/snippets/{id}",
             get(get_snippet).put(update_snippet).delete(delete_snippet),
@@ -108,7 +266,12 @@ pub async fn serve(cfg: ServerConfig) -> Result<()> {
             "/snippets/{id}/preview",
             put(update_snippet_preview).delete(delete_snippet_preview),
         )
+        .route(
+            "/snippets/{id}/preview-from-record",
+            put(update_snippet_preview_from_record),
+        )
         .route("/snippets/{id}/rename", put(rename_snippet))
+        .route("/snippets/{id}/expand", post(expand_snippet))
         .route("/presets", get(list_presets).post(create_preset))
         .route(
             "/presets/{id}",
@@ -118,7 +281,12 @@ pub async fn serve(cfg: ServerConfig) -> Result<()> {
             "/presets/{id}/preview",
             put(update_preset_preview).delete(delete_preset_preview),
         )
+        .route(
+            "/presets/{id}/preview-from-record",
+            put(update_preset_preview_from_record),
+        )
         .route("/presets/{id}/rename", put(rename_preset))
+        .route("/presets/merge", post(merge_presets))
         // 主预设 API
         .route(
             "/main-presets",
@@ -130,28 +298,185 @@ pub async fn serve(cfg: ServerConfig) -> Result<()> {
                 .put(update_main_preset)
                 .delete(delete_main_preset),
         )
+        .route(
+            "/main-presets/default",
+            get(get_default_main_preset).delete(clear_default_main_preset),
+        )
+        .route("/main-presets/{id}/default", put(set_default_main_preset))
+        .route("/main-presets/{id}/diff", post(diff_main_preset))
         .route(
             "/settings/generation",
             get(get_generation_settings).put(save_generation_settings),
         )
+        .route(
+            "/settings/generation/resolved",
+            get(get_resolved_generation_settings),
+        )
+        .route("/settings/profiles", get(list_generation_profiles))
+        .route(
+            "/settings/profiles/{name}",
+            get(get_generation_profile)
+                .put(save_generation_profile)
+                .delete(delete_generation_profile),
+        )
+        .route(
+            "/settings/profiles/{name}/load",
+            post(load_generation_profile),
+        )
         .route("/prompt/parse", post(parse_prompt))
         .route("/prompt/format", post(format_prompt))
+        .route("/prompt/compact", post(compact_prompt))
+        .route("/prompt/budget", post(prompt_budget))
+        .route("/prompt/import", post(import_prompt))
+        .route("/prompt/validate", post(validate_prompt))
+        .route("/prompt/explain", post(explain_prompt))
+        .route("/prompt/weights", post(prompt_weights))
+        .route("/prompt/snippet-weights", post(prompt_snippet_weights))
+        .route("/prompt/insert-tag", post(insert_prompt_tag))
+        .route("/prompt/reorder", post(reorder_prompt_tag))
+        .route("/prompt/split-characters", post(split_characters))
+        .route("/prompt/templates/builtin", get(list_builtin_templates))
         .route("/prompt/dry-run", post(dry_run_prompt))
+        .route("/prompt/dry-run-batch", post(dry_run_prompt_batch))
+        // 收藏种子 API
+        .route(
+            "/seeds/favorites",
+            get(list_favorite_seeds).post(add_favorite_seed),
+        )
+        .route(
+            "/seeds/favorites/{seed}",
+            axum::routing::delete(remove_favorite_seed),
+        )
+        // 标签屏蔽 API
+        .route("/blocklist", get(list_blocked_tags).post(add_blocked_tag))
+        .route(
+            "/blocklist/{tag}",
+            axum::routing::delete(remove_blocked_tag),
+        )
         // 词库 API
         .route("/lexicon", get(get_lexicon_index))
         .route("/lexicon/categories/{name}", get(get_lexicon_category))
         .route("/lexicon/search", get(search_lexicon))
+        .route("/lexicon/recent", get(recent_lexicon_tags))
+        .route("/suggest/cooccur", get(suggest_cooccurring_tags))
+        .route("/stats/tags", get(get_tag_stats))
+        .route("/stats/usage", get(get_usage_stats))
+        .route("/stats/usage/reset", post(reset_usage_stats))
         // 归档 API
         .route("/archives", get(list_archives).post(create_archive))
         .route("/archives/dates", get(list_archivable_dates))
         .route("/archives/selected", post(create_archive_selected))
         .route("/archives/status", get(get_archive_status))
+        .route("/archives/cancel", post(cancel_archive))
+        .route("/gallery/dates/{date}/download", get(download_date_zip))
         .route(
             "/archives/{name}",
             get(download_archive).delete(delete_archive),
         )
-        // 增加请求体大小限制（10MB，适应较大的图片上传）
-        .layer(DefaultBodyLimit::max(10 * 1024 * 1024));
+        .route("/archives/{name}/contents", get(list_archive_contents))
+        .route("/archives/{name}/file", get(extract_archive_file))
+}
+
+pub async fn serve(cfg: ServerConfig) -> Result<()> {
+    let storage = Arc::new(
+        CoreStorage::open(&cfg.db_path, &cfg.preview_dir)?
+            .with_write_retries(cfg.db_write_retries)
+            .with_max_snippet_content(cfg.max_snippet_content_bytes)
+            .with_preview_max_dimension(cfg.preview_max_dimension)
+            .with_png_compression(cfg.png_compression),
+    );
+    let timezone = match cfg.timezone.as_deref() {
+        Some(name) => GalleryTimezone::parse(name)?,
+        None => GalleryTimezone::Local,
+    };
+    if cfg.weight_range.min > cfg.weight_range.max {
+        return Err(anyhow!(
+            "invalid weight range: {} > {}",
+            cfg.weight_range.min,
+            cfg.weight_range.max
+        ));
+    }
+    let gallery = GalleryPaths::new(&cfg.gallery_dir).with_timezone(timezone);
+    let mut client = NaiClient::new(cfg.nai_token)?.with_pool_config(cfg.nai_pool)?;
+    if let Some(proxy) = cfg.nai_proxy.as_deref() {
+        client = client.with_proxy(proxy)?;
+        tracing::info!(proxy, "using proxy for NovelAI requests");
+    }
+    let client = Arc::new(client);
+    let global_affix = GlobalAffix {
+        prefix: cfg.global_prefix.clone(),
+        suffix: cfg.global_suffix.clone(),
+    };
+    let executor_config = ExecutorConfig {
+        store_max_dimension: cfg.store_max_dimension,
+        png_compression: cfg.png_compression,
+        on_record_appended: cfg
+            .webhook_url
+            .clone()
+            .map(|url| webhook::record_webhook(url, cfg.gallery_dir.clone())),
+        max_pending_writes: cfg.max_pending_writes,
+        weight_range: cfg.weight_range,
+        limits: cfg.generation_limits,
+        inter_image_delay: Duration::from_millis(cfg.inter_image_delay_ms),
+        global_affix: global_affix.clone(),
+        verify_writes: cfg.verify_writes,
+        default_uc_preset: cfg.default_uc_preset,
+    };
+    let audit = match cfg.audit_log_path.clone() {
+        Some(path) => {
+            tracing::info!(path=%path.display(), privacy=cfg.audit_privacy, "audit log enabled");
+            Some(Arc::new(AuditLog::open(path, cfg.audit_privacy)?))
+        }
+        None => None,
+    };
+    let queue = TaskQueue::new(
+        Arc::clone(&client),
+        Arc::clone(&storage),
+        gallery.clone(),
+        executor_config,
+        audit,
+    );
+
+    // 从嵌入数据加载词库
+    let lexicon = match Lexicon::load_embedded() {
+        Ok(lex) => {
+            tracing::info!("lexicon loaded from embedded data");
+            Some(Arc::new(lex))
+        }
+        Err(err) => {
+            tracing::warn!("failed to load lexicon: {}", err);
+            None
+        }
+    };
+
+    let state = AppState {
+        storage,
+        queue,
+        gallery_dir: cfg.gallery_dir.clone(),
+        lexicon,
+        nai_client: client,
+        archive_state: ArchiveState::new(),
+        timezone,
+        weight_range: cfg.weight_range,
+        generation_limits: cfg.generation_limits,
+        archive_options: cfg.archive_options,
+        global_affix,
+        db_permits: Arc::new(Semaphore::new(cfg.db_concurrency.max(1))),
+        readiness: ReadinessState::new(),
+        admin_token: cfg.admin_token.as_deref().map(Arc::from),
+        default_uc_preset: cfg.default_uc_preset,
+    };
+    spawn_self_check(state.clone());
+
+    // API 路由都放在 /api 前缀下
+    let api_router = api_routes()
+        .router
+        // 请求体大小限制，超出时返回结构化的 413
+        .layer(DefaultBodyLimit::max(cfg.body_limit))
+        .layer(axum::middleware::from_fn_with_state(
+            cfg.body_limit,
+            body_limit_response,
+        ));
 
     let mut router = Router::new()
         .nest("/api", api_router)
@@ -165,10 +490,33 @@ pub async fn serve(cfg: ServerConfig) -> Result<()> {
         );
     }
 
-    router = router.nest_service("/gallery", ServeDir::new(cfg.gallery_dir.clone()));
+    router = router.nest_service(
+        "/gallery",
+        ServiceBuilder::new()
+            .layer(axum::middleware::from_fn_with_state(
+                ImageEtagState {
+                    app: state.clone(),
+                    root: Arc::new(cfg.gallery_dir.clone()),
+                    namespace: codex_core::CONTENT_HASH_GALLERY,
+                    resolve: codex_core::imaging::resolve_gallery_path,
+                },
+                image_etag,
+            ))
+            .service(ServeDir::new(cfg.gallery_dir.clone())),
+    );
     router = router.nest_service(
         "/previews",
-        ServeDir::new(state.storage.preview_dir().clone()),
+        ServiceBuilder::new()
+            .layer(axum::middleware::from_fn_with_state(
+                ImageEtagState {
+                    app: state.clone(),
+                    root: Arc::new(state.storage.preview_dir().clone()),
+                    namespace: codex_core::CONTENT_HASH_PREVIEWS,
+                    resolve: codex_core::imaging::resolve_preview_path,
+                },
+                image_etag,
+            ))
+            .service(ServeDir::new(state.storage.preview_dir().clone())),
     );
 
     tracing::info!("server listening on {}", cfg.addr);
@@ -199,10 +547,248 @@ async fn index_cache_control(req: Request<Body>, next: Next) -> Response {
     response
 }
 
+#[derive(Debug, Serialize)]
+struct BodyLimitDetail {
+    limit_bytes: usize,
+}
+
+/// 记录读取量是否超过上限的请求体
+///
+/// `DefaultBodyLimit` 的拒绝响应没有可识别的标记，只能据此区分处理器自己返回的 413
+struct MeteredBody {
+    inner: Body,
+    limit: usize,
+    read: usize,
+    exceeded: Arc<AtomicBool>,
+}
+
+impl HttpBody for MeteredBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<http_body::Frame<Bytes>, axum::Error>>> {
+        let this = &mut *self;
+        let poll = Pin::new(&mut this.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &poll
+            && let Some(data) = frame.data_ref()
+        {
+            this.read += data.len();
+            if this.read > this.limit {
+                this.exceeded.store(true, Ordering::Relaxed);
+            }
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// 将请求体超限的拒绝响应替换为带有配置上限的结构化 413，处理器自己返回的 413 保持不变
+async fn body_limit_response(
+    State(limit): State<usize>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let exceeded = Arc::new(AtomicBool::new(false));
+    let (parts, body) = req.into_parts();
+    let body = Body::new(MeteredBody {
+        inner: body,
+        limit,
+        read: 0,
+        exceeded: Arc::clone(&exceeded),
+    });
+    let response = next.run(Request::from_parts(parts, body)).await;
+    if response.status() != StatusCode::PAYLOAD_TOO_LARGE || !exceeded.load(Ordering::Relaxed) {
+        return response;
+    }
+
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        Json(ApiErrorResponse {
+            error: format!(
+                "request body too large (limit {:.1} MB)",
+                limit as f64 / (1024.0 * 1024.0)
+            ),
+            detail: BodyLimitDetail { limit_bytes: limit },
+        }),
+    )
+        .into_response()
+}
+
+/// 重建 snippet 名称索引，返回写入数量与同名冲突
+async fn rebuild_name_index(State(state): State<AppState>) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state.run_db(move || storage.rebuild_name_index()).await {
+        Ok(Ok(report)) => Json(report).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct CompactStorageResponse {
+    reclaimed_bytes: u64,
+}
+
+/// 压缩数据库文件；生成或归档进行中时拒绝执行
+async fn compact_storage(State(state): State<AppState>) -> impl IntoResponse {
+    if state.queue.has_active_tasks().await {
+        return (
+            StatusCode::CONFLICT,
+            "cannot compact storage while generation tasks are running",
+        )
+            .into_response();
+    }
+    if state.archive_state.is_running().await {
+        return (
+            StatusCode::CONFLICT,
+            "cannot compact storage while an archive task is running",
+        )
+            .into_response();
+    }
+    let storage = Arc::clone(&state.storage);
+    match state.run_db(move || storage.compact()).await {
+        Ok(Ok(reclaimed_bytes)) => Json(CompactStorageResponse { reclaimed_bytes }).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct VerifyRecordsQuery {
+    /// 是否扫描图库中没有记录的图片
+    #[serde(default)]
+    orphans: bool,
+}
+
+/// 检查记录引用的图片是否存在，可选列出孤立的图库图片
+async fn verify_records(
+    State(state): State<AppState>,
+    Query(q): Query<VerifyRecordsQuery>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    let gallery = GalleryPaths::new(&state.gallery_dir).with_timezone(state.timezone);
+    match state
+        .run_db(move || storage.verify_records(q.orphans.then_some(&gallery)))
+        .await
+    {
+        Ok(Ok(report)) => Json(report).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RepairRecordsPayload {
+    /// 是否为孤立的图库图片新建记录
+    #[serde(default)]
+    reindex_orphans: bool,
+}
+
+/// 删除图片全部缺失的记录，可选为孤立图片重建记录；生成进行中时拒绝执行
+async fn repair_records(
+    State(state): State<AppState>,
+    Json(payload): Json<RepairRecordsPayload>,
+) -> impl IntoResponse {
+    // 正在写入的图片尚无记录，会被误认为孤立文件
+    if state.queue.has_active_tasks().await {
+        return (
+            StatusCode::CONFLICT,
+            "cannot repair records while generation tasks are running",
+        )
+            .into_response();
+    }
+    let storage = Arc::clone(&state.storage);
+    let gallery = GalleryPaths::new(&state.gallery_dir).with_timezone(state.timezone);
+    match state
+        .run_db(move || storage.repair_records(&gallery, payload.reindex_orphans))
+        .await
+    {
+        Ok(Ok(report)) => Json(report).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// 列出预设与生成设置中指向已删除 snippet 的引用
+async fn list_dangling_refs(State(state): State<AppState>) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state.run_db(move || storage.validate_references()).await {
+        Ok(Ok(refs)) => Json(refs).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// 存活检查：进程在运行即返回 ok，就绪状态见 `/api/ready`
 async fn health() -> &'static str {
     "ok"
 }
 
+/// 生成与归档互斥相关的忙碌状态
+#[derive(Debug, Serialize)]
+struct ServerStatusResponse {
+    /// 有任务正在运行或待处理，此时无法归档
+    generation_active: bool,
+    archive_running: bool,
+    queue_pending: usize,
+}
+
+/// 校验管理 token；未配置 token 时管理操作一律禁用
+fn require_admin(state: &AppState, headers: &HeaderMap) -> Result<(), (StatusCode, &'static str)> {
+    let Some(expected) = state.admin_token.as_deref() else {
+        return Err((StatusCode::FORBIDDEN, "admin token is not configured"));
+    };
+    let provided = headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    match provided {
+        Some(token) if token == expected => Ok(()),
+        _ => Err((StatusCode::UNAUTHORIZED, "invalid admin token")),
+    }
+}
+
+/// 本地统计的生成图片与任务总数
+async fn get_usage_stats(State(state): State<AppState>) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state.run_db(move || storage.usage_stats()).await {
+        Ok(Ok(stats)) => Json(stats).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// 清零用量计数（需要管理 token），返回清零前的统计
+async fn reset_usage_stats(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Err(rejection) = require_admin(&state, &headers) {
+        return rejection.into_response();
+    }
+    let storage = Arc::clone(&state.storage);
+    match state.run_db(move || storage.reset_usage_stats()).await {
+        Ok(Ok(previous)) => Json(previous).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn get_server_status(State(state): State<AppState>) -> impl IntoResponse {
+    Json(ServerStatusResponse {
+        generation_active: state.queue.has_active_tasks().await,
+        archive_running: state.archive_state.is_running().await,
+        queue_pending: state.queue.pending_count().await,
+    })
+}
+
 #[derive(Debug, Serialize)]
 struct QuotaResponse {
     anlas: u64,
@@ -215,6 +801,71 @@ async fn get_quota(State(state): State<AppState>) -> impl IntoResponse {
     }
 }
 
+#[derive(Debug, Serialize)]
+struct SamplerCapability {
+    sampler: Sampler,
+    /// 兼容的噪声调度，第一个为默认值
+    noises: &'static [Noise],
+}
+
+#[derive(Debug, Serialize)]
+struct CapabilitiesResponse {
+    models: Vec<ModelCapability>,
+    samplers: Vec<SamplerCapability>,
+    noises: [Noise; 4],
+    center_presets: Vec<CenterPresetCapability>,
+    weight_range: WeightRange,
+}
+
+#[derive(Debug, Serialize)]
+struct CenterPresetCapability {
+    preset: CenterPreset,
+    center: Center,
+}
+
+#[derive(Debug, Serialize)]
+struct ModelCapability {
+    model: Model,
+    recommended_steps: u32,
+    recommended_scale: f32,
+    steps_range: (u32, u32),
+    scale_range: (f32, f32),
+}
+
+/// 返回可用的模型推荐参数、采样器、噪声调度及其兼容关系，供前端禁用无效组合
+async fn get_capabilities(State(state): State<AppState>) -> impl IntoResponse {
+    let samplers = Sampler::ALL
+        .iter()
+        .map(|&sampler| SamplerCapability {
+            sampler,
+            noises: sampler.supported_noises(),
+        })
+        .collect();
+    let models = Model::ALL
+        .iter()
+        .map(|&model| ModelCapability {
+            model,
+            recommended_steps: model.recommended_steps(),
+            recommended_scale: model.recommended_scale(),
+            steps_range: model.steps_range(),
+            scale_range: model.scale_range(),
+        })
+        .collect();
+    Json(CapabilitiesResponse {
+        models,
+        samplers,
+        noises: Noise::ALL,
+        center_presets: CenterPreset::ALL
+            .iter()
+            .map(|&preset| CenterPresetCapability {
+                preset,
+                center: preset.to_center(),
+            })
+            .collect(),
+        weight_range: state.weight_range,
+    })
+}
+
 #[derive(Debug, Deserialize)]
 struct CreateTaskPayload {
     raw_prompt: String,
@@ -226,6 +877,12 @@ struct CreateTaskPayload {
     /// 主提示词预设设置
     #[serde(default)]
     main_preset: MainPresetSettings,
+    /// 被内容过滤拒绝时换下一个种子重试
+    #[serde(default)]
+    retry_on_filter: bool,
+    /// 输出子目录标签，按项目归类图片
+    #[serde(default)]
+    label: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -236,6 +893,8 @@ pub struct GenerationRecordView {
     raw_prompt: String,
     expanded_prompt: String,
     negative_prompt: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
     images: Vec<GalleryImageView>,
 }
 
@@ -245,6 +904,9 @@ struct GalleryImageView {
     seed: u64,
     width: u32,
     height: u32,
+    /// 因内容过滤换种子重试的次数
+    #[serde(skip_serializing_if = "codex_core::is_zero")]
+    filter_retries: u32,
 }
 
 fn default_count() -> u32 {
@@ -263,25 +925,116 @@ async fn create_task(
     let mut task = GenerateTaskRequest::new(payload.raw_prompt, payload.negative_prompt);
     task.count = payload.count.max(1);
     task.main_preset = payload.main_preset;
+    task.retry_on_filter = payload.retry_on_filter;
+    task.label = payload.label;
     if let Some(params) = payload.params {
         task.params = params;
     }
 
+    if let Err(err) = task.check_limits(state.generation_limits) {
+        return limit_error_response(err);
+    }
+
     let id = task.id;
+    let raw_prompt = task.raw_prompt.clone();
     if let Err(err) = state.queue.submit(task).await {
-        return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
+        return queue_error_response(err);
     }
+    spawn_record_prompt_tags(&state, &raw_prompt);
 
     (StatusCode::ACCEPTED, Json(TaskSubmittedResponse { id })).into_response()
 }
 
+#[derive(Debug, Deserialize)]
+struct PreviewTaskPayload {
+    #[serde(flatten)]
+    task: CreateTaskPayload,
+    /// 是否按 Opus 订阅的免费额度估算
+    #[serde(default)]
+    opus: bool,
+}
+
+/// 提交前预览：处理提示词、校验参数并估算 Anlas，不入队
+async fn preview_task(
+    State(state): State<AppState>,
+    Json(payload): Json<PreviewTaskPayload>,
+) -> impl IntoResponse {
+    let mut task = GenerateTaskRequest::new(payload.task.raw_prompt, payload.task.negative_prompt);
+    task.count = payload.task.count.max(1);
+    task.main_preset = payload.task.main_preset;
+    if let Some(params) = payload.task.params {
+        task.params = params;
+    }
+    task.params.apply_default_uc_preset(state.default_uc_preset);
+
+    let storage = Arc::clone(&state.storage);
+    let weight_range = state.weight_range;
+    let global_affix = state.global_affix.clone();
+    match state
+        .run_db(move || {
+            PromptProcessor::new(storage)
+                .with_global_affix(global_affix)
+                .preview_task(&task, weight_range, payload.opus)
+        })
+        .await
+    {
+        Ok(Ok(preview)) => Json(preview).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// 返回按完整处理流程构建、将发送给 NovelAI 的请求体（不含鉴权），不入队也不发送
+async fn debug_task_payload(
+    State(state): State<AppState>,
+    Json(payload): Json<CreateTaskPayload>,
+) -> impl IntoResponse {
+    let mut task = GenerateTaskRequest::new(payload.raw_prompt, payload.negative_prompt);
+    task.main_preset = payload.main_preset;
+    if let Some(params) = payload.params {
+        task.params = params;
+    }
+    task.params.apply_default_uc_preset(state.default_uc_preset);
+
+    if let Err(err) = task.check_limits(state.generation_limits) {
+        return limit_error_response(err);
+    }
+
+    let storage = Arc::clone(&state.storage);
+    let weight_range = state.weight_range;
+    let global_affix = state.global_affix.clone();
+    match state
+        .run_db(move || {
+            PromptProcessor::new(storage)
+                .with_global_affix(global_affix)
+                .debug_payload(&task, weight_range)
+        })
+        .await
+    {
+        Ok(Ok(json)) => Json(json).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
 #[derive(Debug, Serialize)]
 #[serde(tag = "status", rename_all = "snake_case")]
 pub enum TaskStatusView {
     Pending,
     Running,
-    Completed { record: GenerationRecordView },
-    Failed { error: String },
+    Completed {
+        record: GenerationRecordView,
+    },
+    PartiallyCompleted {
+        record: GenerationRecordView,
+        succeeded: usize,
+        failed: u32,
+        error: String,
+    },
+    Failed {
+        error: String,
+    },
+    Cancelled,
     Unknown,
 }
 
@@ -294,16 +1047,50 @@ async fn get_task(State(state): State<AppState>, Path(id): Path<Uuid>) -> impl I
         Some(TaskStatus::Completed(rec)) => TaskStatusView::Completed {
             record: to_record_view(rec, &gallery),
         },
+        Some(TaskStatus::PartiallyCompleted {
+            record,
+            failed,
+            error,
+            ..
+        }) => TaskStatusView::PartiallyCompleted {
+            succeeded: record.images.len(),
+            record: to_record_view(record, &gallery),
+            failed,
+            error,
+        },
         Some(TaskStatus::Failed(err)) => TaskStatusView::Failed { error: err },
+        Some(TaskStatus::Cancelled) => TaskStatusView::Cancelled,
         None => TaskStatusView::Unknown,
     };
     Json(view)
 }
 
+#[derive(Debug, Serialize)]
+struct AbortPendingResponse {
+    aborted: usize,
+}
+
+/// 取消所有排队中的任务
+async fn abort_pending_tasks(State(state): State<AppState>) -> impl IntoResponse {
+    let aborted = state.queue.abort_pending().await;
+    Json(AbortPendingResponse { aborted })
+}
+
+/// 重新生成部分完成任务中失败的图片
+async fn retry_failed_task(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.queue.retry_failed(&id).await {
+        Ok(()) => (StatusCode::ACCEPTED, Json(TaskSubmittedResponse { id })).into_response(),
+        Err(err) => queue_error_response(err),
+    }
+}
+
 async fn list_recent_records(State(state): State<AppState>) -> impl IntoResponse {
     let storage = Arc::clone(&state.storage);
     let gallery = state.gallery_dir.clone();
-    match tokio::task::spawn_blocking(move || storage.list_recent_records(50)).await {
+    match state.run_db(move || storage.list_recent_records(50)).await {
         Ok(Ok(records)) => {
             let mapped: Vec<_> = records
                 .into_iter()
@@ -311,18 +1098,253 @@ async fn list_recent_records(State(state): State<AppState>) -> impl IntoResponse
                 .collect();
             Json(mapped).into_response()
         }
-        Ok(Err(err)) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
 
-/// 删除单条记录
+/// 列出带有指定标签的记录
+async fn list_records_by_label(
+    State(state): State<AppState>,
+    Path(label): Path<String>,
+) -> impl IntoResponse {
+    // 记录中保存的是清理后的标签，查询前按同样规则清理
+    let Some(label) = sanitize_label(&label) else {
+        return Json(Vec::<GenerationRecordView>::new()).into_response();
+    };
+    let storage = Arc::clone(&state.storage);
+    let gallery = state.gallery_dir.clone();
+    match state.run_db(move || storage.records_by_label(&label)).await {
+        Ok(Ok(records)) => {
+            let mapped: Vec<_> = records
+                .into_iter()
+                .map(|r| to_record_view(r, &gallery))
+                .collect();
+            Json(mapped).into_response()
+        }
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// 列出某一天的记录（日期按配置的时区计算，与图库目录一致）
+async fn list_records_by_date(
+    State(state): State<AppState>,
+    Path(date): Path<String>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    let gallery = state.gallery_dir.clone();
+    let timezone = state.timezone;
+    match state
+        .run_db(move || storage.records_by_date(&date, timezone))
+        .await
+    {
+        Ok(Ok(records)) => {
+            let mapped: Vec<_> = records
+                .into_iter()
+                .map(|r| to_record_view(r, &gallery))
+                .collect();
+            Json(mapped).into_response()
+        }
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// 删除某一天的所有记录、图片文件及日期目录；有任务运行时不允许删除今天
+async fn delete_records_by_date(
+    State(state): State<AppState>,
+    Path(date): Path<String>,
+) -> impl IntoResponse {
+    if !codex_core::archive::is_valid_date(&date) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("invalid date (expected YYYY-MM-DD): {date}"),
+        )
+            .into_response();
+    }
+    if date == state.timezone.today() && state.queue.has_active_tasks().await {
+        return (
+            StatusCode::CONFLICT,
+            "cannot delete today's records while generation tasks are running",
+        )
+            .into_response();
+    }
+
+    let storage = Arc::clone(&state.storage);
+    let gallery = GalleryPaths::new(&state.gallery_dir).with_timezone(state.timezone);
+    match state
+        .run_db(move || storage.delete_records_by_date(&date, &gallery))
+        .await
+    {
+        Ok(Ok(deleted)) => Json(deleted).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// 每天的记录数量，用于日历热力图
+async fn get_record_date_counts(State(state): State<AppState>) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    let timezone = state.timezone;
+    match state
+        .run_db(move || storage.record_date_counts(timezone))
+        .await
+    {
+        Ok(Ok(counts)) => Json(counts).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// 获取单条记录
+async fn get_record(State(state): State<AppState>, Path(id): Path<Uuid>) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    let gallery = state.gallery_dir.clone();
+    match state.run_db(move || storage.get_record(id)).await {
+        Ok(Ok(Some(record))) => Json(to_record_view(record, &gallery)).into_response(),
+        Ok(Ok(None)) => (StatusCode::NOT_FOUND, "record not found").into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// 清理已删除图片的缩略图与内容哈希缓存
+fn forget_deleted_images(
+    storage: &CoreStorage,
+    gallery: &std::path::Path,
+    images: &[&codex_core::GalleryImage],
+) -> CoreResult<()> {
+    for image in images {
+        codex_core::imaging::remove_cached_thumbnails(gallery, &image.path);
+    }
+    let keys: Vec<String> = images
+        .iter()
+        .filter_map(|image| codex_core::gallery_hash_key(gallery, &image.path))
+        .collect();
+    storage.remove_content_hashes(&keys)?;
+    Ok(())
+}
+
+/// 删除单条记录
 async fn delete_record(State(state): State<AppState>, Path(id): Path<Uuid>) -> impl IntoResponse {
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || storage.delete_record(id)).await {
+    let gallery = state.gallery_dir.clone();
+    let result = state
+        .run_db(move || {
+            let record = storage.delete_record(id)?;
+            let images: Vec<_> = record.iter().flat_map(|r| &r.images).collect();
+            forget_deleted_images(&storage, &gallery, &images)?;
+            Ok::<_, CoreError>(record)
+        })
+        .await;
+    match result {
         Ok(Ok(Some(_))) => StatusCode::NO_CONTENT.into_response(),
         Ok(Ok(None)) => StatusCode::NOT_FOUND.into_response(),
-        Ok(Err(err)) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct RegenerateRecordPayload {
+    count: Option<u32>,
+    params: PartialGenerationParams,
+}
+
+/// 以记录保存的参数重新生成，`params` 中提供的字段覆盖原值
+async fn regenerate_record(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    payload: Option<Json<RegenerateRecordPayload>>,
+) -> impl IntoResponse {
+    let payload = payload.map(|Json(p)| p).unwrap_or_default();
+    let storage = Arc::clone(&state.storage);
+    let record = match state.run_db(move || storage.get_record(id)).await {
+        Ok(Ok(Some(record))) => record,
+        Ok(Ok(None)) => return (StatusCode::NOT_FOUND, "record not found").into_response(),
+        Ok(Err(err)) => return core_error_response(err),
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+
+    // 记录中的提示词已展开，不再叠加全局前缀 / 后缀与主预设
+    let mut task = GenerateTaskRequest::from_record(&record);
+    task.count = payload.count.unwrap_or(1).max(1);
+    task.params = task.params.merge(payload.params);
+    if let Err(err) = task.check_limits(state.generation_limits) {
+        return limit_error_response(err);
+    }
+
+    let task_id = task.id;
+    if let Err(err) = state.queue.submit(task).await {
+        return queue_error_response(err);
+    }
+
+    (
+        StatusCode::ACCEPTED,
+        Json(TaskSubmittedResponse { id: task_id }),
+    )
+        .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportNaiQuery {
+    #[serde(default)]
+    image: usize,
+}
+
+/// 导出记录中指定图片的 NovelAI 请求 JSON
+async fn export_record_nai(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(q): Query<ExportNaiQuery>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state.run_db(move || storage.get_record(id)).await {
+        Ok(Ok(Some(record))) => match record.to_novelai_json(q.image, state.weight_range) {
+            Ok(json) => Json(json).into_response(),
+            Err(err) => core_error_response(err),
+        },
+        Ok(Ok(None)) => (StatusCode::NOT_FOUND, "record not found").into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// 导出记录的配方：提示词、参数、主预设及递归引用的 snippet
+async fn export_record_recipe(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state.run_db(move || storage.export_recipe(id)).await {
+        Ok(Ok(recipe)) => Json(recipe).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportRecipePayload {
+    recipe: Recipe,
+    /// 同名 snippet 内容不同时的处理方式
+    #[serde(default)]
+    on_conflict: ImportConflict,
+}
+
+/// 导入配方，返回可直接提交的任务内容
+async fn import_recipe(
+    State(state): State<AppState>,
+    Json(payload): Json<ImportRecipePayload>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state
+        .run_db(move || storage.import_recipe(payload.recipe, payload.on_conflict))
+        .await
+    {
+        Ok(Ok(result)) => Json(result).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
@@ -343,13 +1365,77 @@ async fn delete_records_batch(
     Json(payload): Json<DeleteRecordsBatchPayload>,
 ) -> impl IntoResponse {
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || storage.delete_records(&payload.ids)).await {
+    let gallery = state.gallery_dir.clone();
+    let result = state
+        .run_db(move || {
+            let records = storage.delete_records(&payload.ids)?;
+            let images: Vec<_> = records.iter().flat_map(|r| &r.images).collect();
+            forget_deleted_images(&storage, &gallery, &images)?;
+            Ok::<_, CoreError>(records.len())
+        })
+        .await;
+    match result {
         Ok(Ok(deleted)) => Json(DeleteRecordsBatchResponse { deleted }).into_response(),
-        Ok(Err(err)) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
 
+/// 结构化错误响应，`detail` 携带机器可读的错误原因
+#[derive(Debug, Serialize)]
+struct ApiErrorResponse<T: Serialize> {
+    error: String,
+    detail: T,
+}
+
+/// 将 core 错误映射为响应：不存在返回 404，名称冲突与合并冲突返回 409，
+/// 校验错误返回 400（带结构化原因时放入 `detail`），其余返回 500
+pub(crate) fn core_error_response(err: CoreError) -> Response {
+    let status = match &err {
+        CoreError::NotFound { .. } => StatusCode::NOT_FOUND,
+        CoreError::NameTaken { .. } => StatusCode::CONFLICT,
+        CoreError::Validation(ValidationError::PresetMerge(_)) => StatusCode::CONFLICT,
+        CoreError::InvalidName(_)
+        | CoreError::SnippetNotFound(_)
+        | CoreError::SnippetCycle { .. }
+        | CoreError::Validation(_) => StatusCode::BAD_REQUEST,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    let detail = match &err {
+        CoreError::InvalidName(e) => serde_json::to_value(e).ok(),
+        CoreError::SnippetNotFound(e) => serde_json::to_value(e).ok(),
+        CoreError::SnippetCycle { chain } => {
+            Some(serde_json::json!({ "reason": "cycle", "chain": chain }))
+        }
+        CoreError::Validation(ValidationError::Message(_)) => None,
+        CoreError::Validation(e) => serde_json::to_value(e).ok(),
+        _ => None,
+    };
+    match detail {
+        Some(detail) => (
+            status,
+            Json(ApiErrorResponse {
+                error: err.to_string(),
+                detail,
+            }),
+        )
+            .into_response(),
+        None => (status, err.to_string()).into_response(),
+    }
+}
+
+/// 生成参数超出上限时返回 400，`detail` 中包含字段与上限
+fn limit_error_response(err: LimitExceeded) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(ApiErrorResponse {
+            error: err.to_string(),
+            detail: err,
+        }),
+    )
+        .into_response()
+}
+
 /// Snippet / Preset shared payloads
 
 #[derive(Debug, Deserialize)]
@@ -357,6 +1443,14 @@ struct UpdatePreviewPayload {
     preview_base64: String,
 }
 
+/// 以生成记录中的图片作为预览图
+#[derive(Debug, Deserialize)]
+struct PreviewFromRecordPayload {
+    record_id: Uuid,
+    #[serde(default)]
+    image_index: usize,
+}
+
 #[derive(Debug, Deserialize)]
 struct RenamePayload {
     name: String,
@@ -366,10 +1460,25 @@ struct RenamePayload {
 
 async fn get_generation_settings(State(state): State<AppState>) -> impl IntoResponse {
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || storage.load_last_generation_settings()).await {
+    match state
+        .run_db(move || storage.load_last_generation_settings())
+        .await
+    {
         Ok(Ok(Some(settings))) => Json(settings).into_response(),
         Ok(Ok(None)) => Json(LastGenerationSettings::default()).into_response(),
-        Ok(Err(err)) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn get_resolved_generation_settings(State(state): State<AppState>) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state
+        .run_db(move || storage.load_resolved_generation_settings())
+        .await
+    {
+        Ok(Ok(settings)) => Json(settings).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
@@ -379,11 +1488,87 @@ async fn save_generation_settings(
     Json(settings): Json<LastGenerationSettings>,
 ) -> impl IntoResponse {
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || storage.save_last_generation_settings(&settings))
+    match state
+        .run_db(move || storage.save_last_generation_settings(&settings))
+        .await
+    {
+        Ok(Ok(())) => StatusCode::OK.into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn list_generation_profiles(State(state): State<AppState>) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state
+        .run_db(move || storage.list_generation_profiles())
+        .await
+    {
+        Ok(Ok(profiles)) => Json(profiles).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn get_generation_profile(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state
+        .run_db(move || storage.get_generation_profile(&name))
+        .await
+    {
+        Ok(Ok(Some(settings))) => Json(settings).into_response(),
+        Ok(Ok(None)) => StatusCode::NOT_FOUND.into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn save_generation_profile(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(settings): Json<LastGenerationSettings>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state
+        .run_db(move || storage.save_generation_profile(&name, &settings))
         .await
     {
         Ok(Ok(())) => StatusCode::OK.into_response(),
-        Ok(Err(err)) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn delete_generation_profile(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state
+        .run_db(move || storage.delete_generation_profile(&name))
+        .await
+    {
+        Ok(Ok(())) => StatusCode::NO_CONTENT.into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// 把命名配置载入为当前生成设置，返回载入的设置
+async fn load_generation_profile(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state
+        .run_db(move || storage.load_generation_profile(&name))
+        .await
+    {
+        Ok(Ok(settings)) => Json(settings).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
@@ -401,26 +1586,118 @@ pub enum TaskStatus {
     Pending,
     Running,
     Completed(GenerationRecord),
+    /// 部分图片生成失败，保留原始任务以便重试失败的图片
+    PartiallyCompleted {
+        task: Box<GenerateTaskRequest>,
+        record: GenerationRecord,
+        failed: u32,
+        error: String,
+    },
     Failed(String),
+    /// 排队中被取消，未执行
+    Cancelled,
+}
+
+/// 队列中的作业
+#[derive(Debug, Clone)]
+enum QueueJob {
+    /// 新提交的生成任务
+    Generate(GenerateTaskRequest),
+    /// 重新生成部分完成任务中失败的图片；`error` 为上次的失败原因，取消时用于恢复状态
+    RetryFailed {
+        task: GenerateTaskRequest,
+        record: Box<GenerationRecord>,
+        failed: u32,
+        error: String,
+    },
+}
+
+impl QueueJob {
+    fn task(&self) -> &GenerateTaskRequest {
+        match self {
+            QueueJob::Generate(task) => task,
+            QueueJob::RetryFailed { task, .. } => task,
+        }
+    }
+}
+
+/// 排队作业数上限，队列满时拒绝提交
+const QUEUE_CAPACITY: usize = 32;
+
+/// 作业入队失败
+#[derive(Debug)]
+pub enum QueueError {
+    /// 排队作业数已达上限，稍后再提交
+    Full,
+    Core(CoreError),
+}
+
+impl std::fmt::Display for QueueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Full => write!(f, "task queue is full ({QUEUE_CAPACITY} pending jobs)"),
+            Self::Core(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for QueueError {}
+
+impl From<CoreError> for QueueError {
+    fn from(err: CoreError) -> Self {
+        Self::Core(err)
+    }
+}
+
+/// 队列已满时返回 503 并提示客户端稍后重试
+fn queue_error_response(err: QueueError) -> Response {
+    match err {
+        QueueError::Full => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(axum::http::header::RETRY_AFTER, "1")],
+            err.to_string(),
+        )
+            .into_response(),
+        QueueError::Core(err) => core_error_response(err),
+    }
 }
 
 #[derive(Clone)]
 pub struct TaskQueue {
-    tx: mpsc::Sender<GenerateTaskRequest>,
+    /// 等待执行的作业；使用共享队列而非 channel，以便取消尚未执行的作业
+    pending: Arc<Mutex<VecDeque<QueueJob>>>,
+    /// 队列空位，入队前获取，作业出队或被取消时归还
+    slots: Arc<Semaphore>,
+    notify: Arc<Notify>,
     statuses: Arc<Mutex<HashMap<Uuid, TaskStatus>>>,
 }
 
 impl TaskQueue {
-    pub fn new(client: Arc<NaiClient>, storage: Arc<CoreStorage>, gallery: GalleryPaths) -> Self {
-        let (tx, mut rx) = mpsc::channel::<GenerateTaskRequest>(32);
+    pub fn new(
+        client: Arc<NaiClient>,
+        storage: Arc<CoreStorage>,
+        gallery: GalleryPaths,
+        config: ExecutorConfig,
+        audit: Option<Arc<AuditLog>>,
+    ) -> Self {
+        let pending = Arc::new(Mutex::new(VecDeque::<QueueJob>::new()));
+        let slots = Arc::new(Semaphore::new(QUEUE_CAPACITY));
+        let notify = Arc::new(Notify::new());
         let statuses = Arc::new(Mutex::new(HashMap::new()));
+        let pending_clone = Arc::clone(&pending);
+        let slots_clone = Arc::clone(&slots);
+        let notify_clone = Arc::clone(&notify);
         let status_clone = Arc::clone(&statuses);
         let client_clone = Arc::clone(&client);
         let storage_clone = Arc::clone(&storage);
         let gallery_clone = gallery.clone();
         tokio::spawn(async move {
             let mut is_first_task = true;
-            while let Some(task) = rx.recv().await {
+            loop {
+                while pending_clone.lock().await.is_empty() {
+                    notify_clone.notified().await;
+                }
+
                 // 任务之间添加随机延迟（首个任务除外）
                 if !is_first_task {
                     let delay = random_delay();
@@ -429,6 +1706,18 @@ impl TaskQueue {
                 }
                 is_first_task = false;
 
+                // 延迟期间作业可能已被取消
+                let Some(job) = pending_clone.lock().await.pop_front() else {
+                    continue;
+                };
+                slots_clone.add_permits(1);
+
+                let task = job.task().clone();
+                // 审计只记录本次作业请求与生成的图片；重试时原记录中已有的图片不计入
+                let (requested, prior_images) = match &job {
+                    QueueJob::Generate(task) => (task.count, 0),
+                    QueueJob::RetryFailed { record, failed, .. } => (*failed, record.images.len()),
+                };
                 {
                     let mut map = status_clone.lock().await;
                     map.insert(task.id, TaskStatus::Running);
@@ -438,13 +1727,69 @@ impl TaskQueue {
                     Arc::clone(&client_clone),
                     Arc::clone(&storage_clone),
                     gallery_clone.clone(),
+                    config.clone(),
                 );
-                let res = executor.execute(task.clone()).await;
+                let res = match job {
+                    QueueJob::Generate(task) => executor.execute(task).await,
+                    QueueJob::RetryFailed {
+                        task,
+                        record,
+                        failed,
+                        ..
+                    } => executor.retry_failed(task, *record, failed).await,
+                };
+                if let Some(audit) = &audit {
+                    match &res {
+                        Ok(TaskOutcome::Completed(record)) => {
+                            audit
+                                .record(
+                                    &task,
+                                    requested,
+                                    "completed",
+                                    record.images.get(prior_images..).unwrap_or_default(),
+                                    None,
+                                )
+                                .await;
+                        }
+                        Ok(TaskOutcome::PartiallyCompleted { record, error, .. }) => {
+                            audit
+                                .record(
+                                    &task,
+                                    requested,
+                                    "partially_completed",
+                                    record.images.get(prior_images..).unwrap_or_default(),
+                                    Some(error.clone()),
+                                )
+                                .await;
+                        }
+                        Err(err) => {
+                            audit
+                                .record(&task, requested, "failed", &[], Some(err.to_string()))
+                                .await;
+                        }
+                    }
+                }
+
                 let mut map = status_clone.lock().await;
                 match res {
-                    Ok(record) => {
+                    Ok(TaskOutcome::Completed(record)) => {
                         map.insert(record.task_id, TaskStatus::Completed(record));
                     }
+                    Ok(TaskOutcome::PartiallyCompleted {
+                        record,
+                        failed,
+                        error,
+                    }) => {
+                        map.insert(
+                            task.id,
+                            TaskStatus::PartiallyCompleted {
+                                task: Box::new(task),
+                                record,
+                                failed,
+                                error,
+                            },
+                        );
+                    }
                     Err(err) => {
                         map.insert(task.id, TaskStatus::Failed(err.to_string()));
                     }
@@ -452,15 +1797,98 @@ impl TaskQueue {
             }
         });
 
-        Self { tx, statuses }
+        Self {
+            pending,
+            slots,
+            notify,
+            statuses,
+        }
+    }
+
+    /// 占用一个队列空位；队列已满时立即返回 [`QueueError::Full`]
+    fn reserve_slot(&self) -> Result<SemaphorePermit<'_>, QueueError> {
+        self.slots.try_acquire().map_err(|_| QueueError::Full)
     }
 
-    pub async fn submit(&self, task: GenerateTaskRequest) -> Result<()> {
+    /// 用已占用的空位加入队尾，空位在作业出队或被取消时归还
+    async fn enqueue(&self, slot: SemaphorePermit<'_>, job: QueueJob) {
+        slot.forget();
+        self.pending.lock().await.push_back(job);
+        self.notify.notify_one();
+    }
+
+    /// 提交任务；队列已满时不等待，直接返回 [`QueueError::Full`]
+    pub async fn submit(&self, task: GenerateTaskRequest) -> Result<(), QueueError> {
+        let slot = self.reserve_slot()?;
         {
             let mut map = self.statuses.lock().await;
             map.insert(task.id, TaskStatus::Pending);
         }
-        self.tx.send(task).await.map_err(|e| anyhow!(e))
+        self.enqueue(slot, QueueJob::Generate(task)).await;
+        Ok(())
+    }
+
+    /// 取消所有尚未开始执行的作业（不影响正在执行的任务），返回取消数量
+    ///
+    /// 排队中的重试作业恢复为原来的部分完成状态，之后仍可再次重试
+    pub async fn abort_pending(&self) -> usize {
+        let aborted: Vec<QueueJob> = self.pending.lock().await.drain(..).collect();
+        self.slots.add_permits(aborted.len());
+        let count = aborted.len();
+        let mut map = self.statuses.lock().await;
+        for job in aborted {
+            let (id, status) = match job {
+                QueueJob::Generate(task) => (task.id, TaskStatus::Cancelled),
+                QueueJob::RetryFailed {
+                    task,
+                    record,
+                    failed,
+                    error,
+                } => (
+                    task.id,
+                    TaskStatus::PartiallyCompleted {
+                        task: Box::new(task),
+                        record: *record,
+                        failed,
+                        error,
+                    },
+                ),
+            };
+            map.insert(id, status);
+        }
+        if count > 0 {
+            tracing::info!(count, "aborted pending tasks");
+        }
+        count
+    }
+
+    /// 将部分完成任务中失败的图片重新加入队列，生成结果追加到原记录
+    pub async fn retry_failed(&self, id: &Uuid) -> Result<(), QueueError> {
+        let slot = self.reserve_slot()?;
+        let job = {
+            let mut map = self.statuses.lock().await;
+            let job = match map.get(id) {
+                Some(TaskStatus::PartiallyCompleted {
+                    task,
+                    record,
+                    failed,
+                    error,
+                }) => QueueJob::RetryFailed {
+                    task: task.as_ref().clone(),
+                    record: Box::new(record.clone()),
+                    failed: *failed,
+                    error: error.clone(),
+                },
+                Some(_) => {
+                    return Err(CoreError::invalid("task has no failed images to retry").into());
+                }
+                None => return Err(CoreError::not_found(format!("task {id}")).into()),
+            };
+            map.insert(*id, TaskStatus::Pending);
+            job
+        };
+        self.enqueue(slot, job).await;
+        Ok(())
     }
 
     pub async fn status(&self, id: &Uuid) -> Option<TaskStatus> {
@@ -474,6 +1902,11 @@ impl TaskQueue {
         map.values()
             .any(|s| matches!(s, TaskStatus::Pending | TaskStatus::Running))
     }
+
+    /// 排队等待执行的作业数
+    pub async fn pending_count(&self) -> usize {
+        self.pending.lock().await.len()
+    }
 }
 
 fn to_record_view(rec: GenerationRecord, gallery_root: &std::path::Path) -> GenerationRecordView {
@@ -484,6 +1917,7 @@ fn to_record_view(rec: GenerationRecord, gallery_root: &std::path::Path) -> Gene
         raw_prompt: rec.raw_prompt,
         expanded_prompt: rec.expanded_prompt,
         negative_prompt: rec.negative_prompt,
+        label: rec.label,
         images: rec
             .images
             .into_iter()
@@ -492,6 +1926,7 @@ fn to_record_view(rec: GenerationRecord, gallery_root: &std::path::Path) -> Gene
                 seed: img.seed,
                 width: img.width,
                 height: img.height,
+                filter_retries: img.filter_retries,
             })
             .collect(),
     }
@@ -506,6 +1941,80 @@ fn to_gallery_url(path: &std::path::Path, gallery_root: &std::path::Path) -> Str
     path.to_string_lossy().replace('\\', "/")
 }
 
+#[derive(Debug, Deserialize)]
+struct ThumbnailQuery {
+    /// gallery 内的相对路径，也接受 `/gallery/...` 形式的 URL
+    path: String,
+    #[serde(default = "default_thumbnail_size")]
+    size: u32,
+}
+
+fn default_thumbnail_size() -> u32 {
+    256
+}
+
+/// 缩略图尺寸范围
+const THUMBNAIL_SIZE_RANGE: std::ops::RangeInclusive<u32> = 16..=1024;
+
+/// 读取 snippet / preset 预览图，按文件内容而非扩展名设置 Content-Type
+async fn get_preview(State(state): State<AppState>, Path(path): Path<String>) -> impl IntoResponse {
+    let preview_dir = state.storage.preview_dir().clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let file = codex_core::imaging::resolve_preview_path(&preview_dir, &path)?;
+        if !file.is_file() {
+            return Err(CoreError::not_found("preview"));
+        }
+        Ok(std::fs::read(file)?)
+    })
+    .await;
+    match result {
+        Ok(Ok(bytes)) => (
+            [(
+                axum::http::header::CONTENT_TYPE,
+                codex_core::imaging::sniff_content_type(&bytes),
+            )],
+            bytes,
+        )
+            .into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// 按需生成并缓存 gallery 图片的缩略图
+async fn get_thumbnail(
+    State(state): State<AppState>,
+    Query(q): Query<ThumbnailQuery>,
+) -> impl IntoResponse {
+    let size = q
+        .size
+        .clamp(*THUMBNAIL_SIZE_RANGE.start(), *THUMBNAIL_SIZE_RANGE.end());
+    let rel_path = q
+        .path
+        .strip_prefix("/gallery/")
+        .unwrap_or(&q.path)
+        .to_string();
+    let gallery = state.gallery_dir.clone();
+    let compression = state.storage.png_compression();
+    let result = tokio::task::spawn_blocking(move || {
+        let path = codex_core::imaging::cached_thumbnail(&gallery, &rel_path, size, compression)?;
+        Ok::<_, CoreError>(std::fs::read(path)?)
+    })
+    .await;
+    match result {
+        Ok(Ok(bytes)) => (
+            [
+                (axum::http::header::CONTENT_TYPE, "image/png"),
+                (CACHE_CONTROL, "public, max-age=86400"),
+            ],
+            bytes,
+        )
+            .into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
 // ============== Prompt API ==============
 
 #[derive(Debug, Deserialize)]
@@ -536,11 +2045,237 @@ async fn parse_prompt(Json(payload): Json<PromptPayload>) -> impl IntoResponse {
 #[derive(Debug, Serialize)]
 struct FormatPromptResponse {
     formatted: String,
+    /// 原文是否有未闭合的注释（格式化会把它当作普通文本处理）
+    unclosed_comment: bool,
+    /// 原文的结构问题（不检查 snippet 是否存在），格式化照常进行
+    warnings: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FormatPromptPayload {
+    prompt: String,
+    /// 未提供的选项使用默认值
+    #[serde(default)]
+    options: FormatOptions,
+}
+
+async fn format_prompt(
+    State(state): State<AppState>,
+    Json(payload): Json<FormatPromptPayload>,
+) -> impl IntoResponse {
+    let formatted = PromptParser::format_with(&payload.prompt, payload.options);
+    let warnings = PromptParser::lint(&payload.prompt, state.weight_range, |_| true);
+    let unclosed_comment = PromptParser::strip_comments(&payload.prompt).is_err();
+    Json(FormatPromptResponse {
+        formatted,
+        unclosed_comment,
+        warnings,
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct CompactPromptResponse {
+    compacted: String,
+    /// 压缩前后的字节长度
+    original_length: usize,
+    compacted_length: usize,
 }
 
-async fn format_prompt(Json(payload): Json<PromptPayload>) -> impl IntoResponse {
-    let formatted = PromptParser::format(&payload.prompt);
-    Json(FormatPromptResponse { formatted })
+/// 压缩提示词到最短的等价形式
+async fn compact_prompt(Json(payload): Json<PromptPayload>) -> impl IntoResponse {
+    let compacted = PromptParser::compact(&payload.prompt);
+    Json(CompactPromptResponse {
+        original_length: payload.prompt.len(),
+        compacted_length: compacted.len(),
+        compacted,
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct PromptBudgetResponse {
+    /// 估算的 token 数与分块数
+    tokens: usize,
+    chunks: usize,
+    chunk_tokens: usize,
+    /// 起始于第一个分块之后的标签
+    late_tags: Vec<ChunkedTag>,
+}
+
+/// 估算提示词占用的 CLIP 分块，仅为粗略估计
+async fn prompt_budget(Json(payload): Json<PromptPayload>) -> impl IntoResponse {
+    let estimate = PromptParser::estimate_chunks(&PromptParser::parse(&payload.prompt));
+    Json(PromptBudgetResponse {
+        tokens: estimate.tokens,
+        chunks: estimate.chunks,
+        chunk_tokens: CHUNK_TOKENS,
+        late_tags: estimate.tags.into_iter().filter(|t| t.chunk > 0).collect(),
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct ImportPromptResponse {
+    prompt: String,
+}
+
+/// 导入从 NovelAI 官网复制的提示词
+async fn import_prompt(Json(payload): Json<PromptPayload>) -> impl IntoResponse {
+    let prompt = PromptParser::from_novelai(&payload.prompt);
+    Json(ImportPromptResponse { prompt })
+}
+
+#[derive(Debug, Serialize)]
+struct PromptWeightsResponse {
+    tags: Vec<TagWeight>,
+}
+
+/// 计算提示词中每个标签的有效权重（按出现顺序）
+async fn prompt_weights(Json(payload): Json<PromptPayload>) -> impl IntoResponse {
+    let tags = PromptParser::weight_map(&payload.prompt);
+    Json(PromptWeightsResponse { tags })
+}
+
+#[derive(Debug, Serialize)]
+struct SnippetWeightsResponse {
+    snippets: Vec<SnippetWeight>,
+}
+
+/// 列出每个 snippet 引用在上下文中的有效权重（展开前）
+async fn prompt_snippet_weights(Json(payload): Json<PromptPayload>) -> impl IntoResponse {
+    let snippets = PromptParser::snippet_weights(&payload.prompt);
+    Json(SnippetWeightsResponse { snippets })
+}
+
+#[derive(Debug, Serialize)]
+struct ValidatePromptResponse {
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// 检查提示词语法问题与不存在的 snippet 引用
+async fn validate_prompt(
+    State(state): State<AppState>,
+    Json(payload): Json<PromptPayload>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    let weight_range = state.weight_range;
+    match state
+        .run_db(move || {
+            // 查询出错时不误报为不存在
+            PromptParser::lint(&payload.prompt, weight_range, |name| {
+                !matches!(storage.get_snippet_by_name(name), Ok(None))
+            })
+        })
+        .await
+    {
+        Ok(diagnostics) => Json(ValidatePromptResponse { diagnostics }).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// 逐个 token 给出可读说明，标签释义来自词库，snippet 长度来自存储
+async fn explain_prompt(
+    State(state): State<AppState>,
+    Json(payload): Json<PromptPayload>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    let lexicon = state.lexicon.clone();
+    match state
+        .run_db(move || {
+            PromptParser::explain(
+                &payload.prompt,
+                |tag| {
+                    lexicon
+                        .as_ref()
+                        .and_then(|l| l.lookup(tag))
+                        .and_then(|entry| entry.translation("zh"))
+                        .map(str::to_string)
+                },
+                |name| {
+                    storage
+                        .get_snippet_by_name(name)
+                        .ok()
+                        .flatten()
+                        .map(|s| s.content.chars().count())
+                },
+            )
+        })
+        .await
+    {
+        Ok(explanations) => Json(explanations).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct InsertTagPayload {
+    prompt: String,
+    /// 插入位置（字节偏移）
+    offset: usize,
+    tag: String,
+}
+
+#[derive(Debug, Serialize)]
+struct InsertTagResponse {
+    prompt: String,
+    /// 插入后的光标位置（字节偏移）
+    cursor: usize,
+}
+
+/// 在指定位置插入标签并维护逗号分隔
+async fn insert_prompt_tag(Json(payload): Json<InsertTagPayload>) -> impl IntoResponse {
+    let (prompt, cursor) = PromptParser::insert_tag(&payload.prompt, payload.offset, &payload.tag);
+    Json(InsertTagResponse { prompt, cursor })
+}
+
+#[derive(Debug, Deserialize)]
+struct ReorderTagPayload {
+    prompt: String,
+    /// 要移动的标签序号
+    from: usize,
+    /// 目标序号
+    to: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct ReorderTagResponse {
+    prompt: String,
+}
+
+/// 移动标签位置，权重随标签一起移动
+async fn reorder_prompt_tag(Json(payload): Json<ReorderTagPayload>) -> impl IntoResponse {
+    let prompt = PromptParser::move_tag(&payload.prompt, payload.from, payload.to);
+    Json(ReorderTagResponse { prompt })
+}
+
+#[derive(Debug, Deserialize)]
+struct SplitCharactersPayload {
+    prompt: String,
+    /// 拆分方式，默认按 `|` 拆分
+    #[serde(default)]
+    split: CharacterSplit,
+}
+
+/// 内置的起步提示词模板
+async fn list_builtin_templates() -> impl IntoResponse {
+    Json(PromptTemplate::builtins())
+}
+
+/// 将描述多个角色的提示词拆分为角色提示词，位置从左到右均匀排开
+async fn split_characters(Json(payload): Json<SplitCharactersPayload>) -> impl IntoResponse {
+    let segments = PromptParser::split_characters_with(&payload.prompt, &payload.split);
+    let centers = Center::spread(segments.len());
+    let characters: Vec<CharacterPrompt> = segments
+        .into_iter()
+        .zip(centers)
+        .map(|(prompt, center)| CharacterPrompt {
+            prompt,
+            uc: String::new(),
+            center,
+            enabled: true,
+            add_quality_tags: false,
+            inherit_uc: false,
+        })
+        .collect();
+    Json(characters)
 }
 
 // Dry-run 请求负载
@@ -552,6 +2287,13 @@ struct DryRunPayload {
     main_preset: Option<MainPresetSettings>,
     #[serde(default)]
     character_slots: Vec<CharacterSlotSettings>,
+    /// 是否在最终正面提示词中展示客户端将追加的质量标签
+    #[serde(default)]
+    show_quality_tags: bool,
+    #[serde(default)]
+    model: Model,
+    #[serde(default = "default_true")]
+    add_quality_tags: bool,
 }
 
 /// 执行 dry-run，返回提示词处理链各阶段的结果
@@ -560,19 +2302,401 @@ async fn dry_run_prompt(
     Json(payload): Json<DryRunPayload>,
 ) -> impl IntoResponse {
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || {
-        let processor = PromptProcessor::new(storage);
-        processor.dry_run(
-            &payload.raw_positive,
-            &payload.raw_negative,
-            &payload.main_preset.unwrap_or_default(),
-            &payload.character_slots,
-        )
-    })
-    .await
+    let global_affix = state.global_affix.clone();
+    match state
+        .run_db(move || {
+            let processor = PromptProcessor::new(storage).with_global_affix(global_affix);
+            let mut result = processor.dry_run(
+                &payload.raw_positive,
+                &payload.raw_negative,
+                &payload.main_preset.unwrap_or_default(),
+                &payload.character_slots,
+            )?;
+            if payload.show_quality_tags {
+                result.apply_quality_tags(payload.model, payload.add_quality_tags);
+            }
+            Ok::<_, CoreError>(result)
+        })
+        .await
     {
         Ok(Ok(result)) => Json(result).into_response(),
-        Ok(Err(err)) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
+
+/// 批量 dry-run 单次请求允许的最大提示词数量
+const MAX_DRY_RUN_BATCH: usize = 50;
+
+#[derive(Debug, Deserialize)]
+struct DryRunBatchPrompt {
+    positive: String,
+    #[serde(default)]
+    negative: String,
+}
+
+// 批量 Dry-run 请求负载
+#[derive(Debug, Deserialize)]
+struct DryRunBatchPayload {
+    prompts: Vec<DryRunBatchPrompt>,
+    #[serde(default)]
+    main_preset: Option<MainPresetSettings>,
+    #[serde(default)]
+    character_slots: Vec<CharacterSlotSettings>,
+}
+
+/// 对多个提示词执行同一套预设的 dry-run，按输入顺序返回结果
+async fn dry_run_prompt_batch(
+    State(state): State<AppState>,
+    Json(payload): Json<DryRunBatchPayload>,
+) -> impl IntoResponse {
+    if payload.prompts.len() > MAX_DRY_RUN_BATCH {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("too many prompts (max {})", MAX_DRY_RUN_BATCH),
+        )
+            .into_response();
+    }
+
+    let storage = Arc::clone(&state.storage);
+    let global_affix = state.global_affix.clone();
+    match state
+        .run_db(move || {
+            let processor = PromptProcessor::new(storage).with_global_affix(global_affix);
+            let main_preset = payload.main_preset.unwrap_or_default();
+            payload
+                .prompts
+                .iter()
+                .enumerate()
+                .map(|(idx, prompt)| {
+                    processor
+                        .dry_run(
+                            &prompt.positive,
+                            &prompt.negative,
+                            &main_preset,
+                            &payload.character_slots,
+                        )
+                        .map_err(|err| (idx, err))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .await
+    {
+        Ok(Ok(results)) => Json(results).into_response(),
+        Ok(Err((idx, err))) => {
+            let message = format!("prompt #{idx}: {err}");
+            (core_error_response(err).status(), message).into_response()
+        }
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use axum::http::{Method, header::ALLOW};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::test_support::TestApp;
+
+    /// 接受连接但从不响应的 NovelAI 地址，使取出的第一个作业一直处于执行中
+    async fn stalled_client() -> Arc<NaiClient> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                held.push(stream);
+            }
+        });
+        Arc::new(
+            NaiClient::new("token".to_string())
+                .unwrap()
+                .with_image_base_url(&base_url),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_queue_order_abort_and_retry() {
+        let app = TestApp::new();
+        let queue = TaskQueue::new(
+            stalled_client().await,
+            Arc::clone(&app.state.storage),
+            GalleryPaths::new(app.dir.join("gallery")),
+            ExecutorConfig::default(),
+            None,
+        );
+        let task = || GenerateTaskRequest::new("1girl".to_string(), String::new());
+        let (running, second, third) = (task(), task(), task());
+        for t in [&running, &second, &third] {
+            queue.submit(t.clone()).await.unwrap();
+        }
+        for _ in 0..200 {
+            if matches!(queue.status(&running.id).await, Some(TaskStatus::Running)) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(matches!(
+            queue.status(&running.id).await,
+            Some(TaskStatus::Running)
+        ));
+
+        // 部分完成的任务重新入队，排在已有作业之后
+        let partial = task();
+        let record = GenerationRecord {
+            id: Uuid::new_v4(),
+            task_id: partial.id,
+            created_at: chrono::Utc::now(),
+            raw_prompt: "1girl".to_string(),
+            expanded_prompt: "1girl".to_string(),
+            negative_prompt: String::new(),
+            label: None,
+            raw_negative_prompt: None,
+            main_preset: None,
+            images: Vec::new(),
+            params: None,
+        };
+        queue.statuses.lock().await.insert(
+            partial.id,
+            TaskStatus::PartiallyCompleted {
+                task: Box::new(partial.clone()),
+                record: record.clone(),
+                failed: 2,
+                error: "boom".to_string(),
+            },
+        );
+        queue.retry_failed(&partial.id).await.unwrap();
+        assert!(matches!(
+            queue.status(&partial.id).await,
+            Some(TaskStatus::Pending)
+        ));
+        let order: Vec<Uuid> = queue
+            .pending
+            .lock()
+            .await
+            .iter()
+            .map(|job| job.task().id)
+            .collect();
+        assert_eq!(order, vec![second.id, third.id, partial.id]);
+
+        // 取消排队作业：新任务记为取消，重试作业恢复为部分完成
+        assert_eq!(queue.abort_pending().await, 3);
+        assert!(matches!(
+            queue.status(&second.id).await,
+            Some(TaskStatus::Cancelled)
+        ));
+        let Some(TaskStatus::PartiallyCompleted {
+            record: restored,
+            failed,
+            error,
+            ..
+        }) = queue.status(&partial.id).await
+        else {
+            panic!("retry job should fall back to partially completed");
+        };
+        assert_eq!(
+            (restored.id, failed, error.as_str()),
+            (record.id, 2, "boom")
+        );
+        assert!(matches!(
+            queue.status(&running.id).await,
+            Some(TaskStatus::Running)
+        ));
+        queue.retry_failed(&partial.id).await.unwrap();
+        assert_eq!(queue.pending_count().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_queue_applies_backpressure() {
+        let app = TestApp::new();
+        let queue = TaskQueue::new(
+            stalled_client().await,
+            Arc::clone(&app.state.storage),
+            GalleryPaths::new(app.dir.join("gallery")),
+            ExecutorConfig::default(),
+            None,
+        );
+        let task = || GenerateTaskRequest::new("1girl".to_string(), String::new());
+        queue.submit(task()).await.unwrap();
+        while queue.pending_count().await > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        for _ in 0..QUEUE_CAPACITY {
+            queue.submit(task()).await.unwrap();
+        }
+        // 队列已满时立即拒绝，被拒绝的任务不留下状态
+        let rejected = task();
+        let rejected_id = rejected.id;
+        let err = tokio::time::timeout(Duration::from_millis(100), queue.submit(rejected))
+            .await
+            .expect("submit should not wait while the queue is full")
+            .unwrap_err();
+        assert!(matches!(err, QueueError::Full));
+        assert!(queue.status(&rejected_id).await.is_none());
+        let response = queue_error_response(err);
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(
+            response
+                .headers()
+                .contains_key(axum::http::header::RETRY_AFTER)
+        );
+
+        // 取消后空位归还
+        assert_eq!(queue.abort_pending().await, QUEUE_CAPACITY);
+        queue.submit(task()).await.unwrap();
+        assert_eq!(queue.pending_count().await, 1);
+    }
+
+    /// OpenAPI 路由表与实际注册的路由一致：路径一一对应，每个路径的方法相同
+    #[tokio::test]
+    async fn test_openapi_routes_match_router() {
+        let mut documented: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
+        for (method, path, ..) in openapi::ROUTES {
+            documented
+                .entry(path)
+                .or_default()
+                .insert(method.to_uppercase());
+        }
+        let routes = api_routes();
+        // OpenAPI 没有通配段语法，`{*path}` 记作 `{path}`
+        let registered: BTreeSet<String> =
+            routes.paths.iter().map(|p| p.replace("{*", "{")).collect();
+        assert_eq!(
+            registered.len(),
+            routes.paths.len(),
+            "path registered twice"
+        );
+        assert_eq!(
+            registered,
+            documented
+                .keys()
+                .map(|p| p.to_string())
+                .collect::<BTreeSet<_>>()
+        );
+
+        // 用不支持的方法请求，从 405 响应的 Allow 头读出已注册的方法
+        let app = TestApp::new();
+        let router = routes.router.with_state(app.state.clone());
+        for (path, methods) in &documented {
+            let uri = path
+                .split('/')
+                .map(|seg| if seg.starts_with('{') { "x" } else { seg })
+                .collect::<Vec<_>>()
+                .join("/");
+            let request = Request::builder()
+                .method(Method::TRACE)
+                .uri(&uri)
+                .body(Body::empty())
+                .unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED, "{path}");
+            let allowed: BTreeSet<String> = response.headers()[ALLOW]
+                .to_str()
+                .unwrap()
+                .split(',')
+                .map(|m| m.trim().to_string())
+                // GET 路由会自动响应 HEAD
+                .filter(|m| m != "HEAD")
+                .collect();
+            assert_eq!(&allowed, methods, "{path}");
+        }
+    }
+
+    /// 核心错误按变体映射状态码
+    #[tokio::test]
+    async fn test_typed_errors_map_to_status() {
+        let app = TestApp::new();
+        let id = Uuid::new_v4();
+        let response = retry_failed_task(State(app.state.clone()), Path(id))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        app.state
+            .queue
+            .statuses
+            .lock()
+            .await
+            .insert(id, TaskStatus::Cancelled);
+        let response = retry_failed_task(State(app.state.clone()), Path(id))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = archive::delete_archive(State(app.state.clone()), Path("a.txt".into()))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = load_generation_profile(State(app.state.clone()), Path("missing".into()))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = save_generation_profile(
+            State(app.state.clone()),
+            Path(" ".into()),
+            Json(LastGenerationSettings::default()),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = core_error_response(CoreError::SnippetCycle {
+            chain: vec!["a".into(), "b".into(), "a".into()],
+        });
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_body_limit_rewrites_only_limit_rejections() {
+        let router = Router::new()
+            .route(
+                "/echo",
+                post(|body: Bytes| async move { body.len().to_string() }),
+            )
+            .route(
+                "/own",
+                post(|_: Bytes| async { (StatusCode::PAYLOAD_TOO_LARGE, "quota exceeded") }),
+            )
+            .layer(DefaultBodyLimit::max(16))
+            .layer(axum::middleware::from_fn_with_state(
+                16,
+                body_limit_response,
+            ));
+        let send = |uri: &'static str, body: &'static str| {
+            let router = router.clone();
+            async move {
+                let response = router
+                    .oneshot(Request::post(uri).body(Body::from(body)).unwrap())
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (status, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+
+        assert_eq!(
+            send("/echo", "short").await,
+            (StatusCode::OK, "5".to_string())
+        );
+
+        let (status, body) = send("/echo", "this body is longer than the limit").await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["detail"]["limit_bytes"], 16);
+
+        // 处理器自己返回的 413 原样透传
+        assert_eq!(
+            send("/own", "short").await,
+            (StatusCode::PAYLOAD_TOO_LARGE, "quota exceeded".to_string())
+        );
+    }
+}
@@ -0,0 +1,861 @@
+use std::sync::OnceLock;
+
+use axum::Json;
+use codex_api::{Model, Noise, Sampler};
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+
+/// 单个接口：(方法, 路径, 说明, 请求体 schema, 响应 schema)
+type Route = (
+    &'static str,
+    &'static str,
+    &'static str,
+    Option<&'static str>,
+    Option<&'static str>,
+);
+
+/// 所有 `/api` 下的接口，路径参数写作 `{name}`
+pub(crate) const ROUTES: &[Route] = &[
+    ("get", "/health", "健康检查", None, None),
+    (
+        "get",
+        "/ready",
+        "就绪检查（数据库、图库目录、NovelAI 连通性）",
+        None,
+        None,
+    ),
+    ("get", "/status", "生成 / 归档忙碌状态", None, None),
+    (
+        "post",
+        "/maintenance/rebuild-index",
+        "重建 snippet 名称索引",
+        None,
+        None,
+    ),
+    (
+        "get",
+        "/maintenance/dangling-refs",
+        "列出预设与生成设置中指向已删除 snippet 的引用",
+        None,
+        None,
+    ),
+    (
+        "post",
+        "/maintenance/compact",
+        "压缩数据库文件并返回回收的字节数（生成或归档进行中时返回 409）",
+        None,
+        None,
+    ),
+    (
+        "get",
+        "/maintenance/verify",
+        "检查记录引用的图片文件是否存在（`?orphans=true` 同时列出没有记录的图库图片）",
+        None,
+        None,
+    ),
+    (
+        "post",
+        "/maintenance/repair",
+        "删除图片全部缺失的记录，`reindex_orphans` 时为孤立图片重建记录（生成进行中时返回 409）",
+        None,
+        None,
+    ),
+    ("get", "/quota", "查询 NovelAI 剩余 Anlas", None, None),
+    (
+        "get",
+        "/capabilities",
+        "支持的模型、采样器、噪声调度与角色位置预设",
+        None,
+        None,
+    ),
+    ("get", "/thumb", "获取图库图片缩略图", None, None),
+    ("get", "/previews/{path}", "获取预览图", None, None),
+    (
+        "post",
+        "/tasks",
+        "提交生成任务",
+        Some("CreateTaskPayload"),
+        Some("TaskSubmitted"),
+    ),
+    (
+        "post",
+        "/tasks/abort-pending",
+        "取消所有排队中的任务",
+        None,
+        None,
+    ),
+    (
+        "post",
+        "/tasks/preview",
+        "提交前预览：处理提示词并估算 Anlas",
+        Some("CreateTaskPayload"),
+        None,
+    ),
+    (
+        "post",
+        "/tasks/debug-payload",
+        "返回将发送给 NovelAI 的请求体（不含鉴权），不入队也不发送",
+        Some("CreateTaskPayload"),
+        None,
+    ),
+    (
+        "get",
+        "/tasks/{id}",
+        "查询任务状态",
+        None,
+        Some("TaskStatusView"),
+    ),
+    (
+        "post",
+        "/tasks/{id}/retry-failed",
+        "重试部分完成任务中失败的图片",
+        None,
+        Some("TaskSubmitted"),
+    ),
+    (
+        "get",
+        "/records/recent",
+        "最近的生成记录",
+        None,
+        Some("GenerationRecordViewList"),
+    ),
+    (
+        "get",
+        "/records/by-date/{date}",
+        "某一天的生成记录",
+        None,
+        Some("GenerationRecordViewList"),
+    ),
+    (
+        "delete",
+        "/records/by-date/{date}",
+        "删除某一天的记录及图片",
+        None,
+        None,
+    ),
+    (
+        "get",
+        "/records/by-label/{label}",
+        "带有指定标签的生成记录",
+        None,
+        Some("GenerationRecordViewList"),
+    ),
+    ("get", "/records/date-counts", "每天的记录数量", None, None),
+    (
+        "get",
+        "/records/{id}",
+        "获取生成记录",
+        None,
+        Some("GenerationRecordView"),
+    ),
+    ("delete", "/records/{id}", "删除生成记录及图片", None, None),
+    ("post", "/records/batch", "批量删除记录", None, None),
+    (
+        "post",
+        "/records/{id}/regenerate",
+        "以记录的参数重新生成",
+        None,
+        Some("TaskSubmitted"),
+    ),
+    (
+        "post",
+        "/records/{id}/reexpand",
+        "按当前 snippet 重新展开记录的提示词",
+        None,
+        None,
+    ),
+    (
+        "get",
+        "/records/{id}/export-nai",
+        "导出 NovelAI 请求 JSON",
+        None,
+        None,
+    ),
+    (
+        "get",
+        "/records/{id}/recipe",
+        "导出可分享的生成配方（内联引用的 snippet）",
+        None,
+        None,
+    ),
+    (
+        "post",
+        "/recipe/import",
+        "导入配方，按 on_conflict（skip / rename / overwrite）处理同名 snippet",
+        None,
+        None,
+    ),
+    (
+        "get",
+        "/snippets",
+        "分页列出 snippet",
+        None,
+        Some("SnippetPage"),
+    ),
+    ("post", "/snippets", "创建 snippet", None, None),
+    (
+        "get",
+        "/snippets/names",
+        "按前缀列出 snippet 名称",
+        None,
+        None,
+    ),
+    (
+        "get",
+        "/snippets/validate-name",
+        "校验 snippet 名称",
+        None,
+        None,
+    ),
+    (
+        "get",
+        "/snippets/{id}",
+        "获取 snippet",
+        None,
+        Some("Snippet"),
+    ),
+    (
+        "put",
+        "/snippets/{id}",
+        "更新 snippet",
+        None,
+        Some("Snippet"),
+    ),
+    ("delete", "/snippets/{id}", "删除 snippet", None, None),
+    (
+        "put",
+        "/snippets/{id}/preview",
+        "上传 snippet 预览图",
+        None,
+        None,
+    ),
+    (
+        "delete",
+        "/snippets/{id}/preview",
+        "删除 snippet 预览图",
+        None,
+        None,
+    ),
+    (
+        "put",
+        "/snippets/{id}/preview-from-record",
+        "以记录中的图片作为 snippet 预览图",
+        None,
+        None,
+    ),
+    (
+        "put",
+        "/snippets/{id}/rename",
+        "重命名 snippet 并更新引用",
+        None,
+        None,
+    ),
+    (
+        "post",
+        "/snippets/{id}/expand",
+        "预览 snippet 展开结果",
+        None,
+        None,
+    ),
+    ("get", "/presets", "分页列出角色预设", None, None),
+    ("post", "/presets", "创建角色预设", None, None),
+    ("get", "/presets/{id}", "获取角色预设", None, None),
+    ("put", "/presets/{id}", "更新角色预设", None, None),
+    ("delete", "/presets/{id}", "删除角色预设", None, None),
+    (
+        "put",
+        "/presets/{id}/preview",
+        "上传角色预设预览图",
+        None,
+        None,
+    ),
+    (
+        "delete",
+        "/presets/{id}/preview",
+        "删除角色预设预览图",
+        None,
+        None,
+    ),
+    (
+        "put",
+        "/presets/{id}/preview-from-record",
+        "以记录中的图片作为角色预设预览图",
+        None,
+        None,
+    ),
+    ("put", "/presets/{id}/rename", "重命名角色预设", None, None),
+    ("post", "/presets/merge", "合并角色预设", None, None),
+    ("get", "/main-presets", "分页列出主预设", None, None),
+    ("post", "/main-presets", "创建主预设", None, None),
+    ("get", "/main-presets/{id}", "获取主预设", None, None),
+    ("put", "/main-presets/{id}", "更新主预设", None, None),
+    ("delete", "/main-presets/{id}", "删除主预设", None, None),
+    (
+        "get",
+        "/main-presets/default",
+        "获取默认主预设（未设置时为 null）",
+        None,
+        None,
+    ),
+    (
+        "delete",
+        "/main-presets/default",
+        "清除默认主预设",
+        None,
+        None,
+    ),
+    (
+        "put",
+        "/main-presets/{id}/default",
+        "设为默认主预设，新会话预选",
+        None,
+        None,
+    ),
+    (
+        "post",
+        "/main-presets/{id}/diff",
+        "比较当前主预设设置与已保存的预设，返回不同字段的新旧值",
+        None,
+        None,
+    ),
+    (
+        "get",
+        "/settings/generation",
+        "获取上次生成设置",
+        None,
+        None,
+    ),
+    ("put", "/settings/generation", "保存生成设置", None, None),
+    (
+        "get",
+        "/settings/generation/resolved",
+        "获取生成设置并解析引用的预设",
+        None,
+        None,
+    ),
+    ("get", "/settings/profiles", "列出命名生成配置", None, None),
+    (
+        "get",
+        "/settings/profiles/{name}",
+        "获取命名生成配置",
+        None,
+        None,
+    ),
+    (
+        "put",
+        "/settings/profiles/{name}",
+        "保存命名生成配置，同名覆盖",
+        None,
+        None,
+    ),
+    (
+        "delete",
+        "/settings/profiles/{name}",
+        "删除命名生成配置",
+        None,
+        None,
+    ),
+    (
+        "post",
+        "/settings/profiles/{name}/load",
+        "载入命名生成配置为当前生成设置",
+        None,
+        None,
+    ),
+    (
+        "post",
+        "/prompt/parse",
+        "解析提示词为 token 与高亮范围",
+        None,
+        None,
+    ),
+    ("post", "/prompt/format", "格式化提示词", None, None),
+    (
+        "post",
+        "/prompt/compact",
+        "压缩提示词（去除注释、多余空白与空标签段）",
+        None,
+        None,
+    ),
+    (
+        "post",
+        "/prompt/budget",
+        "估算提示词占用的 CLIP 分块（约 75 token 一块）及落入后续分块的标签",
+        None,
+        None,
+    ),
+    (
+        "post",
+        "/prompt/import",
+        "从 NovelAI 语法导入提示词",
+        None,
+        None,
+    ),
+    ("post", "/prompt/validate", "检查提示词", None, None),
+    (
+        "post",
+        "/prompt/explain",
+        "逐个 token 说明提示词",
+        None,
+        None,
+    ),
+    (
+        "post",
+        "/prompt/weights",
+        "计算每个标签的有效权重",
+        None,
+        None,
+    ),
+    (
+        "post",
+        "/prompt/snippet-weights",
+        "计算每个 snippet 引用的上下文权重",
+        None,
+        None,
+    ),
+    ("post", "/prompt/insert-tag", "在光标处插入标签", None, None),
+    ("post", "/prompt/reorder", "移动标签位置", None, None),
+    (
+        "post",
+        "/prompt/split-characters",
+        "将提示词拆分为角色提示词（返回 CharacterPrompt 数组）",
+        None,
+        None,
+    ),
+    (
+        "post",
+        "/prompt/dry-run",
+        "预览提示词处理链各阶段结果",
+        Some("DryRunPayload"),
+        Some("DryRunResult"),
+    ),
+    ("post", "/prompt/dry-run-batch", "批量 dry-run", None, None),
+    (
+        "get",
+        "/prompt/templates/builtin",
+        "内置的起步提示词模板",
+        None,
+        None,
+    ),
+    ("get", "/seeds/favorites", "列出收藏的种子", None, None),
+    ("post", "/seeds/favorites", "收藏种子", None, None),
+    (
+        "delete",
+        "/seeds/favorites/{seed}",
+        "取消收藏种子",
+        None,
+        None,
+    ),
+    ("get", "/blocklist", "列出屏蔽标签", None, None),
+    ("post", "/blocklist", "添加屏蔽标签", None, None),
+    ("delete", "/blocklist/{tag}", "取消屏蔽标签", None, None),
+    ("get", "/lexicon", "词库索引", None, None),
+    (
+        "get",
+        "/lexicon/categories/{name}",
+        "词库分类数据",
+        None,
+        None,
+    ),
+    (
+        "get",
+        "/lexicon/search",
+        "搜索词库，可用 category / subcategory 限定范围",
+        None,
+        None,
+    ),
+    ("get", "/lexicon/recent", "最近常用的标签", None, None),
+    (
+        "get",
+        "/suggest/cooccur",
+        "历史记录中常一同使用的标签",
+        None,
+        None,
+    ),
+    (
+        "get",
+        "/stats/tags",
+        "全部记录中最常用的标签及记录、图片总数",
+        None,
+        None,
+    ),
+    (
+        "get",
+        "/stats/usage",
+        "本地统计的生成图片、任务总数与首末生成时间",
+        None,
+        None,
+    ),
+    (
+        "post",
+        "/stats/usage/reset",
+        "清零用量计数，需要 `Authorization: Bearer <CODEX_ADMIN_TOKEN>`",
+        None,
+        None,
+    ),
+    ("get", "/archives", "列出归档文件", None, None),
+    ("post", "/archives", "归档今天之前的所有日期", None, None),
+    ("get", "/archives/dates", "可归档的日期", None, None),
+    ("post", "/archives/selected", "归档指定日期", None, None),
+    ("get", "/archives/status", "归档任务状态", None, None),
+    (
+        "post",
+        "/archives/cancel",
+        "取消归档任务，当前日期完成后停止",
+        None,
+        None,
+    ),
+    (
+        "get",
+        "/gallery/dates/{date}/download",
+        "即时打包下载某天的图片",
+        None,
+        None,
+    ),
+    ("get", "/archives/{name}", "下载归档文件", None, None),
+    ("delete", "/archives/{name}", "删除归档文件", None, None),
+    (
+        "get",
+        "/archives/{name}/contents",
+        "列出归档内的文件（名称与大小）",
+        None,
+        None,
+    ),
+    (
+        "get",
+        "/archives/{name}/file",
+        "读取归档内的单个文件（query: entry）",
+        None,
+        None,
+    ),
+    ("get", "/openapi.json", "本文档", None, None),
+];
+
+/// `GET /api/openapi.json`
+pub async fn get_openapi() -> Json<Value> {
+    static DOCUMENT: OnceLock<Value> = OnceLock::new();
+    Json(DOCUMENT.get_or_init(document).clone())
+}
+
+/// 手写的 OpenAPI 3.1 文档；枚举取值由 serde 序列化得到，与重命名保持一致
+fn document() -> Value {
+    let mut paths = Map::new();
+    for &(method, path, summary, request, response) in ROUTES {
+        let item = paths.entry(path.to_string()).or_insert_with(|| json!({}));
+        item[method] = operation(path, summary, request, response);
+    }
+
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "nai-codex API",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "servers": [{ "url": "/api" }],
+        "paths": paths,
+        "components": { "schemas": schemas() },
+    })
+}
+
+fn operation(path: &str, summary: &str, request: Option<&str>, response: Option<&str>) -> Value {
+    let parameters: Vec<Value> = path
+        .split('/')
+        .filter_map(|seg| seg.strip_prefix('{')?.strip_suffix('}'))
+        .map(|name| {
+            json!({
+                "name": name,
+                "in": "path",
+                "required": true,
+                "schema": { "type": "string" },
+            })
+        })
+        .collect();
+
+    let ok = match response {
+        Some(name) => json!({
+            "description": "OK",
+            "content": { "application/json": { "schema": schema_ref(name) } },
+        }),
+        None => json!({ "description": "OK" }),
+    };
+    let mut op = json!({
+        "summary": summary,
+        "responses": {
+            "200": ok,
+            "400": {
+                "description": "请求无效；结构化错误带有 detail",
+                "content": { "application/json": { "schema": schema_ref("ApiError") } },
+            },
+        },
+    });
+    if !parameters.is_empty() {
+        op["parameters"] = Value::Array(parameters);
+    }
+    if let Some(name) = request {
+        op["requestBody"] = json!({
+            "required": true,
+            "content": { "application/json": { "schema": schema_ref(name) } },
+        });
+    }
+    op
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{name}") })
+}
+
+/// 按 serde 序列化结果列出枚举的所有取值
+fn enum_values<T: Serialize>(all: &[T]) -> Value {
+    let values: Vec<Value> = all
+        .iter()
+        .map(|v| serde_json::to_value(v).expect("enum serializes to a string"))
+        .collect();
+    json!({ "type": "string", "enum": values })
+}
+
+fn nullable(ty: &str) -> Value {
+    json!({ "type": [ty, "null"] })
+}
+
+fn status_variant(status: &str, properties: Value, required: &[&str]) -> Value {
+    let mut props = json!({ "status": { "const": status } });
+    if let (Some(props), Value::Object(extra)) = (props.as_object_mut(), properties) {
+        props.extend(extra);
+    }
+    let mut required: Vec<&str> = required.to_vec();
+    required.insert(0, "status");
+    json!({ "type": "object", "properties": props, "required": required })
+}
+
+fn schemas() -> Value {
+    json!({
+        "Model": enum_values(&Model::ALL),
+        "Sampler": enum_values(&Sampler::ALL),
+        "Noise": enum_values(&Noise::ALL),
+        "ApiError": {
+            "type": "object",
+            "properties": {
+                "error": { "type": "string" },
+                "detail": { "description": "结构化错误详情，结构随错误类型而定" },
+            },
+            "required": ["error"],
+        },
+        "Center": {
+            "description": "角色位置：坐标或预设名，响应中总是坐标",
+            "oneOf": [
+                {
+                    "type": "object",
+                    "properties": {
+                        "x": { "type": "number" },
+                        "y": { "type": "number" },
+                    },
+                },
+                {
+                    "type": "string",
+                    "enum": [
+                        "center", "left", "right", "top", "bottom",
+                        "top_left", "top_right", "bottom_left", "bottom_right",
+                    ],
+                },
+            ],
+        },
+        "CharacterPrompt": {
+            "type": "object",
+            "properties": {
+                "prompt": { "type": "string" },
+                "uc": { "type": "string" },
+                "center": schema_ref("Center"),
+                "enabled": { "type": "boolean", "default": true },
+                "add_quality_tags": { "type": "boolean", "default": false },
+                "inherit_uc": { "type": "boolean", "default": false },
+            },
+            "required": ["prompt", "uc"],
+        },
+        "GenerationParams": {
+            "type": "object",
+            "description": "缺省字段取默认值；steps / scale 缺省时使用所选模型的推荐值。V4 / V4.5 模型不支持 SMEA，请求中始终关闭",
+            "properties": {
+                "model": schema_ref("Model"),
+                "width": { "type": "integer" },
+                "height": { "type": "integer" },
+                "steps": { "type": "integer" },
+                "scale": { "type": "number" },
+                "sampler": schema_ref("Sampler"),
+                "noise": schema_ref("Noise"),
+                "cfg_rescale": { "type": "number" },
+                "undesired_content_preset": nullable("integer"),
+                "add_quality_tags": { "type": "boolean" },
+                "character_prompts": {
+                    "type": ["array", "null"],
+                    "items": schema_ref("CharacterPrompt"),
+                },
+                "seed": nullable("integer"),
+                "variety_plus": { "type": "boolean" },
+                "master_seed": nullable("integer"),
+            },
+        },
+        "MainPresetSettings": {
+            "type": "object",
+            "properties": {
+                "before": nullable("string"),
+                "after": nullable("string"),
+                "replace": nullable("string"),
+                "uc_before": nullable("string"),
+                "uc_after": nullable("string"),
+                "uc_replace": nullable("string"),
+            },
+        },
+        "CreateTaskPayload": {
+            "type": "object",
+            "properties": {
+                "raw_prompt": { "type": "string" },
+                "negative_prompt": { "type": "string" },
+                "count": { "type": "integer", "default": 1 },
+                "params": schema_ref("GenerationParams"),
+                "main_preset": schema_ref("MainPresetSettings"),
+                "retry_on_filter": { "type": "boolean", "default": false },
+                "label": nullable("string"),
+            },
+            "required": ["raw_prompt", "negative_prompt"],
+        },
+        "TaskSubmitted": {
+            "type": "object",
+            "properties": { "id": { "type": "string", "format": "uuid" } },
+            "required": ["id"],
+        },
+        "GalleryImageView": {
+            "type": "object",
+            "properties": {
+                "url": { "type": "string" },
+                "seed": { "type": "integer" },
+                "width": { "type": "integer" },
+                "height": { "type": "integer" },
+                "filter_retries": { "type": "integer" },
+            },
+            "required": ["url", "seed", "width", "height"],
+        },
+        "GenerationRecordView": {
+            "type": "object",
+            "properties": {
+                "id": { "type": "string", "format": "uuid" },
+                "task_id": { "type": "string", "format": "uuid" },
+                "created_at": { "type": "string", "format": "date-time" },
+                "raw_prompt": { "type": "string" },
+                "expanded_prompt": { "type": "string" },
+                "negative_prompt": { "type": "string" },
+                "label": { "type": "string" },
+                "images": { "type": "array", "items": schema_ref("GalleryImageView") },
+            },
+            "required": [
+                "id", "task_id", "created_at", "raw_prompt",
+                "expanded_prompt", "negative_prompt", "images",
+            ],
+        },
+        "GenerationRecordViewList": {
+            "type": "array",
+            "items": schema_ref("GenerationRecordView"),
+        },
+        "TaskStatusView": {
+            "oneOf": [
+                status_variant("pending", json!({}), &[]),
+                status_variant("running", json!({}), &[]),
+                status_variant(
+                    "completed",
+                    json!({ "record": schema_ref("GenerationRecordView") }),
+                    &["record"],
+                ),
+                status_variant(
+                    "partially_completed",
+                    json!({
+                        "record": schema_ref("GenerationRecordView"),
+                        "succeeded": { "type": "integer" },
+                        "failed": { "type": "integer" },
+                        "error": { "type": "string" },
+                    }),
+                    &["record", "succeeded", "failed", "error"],
+                ),
+                status_variant("failed", json!({ "error": { "type": "string" } }), &["error"]),
+                status_variant("cancelled", json!({}), &[]),
+                status_variant("unknown", json!({}), &[]),
+            ],
+            "discriminator": { "propertyName": "status" },
+        },
+        "CharacterSlotSettings": {
+            "type": "object",
+            "properties": {
+                "prompt": { "type": "string" },
+                "uc": { "type": "string" },
+                "enabled": { "type": "boolean" },
+                "preset_id": { "type": ["string", "null"], "format": "uuid" },
+            },
+        },
+        "DryRunPayload": {
+            "type": "object",
+            "properties": {
+                "raw_positive": { "type": "string" },
+                "raw_negative": { "type": "string" },
+                "main_preset": schema_ref("MainPresetSettings"),
+                "character_slots": {
+                    "type": "array",
+                    "items": schema_ref("CharacterSlotSettings"),
+                },
+                "show_quality_tags": { "type": "boolean", "default": false },
+                "model": schema_ref("Model"),
+                "add_quality_tags": { "type": "boolean", "default": true },
+            },
+            "required": ["raw_positive", "raw_negative"],
+        },
+        "ProcessedCharacterPrompt": {
+            "type": "object",
+            "properties": {
+                "after_preset": { "type": "string" },
+                "final_prompt": { "type": "string" },
+                "uc_after_preset": { "type": "string" },
+                "final_uc": { "type": "string" },
+                "enabled": { "type": "boolean" },
+            },
+        },
+        "DryRunResult": {
+            "type": "object",
+            "properties": {
+                "raw_positive": { "type": "string" },
+                "positive_after_global": nullable("string"),
+                "positive_after_preset": { "type": "string" },
+                "final_positive": { "type": "string" },
+                "raw_negative": { "type": "string" },
+                "negative_after_preset": { "type": "string" },
+                "final_negative": { "type": "string" },
+                "character_prompts": {
+                    "type": "array",
+                    "items": schema_ref("ProcessedCharacterPrompt"),
+                },
+                "blocked_tags": { "type": "array", "items": { "type": "string" } },
+                "quality_tags": nullable("string"),
+            },
+        },
+        "Snippet": {
+            "type": "object",
+            "properties": {
+                "id": { "type": "string", "format": "uuid" },
+                "name": { "type": "string" },
+                "category": { "type": "string" },
+                "tags": { "type": "array", "items": { "type": "string" } },
+                "description": nullable("string"),
+                "preview_path": nullable("string"),
+                "content": { "type": "string" },
+                "created_at": { "type": "string", "format": "date-time" },
+                "updated_at": { "type": "string", "format": "date-time" },
+            },
+        },
+        "SnippetPage": {
+            "type": "object",
+            "properties": {
+                "items": { "type": "array", "items": schema_ref("Snippet") },
+                "total": { "type": "integer" },
+                "skipped": { "type": "integer" },
+                "has_more": { "type": "boolean" },
+                "next_offset": nullable("integer"),
+            },
+        },
+    })
+}
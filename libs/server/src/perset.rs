@@ -7,11 +7,13 @@ use axum::{
     response::IntoResponse,
 };
 use base64::{self, Engine, prelude::BASE64_STANDARD};
-use codex_core::{CharacterPreset, MainPreset};
+use codex_core::{CharacterPreset, MainPreset, MainPresetSettings, PresetMergeStrategy};
 use serde::Deserialize;
 use uuid::Uuid;
 
-use crate::{AppState, RenamePayload, UpdatePreviewPayload};
+use crate::{
+    AppState, PreviewFromRecordPayload, RenamePayload, UpdatePreviewPayload, core_error_response,
+};
 
 #[derive(Debug, Deserialize)]
 pub struct PresetQuery {
@@ -30,9 +32,12 @@ pub async fn list_presets(
     Query(q): Query<PresetQuery>,
 ) -> impl IntoResponse {
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || storage.list_presets(q.offset, q.limit)).await {
+    match state
+        .run_db(move || storage.list_presets(q.offset, q.limit))
+        .await
+    {
         Ok(Ok(page)) => Json(page).into_response(),
-        Ok(Err(err)) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
@@ -80,23 +85,22 @@ pub async fn create_preset(
     };
 
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || {
-        storage.upsert_preset_with_preview(preset, preview_bytes.as_deref())
-    })
-    .await
+    match state
+        .run_db(move || storage.upsert_preset_with_preview(preset, preview_bytes.as_deref()))
+        .await
     {
         Ok(Ok(saved)) => Json(saved).into_response(),
-        Ok(Err(err)) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
 
 pub async fn get_preset(State(state): State<AppState>, Path(id): Path<Uuid>) -> impl IntoResponse {
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || storage.get_preset(id)).await {
+    match state.run_db(move || storage.get_preset(id)).await {
         Ok(Ok(Some(preset))) => Json(preset).into_response(),
         Ok(Ok(None)) => (StatusCode::NOT_FOUND, "preset not found").into_response(),
-        Ok(Err(err)) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
@@ -123,12 +127,10 @@ pub async fn update_preset(
     let storage_for_get = Arc::clone(&storage);
 
     // First get the existing preset
-    let existing = match tokio::task::spawn_blocking(move || storage_for_get.get_preset(id)).await {
+    let existing = match state.run_db(move || storage_for_get.get_preset(id)).await {
         Ok(Ok(Some(preset))) => preset,
         Ok(Ok(None)) => return (StatusCode::NOT_FOUND, "preset not found").into_response(),
-        Ok(Err(err)) => {
-            return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
-        }
+        Ok(Err(err)) => return core_error_response(err),
         Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     };
 
@@ -168,13 +170,12 @@ pub async fn update_preset(
         None => None,
     };
 
-    match tokio::task::spawn_blocking(move || {
-        storage.upsert_preset_with_preview(preset, preview_bytes.as_deref())
-    })
-    .await
+    match state
+        .run_db(move || storage.upsert_preset_with_preview(preset, preview_bytes.as_deref()))
+        .await
     {
         Ok(Ok(saved)) => Json(saved).into_response(),
-        Ok(Err(err)) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
@@ -190,11 +191,34 @@ pub async fn update_preset_preview(
     };
 
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || storage.update_preset_preview(id, &preview_bytes))
+    match state
+        .run_db(move || storage.update_preset_preview(id, &preview_bytes))
+        .await
+    {
+        Ok(Ok(saved)) => Json(saved).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// 以生成记录中的图片（缩小后）作为 preset 预览图
+pub async fn update_preset_preview_from_record(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<PreviewFromRecordPayload>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    let gallery = state.gallery_dir.clone();
+    match state
+        .run_db(move || {
+            let bytes =
+                storage.record_image_preview(payload.record_id, payload.image_index, &gallery)?;
+            storage.update_preset_preview(id, &bytes)
+        })
         .await
     {
         Ok(Ok(saved)) => Json(saved).into_response(),
-        Ok(Err(err)) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
@@ -204,9 +228,12 @@ pub async fn delete_preset_preview(
     Path(id): Path<Uuid>,
 ) -> impl IntoResponse {
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || storage.delete_preset_preview(id)).await {
+    match state
+        .run_db(move || storage.delete_preset_preview(id))
+        .await
+    {
         Ok(Ok(saved)) => Json(saved).into_response(),
-        Ok(Err(err)) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
@@ -216,10 +243,10 @@ pub async fn delete_preset(
     Path(id): Path<Uuid>,
 ) -> impl IntoResponse {
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || storage.delete_preset(id)).await {
+    match state.run_db(move || storage.delete_preset(id)).await {
         Ok(Ok(true)) => StatusCode::NO_CONTENT.into_response(),
         Ok(Ok(false)) => (StatusCode::NOT_FOUND, "preset not found").into_response(),
-        Ok(Err(err)) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
@@ -230,9 +257,45 @@ pub async fn rename_preset(
     Json(payload): Json<RenamePayload>,
 ) -> impl IntoResponse {
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || storage.rename_preset(id, payload.name)).await {
+    match state
+        .run_db(move || storage.rename_preset(id, payload.name))
+        .await
+    {
         Ok(Ok(saved)) => Json(saved).into_response(),
-        Ok(Err(err)) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MergePresetsPayload {
+    into: Uuid,
+    from: Uuid,
+    #[serde(default)]
+    strategy: PresetMergeStrategy,
+    #[serde(default)]
+    delete_source: bool,
+}
+
+/// 将一个角色预设合并到另一个
+pub async fn merge_presets(
+    State(state): State<AppState>,
+    Json(payload): Json<MergePresetsPayload>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state
+        .run_db(move || {
+            storage.merge_presets(
+                payload.into,
+                payload.from,
+                payload.strategy,
+                payload.delete_source,
+            )
+        })
+        .await
+    {
+        Ok(Ok(merged)) => Json(merged).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
@@ -244,9 +307,12 @@ pub async fn list_main_presets(
     Query(q): Query<PresetQuery>,
 ) -> impl IntoResponse {
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || storage.list_main_presets(q.offset, q.limit)).await {
+    match state
+        .run_db(move || storage.list_main_presets(q.offset, q.limit))
+        .await
+    {
         Ok(Ok(page)) => Json(page).into_response(),
-        Ok(Err(err)) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
@@ -284,9 +350,12 @@ pub async fn create_main_preset(
     preset.uc_replace = payload.uc_replace;
 
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || storage.upsert_main_preset(preset)).await {
+    match state
+        .run_db(move || storage.upsert_main_preset(preset))
+        .await
+    {
         Ok(Ok(saved)) => (StatusCode::CREATED, Json(saved)).into_response(),
-        Ok(Err(err)) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
@@ -296,10 +365,25 @@ pub async fn get_main_preset(
     Path(id): Path<Uuid>,
 ) -> impl IntoResponse {
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || storage.get_main_preset(id)).await {
+    match state.run_db(move || storage.get_main_preset(id)).await {
         Ok(Ok(Some(preset))) => Json(preset).into_response(),
         Ok(Ok(None)) => (StatusCode::NOT_FOUND, "main preset not found").into_response(),
-        Ok(Err(err)) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// 比较当前设置与已保存的主预设，返回不同的字段
+pub async fn diff_main_preset(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(current): Json<MainPresetSettings>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state.run_db(move || storage.get_main_preset(id)).await {
+        Ok(Ok(Some(preset))) => Json(preset.to_settings().diff(&current)).into_response(),
+        Ok(Ok(None)) => (StatusCode::NOT_FOUND, "main preset not found").into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
@@ -325,14 +409,13 @@ pub async fn update_main_preset(
     let storage_for_get = Arc::clone(&storage);
 
     // First get the existing preset
-    let existing = match tokio::task::spawn_blocking(move || storage_for_get.get_main_preset(id))
+    let existing = match state
+        .run_db(move || storage_for_get.get_main_preset(id))
         .await
     {
         Ok(Ok(Some(preset))) => preset,
         Ok(Ok(None)) => return (StatusCode::NOT_FOUND, "main preset not found").into_response(),
-        Ok(Err(err)) => {
-            return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
-        }
+        Ok(Err(err)) => return core_error_response(err),
         Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     };
 
@@ -364,9 +447,51 @@ pub async fn update_main_preset(
     }
     preset.updated_at = chrono::Utc::now();
 
-    match tokio::task::spawn_blocking(move || storage.upsert_main_preset(preset)).await {
+    match state
+        .run_db(move || storage.upsert_main_preset(preset))
+        .await
+    {
         Ok(Ok(saved)) => Json(saved).into_response(),
-        Ok(Err(err)) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// 读取默认主预设；未设置时返回 null
+pub async fn get_default_main_preset(State(state): State<AppState>) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state.run_db(move || storage.default_main_preset()).await {
+        Ok(Ok(preset)) => Json(preset).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// 将主预设设为默认，替换之前的默认主预设
+pub async fn set_default_main_preset(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state
+        .run_db(move || storage.set_default_main_preset(id))
+        .await
+    {
+        Ok(Ok(preset)) => Json(preset).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// 清除默认主预设
+pub async fn clear_default_main_preset(State(state): State<AppState>) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state
+        .run_db(move || storage.clear_default_main_preset())
+        .await
+    {
+        Ok(Ok(_)) => StatusCode::NO_CONTENT.into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
@@ -376,10 +501,10 @@ pub async fn delete_main_preset(
     Path(id): Path<Uuid>,
 ) -> impl IntoResponse {
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || storage.delete_main_preset(id)).await {
+    match state.run_db(move || storage.delete_main_preset(id)).await {
         Ok(Ok(true)) => StatusCode::NO_CONTENT.into_response(),
         Ok(Ok(false)) => (StatusCode::NOT_FOUND, "main preset not found").into_response(),
-        Ok(Err(err)) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
@@ -0,0 +1,126 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+use serde::Serialize;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::AppState;
+
+/// 自检未全部通过时的重试间隔
+const SELF_CHECK_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 单项自检结果
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl CheckResult {
+    fn from_result<T>(name: &'static str, result: Result<T, String>) -> Self {
+        Self {
+            name,
+            ok: result.is_ok(),
+            error: result.err(),
+        }
+    }
+}
+
+/// 就绪状态；自检完成前 `checks` 为空
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReadinessReport {
+    pub ready: bool,
+    pub checks: Vec<CheckResult>,
+}
+
+/// 就绪状态管理器
+#[derive(Clone, Default)]
+pub struct ReadinessState {
+    report: Arc<Mutex<ReadinessReport>>,
+}
+
+impl ReadinessState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn get_report(&self) -> ReadinessReport {
+        self.report.lock().await.clone()
+    }
+
+    async fn set_report(&self, report: ReadinessReport) {
+        *self.report.lock().await = report;
+    }
+}
+
+/// 在后台执行启动自检（数据库读取、gallery 可写、NovelAI token），失败时定期重试直到通过
+pub fn spawn_self_check(state: AppState) {
+    tokio::spawn(async move {
+        loop {
+            let report = run_checks(&state).await;
+            for check in report.checks.iter().filter(|c| !c.ok) {
+                tracing::warn!(
+                    check = check.name,
+                    error = check.error.as_deref().unwrap_or_default(),
+                    "startup self-check failed"
+                );
+            }
+            let ready = report.ready;
+            state.readiness.set_report(report).await;
+            if ready {
+                tracing::info!("startup self-check passed");
+                break;
+            }
+            tokio::time::sleep(SELF_CHECK_RETRY_INTERVAL).await;
+        }
+    });
+}
+
+async fn run_checks(state: &AppState) -> ReadinessReport {
+    let storage = Arc::clone(&state.storage);
+    let db = match state.run_db(move || storage.list_recent_records(1)).await {
+        Ok(result) => result.map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+
+    let gallery_dir = state.gallery_dir.clone();
+    let gallery = tokio::task::spawn_blocking(move || {
+        std::fs::create_dir_all(&gallery_dir)?;
+        let probe = gallery_dir.join(format!(".codex-write-test-{}", Uuid::new_v4()));
+        std::fs::write(&probe, b"ok")?;
+        std::fs::remove_file(&probe)
+    })
+    .await
+    .map_err(|err| err.to_string())
+    .and_then(|result| result.map_err(|err| err.to_string()));
+
+    let token = state
+        .nai_client
+        .inquire_quota()
+        .await
+        .map_err(|err| err.to_string());
+
+    let checks = vec![
+        CheckResult::from_result("database", db),
+        CheckResult::from_result("gallery_writable", gallery),
+        CheckResult::from_result("nai_token", token),
+    ];
+    ReadinessReport {
+        ready: checks.iter().all(|c| c.ok),
+        checks,
+    }
+}
+
+/// 就绪检查：自检全部通过前返回 503
+pub async fn ready(State(state): State<AppState>) -> impl IntoResponse {
+    let report = state.readiness.get_report().await;
+    let status = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
@@ -0,0 +1,58 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use serde::Deserialize;
+
+use crate::{AppState, core_error_response};
+
+#[derive(Debug, Deserialize)]
+pub struct AddFavoriteSeedPayload {
+    seed: u64,
+    label: String,
+}
+
+pub async fn list_favorite_seeds(State(state): State<AppState>) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state.run_db(move || storage.list_favorite_seeds()).await {
+        Ok(Ok(seeds)) => Json(seeds).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// 收藏种子，已收藏的种子更新标签
+pub async fn add_favorite_seed(
+    State(state): State<AppState>,
+    Json(payload): Json<AddFavoriteSeedPayload>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state
+        .run_db(move || storage.add_favorite_seed(payload.seed, &payload.label))
+        .await
+    {
+        Ok(Ok(favorite)) => Json(favorite).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+pub async fn remove_favorite_seed(
+    State(state): State<AppState>,
+    Path(seed): Path<u64>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state
+        .run_db(move || storage.remove_favorite_seed(seed))
+        .await
+    {
+        Ok(Ok(true)) => StatusCode::NO_CONTENT.into_response(),
+        Ok(Ok(false)) => StatusCode::NOT_FOUND.into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
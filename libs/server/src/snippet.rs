@@ -7,11 +7,11 @@ use axum::{
     response::IntoResponse,
 };
 use base64::{self, Engine, prelude::BASE64_STANDARD};
-use codex_core::Snippet;
+use codex_core::{CoreError, Snippet, SnippetNameError, SnippetResolver, validate_snippet_name};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::{AppState, RenamePayload};
+use crate::{AppState, PreviewFromRecordPayload, RenamePayload, core_error_response};
 
 #[derive(Debug, Deserialize)]
 pub struct SnippetQuery {
@@ -27,18 +27,43 @@ fn default_limit() -> usize {
     20
 }
 
+#[derive(Debug, Deserialize)]
+pub struct SnippetNamesQuery {
+    #[serde(default)]
+    prefix: String,
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+/// 按前缀列出 snippet 名称，供编辑器输入 `<snippet:` 时自动补全
+pub async fn list_snippet_names(
+    State(state): State<AppState>,
+    Query(q): Query<SnippetNamesQuery>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    match state
+        .run_db(move || storage.list_snippet_names(&q.prefix, q.limit))
+        .await
+    {
+        Ok(Ok(names)) => Json(names).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
 pub async fn list_snippets(
     State(state): State<AppState>,
     Query(q): Query<SnippetQuery>,
 ) -> impl IntoResponse {
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || {
-        storage.list_snippets(q.q.as_deref(), q.category.as_deref(), q.offset, q.limit)
-    })
-    .await
+    match state
+        .run_db(move || {
+            storage.list_snippets(q.q.as_deref(), q.category.as_deref(), q.offset, q.limit)
+        })
+        .await
     {
         Ok(Ok(page)) => Json(page).into_response(),
-        Ok(Err(err)) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
@@ -69,7 +94,7 @@ pub async fn create_snippet(
 ) -> impl IntoResponse {
     let mut snippet = match Snippet::new(payload.name, payload.category, payload.content) {
         Ok(s) => s,
-        Err(err) => return (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+        Err(err) => return core_error_response(err),
     };
     snippet.tags = payload.tags;
     snippet.description = payload.description;
@@ -83,10 +108,9 @@ pub async fn create_snippet(
     };
 
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || {
-        storage.upsert_snippet(snippet, preview_bytes.as_deref())
-    })
-    .await
+    match state
+        .run_db(move || storage.upsert_snippet(snippet, preview_bytes.as_deref()))
+        .await
     {
         Ok(Ok(saved)) => {
             let body = Json(SnippetResponse {
@@ -96,7 +120,7 @@ pub async fn create_snippet(
             });
             (StatusCode::CREATED, body).into_response()
         }
-        Ok(Err(err)) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
@@ -120,13 +144,10 @@ pub async fn update_snippet(
     let storage_for_get = Arc::clone(&storage);
 
     // First get the existing snippet
-    let existing = match tokio::task::spawn_blocking(move || storage_for_get.get_snippet(id)).await
-    {
+    let existing = match state.run_db(move || storage_for_get.get_snippet(id)).await {
         Ok(Ok(Some(snippet))) => snippet,
         Ok(Ok(None)) => return (StatusCode::NOT_FOUND, "snippet not found").into_response(),
-        Ok(Err(err)) => {
-            return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
-        }
+        Ok(Err(err)) => return core_error_response(err),
         Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     };
 
@@ -156,23 +177,148 @@ pub async fn update_snippet(
         None => None,
     };
 
-    match tokio::task::spawn_blocking(move || {
-        storage.upsert_snippet(snippet, preview_bytes.as_deref())
-    })
-    .await
+    match state
+        .run_db(move || storage.upsert_snippet(snippet, preview_bytes.as_deref()))
+        .await
     {
         Ok(Ok(saved)) => Json(saved).into_response(),
-        Ok(Err(err)) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
 
 pub async fn get_snippet(State(state): State<AppState>, Path(id): Path<Uuid>) -> impl IntoResponse {
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || storage.get_snippet(id)).await {
+    match state.run_db(move || storage.get_snippet(id)).await {
         Ok(Ok(Some(snippet))) => Json(snippet).into_response(),
         Ok(Ok(None)) => (StatusCode::NOT_FOUND, "snippet not found").into_response(),
-        Ok(Err(err)) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ValidateNameQuery {
+    name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ValidateNameResponse {
+    valid: bool,
+    reason: Option<SnippetNameError>,
+    taken: bool,
+}
+
+/// 创建前校验 snippet 名称，并检查是否已被占用
+pub async fn validate_snippet_name_handler(
+    State(state): State<AppState>,
+    Query(q): Query<ValidateNameQuery>,
+) -> impl IntoResponse {
+    let reason = validate_snippet_name(&q.name).err();
+    if reason.is_some() {
+        return Json(ValidateNameResponse {
+            valid: false,
+            reason,
+            taken: false,
+        })
+        .into_response();
+    }
+
+    let storage = Arc::clone(&state.storage);
+    match state
+        .run_db(move || storage.get_snippet_by_name(&q.name))
+        .await
+    {
+        Ok(Ok(existing)) => Json(ValidateNameResponse {
+            valid: true,
+            reason: None,
+            taken: existing.is_some(),
+        })
+        .into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SnippetExpandResponse {
+    content: String,
+    expanded: String,
+}
+
+/// 预览 snippet 内容展开后的结果
+pub async fn expand_snippet(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    let result = state
+        .run_db(move || {
+            let Some(snippet) = storage.get_snippet(id)? else {
+                return Ok(None);
+            };
+            let expanded = SnippetResolver::new(storage).expand_snippet(&snippet)?;
+            Ok::<_, CoreError>(Some(SnippetExpandResponse {
+                content: snippet.content,
+                expanded,
+            }))
+        })
+        .await;
+    match result {
+        Ok(Ok(Some(resp))) => Json(resp).into_response(),
+        Ok(Ok(None)) => (StatusCode::NOT_FOUND, "snippet not found").into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ReexpandQuery {
+    /// 为 true 时将新的展开结果写回记录
+    persist: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReexpandResponse {
+    raw_prompt: String,
+    /// 记录中保存的展开结果
+    previous: String,
+    /// 按当前 snippet 定义重新展开的结果
+    expanded: String,
+    changed: bool,
+    persisted: bool,
+}
+
+/// 按当前 snippet 定义重新展开记录的原始提示词，默认只预览不修改记录
+pub async fn reexpand_record(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(q): Query<ReexpandQuery>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    let result = state
+        .run_db(move || {
+            let Some(record) = storage.get_record(id)? else {
+                return Ok(None);
+            };
+            let expanded = SnippetResolver::new(Arc::clone(&storage)).expand(&record.raw_prompt)?;
+            let changed = expanded != record.expanded_prompt;
+            let persisted =
+                q.persist && changed && storage.update_record_expanded_prompt(id, &expanded)?;
+            Ok::<_, CoreError>(Some(ReexpandResponse {
+                raw_prompt: record.raw_prompt,
+                previous: record.expanded_prompt,
+                expanded,
+                changed,
+                persisted,
+            }))
+        })
+        .await;
+    match result {
+        Ok(Ok(Some(resp))) => Json(resp).into_response(),
+        Ok(Ok(None)) => (StatusCode::NOT_FOUND, "record not found").into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
@@ -182,10 +328,10 @@ pub async fn delete_snippet(
     Path(id): Path<Uuid>,
 ) -> impl IntoResponse {
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || storage.delete_snippet(id)).await {
+    match state.run_db(move || storage.delete_snippet(id)).await {
         Ok(Ok(true)) => StatusCode::NO_CONTENT.into_response(),
         Ok(Ok(false)) => (StatusCode::NOT_FOUND, "snippet not found").into_response(),
-        Ok(Err(err)) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
@@ -206,11 +352,34 @@ pub async fn update_snippet_preview(
     };
 
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || storage.update_snippet_preview(id, &preview_bytes))
+    match state
+        .run_db(move || storage.update_snippet_preview(id, &preview_bytes))
+        .await
+    {
+        Ok(Ok(saved)) => Json(saved).into_response(),
+        Ok(Err(err)) => core_error_response(err),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// 以生成记录中的图片（缩小后）作为 snippet 预览图
+pub async fn update_snippet_preview_from_record(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<PreviewFromRecordPayload>,
+) -> impl IntoResponse {
+    let storage = Arc::clone(&state.storage);
+    let gallery = state.gallery_dir.clone();
+    match state
+        .run_db(move || {
+            let bytes =
+                storage.record_image_preview(payload.record_id, payload.image_index, &gallery)?;
+            storage.update_snippet_preview(id, &bytes)
+        })
         .await
     {
         Ok(Ok(saved)) => Json(saved).into_response(),
-        Ok(Err(err)) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
@@ -220,9 +389,12 @@ pub async fn delete_snippet_preview(
     Path(id): Path<Uuid>,
 ) -> impl IntoResponse {
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || storage.delete_snippet_preview(id)).await {
+    match state
+        .run_db(move || storage.delete_snippet_preview(id))
+        .await
+    {
         Ok(Ok(saved)) => Json(saved).into_response(),
-        Ok(Err(err)) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
@@ -233,9 +405,12 @@ pub async fn rename_snippet(
     Json(payload): Json<RenamePayload>,
 ) -> impl IntoResponse {
     let storage = Arc::clone(&state.storage);
-    match tokio::task::spawn_blocking(move || storage.rename_snippet(id, payload.name)).await {
+    match state
+        .run_db(move || storage.rename_snippet(id, payload.name))
+        .await
+    {
         Ok(Ok(saved)) => Json(saved).into_response(),
-        Ok(Err(err)) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+        Ok(Err(err)) => core_error_response(err),
         Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
 }
@@ -0,0 +1,51 @@
+//! 测试辅助 - 应用状态
+
+use std::sync::Arc;
+
+use codex_api::{GenerationLimits, NaiClient, WeightRange};
+use codex_core::{
+    ArchiveOptions, ExecutorConfig, GalleryPaths, GalleryTimezone, GlobalAffix,
+    test_support::{TestDir, TestStorage},
+};
+use tokio::sync::Semaphore;
+
+use crate::{AppState, ArchiveState, TaskQueue, ready::ReadinessState};
+
+/// 临时目录中的应用状态
+pub(crate) struct TestApp {
+    pub(crate) state: AppState,
+    pub(crate) dir: TestDir,
+}
+
+impl TestApp {
+    pub(crate) fn new() -> Self {
+        let TestStorage { dir, storage } = TestStorage::new();
+        let gallery = GalleryPaths::new(dir.join("gallery"));
+        let client = Arc::new(NaiClient::new("token".to_string()).unwrap());
+        let queue = TaskQueue::new(
+            Arc::clone(&client),
+            Arc::clone(&storage),
+            gallery,
+            ExecutorConfig::default(),
+            None,
+        );
+        let state = AppState {
+            storage,
+            queue,
+            gallery_dir: dir.join("gallery"),
+            lexicon: None,
+            nai_client: client,
+            archive_state: ArchiveState::new(),
+            timezone: GalleryTimezone::Local,
+            weight_range: WeightRange::default(),
+            generation_limits: GenerationLimits::default(),
+            archive_options: ArchiveOptions::default(),
+            global_affix: GlobalAffix::default(),
+            db_permits: Arc::new(Semaphore::new(1)),
+            readiness: ReadinessState::new(),
+            admin_token: None,
+            default_uc_preset: None,
+        };
+        Self { state, dir }
+    }
+}
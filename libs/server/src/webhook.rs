@@ -0,0 +1,43 @@
+use std::{path::PathBuf, time::Duration};
+
+use codex_core::RecordHook;
+
+use crate::to_record_view;
+
+/// webhook 最大尝试次数
+const MAX_ATTEMPTS: u32 = 3;
+
+/// 构建记录保存回调：将记录视图 POST 到指定 URL
+///
+/// 请求在后台发送，失败仅记录日志，不影响任务结果
+pub fn record_webhook(url: String, gallery_dir: PathBuf) -> RecordHook {
+    let client = reqwest::Client::new();
+    RecordHook::new(move |record| {
+        let view = to_record_view(record.clone(), &gallery_dir);
+        let client = client.clone();
+        let url = url.clone();
+        tokio::spawn(async move {
+            for attempt in 1..=MAX_ATTEMPTS {
+                let result = client
+                    .post(&url)
+                    .json(&view)
+                    .send()
+                    .await
+                    .and_then(|resp| resp.error_for_status());
+                match result {
+                    Ok(_) => {
+                        tracing::debug!(record_id=%view.id, "webhook delivered");
+                        return;
+                    }
+                    Err(err) if attempt < MAX_ATTEMPTS => {
+                        tracing::warn!(record_id=%view.id, attempt, error=%err, "webhook failed, retrying");
+                        tokio::time::sleep(Duration::from_secs(attempt as u64)).await;
+                    }
+                    Err(err) => {
+                        tracing::warn!(record_id=%view.id, error=%err, "webhook failed, giving up");
+                    }
+                }
+            }
+        });
+    })
+}
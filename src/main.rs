@@ -1,8 +1,14 @@
 use std::net::{Ipv4Addr, SocketAddr};
 use std::path::PathBuf;
+use std::time::Duration;
 
 use anyhow::Result;
-use codex_server::{ServerConfig, serve};
+use codex_server::{
+    ArchiveCompression, ArchiveOptions, DEFAULT_BODY_LIMIT, DEFAULT_DB_CONCURRENCY,
+    DEFAULT_DB_WRITE_RETRIES, DEFAULT_MAX_PENDING_WRITES, DEFAULT_MAX_SNIPPET_CONTENT_BYTES,
+    DEFAULT_PREVIEW_MAX_DIMENSION, GenerationLimits, PngCompression, PoolConfig, ServerConfig,
+    WeightRange, serve,
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -23,6 +29,136 @@ async fn main() -> Result<()> {
         PathBuf::from(std::env::var("CODEX_GALLERY_DIR").unwrap_or_else(|_| "data/gallery".into()));
     let static_dir = std::env::var("CODEX_STATIC_DIR").ok().map(PathBuf::from);
     let nai_token = std::env::var("CODEX_NAI_TOKEN").expect("CODEX_NAI_TOKEN required");
+    let store_max_dimension = std::env::var("CODEX_STORE_MAX_DIMENSION")
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .filter(|&v| v > 0);
+    let png_compression = match std::env::var("CODEX_PNG_COMPRESSION") {
+        Ok(v) if !v.trim().is_empty() => PngCompression::parse(&v)?,
+        _ => PngCompression::default(),
+    };
+    let body_limit = std::env::var("CODEX_BODY_LIMIT_MB")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|&v| v > 0)
+        .map(|mb| mb * 1024 * 1024)
+        .unwrap_or(DEFAULT_BODY_LIMIT);
+    let webhook_url = std::env::var("CODEX_WEBHOOK_URL")
+        .ok()
+        .filter(|v| !v.trim().is_empty());
+    let max_pending_writes = std::env::var("CODEX_MAX_PENDING_WRITES")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|&v| v > 0)
+        .unwrap_or(DEFAULT_MAX_PENDING_WRITES);
+    let db_write_retries = std::env::var("CODEX_DB_WRITE_RETRIES")
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .unwrap_or(DEFAULT_DB_WRITE_RETRIES);
+    let timezone = std::env::var("CODEX_TIMEZONE")
+        .ok()
+        .filter(|v| !v.trim().is_empty());
+    let db_concurrency = std::env::var("CODEX_DB_CONCURRENCY")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|&v| v > 0)
+        .unwrap_or(DEFAULT_DB_CONCURRENCY);
+    let inter_image_delay_ms = std::env::var("CODEX_INTER_IMAGE_DELAY_MS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(0);
+    let nai_proxy = std::env::var("CODEX_NAI_PROXY")
+        .ok()
+        .filter(|v| !v.trim().is_empty());
+    let default_pool = PoolConfig::default();
+    let nai_pool = PoolConfig {
+        max_idle_per_host: std::env::var("CODEX_NAI_POOL_MAX_IDLE")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(default_pool.max_idle_per_host),
+        idle_timeout: match std::env::var("CODEX_NAI_POOL_IDLE_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+        {
+            Some(0) => None,
+            Some(secs) => Some(Duration::from_secs(secs)),
+            None => default_pool.idle_timeout,
+        },
+    };
+    let verify_writes = std::env::var("CODEX_VERIFY_WRITES")
+        .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false);
+    let max_snippet_content_bytes = std::env::var("CODEX_MAX_SNIPPET_KB")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|&v| v > 0)
+        .map(|kb| kb * 1024)
+        .unwrap_or(DEFAULT_MAX_SNIPPET_CONTENT_BYTES);
+    let audit_log_path = std::env::var("CODEX_AUDIT_LOG")
+        .ok()
+        .filter(|v| !v.trim().is_empty())
+        .map(PathBuf::from);
+    let audit_privacy = std::env::var("CODEX_AUDIT_PRIVACY")
+        .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false);
+    let default_weight_range = WeightRange::default();
+    let weight_range = WeightRange {
+        min: std::env::var("CODEX_WEIGHT_MIN")
+            .ok()
+            .and_then(|v| v.parse::<f64>().ok())
+            .unwrap_or(default_weight_range.min),
+        max: std::env::var("CODEX_WEIGHT_MAX")
+            .ok()
+            .and_then(|v| v.parse::<f64>().ok())
+            .unwrap_or(default_weight_range.max),
+    };
+    let default_limits = GenerationLimits::default();
+    let env_limit = |name: &str, default: u32| {
+        std::env::var(name)
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .filter(|&v| v > 0)
+            .unwrap_or(default)
+    };
+    let generation_limits = GenerationLimits {
+        max_width: env_limit("CODEX_MAX_WIDTH", default_limits.max_width),
+        max_height: env_limit("CODEX_MAX_HEIGHT", default_limits.max_height),
+        max_steps: env_limit("CODEX_MAX_STEPS", default_limits.max_steps),
+    };
+    let default_archive = ArchiveOptions::default();
+    let archive_options = ArchiveOptions {
+        compression: match std::env::var("CODEX_ARCHIVE_COMPRESSION") {
+            Ok(v) if !v.trim().is_empty() => ArchiveCompression::parse(&v)?,
+            _ => default_archive.compression,
+        },
+        buffer_size: std::env::var("CODEX_ARCHIVE_BUFFER_KB")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|&v| v > 0)
+            .map(|kb| kb * 1024)
+            .unwrap_or(default_archive.buffer_size),
+        keep_recent_days: std::env::var("CODEX_ARCHIVE_KEEP_DAYS")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(default_archive.keep_recent_days),
+    };
+    let global_prefix = std::env::var("CODEX_GLOBAL_PREFIX")
+        .ok()
+        .filter(|v| !v.trim().is_empty());
+    let global_suffix = std::env::var("CODEX_GLOBAL_SUFFIX")
+        .ok()
+        .filter(|v| !v.trim().is_empty());
+    let default_uc_preset = std::env::var("CODEX_DEFAULT_UC_PRESET")
+        .ok()
+        .and_then(|v| v.trim().parse::<u8>().ok());
+    let admin_token = std::env::var("CODEX_ADMIN_TOKEN")
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty());
+    let preview_max_dimension = std::env::var("CODEX_PREVIEW_MAX_DIMENSION")
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .unwrap_or(DEFAULT_PREVIEW_MAX_DIMENSION);
 
     let cfg = ServerConfig {
         addr,
@@ -31,6 +167,29 @@ async fn main() -> Result<()> {
         gallery_dir,
         static_dir,
         nai_token,
+        store_max_dimension,
+        png_compression,
+        body_limit,
+        webhook_url,
+        max_pending_writes,
+        db_write_retries,
+        timezone,
+        weight_range,
+        generation_limits,
+        db_concurrency,
+        inter_image_delay_ms,
+        nai_proxy,
+        nai_pool,
+        verify_writes,
+        max_snippet_content_bytes,
+        audit_log_path,
+        audit_privacy,
+        archive_options,
+        preview_max_dimension,
+        global_prefix,
+        global_suffix,
+        admin_token,
+        default_uc_preset,
     };
 
     serve(cfg).await
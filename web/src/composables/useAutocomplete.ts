@@ -11,7 +11,7 @@ import { useDebounceFn } from '@vueuse/core';
 import {
   searchLexicon,
   fetchSnippets,
-  type LexiconEntry,
+  type LexiconSearchHit,
   type SnippetSummary,
 } from 'src/services/api';
 
@@ -74,11 +74,11 @@ export function useAutocomplete(options: UseAutocompleteOptions) {
     try {
       if (mode === 'lexicon') {
         const result = await searchLexicon({ q: query, limit: 10 });
-        autocompleteItems.value = result.entries.map((e: LexiconEntry) => ({
+        autocompleteItems.value = result.entries.map((e: LexiconSearchHit) => ({
           type: 'lexicon' as const,
           tag: e.tag,
           label: e.tag,
-          sublabel: e.zh,
+          sublabel: e.display ?? e.translations.zh,
         }));
       } else {
         const params: { q?: string; limit: number } = { limit: 10 };
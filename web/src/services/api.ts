@@ -10,11 +10,25 @@ export const previewsBase = '/previews';
 
 export type Center = { x: number; y: number };
 
+// 发送时也可以直接使用预设名代替坐标
+export type CenterPreset =
+  | 'center'
+  | 'left'
+  | 'right'
+  | 'top'
+  | 'bottom'
+  | 'top_left'
+  | 'top_right'
+  | 'bottom_left'
+  | 'bottom_right';
+
 export type CharacterPrompt = {
   prompt: string;
   uc: string;
-  center?: Center;
+  center?: Center | CenterPreset;
   enabled?: boolean;
+  add_quality_tags?: boolean;
+  inherit_uc?: boolean;
 };
 
 export type GenerationParams = {
@@ -31,6 +45,7 @@ export type GenerationParams = {
   character_prompts?: CharacterPrompt[];
   seed?: number | null;
   variety_plus?: boolean;
+  master_seed?: number | null;
 };
 
 // 主提示词预设设置
@@ -52,6 +67,10 @@ export type TaskSubmitPayload = {
   preset_id?: string | null;
   // 主提示词预设设置
   main_preset?: MainPresetSettings;
+  // 被内容过滤拒绝时换下一个种子重试
+  retry_on_filter?: boolean;
+  // 输出子目录标签
+  label?: string | null;
 };
 
 export type TaskStatus =
@@ -68,10 +87,23 @@ export type GenerationRecord = {
   raw_prompt: string;
   expanded_prompt: string;
   negative_prompt: string;
-  images: Array<{ url: string; seed: number; width: number; height: number }>;
+  label?: string;
+  images: Array<{
+    url: string;
+    seed: number;
+    width: number;
+    height: number;
+    filter_retries?: number;
+  }>;
 };
 
-export type Page<T> = { items: T[]; total: number };
+export type Page<T> = {
+  items: T[];
+  total: number;
+  skipped?: number;
+  has_more: boolean;
+  next_offset: number | null;
+};
 
 export type Snippet = {
   id: string;
@@ -174,6 +206,55 @@ export async function fetchRecentRecords() {
   return data;
 }
 
+export type Recipe = {
+  version: number;
+  exported_at: string;
+  source_record?: string | null;
+  raw_prompt: string;
+  negative_prompt: string;
+  params?: GenerationParams | null;
+  main_preset?: MainPresetSettings | null;
+  snippets: Snippet[];
+  unresolved_snippets: string[];
+};
+
+export async function fetchRecordRecipe(id: string) {
+  const { data } = await api.get<Recipe>(`/records/${id}/recipe`);
+  return data;
+}
+
+export type ImportConflict = 'skip' | 'rename' | 'overwrite';
+
+export type RecipeImportResult = {
+  task: {
+    raw_prompt: string;
+    negative_prompt: string;
+    params?: GenerationParams;
+    main_preset: MainPresetSettings;
+  };
+  created: string[];
+  unchanged: string[];
+  skipped: string[];
+  overwritten: string[];
+  renamed: Array<{ from: string; to: string }>;
+  unresolved: string[];
+};
+
+export async function importRecipe(recipe: Recipe, onConflict: ImportConflict = 'skip') {
+  const { data } = await api.post<RecipeImportResult>('/recipe/import', {
+    recipe,
+    on_conflict: onConflict,
+  });
+  return data;
+}
+
+export async function fetchRecordsByLabel(label: string) {
+  const { data } = await api.get<GenerationRecord[]>(
+    `/records/by-label/${encodeURIComponent(label)}`,
+  );
+  return data;
+}
+
 export async function deleteRecord(id: string) {
   await api.delete(`/records/${id}`);
 }
@@ -248,6 +329,7 @@ export type RenameSnippetResult = {
   snippet: Snippet;
   updated_presets: number;
   updated_settings: boolean;
+  updated_profiles: number;
 };
 
 export async function renameSnippet(id: string, name: string) {
@@ -359,6 +441,32 @@ export async function updateMainPreset(
   return data;
 }
 
+export async function fetchDefaultMainPreset() {
+  const { data } = await api.get<MainPreset | null>('/main-presets/default');
+  return data;
+}
+
+export async function setDefaultMainPreset(id: string) {
+  const { data } = await api.put<MainPreset>(`/main-presets/${id}/default`);
+  return data;
+}
+
+export type PresetFieldDiff = {
+  field: keyof MainPresetSettings;
+  old: string | null;
+  new: string | null;
+};
+
+// 当前设置与已保存预设不同的字段
+export async function diffMainPreset(id: string, settings: MainPresetSettings) {
+  const { data } = await api.post<PresetFieldDiff[]>(`/main-presets/${id}/diff`, settings);
+  return data;
+}
+
+export async function clearDefaultMainPreset() {
+  await api.delete('/main-presets/default');
+}
+
 export async function deleteMainPreset(id: string) {
   await api.delete(`/main-presets/${id}`);
 }
@@ -391,6 +499,39 @@ export async function saveGenerationSettings(settings: LastGenerationSettings) {
   await api.put('/settings/generation', settings);
 }
 
+export type GenerationProfile = {
+  name: string;
+  settings: LastGenerationSettings;
+};
+
+export async function fetchGenerationProfiles() {
+  const { data } = await api.get<GenerationProfile[]>('/settings/profiles');
+  return data;
+}
+
+export async function fetchGenerationProfile(name: string) {
+  const { data } = await api.get<LastGenerationSettings>(
+    `/settings/profiles/${encodeURIComponent(name)}`,
+  );
+  return data;
+}
+
+export async function saveGenerationProfile(name: string, settings: LastGenerationSettings) {
+  await api.put(`/settings/profiles/${encodeURIComponent(name)}`, settings);
+}
+
+export async function deleteGenerationProfile(name: string) {
+  await api.delete(`/settings/profiles/${encodeURIComponent(name)}`);
+}
+
+// 载入为当前生成设置
+export async function loadGenerationProfile(name: string) {
+  const { data } = await api.post<LastGenerationSettings>(
+    `/settings/profiles/${encodeURIComponent(name)}/load`,
+  );
+  return data;
+}
+
 // ============== Prompt API ==============
 
 export type HighlightSpan = {
@@ -426,6 +567,80 @@ export async function formatPrompt(prompt: string) {
   return data.formatted;
 }
 
+export interface CompactPromptResult {
+  compacted: string;
+  original_length: number;
+  compacted_length: number;
+}
+
+export async function compactPrompt(prompt: string) {
+  const { data } = await api.post<CompactPromptResult>('/prompt/compact', { prompt });
+  return data;
+}
+
+export interface ChunkedTag {
+  tag: string;
+  chunk: number;
+  start_token: number;
+  start: number;
+  end: number;
+}
+
+/** 粗略估算，并非真实分词 */
+export interface PromptBudget {
+  tokens: number;
+  chunks: number;
+  chunk_tokens: number;
+  late_tags: ChunkedTag[];
+}
+
+export async function fetchPromptBudget(prompt: string) {
+  const { data } = await api.post<PromptBudget>('/prompt/budget', { prompt });
+  return data;
+}
+
+export type CharacterSplit =
+  | { kind: 'delimiter'; delimiter: string }
+  | { kind: 'markers'; markers: string[] };
+
+export async function splitCharacters(prompt: string, split?: CharacterSplit) {
+  const { data } = await api.post<CharacterPrompt[]>('/prompt/split-characters', {
+    prompt,
+    split,
+  });
+  return data;
+}
+
+export type SnippetWeight = {
+  name: string;
+  weight: number;
+  start: number;
+  end: number;
+};
+
+export async function fetchSnippetWeights(prompt: string) {
+  const { data } = await api.post<{ snippets: SnippetWeight[] }>('/prompt/snippet-weights', {
+    prompt,
+  });
+  return data.snippets;
+}
+
+export type PromptTemplate = {
+  id: string;
+  name: string;
+  category: string;
+  description?: string | null;
+  positive: string;
+  negative: string;
+  width?: number;
+  height?: number;
+};
+
+export async function fetchBuiltinTemplates() {
+  const { data } = await api.get<PromptTemplate[]>('/prompt/templates/builtin');
+  return data;
+}
+
 // ============== Dry-Run API ==============
 
 export type ProcessedCharacterPrompt = {
@@ -438,6 +653,7 @@ export type ProcessedCharacterPrompt = {
 
 export type DryRunResult = {
   raw_positive: string;
+  positive_after_global?: string | null;
   positive_after_preset: string;
   final_positive: string;
   raw_negative: string;
@@ -462,7 +678,7 @@ export async function dryRunPrompt(payload: DryRunPayload) {
 
 export type LexiconEntry = {
   tag: string;
-  zh: string;
+  translations: Record<string, string>;
   weight?: number;
   category: string;
   subcategory: string;
@@ -492,8 +708,13 @@ export type CategoryData = {
   subcategories: Record<string, LexiconEntry[]>;
 };
 
+export type LexiconSearchHit = LexiconEntry & {
+  matched_lang: string | null;
+  display: string | null;
+};
+
 export type LexiconSearchResult = {
-  entries: LexiconEntry[];
+  entries: LexiconSearchHit[];
   total: number;
 };
 
@@ -507,11 +728,52 @@ export async function fetchLexiconCategory(name: string) {
   return data;
 }
 
-export async function searchLexicon(params: { q: string; limit?: number; offset?: number }) {
+export async function searchLexicon(params: {
+  q: string;
+  limit?: number;
+  offset?: number;
+  lang?: string;
+  category?: string;
+  subcategory?: string;
+}) {
   const { data } = await api.get<LexiconSearchResult>('/lexicon/search', { params });
   return data;
 }
 
+export type TagStats = {
+  total_generations: number;
+  total_images: number;
+  first_generation: string | null;
+  last_generation: string | null;
+  tags: Array<{ tag: string; count: number }>;
+};
+
+export async function fetchTagStats(limit?: number) {
+  const { data } = await api.get<TagStats>('/stats/tags', { params: { limit } });
+  return data;
+}
+
+// 本地统计的生成用量
+export type UsageStats = {
+  total_images: number;
+  total_tasks: number;
+  first_generated_at: string | null;
+  last_generated_at: string | null;
+};
+
+export async function fetchUsageStats() {
+  const { data } = await api.get<UsageStats>('/stats/usage');
+  return data;
+}
+
+// 需要服务端配置的 CODEX_ADMIN_TOKEN；返回清零前的统计
+export async function resetUsageStats(adminToken: string) {
+  const { data } = await api.post<UsageStats>('/stats/usage/reset', undefined, {
+    headers: { Authorization: `Bearer ${adminToken}` },
+  });
+  return data;
+}
+
 // ============== Archives ==============
 
 export type ArchiveInfo = {
@@ -520,9 +782,17 @@ export type ArchiveInfo = {
   created_at: string;
 };
 
+export type ArchiveEntry = {
+  name: string;
+  size: number;
+  compressed_size: number;
+};
+
 export type ArchiveResult = {
   archives: ArchiveInfo[];
   deleted_records: number;
+  cancelled: boolean;
+  remaining_dates: string[];
 };
 
 export type ArchivableDate = {
@@ -536,7 +806,13 @@ export type ArchiveTaskStatus =
   | { status: 'idle' }
   | { status: 'running'; message: string }
   | { status: 'completed'; archives: ArchiveInfo[]; deleted_records: number }
-  | { status: 'failed'; error: string };
+  | { status: 'failed'; error: string }
+  | {
+      status: 'cancelled';
+      archives: ArchiveInfo[];
+      deleted_records: number;
+      remaining_dates: string[];
+    };
 
 export type ArchiveStartedResponse = {
   message: string;
@@ -552,6 +828,17 @@ export async function fetchArchivableDates() {
   return data;
 }
 
+export type ServerStatus = {
+  generation_active: boolean;
+  archive_running: boolean;
+  queue_pending: number;
+};
+
+export async function fetchServerStatus() {
+  const { data } = await api.get<ServerStatus>('/status');
+  return data;
+}
+
 export async function fetchArchiveStatus() {
   const { data } = await api.get<ArchiveTaskStatus>('/archives/status');
   return data;
@@ -562,6 +849,12 @@ export async function createArchive() {
   return data;
 }
 
+// 当前日期归档完成后停止
+export async function cancelArchive() {
+  const { data } = await api.post<ArchiveStartedResponse>('/archives/cancel');
+  return data;
+}
+
 export async function createArchiveSelected(dates: string[]) {
   const { data } = await api.post<ArchiveStartedResponse>('/archives/selected', { dates });
   return data;
@@ -571,6 +864,17 @@ export async function deleteArchive(name: string) {
   await api.delete(`/archives/${encodeURIComponent(name)}`);
 }
 
+export async function fetchArchiveContents(name: string) {
+  const { data } = await api.get<ArchiveEntry[]>(
+    `/archives/${encodeURIComponent(name)}/contents`,
+  );
+  return data;
+}
+
+export function getArchiveFileUrl(name: string, entry: string) {
+  return `${apiBase}/archives/${encodeURIComponent(name)}/file?entry=${encodeURIComponent(entry)}`;
+}
+
 export function getArchiveDownloadUrl(name: string) {
   return `${apiBase}/archives/${encodeURIComponent(name)}`;
 }